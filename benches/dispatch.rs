@@ -0,0 +1,152 @@
+//! Dispatching queued protocols at high peer counts, without any transport.
+//!
+//! Run with `cargo bench --bench dispatch`. Measures how long it takes to dispatch one protocol to
+//! each of many connected peers and to dispatch protocols to a single peer while many protocols
+//! are queued for peers that are not connected.
+
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use libp2p::core::connection::ConnectionId;
+use libp2p::core::{ConnectedPoint, Multiaddr};
+use libp2p::futures::task::{noop_waker_ref, Context, Poll};
+use libp2p::swarm::{AddressRecord, NetworkBehaviour, NetworkBehaviourAction, PollParameters};
+use libp2p::PeerId;
+use libp2p_async_await::{Behaviour, BehaviourOutEvent, ProtocolInEvent, ProtocolOutEvent};
+
+type BenchBehaviour = Behaviour<(), (), anyhow::Error>;
+type BenchAction = NetworkBehaviourAction<
+    ProtocolInEvent<(), (), anyhow::Error>,
+    BehaviourOutEvent<(), (), anyhow::Error>,
+>;
+
+const PEER_COUNTS: [usize; 3] = [100, 1_000, 10_000];
+const PROTOCOLS: usize = 1000;
+
+struct DummyPollParameters(PeerId);
+
+impl PollParameters for DummyPollParameters {
+    type SupportedProtocolsIter = std::iter::Empty<Vec<u8>>;
+    type ListenedAddressesIter = std::iter::Empty<Multiaddr>;
+    type ExternalAddressesIter = std::iter::Empty<AddressRecord>;
+
+    fn supported_protocols(&self) -> Self::SupportedProtocolsIter {
+        std::iter::empty()
+    }
+
+    fn listened_addresses(&self) -> Self::ListenedAddressesIter {
+        std::iter::empty()
+    }
+
+    fn external_addresses(&self) -> Self::ExternalAddressesIter {
+        std::iter::empty()
+    }
+
+    fn local_peer_id(&self) -> &PeerId {
+        &self.0
+    }
+}
+
+fn dispatch(c: &mut Criterion) {
+    let mut group = c.benchmark_group("dispatch");
+    group.sample_size(10);
+
+    for peers in PEER_COUNTS {
+        group.throughput(Throughput::Elements(peers as u64));
+        group.bench_with_input(
+            BenchmarkId::new("one per connected peer", peers),
+            &peers,
+            |b, &peers| {
+                b.iter_batched(
+                    || connected_peers(peers),
+                    |behaviour| one_per_connected_peer(behaviour, peers),
+                    BatchSize::LargeInput,
+                )
+            },
+        );
+
+        group.throughput(Throughput::Elements(PROTOCOLS as u64));
+        group.bench_with_input(
+            BenchmarkId::new("behind disconnected", peers),
+            &peers,
+            |b, &peers| {
+                b.iter_batched(
+                    || disconnected_peers(peers),
+                    behind_disconnected_peers,
+                    BatchSize::LargeInput,
+                )
+            },
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, dispatch);
+criterion_main!(benches);
+
+/// A behaviour with one protocol queued for each of the given number of connected peers.
+fn connected_peers(peers: usize) -> BenchBehaviour {
+    let mut behaviour = BenchBehaviour::new(b"/bench/1.0.0");
+    for id in 0..peers {
+        let peer = PeerId::random();
+        behaviour.inject_connection_established(&peer, &ConnectionId::new(id), &dialer());
+        behaviour.do_protocol_dialer(peer, |_| async { Ok(()) });
+    }
+
+    behaviour
+}
+
+/// Dispatches the protocol queued for each connected peer.
+fn one_per_connected_peer(mut behaviour: BenchBehaviour, peers: usize) -> BenchBehaviour {
+    let mut dispatched = 0;
+    while let Poll::Ready(NetworkBehaviourAction::NotifyHandler { .. }) = poll(&mut behaviour) {
+        dispatched += 1;
+    }
+    assert_eq!(dispatched, peers);
+
+    behaviour
+}
+
+/// A behaviour with one protocol queued for each of the given number of peers that are not
+/// connected and many protocols queued for a single connected peer.
+fn disconnected_peers(peers: usize) -> (BenchBehaviour, PeerId, ConnectionId) {
+    let mut behaviour = BenchBehaviour::new(b"/bench/1.0.0");
+    for _ in 0..peers {
+        behaviour.do_protocol_dialer(PeerId::random(), |_| async { Ok(()) });
+    }
+    let peer = PeerId::random();
+    let connection = ConnectionId::new(0);
+    behaviour.inject_connection_established(&peer, &connection, &dialer());
+    for _ in 0..PROTOCOLS {
+        behaviour.do_protocol_dialer(peer, |_| async { Ok(()) });
+    }
+
+    (behaviour, peer, connection)
+}
+
+/// Dispatches the protocols of the connected peer one after the other.
+fn behind_disconnected_peers(
+    (mut behaviour, peer, connection): (BenchBehaviour, PeerId, ConnectionId),
+) -> BenchBehaviour {
+    for _ in 0..PROTOCOLS {
+        assert!(matches!(
+            poll(&mut behaviour),
+            Poll::Ready(NetworkBehaviourAction::NotifyHandler { .. })
+        ));
+        behaviour.inject_event(peer, connection, ProtocolOutEvent::Outbound(Ok(())));
+        behaviour.drain_events();
+    }
+
+    behaviour
+}
+
+fn poll(behaviour: &mut BenchBehaviour) -> Poll<BenchAction> {
+    let mut cx = Context::from_waker(noop_waker_ref());
+
+    behaviour.poll(&mut cx, &mut DummyPollParameters(PeerId::random()))
+}
+
+fn dialer() -> ConnectedPoint {
+    ConnectedPoint::Dialer {
+        address: "/memory/1234".parse().unwrap(),
+    }
+}
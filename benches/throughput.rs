@@ -0,0 +1,142 @@
+//! Request/response throughput over a memory transport.
+//!
+//! Run with `cargo bench --bench throughput`. Measures the throughput of executing one protocol
+//! per request and of exchanging many requests over the substream of a single protocol, for
+//! several message sizes.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use harness::{new_connected_swarm_pair, Actor};
+use libp2p::futures::FutureExt;
+use libp2p::swarm::SwarmEvent;
+use libp2p_async_await::{Behaviour, BehaviourOutEvent};
+use std::time::{Duration, Instant};
+use tokio::runtime::{Handle, Runtime};
+
+#[path = "../tests/harness/mod.rs"]
+mod harness;
+
+type BenchBehaviour = Behaviour<(), (), anyhow::Error>;
+
+const MESSAGE_SIZES: [usize; 3] = [64, 1024, 16 * 1024];
+
+fn throughput(c: &mut Criterion) {
+    let _ = env_logger::try_init();
+    let runtime = Runtime::new().expect("runtime to build");
+    let mut group = c.benchmark_group("throughput");
+    group.throughput(Throughput::Elements(1));
+
+    for size in MESSAGE_SIZES {
+        group.bench_with_input(
+            BenchmarkId::new("protocol per request", size),
+            &size,
+            |b, &size| {
+                b.iter_custom(|requests| runtime.block_on(protocol_per_request(size, requests)))
+            },
+        );
+        group.bench_with_input(
+            BenchmarkId::new("requests per protocol", size),
+            &size,
+            |b, &size| {
+                b.iter_custom(|requests| runtime.block_on(requests_per_protocol(size, requests)))
+            },
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, throughput);
+criterion_main!(benches);
+
+/// Executes one protocol per request, returning how long the requests took.
+async fn protocol_per_request(size: usize, requests: u64) -> Duration {
+    let (mut alice, mut bob) = new_pair().await;
+
+    let start = Instant::now();
+    for _ in 0..requests {
+        alice.swarm.behaviour_mut().do_protocol_dialer(
+            bob.peer_id,
+            move |mut substream| async move {
+                substream.write_message(&vec![0; size]).await?;
+                substream.read_message(size).await?;
+                Ok(())
+            },
+        );
+        bob.swarm.behaviour_mut().do_protocol_listener(
+            alice.peer_id,
+            move |mut substream| async move {
+                substream.respond(size, |request| request).await?;
+                Ok(())
+            },
+        );
+
+        run_until_done(&mut alice, &mut bob).await;
+    }
+
+    start.elapsed()
+}
+
+/// Exchanges all requests within a single protocol, returning how long they took.
+async fn requests_per_protocol(size: usize, requests: u64) -> Duration {
+    let (mut alice, mut bob) = new_pair().await;
+
+    let start = Instant::now();
+    alice
+        .swarm
+        .behaviour_mut()
+        .do_protocol_dialer(bob.peer_id, move |mut substream| async move {
+            let request = vec![0; size];
+            for _ in 0..requests {
+                substream.write_message(&request).await?;
+                substream.read_message(size).await?;
+            }
+            Ok(())
+        });
+    bob.swarm.behaviour_mut().do_protocol_listener(
+        alice.peer_id,
+        move |mut substream| async move {
+            for _ in 0..requests {
+                substream.respond(size, |request| request).await?;
+            }
+            Ok(())
+        },
+    );
+    run_until_done(&mut alice, &mut bob).await;
+
+    start.elapsed()
+}
+
+async fn new_pair() -> (Actor<BenchBehaviour>, Actor<BenchBehaviour>) {
+    new_connected_swarm_pair(
+        |_, _| BenchBehaviour::new(b"/bench/1.0.0"),
+        Handle::current(),
+    )
+    .await
+}
+
+/// Drives both swarms until both sides completed their protocol.
+async fn run_until_done(alice: &mut Actor<BenchBehaviour>, bob: &mut Actor<BenchBehaviour>) {
+    let mut alice_done = false;
+    let mut bob_done = false;
+
+    while !(alice_done && bob_done) {
+        libp2p::futures::select! {
+            event = alice.swarm.next_event().fuse() => match event {
+                SwarmEvent::Behaviour(BehaviourOutEvent::Outbound(_, res, _)) => {
+                    res.expect("request to succeed");
+                    alice_done = true;
+                }
+                SwarmEvent::Behaviour(event) => panic!("unexpected event {:?}", event),
+                _ => {}
+            },
+            event = bob.swarm.next_event().fuse() => match event {
+                SwarmEvent::Behaviour(BehaviourOutEvent::Inbound(_, res)) => {
+                    res.expect("response to succeed");
+                    bob_done = true;
+                }
+                SwarmEvent::Behaviour(event) => panic!("unexpected event {:?}", event),
+                _ => {}
+            },
+        }
+    }
+}
@@ -0,0 +1,44 @@
+//! Exchanging human-readable agent strings, e.g. `my-app/1.2.0`, at the start of a protocol.
+
+use crate::FramingError;
+use std::fmt;
+use std::string::FromUtf8Error;
+
+/// The maximum size of an agent string read by `exchange_agent`.
+pub const MAX_AGENT_SIZE: usize = 256;
+
+/// The error returned when exchanging agent strings fails.
+#[derive(Debug)]
+pub enum AgentError {
+    /// Sending our agent string failed.
+    Write(FramingError),
+    Read(FramingError),
+    /// The agent string of the remote is not valid UTF-8.
+    InvalidUtf8(FromUtf8Error),
+}
+
+impl From<FramingError> for AgentError {
+    fn from(e: FramingError) -> Self {
+        AgentError::Read(e)
+    }
+}
+
+impl fmt::Display for AgentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AgentError::Write(e) => write!(f, "failed to send agent string: {}", e),
+            AgentError::Read(e) => write!(f, "{}", e),
+            AgentError::InvalidUtf8(_) => write!(f, "agent string is not valid UTF-8"),
+        }
+    }
+}
+
+impl std::error::Error for AgentError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            AgentError::Write(e) => Some(e),
+            AgentError::Read(e) => Some(e),
+            AgentError::InvalidUtf8(e) => Some(e),
+        }
+    }
+}
@@ -0,0 +1,143 @@
+//! Authorizing protocols with tokens bound to the `PeerId` of the remote.
+//!
+//! A protocol fn that wants to authorize the remote reads its token first through
+//! `verify_token`, before running the main logic. With the `signed-token` feature, tokens can be
+//! signed by the identity key of the remote, see [`SignedToken`].
+
+use crate::FramingError;
+use libp2p::PeerId;
+use std::fmt;
+
+#[cfg(feature = "signed-token")]
+use libp2p::identity::{error::SigningError, Keypair, PublicKey};
+
+/// The maximum size of a token read by `verify_token`.
+pub const MAX_TOKEN_SIZE: usize = 8 * 1024;
+
+/// The error returned when authorizing the remote fails.
+#[derive(Debug)]
+pub enum AuthError {
+    Read(FramingError),
+    /// The token sent by the remote was rejected by the verifier.
+    Invalid(PeerId),
+}
+
+impl From<FramingError> for AuthError {
+    fn from(e: FramingError) -> Self {
+        AuthError::Read(e)
+    }
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthError::Read(e) => write!(f, "{}", e),
+            AuthError::Invalid(peer) => write!(f, "token is not valid for peer {}", peer),
+        }
+    }
+}
+
+impl std::error::Error for AuthError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            AuthError::Read(e) => Some(e),
+            AuthError::Invalid(_) => None,
+        }
+    }
+}
+
+/// Prepended to the claims before signing them, so that tokens can't be mistaken for other
+/// signatures made with the same key.
+#[cfg(feature = "signed-token")]
+const SIGNING_DOMAIN: &[u8] = b"libp2p-async-await-token:";
+
+/// A token carrying claims signed by the identity key of a peer.
+///
+/// Encoded as the length-prefixed protobuf encoding of the public key, the length-prefixed claims
+/// and the signature, with big-endian `u32` lengths.
+#[cfg(feature = "signed-token")]
+#[derive(Debug, Clone)]
+pub struct SignedToken {
+    public_key: PublicKey,
+    claims: Vec<u8>,
+    signature: Vec<u8>,
+}
+
+#[cfg(feature = "signed-token")]
+impl SignedToken {
+    /// Signs the claims with the keypair and returns the encoded token.
+    pub fn sign(keypair: &Keypair, claims: &[u8]) -> Result<Vec<u8>, SigningError> {
+        let signature = keypair.sign(&signing_message(claims))?;
+        let public_key = keypair.public().into_protobuf_encoding();
+
+        let mut token = Vec::with_capacity(8 + public_key.len() + claims.len() + signature.len());
+        token.extend_from_slice(&(public_key.len() as u32).to_be_bytes());
+        token.extend_from_slice(&public_key);
+        token.extend_from_slice(&(claims.len() as u32).to_be_bytes());
+        token.extend_from_slice(claims);
+        token.extend_from_slice(&signature);
+
+        Ok(token)
+    }
+
+    /// Decodes a token, returning `None` if it is malformed.
+    ///
+    /// The signature is not checked, use [`SignedToken::verify`] for that.
+    pub fn decode(token: &[u8]) -> Option<Self> {
+        let (public_key, rest) = split_length_prefixed(token)?;
+        let (claims, signature) = split_length_prefixed(rest)?;
+
+        Some(Self {
+            public_key: PublicKey::from_protobuf_encoding(public_key).ok()?,
+            claims: claims.to_vec(),
+            signature: signature.to_vec(),
+        })
+    }
+
+    /// The peer that signed the token.
+    pub fn peer_id(&self) -> PeerId {
+        self.public_key.clone().into_peer_id()
+    }
+
+    pub fn claims(&self) -> &[u8] {
+        &self.claims
+    }
+
+    /// Returns whether the token was signed by the identity key of `peer`.
+    pub fn verify(&self, peer: &PeerId) -> bool {
+        self.peer_id() == *peer
+            && self
+                .public_key
+                .verify(&signing_message(&self.claims), &self.signature)
+    }
+}
+
+/// A verifier for `verify_token` that accepts any [`SignedToken`] signed by the expected peer,
+/// regardless of its claims.
+#[cfg(feature = "signed-token")]
+pub fn verify_signed_token(expected_peer: &PeerId, token: &[u8]) -> bool {
+    SignedToken::decode(token).is_some_and(|token| token.verify(expected_peer))
+}
+
+#[cfg(feature = "signed-token")]
+fn signing_message(claims: &[u8]) -> Vec<u8> {
+    let mut msg = Vec::with_capacity(SIGNING_DOMAIN.len() + claims.len());
+    msg.extend_from_slice(SIGNING_DOMAIN);
+    msg.extend_from_slice(claims);
+
+    msg
+}
+
+#[cfg(feature = "signed-token")]
+fn split_length_prefixed(bytes: &[u8]) -> Option<(&[u8], &[u8])> {
+    if bytes.len() < 4 {
+        return None;
+    }
+    let (length, rest) = bytes.split_at(4);
+    let length = u32::from_be_bytes([length[0], length[1], length[2], length[3]]) as usize;
+    if rest.len() < length {
+        return None;
+    }
+
+    Some(rest.split_at(length))
+}
@@ -0,0 +1,266 @@
+//! Shaping the bytes exchanged with each peer.
+//!
+//! Every connected peer has a budget of bytes that its substreams read from and write to alike,
+//! across all protocols and connections. The budget is a token bucket like the one of
+//! [`crate::egress`]: transfers take as many tokens as they can and wait for the bucket to refill
+//! if it is empty, so exceeding the limit delays reads and writes instead of failing them.
+
+use crate::egress::Bucket;
+use libp2p::futures::{AsyncRead, AsyncWrite, Future};
+use libp2p::PeerId;
+use std::collections::HashMap;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use wasm_timer::Delay;
+
+/// The limits of all peers, see [`crate::Behaviour::set_bandwidth_limit_per_peer`].
+#[derive(Default)]
+pub(crate) struct Bandwidth(Mutex<Limits>);
+
+#[derive(Default)]
+struct Limits {
+    default: Option<u64>,
+    overrides: HashMap<PeerId, Option<u64>>,
+    peers: HashMap<PeerId, Arc<PeerBudget>>,
+}
+
+impl Limits {
+    fn limit(&self, peer: &PeerId) -> Option<u64> {
+        self.overrides.get(peer).copied().unwrap_or(self.default)
+    }
+}
+
+impl Bandwidth {
+    pub(crate) fn default_limit(&self) -> Option<u64> {
+        self.0.lock().expect("lock not to be poisoned").default
+    }
+
+    pub(crate) fn limit(&self, peer: &PeerId) -> Option<u64> {
+        self.0.lock().expect("lock not to be poisoned").limit(peer)
+    }
+
+    pub(crate) fn overrides(&self) -> HashMap<PeerId, Option<u64>> {
+        self.0
+            .lock()
+            .expect("lock not to be poisoned")
+            .overrides
+            .clone()
+    }
+
+    /// The budget of the peer, shared by all of its connections.
+    pub(crate) fn peer(&self, peer: &PeerId) -> Arc<PeerBudget> {
+        let mut limits = self.0.lock().expect("lock not to be poisoned");
+        let limit = limits.limit(peer);
+
+        limits
+            .peers
+            .entry(*peer)
+            .or_insert_with(|| Arc::new(PeerBudget::new(limit)))
+            .clone()
+    }
+
+    /// The budget of the peer if it is connected.
+    pub(crate) fn get(&self, peer: &PeerId) -> Option<Arc<PeerBudget>> {
+        self.0
+            .lock()
+            .expect("lock not to be poisoned")
+            .peers
+            .get(peer)
+            .cloned()
+    }
+
+    /// Forgets the budget of a peer that disconnected.
+    pub(crate) fn remove(&self, peer: &PeerId) {
+        self.0
+            .lock()
+            .expect("lock not to be poisoned")
+            .peers
+            .remove(peer);
+    }
+
+    /// Replaces the limit of peers without their own, starting out with a full bucket.
+    pub(crate) fn set_default(&self, bytes_per_sec: Option<u64>) {
+        let mut limits = self.0.lock().expect("lock not to be poisoned");
+        limits.default = bytes_per_sec;
+
+        let Limits {
+            overrides, peers, ..
+        } = &*limits;
+        for (peer, budget) in peers {
+            if !overrides.contains_key(peer) {
+                budget.set(bytes_per_sec);
+            }
+        }
+    }
+
+    /// Replaces the limit of a single peer, `None` lifts it.
+    pub(crate) fn set_override(&self, peer: PeerId, bytes_per_sec: Option<u64>) {
+        let mut limits = self.0.lock().expect("lock not to be poisoned");
+        limits.overrides.insert(peer, bytes_per_sec);
+
+        if let Some(budget) = limits.peers.get(&peer) {
+            budget.set(bytes_per_sec);
+        }
+    }
+
+    /// Has a peer fall back to the limit of peers without their own.
+    pub(crate) fn remove_override(&self, peer: &PeerId) {
+        let mut limits = self.0.lock().expect("lock not to be poisoned");
+        limits.overrides.remove(peer);
+
+        if let Some(budget) = limits.peers.get(peer) {
+            budget.set(limits.default);
+        }
+    }
+}
+
+/// The bytes exchanged with a peer and its limit.
+#[derive(Default)]
+pub(crate) struct PeerBudget {
+    bucket: Mutex<Option<Bucket>>,
+    bytes_read: AtomicU64,
+    bytes_written: AtomicU64,
+}
+
+impl PeerBudget {
+    fn new(bytes_per_sec: Option<u64>) -> Self {
+        Self {
+            bucket: Mutex::new(bytes_per_sec.map(Bucket::new)),
+            ..Self::default()
+        }
+    }
+
+    fn set(&self, bytes_per_sec: Option<u64>) {
+        *self.bucket.lock().expect("lock not to be poisoned") = bytes_per_sec.map(Bucket::new);
+    }
+
+    pub(crate) fn bytes_read(&self) -> u64 {
+        self.bytes_read.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn bytes_written(&self) -> u64 {
+        self.bytes_written.load(Ordering::Relaxed)
+    }
+
+    fn take(&self, wanted: usize) -> Result<usize, Duration> {
+        match self
+            .bucket
+            .lock()
+            .expect("lock not to be poisoned")
+            .as_mut()
+        {
+            Some(bucket) => bucket.take(wanted),
+            None => Ok(wanted),
+        }
+    }
+
+    fn refund(&self, bytes: usize) {
+        if let Some(bucket) = self
+            .bucket
+            .lock()
+            .expect("lock not to be poisoned")
+            .as_mut()
+        {
+            bucket.refund(bytes);
+        }
+    }
+}
+
+/// A socket whose reads and writes take from the [`PeerBudget`] of its peer.
+pub(crate) struct PeerSocket<T> {
+    socket: T,
+    budget: Arc<PeerBudget>,
+    delay: Option<Delay>,
+}
+
+impl<T> PeerSocket<T> {
+    pub(crate) fn new(socket: T, budget: Arc<PeerBudget>) -> Self {
+        Self {
+            socket,
+            budget,
+            delay: None,
+        }
+    }
+
+    /// Takes up to `wanted` bytes from the budget, registering a wakeup if it is empty.
+    fn poll_take(&mut self, cx: &mut Context<'_>, wanted: usize) -> Poll<usize> {
+        loop {
+            if let Some(delay) = self.delay.as_mut() {
+                match Pin::new(delay).poll(cx) {
+                    Poll::Ready(_) => self.delay = None,
+                    Poll::Pending => return Poll::Pending,
+                }
+            }
+
+            match self.budget.take(wanted) {
+                Ok(allowed) => return Poll::Ready(allowed),
+                Err(wait) => self.delay = Some(Delay::new(wait)),
+            }
+        }
+    }
+
+    /// Hands back what was taken but not transferred and counts what was.
+    fn settle(&self, allowed: usize, poll: &Poll<io::Result<usize>>, transferred: &AtomicU64) {
+        match poll {
+            Poll::Ready(Ok(bytes)) => {
+                self.budget.refund(allowed - bytes);
+                transferred.fetch_add(*bytes as u64, Ordering::Relaxed);
+            }
+            Poll::Ready(Err(_)) | Poll::Pending => self.budget.refund(allowed),
+        }
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for PeerSocket<T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        if buf.is_empty() {
+            return Pin::new(&mut self.socket).poll_read(cx, buf);
+        }
+
+        let allowed = match self.poll_take(cx, buf.len()) {
+            Poll::Ready(allowed) => allowed,
+            Poll::Pending => return Poll::Pending,
+        };
+        let poll = Pin::new(&mut self.socket).poll_read(cx, &mut buf[..allowed]);
+        self.settle(allowed, &poll, &self.budget.bytes_read);
+
+        poll
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for PeerSocket<T> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        if buf.is_empty() {
+            return Pin::new(&mut self.socket).poll_write(cx, buf);
+        }
+
+        let allowed = match self.poll_take(cx, buf.len()) {
+            Poll::Ready(allowed) => allowed,
+            Poll::Pending => return Poll::Pending,
+        };
+        let poll = Pin::new(&mut self.socket).poll_write(cx, &buf[..allowed]);
+        self.settle(allowed, &poll, &self.budget.bytes_written);
+
+        poll
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.socket).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.socket).poll_close(cx)
+    }
+}
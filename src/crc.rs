@@ -0,0 +1,84 @@
+//! Checksummed frames for peers that protect their messages with a CRC-32.
+
+use crate::FramingError;
+use std::fmt;
+
+/// The number of bytes the checksum trailer takes up in a frame.
+pub(crate) const CHECKSUM_LEN: usize = 4;
+
+/// Computes the CRC-32 (IEEE 802.3) checksum of the data.
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= u32::from(*byte);
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+
+    !crc
+}
+
+/// Appends the big-endian checksum of the message to it.
+pub(crate) fn append_checksum(msg: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(msg.len() + CHECKSUM_LEN);
+    frame.extend_from_slice(msg);
+    frame.extend_from_slice(&crc32(msg).to_be_bytes());
+
+    frame
+}
+
+/// Splits the checksum trailer off the frame and verifies it.
+pub(crate) fn verify_checksum(mut frame: Vec<u8>) -> Result<Vec<u8>, ChecksumError> {
+    let mut checksum = [0; CHECKSUM_LEN];
+    checksum.copy_from_slice(&frame[frame.len() - CHECKSUM_LEN..]);
+    frame.truncate(frame.len() - CHECKSUM_LEN);
+
+    let expected = u32::from_be_bytes(checksum);
+    let actual = crc32(&frame);
+    if expected != actual {
+        return Err(ChecksumError::Mismatch { expected, actual });
+    }
+
+    Ok(frame)
+}
+
+/// The error returned when reading a checksummed message fails.
+#[derive(Debug)]
+pub enum ChecksumError {
+    Read(FramingError),
+    /// The checksum sent by the remote does not match the message.
+    Mismatch {
+        expected: u32,
+        actual: u32,
+    },
+}
+
+impl From<FramingError> for ChecksumError {
+    fn from(e: FramingError) -> Self {
+        ChecksumError::Read(e)
+    }
+}
+
+impl fmt::Display for ChecksumError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChecksumError::Read(e) => write!(f, "{}", e),
+            ChecksumError::Mismatch { expected, actual } => write!(
+                f,
+                "message checksum {:#010x} does not match {:#010x}",
+                actual, expected
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ChecksumError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ChecksumError::Read(e) => Some(e),
+            ChecksumError::Mismatch { .. } => None,
+        }
+    }
+}
@@ -0,0 +1,130 @@
+//! Executing protocol fns on substreams that were negotiated outside of a libp2p `Swarm`.
+
+use crate::{execute, Handshake, InboundSubstream, OutboundSubstream, ProtocolOutEvent};
+use libp2p::futures::future::BoxFuture;
+use libp2p::futures::stream::FuturesUnordered;
+use libp2p::futures::task::{Context, Poll, Waker};
+use libp2p::futures::{FutureExt, Stream, StreamExt};
+use std::future::Future;
+use std::pin::Pin;
+
+/// Executes protocol fns on substreams handed to it and yields their results.
+///
+/// This is the protocol execution engine of [`crate::Behaviour`] without any of the connection
+/// management, for embedding into runtimes that negotiate substreams themselves. Substreams are
+/// constructed with [`InboundSubstream::new`] and [`OutboundSubstream::new`]. Unlike the
+/// behaviour, the driver executes any number of protocols concurrently.
+///
+/// Results are yielded as [`ProtocolOutEvent::Inbound`] and [`ProtocolOutEvent::Outbound`], or
+/// [`ProtocolOutEvent::InboundFailed`] and [`ProtocolOutEvent::OutboundFailed`] if the handshake
+/// failed. The stream never ends, it is pending while no protocol is executing.
+pub struct Driver<I, O, E> {
+    handshake: Handshake,
+    executions: FuturesUnordered<BoxFuture<'static, ProtocolOutEvent<I, O, E>>>,
+    waker: Option<Waker>,
+}
+
+impl<I, O, E> Default for Driver<I, O, E> {
+    fn default() -> Self {
+        Self {
+            handshake: Handshake::default(),
+            executions: FuturesUnordered::new(),
+            waker: None,
+        }
+    }
+}
+
+impl<I, O, E> Driver<I, O, E>
+where
+    I: Send + 'static,
+    O: Send + 'static,
+    E: Send + 'static,
+{
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Like [`crate::Behaviour::set_preface`].
+    pub fn set_preface(&mut self, preface: Option<&'static [u8]>) {
+        self.handshake.preface = preface;
+    }
+
+    /// Like [`crate::Behaviour::set_negotiate_framing`].
+    pub fn set_negotiate_framing(&mut self, negotiate: bool) {
+        self.handshake.negotiate_framing = negotiate;
+    }
+
+    pub fn execute_inbound<F>(
+        &mut self,
+        substream: InboundSubstream,
+        protocol: impl FnOnce(InboundSubstream) -> F + Send + 'static,
+    ) where
+        F: Future<Output = Result<I, E>> + Send + 'static,
+    {
+        let protocol_fn = Box::new(move |substream| {
+            protocol(substream)
+                .map(|res| res.map(|out| (out, None)))
+                .boxed()
+        });
+        let execution = execute(protocol_fn, substream, self.handshake).map(|res| match res {
+            Ok(res) => ProtocolOutEvent::Inbound(res.map(|(out, _)| out)),
+            Err(failure) => ProtocolOutEvent::InboundFailed(failure),
+        });
+
+        self.push(execution);
+    }
+
+    pub fn execute_outbound<F>(
+        &mut self,
+        substream: OutboundSubstream,
+        protocol: impl FnOnce(OutboundSubstream) -> F + Send + 'static,
+    ) where
+        F: Future<Output = Result<O, E>> + Send + 'static,
+    {
+        let protocol_fn = Box::new(move |substream| {
+            protocol(substream)
+                .map(|res| res.map(|out| (out, None)))
+                .boxed()
+        });
+        let execution = execute(protocol_fn, substream, self.handshake).map(|res| match res {
+            Ok(res) => ProtocolOutEvent::Outbound(res.map(|(out, _)| out)),
+            Err(failure) => ProtocolOutEvent::OutboundFailed(failure),
+        });
+
+        self.push(execution);
+    }
+
+    /// The number of protocols currently executing.
+    pub fn len(&self) -> usize {
+        self.executions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.executions.is_empty()
+    }
+
+    fn push(
+        &mut self,
+        execution: impl Future<Output = ProtocolOutEvent<I, O, E>> + Send + 'static,
+    ) {
+        self.executions.push(execution.boxed());
+
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl<I, O, E> Stream for Driver<I, O, E> {
+    type Item = ProtocolOutEvent<I, O, E>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        match self.executions.poll_next_unpin(cx) {
+            Poll::Ready(Some(event)) => Poll::Ready(Some(event)),
+            Poll::Ready(None) | Poll::Pending => {
+                self.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
@@ -0,0 +1,167 @@
+//! Shaping the bytes written across all substreams of a behaviour.
+//!
+//! The budget is a token bucket of bytes that refills at the configured rate and holds at most
+//! one second worth of bytes. Writes take as many tokens as they can and write only that many
+//! bytes, waiting for the bucket to refill if it is empty.
+
+use libp2p::futures::{AsyncRead, AsyncWrite, Future};
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use wasm_timer::Delay;
+
+/// The write budget shared by all substreams, see [`crate::Behaviour::set_write_rate_limit`].
+#[derive(Default)]
+pub(crate) struct EgressLimit(Mutex<Option<Bucket>>);
+
+/// A budget of bytes, also used by [`crate::bandwidth`].
+pub(crate) struct Bucket {
+    bytes_per_sec: u64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    /// A bucket for the given rate, starting out full. A rate of 0 is treated as 1.
+    pub(crate) fn new(bytes_per_sec: u64) -> Self {
+        Self {
+            bytes_per_sec: bytes_per_sec.max(1),
+            tokens: bytes_per_sec.max(1) as f64,
+            updated: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let added =
+            now.saturating_duration_since(self.updated).as_secs_f64() * self.bytes_per_sec as f64;
+
+        self.tokens = (self.tokens + added).min(self.bytes_per_sec as f64);
+        self.updated = now;
+    }
+
+    /// Takes up to `wanted` bytes, returning how long to wait if the bucket is empty.
+    pub(crate) fn take(&mut self, wanted: usize) -> Result<usize, Duration> {
+        self.refill(Instant::now());
+
+        if self.tokens >= 1.0 {
+            let taken = wanted.min(self.tokens as usize);
+            self.tokens -= taken as f64;
+
+            return Ok(taken);
+        }
+
+        // Waiting for larger transfers to fit avoids trickling them out a byte at a time.
+        let needed = (wanted as u64).min(self.bytes_per_sec) as f64 - self.tokens;
+
+        Err(Duration::from_secs_f64(needed / self.bytes_per_sec as f64))
+    }
+
+    /// Hands back bytes that were taken but not transferred.
+    pub(crate) fn refund(&mut self, bytes: usize) {
+        self.tokens = (self.tokens + bytes as f64).min(self.bytes_per_sec as f64);
+    }
+}
+
+impl EgressLimit {
+    pub(crate) fn bytes_per_sec(&self) -> Option<u64> {
+        self.0
+            .lock()
+            .expect("lock not to be poisoned")
+            .as_ref()
+            .map(|bucket| bucket.bytes_per_sec)
+    }
+
+    /// Replaces the limit, starting out with a full bucket. `None` removes it.
+    pub(crate) fn set(&self, bytes_per_sec: Option<u64>) {
+        *self.0.lock().expect("lock not to be poisoned") = bytes_per_sec.map(Bucket::new);
+    }
+
+    /// Takes up to `wanted` bytes from the budget, returning how long to wait if it is empty.
+    fn take(&self, wanted: usize) -> Result<usize, Duration> {
+        match self.0.lock().expect("lock not to be poisoned").as_mut() {
+            Some(bucket) => bucket.take(wanted),
+            None => Ok(wanted),
+        }
+    }
+
+    /// Hands back bytes that were taken but not written.
+    fn refund(&self, bytes: usize) {
+        if let Some(bucket) = self.0.lock().expect("lock not to be poisoned").as_mut() {
+            bucket.refund(bytes);
+        }
+    }
+}
+
+/// A socket whose writes take from an [`EgressLimit`].
+pub(crate) struct LimitedSocket<T> {
+    socket: T,
+    limit: Arc<EgressLimit>,
+    delay: Option<Delay>,
+}
+
+impl<T> LimitedSocket<T> {
+    pub(crate) fn new(socket: T, limit: Arc<EgressLimit>) -> Self {
+        Self {
+            socket,
+            limit,
+            delay: None,
+        }
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for LimitedSocket<T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.socket).poll_read(cx, buf)
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for LimitedSocket<T> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        if buf.is_empty() {
+            return Pin::new(&mut self.socket).poll_write(cx, buf);
+        }
+
+        loop {
+            if let Some(delay) = self.delay.as_mut() {
+                match Pin::new(delay).poll(cx) {
+                    Poll::Ready(_) => self.delay = None,
+                    Poll::Pending => return Poll::Pending,
+                }
+            }
+
+            let allowed = match self.limit.take(buf.len()) {
+                Ok(allowed) => allowed,
+                Err(wait) => {
+                    self.delay = Some(Delay::new(wait));
+                    continue;
+                }
+            };
+
+            let poll = Pin::new(&mut self.socket).poll_write(cx, &buf[..allowed]);
+            match &poll {
+                Poll::Ready(Ok(written)) => self.limit.refund(allowed - written),
+                Poll::Ready(Err(_)) | Poll::Pending => self.limit.refund(allowed),
+            }
+
+            return poll;
+        }
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.socket).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.socket).poll_close(cx)
+    }
+}
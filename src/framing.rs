@@ -0,0 +1,252 @@
+//! Delimiting messages on substreams.
+//!
+//! The message helpers of [`crate::InboundSubstream`] and [`crate::OutboundSubstream`], like
+//! `write_message` and `read_message`, delegate to the [`Framing`] set through
+//! [`crate::Behaviour::set_framing`] when the substream was opened. By default, messages are
+//! prefixed with their length as an unsigned varint, see [`LengthPrefixed`].
+//!
+//! ```
+//! # use libp2p::futures::future::BoxFuture;
+//! # use libp2p::futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, FutureExt};
+//! # use libp2p_async_await::framing::{FrameMemory, Framing};
+//! # use libp2p_async_await::FramingError;
+//! # use std::io;
+//! /// Messages that are terminated by a newline.
+//! struct Lines;
+//!
+//! impl Framing for Lines {
+//!     fn write_frame<'a>(
+//!         &'a self,
+//!         socket: &'a mut (dyn AsyncWrite + Unpin + Send),
+//!         msg: &'a [u8],
+//!     ) -> BoxFuture<'a, io::Result<()>> {
+//!         async move {
+//!             socket.write_all(msg).await?;
+//!             socket.write_all(b"\n").await
+//!         }
+//!         .boxed()
+//!     }
+//!
+//!     fn read_frame<'a>(
+//!         &'a self,
+//!         socket: &'a mut (dyn AsyncRead + Unpin + Send),
+//!         max_size: usize,
+//!         memory: &'a mut FrameMemory,
+//!     ) -> BoxFuture<'a, Result<Vec<u8>, FramingError>> {
+//!         async move {
+//!             let mut frame = Vec::new();
+//!             let mut byte = [0; 1];
+//!             loop {
+//!                 socket.read_exact(&mut byte).await?;
+//!                 if byte[0] == b'\n' {
+//!                     return Ok(frame);
+//!                 }
+//!                 if frame.len() == max_size {
+//!                     return Err(FramingError::TooLarge { length: max_size + 1, max_size });
+//!                 }
+//!                 memory.reserve(1)?;
+//!                 frame.push(byte[0]);
+//!             }
+//!         }
+//!         .boxed()
+//!     }
+//! }
+//! ```
+
+use crate::memory::{ConnectionMemory, Reservation};
+use crate::FramingError;
+use libp2p::core::upgrade;
+use libp2p::futures::future::BoxFuture;
+use libp2p::futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, FutureExt};
+use std::io;
+use std::sync::{Arc, RwLock};
+
+/// How messages are delimited on substreams, see the [module docs](self).
+pub trait Framing: Send + Sync {
+    /// Writes the message as a single frame.
+    ///
+    /// The substream helpers flush the socket afterwards.
+    fn write_frame<'a>(
+        &'a self,
+        socket: &'a mut (dyn AsyncWrite + Unpin + Send),
+        msg: &'a [u8],
+    ) -> BoxFuture<'a, io::Result<()>>;
+
+    /// Reads a single frame, failing with [`FramingError::TooLarge`] for frames of more than
+    /// `max_size` bytes.
+    ///
+    /// The frame has to be reserved in `memory` before it is buffered.
+    fn read_frame<'a>(
+        &'a self,
+        socket: &'a mut (dyn AsyncRead + Unpin + Send),
+        max_size: usize,
+        memory: &'a mut FrameMemory,
+    ) -> BoxFuture<'a, Result<Vec<u8>, FramingError>>;
+
+    /// Like `read_frame` but hands the frame to `sink` in chunks of at most `chunk_size` bytes
+    /// and returns its length.
+    ///
+    /// The default reads the whole frame before handing it out. Framings that know the length of
+    /// a frame up front can hold only one chunk at a time instead, like [`LengthPrefixed`] does.
+    fn read_frame_chunked<'a>(
+        &'a self,
+        socket: &'a mut (dyn AsyncRead + Unpin + Send),
+        max_size: usize,
+        chunk_size: usize,
+        memory: &'a mut FrameMemory,
+        sink: &'a mut (dyn FnMut(&[u8]) + Send),
+    ) -> BoxFuture<'a, Result<usize, FramingError>> {
+        async move {
+            let frame = self.read_frame(socket, max_size, memory).await?;
+            for chunk in frame.chunks(chunk_size.max(1)) {
+                sink(chunk);
+            }
+
+            Ok(frame.len())
+        }
+        .boxed()
+    }
+
+    /// Like `read_frame` but resolves to `None` if the socket ends before the first byte of a
+    /// frame.
+    fn read_frame_or_eof<'a>(
+        &'a self,
+        socket: &'a mut (dyn AsyncRead + Unpin + Send),
+        max_size: usize,
+        memory: &'a mut FrameMemory,
+    ) -> BoxFuture<'a, Result<Option<Vec<u8>>, FramingError>> {
+        async move {
+            let mut first = [0; 1];
+            if socket.read(&mut first).await? == 0 {
+                return Ok(None);
+            }
+
+            let mut socket = (&first[..]).chain(socket);
+            self.read_frame(&mut socket, max_size, memory)
+                .await
+                .map(Some)
+        }
+        .boxed()
+    }
+
+    /// The payload among the `received` bytes of a frame that did not arrive completely yet.
+    ///
+    /// `read_message_timeout_partial` returns it for frames that are still incomplete once its
+    /// timeout passes. The default reports no payload until the frame is complete.
+    fn partial_payload<'b>(&self, _received: &'b [u8]) -> &'b [u8] {
+        &[]
+    }
+}
+
+/// Prefixes every message with its length as an unsigned varint, the default [`Framing`].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LengthPrefixed;
+
+impl Framing for LengthPrefixed {
+    fn write_frame<'a>(
+        &'a self,
+        socket: &'a mut (dyn AsyncWrite + Unpin + Send),
+        msg: &'a [u8],
+    ) -> BoxFuture<'a, io::Result<()>> {
+        async move { socket.write_all(&crate::length_prefixed(msg)).await }.boxed()
+    }
+
+    fn read_frame<'a>(
+        &'a self,
+        socket: &'a mut (dyn AsyncRead + Unpin + Send),
+        max_size: usize,
+        memory: &'a mut FrameMemory,
+    ) -> BoxFuture<'a, Result<Vec<u8>, FramingError>> {
+        async move {
+            let mut socket = socket;
+            let length = upgrade::read_varint(&mut socket).await?;
+            if length > max_size {
+                return Err(FramingError::TooLarge { length, max_size });
+            }
+            memory.reserve(length)?;
+
+            let mut message = vec![0; length];
+            socket.read_exact(&mut message).await?;
+
+            Ok(message)
+        }
+        .boxed()
+    }
+
+    fn read_frame_chunked<'a>(
+        &'a self,
+        socket: &'a mut (dyn AsyncRead + Unpin + Send),
+        max_size: usize,
+        chunk_size: usize,
+        memory: &'a mut FrameMemory,
+        sink: &'a mut (dyn FnMut(&[u8]) + Send),
+    ) -> BoxFuture<'a, Result<usize, FramingError>> {
+        async move {
+            let mut socket = socket;
+            let length = upgrade::read_varint(&mut socket).await?;
+            if length > max_size {
+                return Err(FramingError::TooLarge { length, max_size });
+            }
+            let buffer_size = chunk_size.clamp(1, length.max(1));
+            memory.reserve(buffer_size)?;
+
+            let mut buffer = vec![0; buffer_size];
+            let mut remaining = length;
+            while remaining > 0 {
+                let read = remaining.min(buffer.len());
+                let chunk = &mut buffer[..read];
+                socket.read_exact(chunk).await?;
+                sink(chunk);
+                remaining -= read;
+            }
+
+            Ok(length)
+        }
+        .boxed()
+    }
+
+    fn partial_payload<'b>(&self, received: &'b [u8]) -> &'b [u8] {
+        // The length prefix ends with the first byte that has the continuation bit unset.
+        match received.iter().position(|byte| byte & 0x80 == 0) {
+            Some(end) => &received[end + 1..],
+            None => &[],
+        }
+    }
+}
+
+/// The memory a read reserved for the frame it buffers, see
+/// [`crate::Behaviour::set_max_read_memory_per_connection`].
+///
+/// Released once the read completes.
+pub struct FrameMemory(Reservation);
+
+impl FrameMemory {
+    pub(crate) fn new(memory: &Arc<ConnectionMemory>) -> Self {
+        Self(memory.reserve(0).expect("nothing to always fit"))
+    }
+
+    /// Reserves another `bytes`, failing with [`FramingError::MemoryLimit`] if the connection
+    /// does not have that many left.
+    pub fn reserve(&mut self, bytes: usize) -> Result<(), FramingError> {
+        self.0.extend(bytes)
+    }
+}
+
+/// The framing of a behaviour, shared with the substreams of all connections.
+pub(crate) struct SharedFraming(RwLock<Arc<dyn Framing>>);
+
+impl SharedFraming {
+    pub(crate) fn get(&self) -> Arc<dyn Framing> {
+        self.0.read().expect("lock not to be poisoned").clone()
+    }
+
+    pub(crate) fn set(&self, framing: Arc<dyn Framing>) {
+        *self.0.write().expect("lock not to be poisoned") = framing;
+    }
+}
+
+impl Default for SharedFraming {
+    fn default() -> Self {
+        Self(RwLock::new(Arc::new(LengthPrefixed)))
+    }
+}
@@ -0,0 +1,348 @@
+//! Keepalive pings within long-lived protocols.
+//!
+//! A substream wrapped through `with_keepalive` tags every frame with its type. While reading or
+//! running a future through [`Keepalive::run`], it pings the remote whenever the interval elapses
+//! and answers the pings of the remote. A remote that sends nothing at all, neither messages nor
+//! pongs, for longer than the timeout after a ping is considered dead, which detects half-open
+//! connections long before TCP would. Any bytes arriving count, so messages that take longer than
+//! the timeout to arrive do not fail a remote that is still sending them. Both sides have to wrap
+//! their substream.
+
+use crate::FramingError;
+use libp2p::futures::future::{self, poll_fn, Either};
+use libp2p::futures::task::Poll;
+use libp2p::futures::{AsyncRead, AsyncWrite, AsyncWriteExt, Future, FutureExt};
+use std::pin::Pin;
+use std::time::Duration;
+use std::{fmt, io};
+use wasm_timer::Delay;
+
+/// Marks a frame carrying a message.
+const DATA: u8 = 0;
+/// Marks a frame asking the remote to prove it is alive.
+const PING: u8 = 1;
+/// Marks the answer to a ping.
+const PONG: u8 = 2;
+
+/// A substream that exchanges keepalive pings, see the [module docs](self).
+///
+/// Pings are only sent and answered while [`Keepalive::read_message`] or [`Keepalive::run`] is in
+/// progress. Protocols should spend their idle periods waiting for the remote in the former and
+/// wrap whatever else takes longer than the timeout, e.g. computing a response, in the latter.
+/// Otherwise the remote considers them dead once it waited for longer than its interval and
+/// timeout.
+pub struct Keepalive<S> {
+    substream: S,
+    interval: Duration,
+    timeout: Duration,
+    next_ping: Delay,
+    /// Fires if nothing arrives in time after a ping, set while waiting for the remote.
+    deadline: Option<Delay>,
+    /// Bytes read from the substream that do not form a complete frame yet.
+    buffer: Vec<u8>,
+}
+
+impl<S> Keepalive<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    pub(crate) fn new(substream: S, interval: Duration, timeout: Duration) -> Self {
+        Self {
+            substream,
+            interval,
+            timeout,
+            next_ping: Delay::new(interval),
+            deadline: None,
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Writes the message as a single frame.
+    pub async fn write_message(&mut self, msg: &[u8]) -> Result<(), FramingError> {
+        self.write_frame(DATA, msg).await
+    }
+
+    /// Reads a single frame of at most `max_size` bytes, exchanging pings while waiting for it.
+    pub async fn read_message(&mut self, max_size: usize) -> Result<Vec<u8>, KeepaliveError> {
+        loop {
+            self.answer_control_frames().await?;
+            // Only a message can be left at the front of the buffer.
+            if let Some(frame) = self.next_frame(max_size)? {
+                return Ok(frame[1..].to_vec());
+            }
+
+            let event = self.poll_event().await?;
+            self.on_event(event).await?;
+        }
+    }
+
+    /// Runs the future, exchanging pings until it completes.
+    ///
+    /// This keeps the substream alive while the protocol does something other than reading, e.g.
+    /// computing a response. Once a message of the remote arrives, it stays buffered for the next
+    /// [`Keepalive::read_message`] and pings are neither sent nor answered until then.
+    pub async fn run<F: Future>(&mut self, future: F) -> Result<F::Output, KeepaliveError> {
+        libp2p::futures::pin_mut!(future);
+
+        loop {
+            self.answer_control_frames().await?;
+            if self.starts_with_data()? {
+                self.deadline = None;
+                return Ok(future.await);
+            }
+
+            let event = {
+                let event = self.poll_event();
+                libp2p::futures::pin_mut!(event);
+                match future::select(future.as_mut(), event).await {
+                    Either::Left((output, _)) => return Ok(output),
+                    Either::Right((event, _)) => event?,
+                }
+            };
+            self.on_event(event).await?;
+        }
+    }
+
+    /// Pings the remote and waits until it proves to be alive, failing with
+    /// [`KeepaliveError::KeepaliveTimeout`] if it stays silent for longer than the timeout.
+    ///
+    /// A message arriving in the meantime counts as an answer and stays buffered for the next
+    /// [`Keepalive::read_message`].
+    pub async fn ping(&mut self) -> Result<(), KeepaliveError> {
+        self.write_frame(PING, &[])
+            .await
+            .map_err(KeepaliveError::Write)?;
+        self.deadline = Some(Delay::new(self.timeout));
+
+        loop {
+            if self.starts_with_data()? {
+                self.deadline = None;
+                return Ok(());
+            }
+            if let Some(frame) = self.next_frame(0)? {
+                self.deadline = None;
+
+                match frame.split_first() {
+                    Some((&PING, _)) => self
+                        .write_frame(PONG, &[])
+                        .await
+                        .map_err(KeepaliveError::Write)?,
+                    Some((&PONG, _)) => {}
+                    _ => return Err(KeepaliveError::Malformed),
+                }
+                return Ok(());
+            }
+
+            match self.poll_event().await? {
+                Event::Read => {}
+                Event::PingDue => self.next_ping.reset(self.interval),
+                Event::TimedOut => return Err(KeepaliveError::KeepaliveTimeout),
+            }
+        }
+    }
+
+    /// Stops exchanging pings and hands back the substream.
+    pub fn into_inner(self) -> S {
+        self.substream
+    }
+
+    /// Answers the pings at the front of the buffer, up to the first message or incomplete frame.
+    async fn answer_control_frames(&mut self) -> Result<(), KeepaliveError> {
+        while !self.starts_with_data()? {
+            let frame = match self.next_frame(0)? {
+                Some(frame) => frame,
+                None => return Ok(()),
+            };
+
+            match frame.split_first() {
+                Some((&PING, _)) => self
+                    .write_frame(PONG, &[])
+                    .await
+                    .map_err(KeepaliveError::Write)?,
+                Some((&PONG, _)) => {}
+                _ => return Err(KeepaliveError::Malformed),
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn on_event(&mut self, event: Event) -> Result<(), KeepaliveError> {
+        match event {
+            // Any bytes prove that the remote is alive, even in the middle of a message.
+            Event::Read => self.deadline = None,
+            Event::PingDue => {
+                self.write_frame(PING, &[])
+                    .await
+                    .map_err(KeepaliveError::Write)?;
+                self.next_ping.reset(self.interval);
+                if self.deadline.is_none() {
+                    self.deadline = Some(Delay::new(self.timeout));
+                }
+            }
+            Event::TimedOut => return Err(KeepaliveError::KeepaliveTimeout),
+        }
+
+        Ok(())
+    }
+
+    /// Waits for the remote to send more data or one of the timers to fire.
+    async fn poll_event(&mut self) -> Result<Event, KeepaliveError> {
+        let Self {
+            substream,
+            next_ping,
+            deadline,
+            buffer,
+            ..
+        } = self;
+
+        poll_fn(|cx| {
+            if deadline
+                .as_mut()
+                .is_some_and(|deadline| deadline.poll_unpin(cx).is_ready())
+            {
+                return Poll::Ready(Ok(Event::TimedOut));
+            }
+            if next_ping.poll_unpin(cx).is_ready() {
+                return Poll::Ready(Ok(Event::PingDue));
+            }
+
+            let mut chunk = [0; 1024];
+            match Pin::new(&mut *substream).poll_read(cx, &mut chunk) {
+                Poll::Ready(Ok(0)) => Poll::Ready(Err(FramingError::from(io::Error::from(
+                    io::ErrorKind::UnexpectedEof,
+                ))
+                .into())),
+                Poll::Ready(Ok(read)) => {
+                    buffer.extend_from_slice(&chunk[..read]);
+                    Poll::Ready(Ok(Event::Read))
+                }
+                Poll::Ready(Err(e)) => Poll::Ready(Err(FramingError::from(e).into())),
+                Poll::Pending => Poll::Pending,
+            }
+        })
+        .await
+    }
+
+    /// Takes the next complete frame out of the buffer, if there is one.
+    fn next_frame(&mut self, max_size: usize) -> Result<Option<Vec<u8>>, KeepaliveError> {
+        let (length, prefix) = match decode_varint(&self.buffer)? {
+            Some(decoded) => decoded,
+            None => return Ok(None),
+        };
+        // The type of the frame takes up one byte.
+        if length > max_size.saturating_add(1) {
+            return Err(FramingError::TooLarge {
+                length: length.saturating_sub(1),
+                max_size,
+            }
+            .into());
+        }
+        if self.buffer.len() - prefix < length {
+            return Ok(None);
+        }
+
+        let frame = self.buffer[prefix..prefix + length].to_vec();
+        self.buffer.drain(..prefix + length);
+
+        Ok(Some(frame))
+    }
+
+    /// Whether the buffer starts with a message frame, complete or not.
+    fn starts_with_data(&self) -> Result<bool, KeepaliveError> {
+        Ok(match decode_varint(&self.buffer)? {
+            Some((length, prefix)) => length > 0 && self.buffer.get(prefix) == Some(&DATA),
+            None => false,
+        })
+    }
+
+    async fn write_frame(&mut self, kind: u8, msg: &[u8]) -> Result<(), FramingError> {
+        let mut frame = Vec::with_capacity(1 + msg.len());
+        frame.push(kind);
+        frame.extend_from_slice(msg);
+
+        self.substream
+            .write_all(&crate::length_prefixed(&frame))
+            .await?;
+        self.substream.flush().await?;
+
+        Ok(())
+    }
+}
+
+impl<S> fmt::Debug for Keepalive<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Keepalive")
+            .field("interval", &self.interval)
+            .field("timeout", &self.timeout)
+            .finish()
+    }
+}
+
+enum Event {
+    Read,
+    PingDue,
+    TimedOut,
+}
+
+/// Decodes the unsigned varint length prefix, returning `None` if it is incomplete.
+fn decode_varint(bytes: &[u8]) -> Result<Option<(usize, usize)>, KeepaliveError> {
+    let mut length = 0usize;
+    for (i, byte) in bytes.iter().enumerate().take(10) {
+        length |= usize::from(byte & 0x7f)
+            .checked_shl(7 * i as u32)
+            .ok_or(KeepaliveError::Malformed)?;
+        if byte & 0x80 == 0 {
+            return Ok(Some((length, i + 1)));
+        }
+    }
+    if bytes.len() >= 10 {
+        return Err(KeepaliveError::Malformed);
+    }
+
+    Ok(None)
+}
+
+/// The error returned when reading from a [`Keepalive`] substream fails.
+#[derive(Debug)]
+pub enum KeepaliveError {
+    Read(FramingError),
+    /// Sending a ping or pong failed.
+    Write(FramingError),
+    /// The remote sent a frame that is not part of the keepalive framing.
+    Malformed,
+    /// The remote did not send anything within the timeout after a ping.
+    KeepaliveTimeout,
+}
+
+impl From<FramingError> for KeepaliveError {
+    fn from(e: FramingError) -> Self {
+        KeepaliveError::Read(e)
+    }
+}
+
+impl fmt::Display for KeepaliveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeepaliveError::Read(e) => write!(f, "{}", e),
+            KeepaliveError::Write(e) => write!(f, "failed to write keepalive frame: {}", e),
+            KeepaliveError::Malformed => {
+                write!(
+                    f,
+                    "received a frame that is not part of the keepalive framing"
+                )
+            }
+            KeepaliveError::KeepaliveTimeout => write!(f, "remote did not answer keepalive ping"),
+        }
+    }
+}
+
+impl std::error::Error for KeepaliveError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            KeepaliveError::Read(e) => Some(e),
+            KeepaliveError::Write(e) => Some(e),
+            _ => None,
+        }
+    }
+}
@@ -1,371 +1,5946 @@
+pub mod agent;
+pub mod auth;
+mod bandwidth;
+#[cfg(feature = "crc")]
+mod crc;
+pub mod driver;
+mod egress;
+pub mod framing;
+#[cfg(feature = "keepalive")]
+pub mod keepalive;
+pub mod limit;
+mod memory;
+pub mod merge;
+pub mod multi;
+mod pipe;
+#[cfg(feature = "probe")]
+mod probe;
+mod queue;
+pub mod sequence;
+pub mod stream;
+pub mod transfer;
+pub mod typed;
+
+#[cfg(feature = "crc")]
+pub use crc::ChecksumError;
+pub use pipe::pipe;
+
+use bandwidth::{Bandwidth, PeerBudget, PeerSocket};
+use egress::{EgressLimit, LimitedSocket};
+use framing::{FrameMemory, Framing, LengthPrefixed, SharedFraming};
 use libp2p::core::connection::ConnectionId;
-use libp2p::core::{upgrade, ConnectedPoint, Multiaddr, UpgradeInfo};
-use libp2p::futures::future::BoxFuture;
-use libp2p::futures::task::{Context, Poll};
-use libp2p::futures::FutureExt;
+use libp2p::core::{ConnectedPoint, Multiaddr, UpgradeInfo};
+use libp2p::futures::channel::oneshot;
+use libp2p::futures::future::{self, BoxFuture, Either};
+use libp2p::futures::stream::FuturesUnordered;
+use libp2p::futures::task::{noop_waker_ref, Context, Poll, Waker};
+use libp2p::futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, FutureExt, StreamExt};
 use libp2p::swarm::protocols_handler::OutboundUpgradeSend;
 use libp2p::swarm::{
-    KeepAlive, NegotiatedSubstream, NetworkBehaviour, NetworkBehaviourAction, NotifyHandler,
-    PollParameters, ProtocolsHandler, ProtocolsHandlerEvent, ProtocolsHandlerUpgrErr,
-    SubstreamProtocol,
+    IntoProtocolsHandler, KeepAlive, NegotiatedSubstream, NetworkBehaviour, NetworkBehaviourAction,
+    NotifyHandler, PollParameters, ProtocolsHandler, ProtocolsHandlerEvent,
+    ProtocolsHandlerUpgrErr, SubstreamProtocol,
 };
 use libp2p::{InboundUpgrade, OutboundUpgrade, PeerId};
-use std::collections::{HashMap, VecDeque};
+use memory::{ConnectionMemory, ReadMemory};
+use queue::{Position, Queue};
+use std::any::Any;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
 use std::convert::Infallible;
 use std::future::{Future, Ready};
-use std::{io, iter, mem};
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use std::{fmt, io, iter, mem};
+#[cfg(feature = "tokio")]
+use tokio::sync::broadcast;
+use wasm_timer::Delay;
+
+/// The `log` target used for all messages emitted by this crate.
+///
+/// Detailed state transitions are logged on debug level, which allows enabling them selectively
+/// through e.g. `RUST_LOG=libp2p_async_await=debug`.
+pub const LOG_TARGET: &str = "libp2p_async_await";
+
+/// The version of the wire format used by `write_message` and `read_message`.
+///
+/// Version 1 frames every message as its length, encoded as an unsigned varint, followed by the
+/// message itself. See [`Behaviour::set_negotiate_framing`] for agreeing on a version with peers.
+pub const FRAMING_VERSION: u8 = 1;
+
+/// The oldest framing version we can still speak.
+const OLDEST_FRAMING_VERSION: u8 = 1;
+
+/// How long writing the notification of [`Behaviour::set_timeout_notification`] may take.
+const TIMEOUT_NOTIFICATION_GRACE: Duration = Duration::from_millis(500);
 
-type Protocol<T, E> = BoxFuture<'static, Result<T, E>>;
-type InboundProtocolFn<I, E> = Box<dyn FnOnce(InboundSubstream) -> Protocol<I, E> + Send + 'static>;
-type OutboundProtocolFn<O, E> =
-    Box<dyn FnOnce(OutboundSubstream) -> Protocol<O, E> + Send + 'static>;
+/// A running protocol, optionally handing back its substream for reuse.
+type Protocol<T, S, E> = BoxFuture<'static, Result<(T, Option<S>), E>>;
+type ProtocolFn<T, S, E> = Box<dyn FnOnce(S) -> Protocol<T, S, E> + Send + 'static>;
+type InboundProtocolFn<I, E> = ProtocolFn<I, InboundSubstream, E>;
+type OutboundProtocolFn<O, E> = ProtocolFn<O, OutboundSubstream, E>;
+/// Serves every inbound substream negotiated for a protocol, see
+/// [`Behaviour::set_inbound_handler_for`].
+type InboundHandlerFn<I, E> =
+    Arc<dyn Fn(InboundSubstream) -> BoxFuture<'static, Result<I, E>> + Send + Sync>;
+type InboundHandlers<I, E> = Arc<RwLock<HashMap<&'static [u8], InboundHandlerFn<I, E>>>>;
+type SharedInboundPool<I, E> = Arc<RwLock<Option<InboundPool<I, E>>>>;
+/// Produces the result of an outbound protocol whose substream failed to negotiate, see
+/// [`Behaviour::do_protocol_dialer_with_fallback`].
+type FallbackFn<O, E> = Box<dyn FnOnce() -> BoxFuture<'static, Result<O, E>> + Send>;
+/// A running fallback and the number, peer and tag of its protocol.
+type Fallback<O, E> = BoxFuture<'static, (u64, PeerId, Option<Tag>, Result<O, E>)>;
+/// The socket of a substream of a protocol that timed out and the framing to notify the remote
+/// with, see [`Behaviour::set_timeout_notification`].
+type StashedSocket = (Box<dyn Io>, Arc<dyn Framing>);
+
+/// A protocol being executed by the handler, which may fail before the protocol fn completes.
+type Execution<T, S, E> =
+    BoxFuture<'static, Result<Result<(T, Option<S>), ProtocolError<E>>, Failure>>;
 
 enum InboundProtocolState<T, E> {
     GotFunctionNeedSubstream(InboundProtocolFn<T, E>),
     GotSubstreamNeedFunction(InboundSubstream),
-    Executing(Protocol<T, E>),
+    Executing(Execution<T, InboundSubstream, E>),
 }
 
 enum OutboundProtocolState<T, E> {
     GotFunctionNeedSubstream(OutboundProtocolFn<T, E>),
     GotFunctionRequestedSubstream(OutboundProtocolFn<T, E>),
-    Executing(Protocol<T, E>),
-}
-
-enum ProtocolState<I, O, E> {
-    None,
-    Inbound(InboundProtocolState<I, E>),
-    Outbound(OutboundProtocolState<O, E>),
-    Done,
-    Poisoned,
+    Executing(Execution<T, OutboundSubstream, E>),
 }
 
-pub struct Handler<TInboundOut, TOutboundOut, TErr> {
-    state: ProtocolState<TInboundOut, TOutboundOut, TErr>,
-    info: &'static [u8],
+/// What to exchange with the remote before handing a substream to a protocol fn.
+#[derive(Clone, Copy, Default)]
+struct Handshake {
+    preface: Option<&'static [u8]>,
+    negotiate_framing: bool,
 }
 
-impl<TInboundOut, TOutboundOut, TErr> Handler<TInboundOut, TOutboundOut, TErr> {
-    pub fn new(info: &'static [u8]) -> Self {
-        Self {
-            state: ProtocolState::None,
-            info,
+/// Starts executing the protocol fn on the given substream.
+///
+/// Fresh substreams first perform the configured handshake with the remote.
+fn execute<T, S, E>(
+    protocol_fn: ProtocolFn<T, S, E>,
+    mut substream: S,
+    handshake: Handshake,
+) -> Execution<T, S, E>
+where
+    T: Send + 'static,
+    S: Substream,
+    E: Send + 'static,
+{
+    async move {
+        if let Some(preface) = handshake.preface {
+            if !exchange_preface(&mut substream, preface).await {
+                return Err(Failure::PrefaceMismatch);
+            }
+        }
+        if handshake.negotiate_framing {
+            negotiate_framing(&mut substream).await?;
         }
-    }
-}
 
-pub struct ProtocolInfo {
-    info: &'static [u8],
-}
+        let transport_error = substream.transport_error();
+        let res = protocol_fn(substream).await;
 
-impl ProtocolInfo {
-    fn new(info: &'static [u8]) -> Self {
-        Self { info }
+        Ok(res.map_err(|application| match transport_error.take() {
+            Some(error) => ProtocolError::Transport { error, application },
+            None => ProtocolError::Application(application),
+        }))
     }
+    .boxed()
 }
 
-impl UpgradeInfo for ProtocolInfo {
-    type Info = &'static [u8];
-    type InfoIter = iter::Once<&'static [u8]>;
+/// Completes once the substreams of the connection went `timeout` without reading or writing.
+///
+/// Sleeps until the earliest moment the protocol could have stalled, checking again whenever it
+/// made progress in the meantime.
+async fn stalled(connection: Arc<ConnectionShared>, timeout: Duration) {
+    loop {
+        let idle = connection.last_progress().elapsed();
+        if idle >= timeout {
+            return;
+        }
 
-    fn protocol_info(&self) -> Self::InfoIter {
-        iter::once(self.info)
+        let _ = Delay::new(timeout - idle).await;
     }
 }
 
-pub struct InboundSubstream(NegotiatedSubstream);
+/// Exchanges the newest framing version each side supports and picks the older one.
+async fn negotiate_framing(
+    socket: &mut (impl AsyncRead + AsyncWrite + Unpin),
+) -> Result<u8, Failure> {
+    let exchange = async {
+        socket.write_all(&[FRAMING_VERSION]).await?;
+        socket.flush().await?;
 
-pub struct OutboundSubstream(NegotiatedSubstream);
+        let mut remote = [0; 1];
+        socket.read_exact(&mut remote).await?;
 
-macro_rules! impl_read_write {
-    ($t:ty) => {
-        impl $t {
-            pub async fn write_message(&mut self, msg: &[u8]) -> Result<(), io::Error> {
-                upgrade::write_with_len_prefix(&mut self.0, msg).await
-            }
+        Ok::<_, io::Error>(remote[0])
+    };
 
-            pub async fn read_message(
-                &mut self,
-                max_size: usize,
-            ) -> Result<Vec<u8>, upgrade::ReadOneError> {
-                upgrade::read_one(&mut self.0, max_size).await
-            }
+    let version = match exchange.await {
+        Ok(remote) => remote.min(FRAMING_VERSION),
+        Err(e) => {
+            log::debug!(target: LOG_TARGET, "Failed to negotiate framing: {}", e);
+            return Err(Failure::IncompatibleFraming);
         }
     };
+
+    if version < OLDEST_FRAMING_VERSION {
+        log::debug!(target: LOG_TARGET, "Remote only supports framing version={}.", version);
+        return Err(Failure::IncompatibleFraming);
+    }
+
+    Ok(version)
 }
 
-impl_read_write!(InboundSubstream);
-impl_read_write!(OutboundSubstream);
+/// Writes our preface and checks that the remote sent the same one.
+async fn exchange_preface(
+    socket: &mut (impl AsyncRead + AsyncWrite + Unpin),
+    preface: &'static [u8],
+) -> bool {
+    let exchange = async {
+        socket.write_all(preface).await?;
+        socket.flush().await?;
 
-impl InboundUpgrade<NegotiatedSubstream> for ProtocolInfo {
-    type Output = InboundSubstream;
-    type Error = Infallible;
-    type Future = Ready<Result<Self::Output, Self::Error>>;
+        let mut remote = vec![0; preface.len()];
+        socket.read_exact(&mut remote).await?;
+
+        Ok::<_, io::Error>(remote == preface)
+    };
 
-    fn upgrade_inbound(self, socket: NegotiatedSubstream, _: Self::Info) -> Self::Future {
-        std::future::ready(Ok(InboundSubstream(socket)))
+    match exchange.await {
+        Ok(matches) => matches,
+        Err(e) => {
+            log::debug!(target: LOG_TARGET, "Failed to exchange preface: {}", e);
+            false
+        }
     }
 }
 
-impl OutboundUpgrade<NegotiatedSubstream> for ProtocolInfo {
-    type Output = OutboundSubstream;
-    type Error = Infallible;
-    type Future = Ready<Result<Self::Output, Self::Error>>;
+enum ProtocolState<I, O, E> {
+    None,
+    Inbound(InboundProtocolState<I, E>),
+    Outbound(OutboundProtocolState<O, E>),
+    Poisoned,
+}
 
-    fn upgrade_outbound(self, socket: NegotiatedSubstream, _: Self::Info) -> Self::Future {
-        std::future::ready(Ok(OutboundSubstream(socket)))
-    }
+/// Why a connection closed, as far as the behaviour can tell.
+///
+/// libp2p does not tell behaviours why a connection closed, only the swarm reports the cause in
+/// `SwarmEvent::ConnectionClosed`. The reason is inferred from the state of the connection
+/// instead, so it cannot tell apart whether we or the remote closed an idle connection, nor the
+/// kind of error that cut a protocol short.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CloseReason {
+    /// A protocol requested the connection to be closed, see [`BehaviourOutEvent::DisconnectPeer`].
+    Graceful,
+    /// The connection closed while a protocol was executing on it.
+    Error,
+    /// The connection closed while it was idle, most likely because it was no longer kept
+    /// alive.
+    KeepAliveTimeout,
 }
 
-pub enum ProtocolInEvent<I, O, E> {
-    ExecuteInbound(InboundProtocolFn<I, E>),
-    ExecuteOutbound(OutboundProtocolFn<O, E>),
+/// Whether handlers keep their connection alive while it is idle.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum KeepAlivePolicy {
+    /// Idle connections are kept alive until the deadlines set through
+    /// [`Behaviour::touch_keep_alive`] and [`Behaviour::set_idle_timeout`] passed, indefinitely
+    /// if neither is set.
+    #[default]
+    UntilDeadline,
+    /// Connections are not kept alive once idle after executing a protocol, leaving it to other
+    /// behaviours to keep them open.
+    ///
+    /// Connections are still kept alive while a protocol is waiting for or executing on a
+    /// substream and while notifications are being sent. The swarm only postpones closing a
+    /// connection while substreams are being negotiated, so it would otherwise close it under
+    /// an executing protocol. Connections that did not execute a protocol yet are kept alive as
+    /// with [`KeepAlivePolicy::UntilDeadline`], otherwise they would be closed before a protocol
+    /// could be dispatched to them.
+    ///
+    /// The connection is closed as soon as our protocol terminated, which fails the remote's
+    /// protocol if it has not terminated yet.
+    Never,
 }
 
-pub enum ProtocolOutEvent<I, O, E> {
-    Inbound(Result<I, E>),
-    Outbound(Result<O, E>),
+/// What a [`KeepAliveDecider`] knows about an idle connection.
+#[derive(Clone, Copy, Debug)]
+pub struct KeepAliveState {
+    /// How many protocols the connection served so far.
+    pub executions: usize,
+    /// When any substream of the connection was last read from or written to.
+    pub last_activity: Instant,
+    /// What the handler keeps the connection alive for on its own, following the
+    /// [`KeepAlivePolicy`], the idle timeout and the deadlines set through
+    /// [`Behaviour::touch_keep_alive`].
+    pub keep_alive: KeepAlive,
 }
 
-impl<TInboundOut, TOutboundOut, TErr> ProtocolsHandler for Handler<TInboundOut, TOutboundOut, TErr>
+/// Decides whether idle connections are kept alive, see [`Behaviour::set_keep_alive_decider`].
+///
+/// Implemented for closures taking the peer and the [`KeepAliveState`] of its connection.
+pub trait KeepAliveDecider: Send + Sync {
+    fn keep_alive(&self, peer: &PeerId, state: &KeepAliveState) -> KeepAlive;
+}
+
+impl<F> KeepAliveDecider for F
 where
-    TInboundOut: Send + 'static,
-    TOutboundOut: Send + 'static,
-    TErr: Send + 'static,
+    F: Fn(&PeerId, &KeepAliveState) -> KeepAlive + Send + Sync,
 {
-    type InEvent = ProtocolInEvent<TInboundOut, TOutboundOut, TErr>;
-    type OutEvent = ProtocolOutEvent<TInboundOut, TOutboundOut, TErr>;
-    type Error = Infallible;
-    type InboundProtocol = ProtocolInfo;
-    type OutboundProtocol = ProtocolInfo;
-    type InboundOpenInfo = ();
-    type OutboundOpenInfo = ();
+    fn keep_alive(&self, peer: &PeerId, state: &KeepAliveState) -> KeepAlive {
+        self(peer, state)
+    }
+}
 
-    fn listen_protocol(&self) -> SubstreamProtocol<Self::InboundProtocol, Self::InboundOpenInfo> {
-        SubstreamProtocol::new(ProtocolInfo::new(self.info), ())
+type AcceptInboundFn = Box<dyn Fn(&PeerId, &ConnectedPoint) -> bool + Send + Sync>;
+type BanCheckFn = Box<dyn Fn(&PeerId) -> bool + Send + Sync>;
+type EventReadyFn = Box<dyn Fn() + Send + Sync>;
+/// Wraps every negotiated substream, see [`Behaviour::wrap_substream`].
+type WrapSubstreamFn = Box<dyn Fn(NegotiatedSubstream) -> Box<dyn Io> + Send + Sync>;
+type DialAddressSelectorFn = Box<dyn Fn(&PeerId, Vec<Multiaddr>) -> Vec<Multiaddr> + Send + Sync>;
+#[cfg(feature = "tokio")]
+type PublishEventFn<I, O, E> = Box<dyn Fn(&BehaviourOutEvent<I, O, E>) + Send + Sync>;
+
+/// How long reading or writing a single message on a substream may take, `None` if unbounded.
+#[derive(Default)]
+struct IoTimeouts {
+    read: RwLock<Option<Duration>>,
+    write: RwLock<Option<Duration>>,
+}
+
+impl IoTimeouts {
+    fn read(&self) -> Option<Duration> {
+        *self.read.read().expect("lock not to be poisoned")
     }
 
-    fn inject_fully_negotiated_inbound(
-        &mut self,
-        substream: InboundSubstream,
-        _: Self::InboundOpenInfo,
-    ) {
-        match mem::replace(&mut self.state, ProtocolState::Poisoned) {
-            ProtocolState::None => {
-                self.state = ProtocolState::Inbound(
-                    InboundProtocolState::GotSubstreamNeedFunction(substream),
-                );
-            }
-            ProtocolState::Inbound(InboundProtocolState::GotFunctionNeedSubstream(protocol_fn)) => {
-                self.state =
-                    ProtocolState::Inbound(InboundProtocolState::Executing(protocol_fn(substream)));
-            }
-            ProtocolState::Inbound(_) | ProtocolState::Done => {
-                panic!("Illegal state, substream is already present.");
-            }
-            ProtocolState::Outbound(_) => {
-                panic!("Failed to process inbound substream in outbound protocol.");
-            }
-            ProtocolState::Poisoned => {
-                panic!("Illegal state, currently in transient state poisoned.");
-            }
-        }
+    fn write(&self) -> Option<Duration> {
+        *self.write.read().expect("lock not to be poisoned")
     }
+}
 
-    fn inject_fully_negotiated_outbound(
-        &mut self,
-        substream: OutboundSubstream,
-        _: Self::OutboundOpenInfo,
-    ) {
-        match mem::replace(&mut self.state, ProtocolState::Poisoned) {
-            ProtocolState::Outbound(OutboundProtocolState::GotFunctionRequestedSubstream(
-                protocol_fn,
-            )) => {
-                self.state = ProtocolState::Outbound(OutboundProtocolState::Executing(
-                    protocol_fn(substream),
-                ));
-            }
-            ProtocolState::None
-            | ProtocolState::Outbound(OutboundProtocolState::GotFunctionNeedSubstream(_)) => {
-                panic!("Illegal state, receiving substream means it was requested.");
-            }
-            ProtocolState::Outbound(_) | ProtocolState::Done => {
-                panic!("Illegal state, substream is already present.");
-            }
-            ProtocolState::Inbound(_) => {
-                panic!("Failed to process outbound substream in inbound protocol.");
-            }
-            ProtocolState::Poisoned => {
-                panic!("Illegal state, currently in transient state poisoned.");
-            }
-        }
+/// The substreams open across all connections, see [`Behaviour::set_max_open_substreams`].
+#[derive(Default)]
+struct OpenSubstreams {
+    count: AtomicUsize,
+    /// The inbound substreams among `count` that wait for room, see [`DeferredSlot`].
+    deferred: AtomicUsize,
+    max: RwLock<Option<usize>>,
+    /// Woken whenever a substream closes or the limit changes.
+    wakers: Mutex<Vec<Waker>>,
+}
+
+impl OpenSubstreams {
+    fn count(&self) -> usize {
+        self.count
+            .load(Ordering::SeqCst)
+            .saturating_sub(self.deferred.load(Ordering::SeqCst))
     }
 
-    fn inject_event(&mut self, event: Self::InEvent) {
-        match event {
-            ProtocolInEvent::ExecuteInbound(protocol_fn) => {
-                match mem::replace(&mut self.state, ProtocolState::Poisoned) {
-                    ProtocolState::None => {
-                        self.state = ProtocolState::Inbound(
-                            InboundProtocolState::GotFunctionNeedSubstream(protocol_fn),
-                        );
-                    }
-                    ProtocolState::Inbound(InboundProtocolState::GotSubstreamNeedFunction(
-                        substream,
-                    )) => {
-                        self.state = ProtocolState::Inbound(InboundProtocolState::Executing(
-                            protocol_fn(substream),
-                        ));
-                    }
-                    ProtocolState::Inbound(_) | ProtocolState::Done => {
-                        panic!("Illegal state, protocol fn is already present.");
-                    }
-                    ProtocolState::Outbound(_) => {
-                        panic!("Failed to process inbound protocol fn in outbound protocol.");
-                    }
-                    ProtocolState::Poisoned => {
-                        panic!("Illegal state, currently in transient state poisoned.");
-                    }
-                }
-            }
-            ProtocolInEvent::ExecuteOutbound(protocol_fn) => {
-                match mem::replace(&mut self.state, ProtocolState::Poisoned) {
-                    ProtocolState::None => {
-                        self.state = ProtocolState::Outbound(
-                            OutboundProtocolState::GotFunctionNeedSubstream(protocol_fn),
-                        );
-                    }
-                    ProtocolState::Outbound(_) | ProtocolState::Done => {
-                        panic!("Illegal state, protocol fn is already present.");
-                    }
-                    ProtocolState::Inbound(_) => {
-                        panic!("Failed to process outbound protocol fn in inbound protocol.");
-                    }
-                    ProtocolState::Poisoned => {
-                        panic!("Illegal state, currently in transient state poisoned.");
-                    }
-                }
-            }
-        }
+    fn max(&self) -> Option<usize> {
+        *self.max.read().expect("lock not to be poisoned")
     }
 
-    fn inject_dial_upgrade_error(
-        &mut self,
-        _: Self::OutboundOpenInfo,
-        err: ProtocolsHandlerUpgrErr<<Self::OutboundProtocol as OutboundUpgradeSend>::Error>,
-    ) {
-        log::error!("Failed to upgrade: {}", err);
+    /// Whether no more substreams may be opened.
+    fn is_full(&self) -> bool {
+        self.max().is_some_and(|max| self.count() >= max)
     }
 
-    fn connection_keep_alive(&self) -> KeepAlive {
-        KeepAlive::Yes
+    /// Whether more substreams are open than allowed, including a freshly negotiated one.
+    fn is_exceeded(&self) -> bool {
+        self.max().is_some_and(|max| self.count() > max)
     }
 
-    #[allow(clippy::type_complexity)]
-    fn poll(
-        &mut self,
-        cx: &mut Context<'_>,
-    ) -> Poll<
-        ProtocolsHandlerEvent<
-            Self::OutboundProtocol,
-            Self::OutboundOpenInfo,
-            Self::OutEvent,
-            Self::Error,
-        >,
-    > {
-        match mem::replace(&mut self.state, ProtocolState::Poisoned) {
-            ProtocolState::Inbound(InboundProtocolState::Executing(mut protocol)) => match protocol
-                .poll_unpin(cx)
-            {
-                Poll::Ready(res) => {
-                    self.state = ProtocolState::Done;
-                    Poll::Ready(ProtocolsHandlerEvent::Custom(ProtocolOutEvent::Inbound(
-                        res,
-                    )))
-                }
-                Poll::Pending => {
-                    self.state = ProtocolState::Inbound(InboundProtocolState::Executing(protocol));
-                    Poll::Pending
-                }
-            },
-            ProtocolState::Outbound(OutboundProtocolState::Executing(mut protocol)) => {
-                match protocol.poll_unpin(cx) {
-                    Poll::Ready(res) => {
-                        self.state = ProtocolState::Done;
-                        Poll::Ready(ProtocolsHandlerEvent::Custom(ProtocolOutEvent::Outbound(
-                            res,
-                        )))
-                    }
-                    Poll::Pending => {
-                        self.state =
-                            ProtocolState::Outbound(OutboundProtocolState::Executing(protocol));
-                        Poll::Pending
-                    }
-                }
-            }
-            ProtocolState::Outbound(OutboundProtocolState::GotFunctionNeedSubstream(protocol)) => {
-                self.state = ProtocolState::Outbound(
-                    OutboundProtocolState::GotFunctionRequestedSubstream(protocol),
-                );
-                Poll::Ready(ProtocolsHandlerEvent::OutboundSubstreamRequest {
-                    protocol: SubstreamProtocol::new(ProtocolInfo::new(self.info), ()),
-                })
-            }
-            ProtocolState::Poisoned => {
-                unreachable!("Protocol is poisoned (transient state)")
-            }
-            other => {
-                self.state = other;
-                Poll::Pending
-            }
+    fn register(&self, waker: &Waker) {
+        let mut wakers = self.wakers.lock().expect("lock not to be poisoned");
+        if !wakers.iter().any(|registered| registered.will_wake(waker)) {
+            wakers.push(waker.clone());
+        }
+    }
+
+    fn wake(&self) {
+        let wakers = mem::take(&mut *self.wakers.lock().expect("lock not to be poisoned"));
+        for waker in wakers {
+            waker.wake();
         }
     }
 }
 
-/// A behaviour that can execute await/.async protocols.
+/// Counts towards the [`OpenSubstreams`] for as long as it is alive.
 ///
-/// Note: It is not possible to execute the same protocol with the same peer several simultaneous times.
-pub struct Behaviour<I, O, E> {
-    protocol_in_events: VecDeque<(PeerId, ProtocolInEvent<I, O, E>)>,
-    protocol_out_events: VecDeque<(PeerId, ProtocolOutEvent<I, O, E>)>,
+/// Outbound substreams take their slot when the protocol needing them is dispatched, so the
+/// limit also holds for substreams that are still being requested.
+pub struct SubstreamSlot(Arc<OpenSubstreams>);
 
-    connected_peers: HashMap<PeerId, Vec<Multiaddr>>,
+impl SubstreamSlot {
+    fn new(open: Arc<OpenSubstreams>) -> Self {
+        open.count.fetch_add(1, Ordering::SeqCst);
 
-    info: &'static [u8],
+        Self(open)
+    }
 }
 
-impl<I, O, E> Behaviour<I, O, E> {
-    /// Constructs a new [`Behaviour`] with the given protocol info.
-    ///
-    /// # Example
-    ///
-    /// ```
-    /// # use libp2p_async_await::Behaviour;
-    ///
-    /// let _ = Behaviour::new(b"/foo/bar/1.0.0");
-    /// ```
-    pub fn new(info: &'static [u8]) -> Self {
-        Self {
-            protocol_in_events: VecDeque::default(),
-            protocol_out_events: VecDeque::default(),
-            connected_peers: HashMap::default(),
-            info,
-        }
+impl Drop for SubstreamSlot {
+    fn drop(&mut self) {
+        self.0.count.fetch_sub(1, Ordering::SeqCst);
+        self.0.wake();
     }
 }
 
-impl<I, O, E> Behaviour<I, O, E> {
-    pub fn do_protocol_listener<F>(
-        &mut self,
-        peer: PeerId,
-        protocol: impl FnOnce(InboundSubstream) -> F + Send + 'static,
-    ) where
+/// Keeps an inbound substream that was negotiated while the [`OpenSubstreams`] were at their
+/// limit from counting towards them until there is room for it.
+struct DeferredSlot(Arc<OpenSubstreams>);
+
+impl DeferredSlot {
+    fn new(open: Arc<OpenSubstreams>) -> Self {
+        open.deferred.fetch_add(1, Ordering::SeqCst);
+
+        Self(open)
+    }
+}
+
+impl Drop for DeferredSlot {
+    fn drop(&mut self) {
+        self.0.deferred.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// A socket holding on to its [`SubstreamSlot`].
+struct CountedSocket<T> {
+    socket: T,
+    _slot: SubstreamSlot,
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for CountedSocket<T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.socket).poll_read(cx, buf)
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for CountedSocket<T> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.socket).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.socket).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.socket).poll_close(cx)
+    }
+}
+
+/// State shared between a [`Behaviour`] and all of its handlers.
+struct Shared {
+    ready: AtomicBool,
+    /// The protocols we accept inbound substreams for, `None` allows all advertised ones.
+    inbound_allowed: RwLock<Option<Vec<&'static [u8]>>>,
+    /// The frame written on inbound substreams for protocols that are not allowed, if any.
+    rejection_frame: RwLock<Option<Vec<u8>>>,
+    /// The magic bytes exchanged at the start of every fresh substream, if any.
+    preface: RwLock<Option<&'static [u8]>>,
+    negotiate_framing: AtomicBool,
+    /// How many of the advertised protocols outbound substreams offer, if limited.
+    max_negotiation_protocols: RwLock<Option<usize>>,
+    /// How long idle connections are kept alive after their last substream activity, if at all.
+    idle_timeout: RwLock<Option<Duration>>,
+    keep_alive_policy: RwLock<KeepAlivePolicy>,
+    /// Overrides the keep-alive of idle connections, `None` keeps what the handler decided.
+    keep_alive_decider: RwLock<Option<Box<dyn KeepAliveDecider>>>,
+    /// How many protocols a connection serves before it is closed, if limited.
+    max_executions: RwLock<Option<usize>>,
+    /// How many bytes the remote already sent are drained from the substreams of cancelled
+    /// protocols, if any.
+    cancel_drain_limit: RwLock<Option<usize>>,
+    /// The message written to the substreams of protocols that timed out, if any.
+    timeout_notification: RwLock<Option<Vec<u8>>>,
+    /// How often executing protocols report progress, if at all.
+    progress_interval: RwLock<Option<Duration>>,
+    /// Decides whether we accept inbound substreams from a peer, `None` accepts all.
+    accept_inbound: RwLock<Option<AcceptInboundFn>>,
+    /// Decides whether a peer is banned, `None` bans no one.
+    is_banned: RwLock<Option<BanCheckFn>>,
+    /// How long inbound protocols may execute, if limited.
+    inbound_timeout: RwLock<Option<Duration>>,
+    /// How long outbound protocols may execute, if limited.
+    outbound_timeout: RwLock<Option<Duration>>,
+    /// How long protocols may go without reading or writing a byte, if limited.
+    stall_timeout: RwLock<Option<Duration>>,
+    /// Shared with the substreams of all connections.
+    io_timeouts: Arc<IoTimeouts>,
+    /// Whether simultaneously opened protocols are resolved by a tie-break.
+    resolve_simultaneous_open: AtomicBool,
+    /// Our own peer id, known once the behaviour was polled.
+    local_peer_id: RwLock<Option<PeerId>>,
+    /// How long writes to each connected peer waited for the transport, in nanoseconds.
+    write_pending: Mutex<HashMap<PeerId, Arc<AtomicU64>>>,
+    /// Shared with the substreams of all connections.
+    open_substreams: Arc<OpenSubstreams>,
+    /// Shared with the substreams of all connections.
+    wrap_substream: Arc<RwLock<Option<WrapSubstreamFn>>>,
+    /// Whether handlers add the bytes transferred by the protocols they executed to
+    /// `throughput`.
+    track_throughput: AtomicBool,
+    /// The bytes transferred by each negotiated protocol and the time it took, summed over its
+    /// executions.
+    throughput: Mutex<HashMap<&'static [u8], Throughput>>,
+    /// Shared with the substreams of all connections.
+    egress_limit: Arc<EgressLimit>,
+    /// Shared with the substreams of all connections.
+    framing: Arc<SharedFraming>,
+    /// Shared with the substreams of all connections.
+    read_memory: Arc<ReadMemory>,
+    /// The budget of each connected peer, shared with the substreams of its connections.
+    bandwidth: Bandwidth,
+}
+
+impl Shared {
+    fn write_pending(&self, peer: &PeerId) -> Arc<AtomicU64> {
+        self.write_pending
+            .lock()
+            .expect("lock not to be poisoned")
+            .entry(*peer)
+            .or_default()
+            .clone()
+    }
+
+    fn handshake(&self) -> Handshake {
+        Handshake {
+            preface: *self.preface.read().expect("lock not to be poisoned"),
+            negotiate_framing: self.negotiate_framing.load(Ordering::SeqCst),
+        }
+    }
+
+    fn progress_interval(&self) -> Option<Duration> {
+        *self
+            .progress_interval
+            .read()
+            .expect("lock not to be poisoned")
+    }
+
+    fn idle_timeout(&self) -> Option<Duration> {
+        *self.idle_timeout.read().expect("lock not to be poisoned")
+    }
+
+    fn protocol_timeout(&self, direction: Direction) -> Option<Duration> {
+        let timeout = match direction {
+            Direction::Inbound => &self.inbound_timeout,
+            Direction::Outbound => &self.outbound_timeout,
+        };
+
+        *timeout.read().expect("lock not to be poisoned")
+    }
+
+    fn stall_timeout(&self) -> Option<Duration> {
+        *self.stall_timeout.read().expect("lock not to be poisoned")
+    }
+
+    fn max_negotiation_protocols(&self) -> Option<usize> {
+        *self
+            .max_negotiation_protocols
+            .read()
+            .expect("lock not to be poisoned")
+    }
+
+    fn max_executions(&self) -> Option<usize> {
+        *self.max_executions.read().expect("lock not to be poisoned")
+    }
+
+    fn cancel_drain_limit(&self) -> Option<usize> {
+        *self
+            .cancel_drain_limit
+            .read()
+            .expect("lock not to be poisoned")
+    }
+
+    fn timeout_notification(&self) -> Option<Vec<u8>> {
+        self.timeout_notification
+            .read()
+            .expect("lock not to be poisoned")
+            .clone()
+    }
+
+    fn keep_alive_policy(&self) -> KeepAlivePolicy {
+        *self
+            .keep_alive_policy
+            .read()
+            .expect("lock not to be poisoned")
+    }
+
+    fn keep_alive(&self, peer: &PeerId, state: &KeepAliveState) -> KeepAlive {
+        match &*self
+            .keep_alive_decider
+            .read()
+            .expect("lock not to be poisoned")
+        {
+            Some(decider) => decider.keep_alive(peer, state),
+            None => state.keep_alive,
+        }
+    }
+
+    fn accepts_inbound(&self, peer: &PeerId, point: &ConnectedPoint) -> bool {
+        match &*self.accept_inbound.read().expect("lock not to be poisoned") {
+            Some(accept) => accept(peer, point),
+            None => true,
+        }
+    }
+
+    fn is_banned(&self, peer: &PeerId) -> bool {
+        match &*self.is_banned.read().expect("lock not to be poisoned") {
+            Some(is_banned) => is_banned(peer),
+            None => false,
+        }
+    }
+
+    /// Whether we give up our outbound protocol in favour of the remote's when both opened one
+    /// at the same time.
+    ///
+    /// The peer with the greater peer id yields, so exactly one of two peers that both resolve
+    /// simultaneous opens does.
+    fn yields_to(&self, remote: &PeerId) -> bool {
+        if !self.resolves_simultaneous_open() {
+            return false;
+        }
+
+        match &*self.local_peer_id.read().expect("lock not to be poisoned") {
+            Some(local) => local.to_bytes() > remote.to_bytes(),
+            None => false,
+        }
+    }
+
+    fn resolves_simultaneous_open(&self) -> bool {
+        self.resolve_simultaneous_open.load(Ordering::SeqCst)
+    }
+
+    fn rejection_frame(&self) -> Option<Vec<u8>> {
+        self.rejection_frame
+            .read()
+            .expect("lock not to be poisoned")
+            .clone()
+    }
+
+    fn is_inbound_allowed(&self, protocol: &[u8]) -> bool {
+        match &*self
+            .inbound_allowed
+            .read()
+            .expect("lock not to be poisoned")
+        {
+            Some(allowed) => allowed.contains(&protocol),
+            None => true,
+        }
+    }
+}
+
+/// The reason a [`Handler`] requested an outbound substream.
+pub enum OutboundOpenInfo {
+    /// The substream for the executing protocol, identified by the number of the request.
+    Protocol(u64),
+    Notification(Vec<u8>),
+    /// An additional substream requested by the executing protocol fn.
+    Additional(AdditionalSubstream),
+    /// A substream probing whether the remote supports the protocol, identified by the probe.
+    #[cfg(feature = "probe")]
+    Probe(u64, &'static [u8]),
+}
+
+/// Hands an additional outbound substream to the protocol fn that requested it.
+pub struct AdditionalSubstream(oneshot::Sender<Result<OutboundSubstream, Failure>>);
+
+/// Opens additional outbound substreams on the connection of an executing protocol.
+///
+/// Obtained through the `opener` fn of substreams. The handler requests the substreams while the
+/// protocol fn keeps running, so several of them can be negotiated at the same time and the
+/// protocol fn aggregates their results into the single event it terminates with.
+#[derive(Clone)]
+pub struct SubstreamOpener(Arc<ConnectionShared>);
+
+impl SubstreamOpener {
+    /// Opens another substream for `protocol`, see [`OutboundSubstream::open_outbound`].
+    pub fn open_outbound(
+        &self,
+        protocol: &'static [u8],
+    ) -> impl Future<Output = Result<OutboundSubstream, Failure>> + Send + 'static {
+        let connection = self.0.clone();
+
+        async move { connection.open_outbound(protocol).await }
+    }
+
+    /// Opens `count` substreams for `protocol` concurrently.
+    ///
+    /// Fails with the first failure, dropping the substreams negotiated until then.
+    pub fn open_outbound_many(
+        &self,
+        protocol: &'static [u8],
+        count: usize,
+    ) -> impl Future<Output = Result<Vec<OutboundSubstream>, Failure>> + Send + 'static {
+        future::try_join_all((0..count).map(|_| self.open_outbound(protocol)))
+    }
+}
+
+impl fmt::Debug for SubstreamOpener {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut f = f.debug_tuple("SubstreamOpener");
+        if let Some(peer) = &self.0.peer {
+            f.field(peer);
+        }
+        f.finish()
+    }
+}
+
+/// Serves inbound substreams no handler is set for, see [`Behaviour::set_inbound_pool`].
+struct InboundPool<I, E> {
+    handler: InboundHandlerFn<I, E>,
+    /// How many more substreams the pool may serve at the same time.
+    free: Arc<AtomicUsize>,
+}
+
+impl<I, E> InboundPool<I, E>
+where
+    I: Send + 'static,
+    E: Send + 'static,
+{
+    /// Binds the handler to the next substream if a slot is free, which it takes until the
+    /// protocol terminates.
+    fn take(&self) -> Option<InboundProtocolFn<I, E>> {
+        self.free
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |free| {
+                free.checked_sub(1)
+            })
+            .ok()?;
+        let slot = InboundPoolSlot(self.free.clone());
+        let handler = self.handler.clone();
+
+        Some(Box::new(move |substream| {
+            handler(substream)
+                .map(move |res| {
+                    drop(slot);
+                    res.map(|out| (out, None))
+                })
+                .boxed()
+        }))
+    }
+}
+
+/// Frees a slot of an [`InboundPool`] once dropped.
+struct InboundPoolSlot(Arc<AtomicUsize>);
+
+impl Drop for InboundPoolSlot {
+    fn drop(&mut self) {
+        self.0.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+/// Creates a [`Handler`] once the peer of a connection is known.
+pub struct IntoHandler<TInboundOut, TOutboundOut, TErr> {
+    protocols: Vec<&'static [u8]>,
+    shared: Arc<Shared>,
+    inbound_handlers: InboundHandlers<TInboundOut, TErr>,
+    inbound_pool: SharedInboundPool<TInboundOut, TErr>,
+    #[allow(clippy::type_complexity)]
+    marker: PhantomData<fn() -> (TInboundOut, TOutboundOut, TErr)>,
+}
+
+impl<TInboundOut, TOutboundOut, TErr> IntoProtocolsHandler
+    for IntoHandler<TInboundOut, TOutboundOut, TErr>
+where
+    TInboundOut: Send + 'static,
+    TOutboundOut: Send + 'static,
+    TErr: Send + 'static,
+{
+    type Handler = Handler<TInboundOut, TOutboundOut, TErr>;
+
+    fn into_handler(self, peer: &PeerId, point: &ConnectedPoint) -> Self::Handler {
+        Handler::new(
+            *peer,
+            point.clone(),
+            self.protocols,
+            self.shared,
+            self.inbound_handlers,
+            self.inbound_pool,
+        )
+    }
+
+    fn inbound_protocol(&self) -> ProtocolInfo {
+        ProtocolInfo::new(
+            self.protocols.clone(),
+            Arc::new(ConnectionShared::new(
+                None,
+                self.shared.io_timeouts.clone(),
+                Arc::default(),
+                Arc::default(),
+                self.shared.open_substreams.clone(),
+                self.shared.wrap_substream.clone(),
+                self.shared.egress_limit.clone(),
+                self.shared.framing.clone(),
+                self.shared.read_memory.clone(),
+            )),
+        )
+    }
+}
+
+pub struct Handler<TInboundOut, TOutboundOut, TErr> {
+    state: ProtocolState<TInboundOut, TOutboundOut, TErr>,
+    peer: PeerId,
+    point: ConnectedPoint,
+    protocols: Vec<&'static [u8]>,
+    shared: Arc<Shared>,
+    inbound_handlers: InboundHandlers<TInboundOut, TErr>,
+    inbound_pool: SharedInboundPool<TInboundOut, TErr>,
+
+    /// Substreams handed back by a previous protocol, to be used by the next one.
+    reusable_inbound: Option<InboundSubstream>,
+    reusable_outbound: Option<OutboundSubstream>,
+    /// Inbound substreams negotiated while a protocol executes, handed to its protocol fn if it
+    /// accepts inbound substreams or served in order once the handler is idle.
+    queued_inbound: VecDeque<InboundSubstream>,
+    /// Inbound substreams negotiated while too many substreams were open, accepted in order once
+    /// there is room, see [`Behaviour::set_max_open_substreams`].
+    deferred_inbound: VecDeque<(InboundSubstream, DeferredSlot)>,
+    /// The protocol the next outbound substream is requested for, all advertised ones if `None`.
+    outbound_protocol: Option<&'static [u8]>,
+    /// The number of outbound substreams requested so far and the one we are waiting for, if any.
+    /// Substreams requested for protocols that were cancelled in the meantime are dropped.
+    outbound_requests: u64,
+    pending_outbound_request: Option<u64>,
+    /// The slot the next outbound substream takes, see [`Behaviour::set_max_open_substreams`].
+    outbound_slot: Option<SubstreamSlot>,
+
+    /// Deadline until which an idle connection is kept alive, if any.
+    keep_alive_until: Option<Instant>,
+
+    connection: Arc<ConnectionShared>,
+    disconnecting: bool,
+    /// How many protocols terminated on this connection.
+    executions: usize,
+
+    /// Fires whenever the executing protocol is due to report progress.
+    progress_timer: Option<Delay>,
+    /// The negotiated protocol of the executing protocol fn and when it started, if it got to
+    /// start.
+    execution_started: Option<(&'static [u8], Instant)>,
+
+    /// Notifications waiting for a substream to be requested, requested or being sent.
+    pending_notifications: VecDeque<(Vec<u8>, SubstreamSlot)>,
+    requested_notifications: usize,
+    notifications: FuturesUnordered<Execution<(), OutboundSubstream, io::Error>>,
+    /// Rejected inbound substreams the rejection frame is being written to.
+    rejections: FuturesUnordered<BoxFuture<'static, ()>>,
+    #[cfg(feature = "probe")]
+    probes: probe::Probes,
+
+    pending_events: VecDeque<ProtocolOutEvent<TInboundOut, TOutboundOut, TErr>>,
+}
+
+impl<TInboundOut, TOutboundOut, TErr> Handler<TInboundOut, TOutboundOut, TErr> {
+    fn new(
+        peer: PeerId,
+        point: ConnectedPoint,
+        protocols: Vec<&'static [u8]>,
+        shared: Arc<Shared>,
+        inbound_handlers: InboundHandlers<TInboundOut, TErr>,
+        inbound_pool: SharedInboundPool<TInboundOut, TErr>,
+    ) -> Self {
+        let connection = Arc::new(ConnectionShared::new(
+            Some(peer),
+            shared.io_timeouts.clone(),
+            shared.write_pending(&peer),
+            shared.bandwidth.peer(&peer),
+            shared.open_substreams.clone(),
+            shared.wrap_substream.clone(),
+            shared.egress_limit.clone(),
+            shared.framing.clone(),
+            shared.read_memory.clone(),
+        ));
+
+        Self {
+            state: ProtocolState::None,
+            peer,
+            point,
+            protocols,
+            shared,
+            inbound_handlers,
+            inbound_pool,
+            reusable_inbound: None,
+            reusable_outbound: None,
+            queued_inbound: VecDeque::default(),
+            deferred_inbound: VecDeque::default(),
+            outbound_protocol: None,
+            outbound_requests: 0,
+            pending_outbound_request: None,
+            outbound_slot: None,
+            keep_alive_until: None,
+            connection,
+            disconnecting: false,
+            executions: 0,
+            progress_timer: None,
+            execution_started: None,
+            pending_notifications: VecDeque::default(),
+            requested_notifications: 0,
+            notifications: FuturesUnordered::new(),
+            rejections: FuturesUnordered::new(),
+            #[cfg(feature = "probe")]
+            probes: probe::Probes::default(),
+            pending_events: VecDeque::default(),
+        }
+    }
+
+    fn protocol_info(&self, protocol: Option<&'static [u8]>) -> ProtocolInfo {
+        let protocols = match protocol {
+            Some(protocol) => vec![protocol],
+            None => self.protocols.clone(),
+        };
+
+        ProtocolInfo::new(protocols, self.connection.clone())
+    }
+
+    /// Like [`Handler::protocol_info`] but offers only the most preferred protocols, see
+    /// [`Behaviour::set_max_negotiation_protocols`].
+    fn outbound_protocol_info(&self, protocol: Option<&'static [u8]>) -> ProtocolInfo {
+        let mut info = self.protocol_info(protocol);
+        if let Some(max) = self.shared.max_negotiation_protocols() {
+            info.protocols.truncate(max.max(1));
+        }
+
+        info
+    }
+
+    /// Starts executing the protocol fn, reporting which protocol the substream was negotiated
+    /// for.
+    fn start_execution<T, S>(
+        &mut self,
+        protocol_fn: ProtocolFn<T, S, TErr>,
+        substream: S,
+        handshake: Handshake,
+    ) -> Execution<T, S, TErr>
+    where
+        T: Send + 'static,
+        S: Substream,
+        TErr: Send + 'static,
+    {
+        let protocol = substream.negotiated_protocol();
+        self.pending_events
+            .push_back(ProtocolOutEvent::Executing(protocol));
+        self.execution_started = Some((protocol, Instant::now()));
+        self.connection.bytes_read.store(0, Ordering::Relaxed);
+        self.connection.bytes_written.store(0, Ordering::Relaxed);
+
+        self.connection.progress();
+
+        let timeout = self.shared.protocol_timeout(substream.direction());
+        let execution = execute(protocol_fn, substream, handshake);
+
+        let execution = match timeout {
+            Some(timeout) => {
+                let connection = self.connection.clone();
+                let notification = self.shared.timeout_notification();
+
+                async move {
+                    match future::select(execution, Delay::new(timeout)).await {
+                        Either::Left((res, _)) => res,
+                        Either::Right((_, execution)) => {
+                            if let Some(notification) = notification {
+                                connection.notify_timeout(execution, &notification).await;
+                            }
+
+                            Err(Failure::Timeout)
+                        }
+                    }
+                }
+                .boxed()
+            }
+            None => execution,
+        };
+
+        match self.shared.stall_timeout() {
+            Some(timeout) => {
+                let stalled = stalled(self.connection.clone(), timeout).boxed();
+
+                future::select(execution, stalled)
+                    .map(|res| match res {
+                        Either::Left((res, _)) => res,
+                        Either::Right(_) => Err(Failure::Stalled),
+                    })
+                    .boxed()
+            }
+            None => execution,
+        }
+    }
+
+    fn is_executing(&self) -> bool {
+        matches!(
+            self.state,
+            ProtocolState::Inbound(InboundProtocolState::Executing(_))
+                | ProtocolState::Outbound(OutboundProtocolState::Executing(_))
+        )
+    }
+
+    fn has_notifications(&self) -> bool {
+        !self.pending_notifications.is_empty()
+            || self.requested_notifications > 0
+            || !self.notifications.is_empty()
+    }
+
+    /// Like `on_protocol_terminated` but drains what the remote already sent on the substreams of
+    /// the cancelled protocol before dropping them, see [`Behaviour::set_cancel_drain_limit`].
+    fn on_protocol_cancelled(&mut self) {
+        let limit = self.shared.cancel_drain_limit().unwrap_or(0);
+        self.connection.drain_limit.store(limit, Ordering::SeqCst);
+        self.on_protocol_terminated();
+        self.connection.drain_limit.store(0, Ordering::SeqCst);
+    }
+
+    /// Returns to idle after a protocol terminated, acting on a disconnect request made by it.
+    ///
+    /// Events pushed here are emitted after the event terminating the protocol.
+    fn on_protocol_terminated(&mut self) {
+        self.state = ProtocolState::None;
+        self.outbound_slot = None;
+        self.connection.reset_finish();
+        self.progress_timer = None;
+        self.executions += 1;
+
+        if let Some((protocol, started)) = self.execution_started.take() {
+            if self.shared.track_throughput.load(Ordering::SeqCst) {
+                let mut throughput = self
+                    .shared
+                    .throughput
+                    .lock()
+                    .expect("lock not to be poisoned");
+                let throughput = throughput.entry(protocol).or_default();
+                throughput.bytes_read += self.connection.bytes_read.load(Ordering::Relaxed);
+                throughput.bytes_written += self.connection.bytes_written.load(Ordering::Relaxed);
+                throughput.duration += started.elapsed();
+            }
+        }
+
+        if !self.disconnecting && self.connection.disconnect_requested.load(Ordering::SeqCst) {
+            log::debug!(target: LOG_TARGET, "Protocol requested to disconnect.");
+            self.disconnecting = true;
+            self.pending_events
+                .push_back(ProtocolOutEvent::DisconnectRequested);
+        }
+        if !self.disconnecting
+            && self
+                .shared
+                .max_executions()
+                .is_some_and(|max| self.executions >= max)
+        {
+            log::debug!(target: LOG_TARGET, "Connection served its maximum number of protocols.");
+            self.disconnecting = true;
+        }
+        if !self.disconnecting {
+            self.pending_events.push_back(ProtocolOutEvent::Idle);
+        }
+    }
+}
+
+/// A frame a substream started reading through `read_message_timeout_partial` but did not
+/// complete yet.
+#[derive(Default)]
+struct PartialFrame {
+    /// The bytes of the frame that arrived so far, as they were on the wire.
+    received: Vec<u8>,
+}
+
+impl PartialFrame {
+    /// Reads the frame from its start again, first the bytes that arrived so far and then the
+    /// ones of `socket`, which are added to them.
+    fn replay<'a, S>(&'a mut self, socket: &'a mut S) -> Replay<'a, S> {
+        Replay {
+            partial: self,
+            socket,
+            position: 0,
+        }
+    }
+}
+
+/// See [`PartialFrame::replay`].
+struct Replay<'a, S> {
+    partial: &'a mut PartialFrame,
+    socket: &'a mut S,
+    position: usize,
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Replay<'_, S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        let replayed = &this.partial.received[this.position..];
+        if !replayed.is_empty() {
+            let read = replayed.len().min(buf.len());
+            buf[..read].copy_from_slice(&replayed[..read]);
+            this.position += read;
+
+            return Poll::Ready(Ok(read));
+        }
+
+        let read = match Pin::new(&mut *this.socket).poll_read(cx, buf) {
+            Poll::Ready(Ok(read)) => read,
+            poll => return poll,
+        };
+        this.partial.received.extend_from_slice(&buf[..read]);
+        this.position += read;
+
+        Poll::Ready(Ok(read))
+    }
+}
+
+/// The IO error that failed a substream, shared with the execution of its protocol fn.
+///
+/// Cleared once the substream transfers data again, so protocol fns that recovered from a failed
+/// read or write and then fail for a reason of their own report an application error.
+#[derive(Default)]
+struct TransportError {
+    error: Mutex<Option<io::Error>>,
+    /// Whether `error` is set, to not take the lock on every successful transfer.
+    failed: AtomicBool,
+}
+
+impl TransportError {
+    fn record(&self, e: &io::Error) {
+        let mut error = self.error.lock().expect("lock not to be poisoned");
+        if error.is_none() {
+            *error = Some(io::Error::new(e.kind(), e.to_string()));
+            self.failed.store(true, Ordering::SeqCst);
+        }
+    }
+
+    fn recovered(&self) {
+        if self.failed.swap(false, Ordering::SeqCst) {
+            *self.error.lock().expect("lock not to be poisoned") = None;
+        }
+    }
+
+    fn take(&self) -> Option<io::Error> {
+        self.failed.store(false, Ordering::SeqCst);
+        self.error.lock().expect("lock not to be poisoned").take()
+    }
+}
+
+/// State shared between a [`Handler`] and the substreams handed to its protocol fns.
+struct ConnectionShared {
+    /// The remote, unknown for substreams constructed directly.
+    peer: Option<PeerId>,
+    /// Set by protocol fns through their substream to close this connection.
+    disconnect_requested: AtomicBool,
+    /// Updated whenever a substream is read from or written to.
+    last_activity: Mutex<Instant>,
+    /// When the substreams last read or wrote a byte, unlike `last_activity` not counting polls
+    /// that were pending.
+    last_progress: Mutex<Instant>,
+    /// Additional substreams protocol fns are waiting for.
+    substream_requests: Mutex<SubstreamRequests>,
+    /// Whether no handler services this connection, i.e. its substreams were constructed directly.
+    detached: bool,
+    /// Set by the handler to make the substreams of the executing protocol finish.
+    finish_requested: AtomicBool,
+    /// Set by the handler while dropping a cancelled protocol, how many bytes its substreams
+    /// drain before they are dropped.
+    drain_limit: AtomicUsize,
+    /// Set by the handler while dropping a protocol that timed out, makes its substreams hand
+    /// their socket to `stashed_sockets` instead of dropping it.
+    stash_sockets: AtomicBool,
+    stashed_sockets: Mutex<Vec<StashedSocket>>,
+    /// The tasks waiting to read from one of the substreams.
+    read_wakers: Mutex<Vec<Waker>>,
+    io_timeouts: Arc<IoTimeouts>,
+    /// Since when a write to one of the substreams is waiting for the transport, if it is.
+    write_pending_since: Mutex<Option<Instant>>,
+    /// How long writes waited for the transport in nanoseconds, shared by all connections to
+    /// the peer.
+    write_pending: Arc<AtomicU64>,
+    /// The budget of the remote, shared by all connections to it.
+    bandwidth: Arc<PeerBudget>,
+    open_substreams: Arc<OpenSubstreams>,
+    wrap_substream: Arc<RwLock<Option<WrapSubstreamFn>>>,
+    egress_limit: Arc<EgressLimit>,
+    framing: Arc<SharedFraming>,
+    memory: Arc<ConnectionMemory>,
+    /// The bytes read from and written to the substreams since the executing protocol started.
+    bytes_read: AtomicU64,
+    bytes_written: AtomicU64,
+}
+
+#[derive(Default)]
+struct SubstreamRequests {
+    outbound: VecDeque<(&'static [u8], AdditionalSubstream)>,
+    inbound: VecDeque<oneshot::Sender<InboundSubstream>>,
+    /// Wakes the handler to service new requests.
+    waker: Option<Waker>,
+}
+
+impl ConnectionShared {
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        peer: Option<PeerId>,
+        io_timeouts: Arc<IoTimeouts>,
+        write_pending: Arc<AtomicU64>,
+        bandwidth: Arc<PeerBudget>,
+        open_substreams: Arc<OpenSubstreams>,
+        wrap_substream: Arc<RwLock<Option<WrapSubstreamFn>>>,
+        egress_limit: Arc<EgressLimit>,
+        framing: Arc<SharedFraming>,
+        read_memory: Arc<ReadMemory>,
+    ) -> Self {
+        Self {
+            peer,
+            disconnect_requested: AtomicBool::new(false),
+            last_activity: Mutex::new(Instant::now()),
+            last_progress: Mutex::new(Instant::now()),
+            substream_requests: Mutex::default(),
+            detached: false,
+            finish_requested: AtomicBool::new(false),
+            drain_limit: AtomicUsize::new(0),
+            stash_sockets: AtomicBool::new(false),
+            stashed_sockets: Mutex::default(),
+            read_wakers: Mutex::default(),
+            io_timeouts,
+            write_pending_since: Mutex::default(),
+            write_pending,
+            bandwidth,
+            open_substreams,
+            wrap_substream,
+            egress_limit,
+            framing,
+            memory: Arc::new(ConnectionMemory::new(read_memory)),
+            bytes_read: AtomicU64::new(0),
+            bytes_written: AtomicU64::new(0),
+        }
+    }
+
+    fn detached() -> Self {
+        Self {
+            detached: true,
+            ..Self::new(
+                None,
+                Arc::default(),
+                Arc::default(),
+                Arc::default(),
+                Arc::default(),
+                Arc::default(),
+                Arc::default(),
+                Arc::default(),
+                Arc::default(),
+            )
+        }
+    }
+
+    fn substream_requests(&self) -> std::sync::MutexGuard<'_, SubstreamRequests> {
+        self.substream_requests
+            .lock()
+            .expect("lock not to be poisoned")
+    }
+
+    async fn open_outbound(&self, protocol: &'static [u8]) -> Result<OutboundSubstream, Failure> {
+        if self.detached {
+            return Err(Failure::ConnectionClosed);
+        }
+
+        let (sender, receiver) = oneshot::channel();
+        {
+            let mut requests = self.substream_requests();
+            requests
+                .outbound
+                .push_back((protocol, AdditionalSubstream(sender)));
+            if let Some(waker) = requests.waker.take() {
+                waker.wake();
+            }
+        }
+
+        receiver.await.unwrap_or(Err(Failure::ConnectionClosed))
+    }
+
+    async fn accept_inbound(&self) -> Result<InboundSubstream, Failure> {
+        if self.detached {
+            return Err(Failure::ConnectionClosed);
+        }
+
+        let (sender, receiver) = oneshot::channel();
+        {
+            let mut requests = self.substream_requests();
+            requests.inbound.push_back(sender);
+            if let Some(waker) = requests.waker.take() {
+                waker.wake();
+            }
+        }
+
+        receiver.await.map_err(|_| Failure::ConnectionClosed)
+    }
+
+    /// Reads and discards what the remote already sent on a substream that is dropped, up to the
+    /// limit set while dropping a cancelled protocol.
+    ///
+    /// Only data that arrived already is drained, so this never waits for the remote.
+    fn drain(&self, socket: &mut dyn Io) {
+        let mut remaining = self.drain_limit.load(Ordering::SeqCst);
+        let mut cx = Context::from_waker(noop_waker_ref());
+        let mut buf = [0; 1024];
+
+        while remaining > 0 {
+            let max = remaining.min(buf.len());
+            match Pin::new(&mut *socket).poll_read(&mut cx, &mut buf[..max]) {
+                Poll::Ready(Ok(0)) | Poll::Ready(Err(_)) | Poll::Pending => break,
+                Poll::Ready(Ok(read)) => remaining -= read,
+            }
+        }
+    }
+
+    /// Hands the socket of a substream that is dropped to `stashed_sockets` if the handler is
+    /// dropping a protocol that timed out.
+    fn stash(&self, socket: &mut Box<dyn Io>, framing: &Arc<dyn Framing>) {
+        if !self.stash_sockets.load(Ordering::SeqCst) {
+            return;
+        }
+
+        let socket = mem::replace(
+            socket,
+            Box::new(libp2p::futures::io::Cursor::new(Vec::new())),
+        );
+        self.stashed_sockets
+            .lock()
+            .expect("lock not to be poisoned")
+            .push((socket, framing.clone()));
+    }
+
+    /// Drops the execution of a protocol that timed out, writing `notification` as a message to
+    /// each of its substreams and closing them within [`TIMEOUT_NOTIFICATION_GRACE`].
+    async fn notify_timeout<F>(&self, execution: F, notification: &[u8]) {
+        self.stash_sockets.store(true, Ordering::SeqCst);
+        drop(execution);
+        self.stash_sockets.store(false, Ordering::SeqCst);
+
+        let sockets = mem::take(
+            &mut *self
+                .stashed_sockets
+                .lock()
+                .expect("lock not to be poisoned"),
+        );
+        let notify =
+            future::join_all(sockets.into_iter().map(|(mut socket, framing)| async move {
+                framing.write_frame(&mut socket, notification).await?;
+                socket.close().await
+            }));
+        if with_timeout(Some(TIMEOUT_NOTIFICATION_GRACE), notify)
+            .await
+            .is_none()
+        {
+            log::debug!(target: LOG_TARGET, "Timed out notifying the remote of a protocol timeout");
+        }
+    }
+
+    /// Makes the substreams close their write side and read EOF, waking up pending reads.
+    fn request_finish(&self) {
+        self.finish_requested.store(true, Ordering::SeqCst);
+
+        for waker in self.read_wakers().drain(..) {
+            waker.wake();
+        }
+    }
+
+    fn reset_finish(&self) {
+        self.finish_requested.store(false, Ordering::SeqCst);
+        self.read_wakers().clear();
+    }
+
+    fn read_wakers(&self) -> std::sync::MutexGuard<'_, Vec<Waker>> {
+        self.read_wakers.lock().expect("lock not to be poisoned")
+    }
+
+    fn touch(&self) {
+        *self.last_activity.lock().expect("lock not to be poisoned") = Instant::now();
+    }
+
+    fn last_activity(&self) -> Instant {
+        *self.last_activity.lock().expect("lock not to be poisoned")
+    }
+
+    fn progress(&self) {
+        *self.last_progress.lock().expect("lock not to be poisoned") = Instant::now();
+    }
+
+    fn last_progress(&self) -> Instant {
+        *self.last_progress.lock().expect("lock not to be poisoned")
+    }
+
+    /// The error of a read or write that timed out, naming the remote if known.
+    fn timed_out(&self, operation: &str) -> io::Error {
+        let msg = match self.peer {
+            Some(peer) => format!("timed out {} (peer {})", operation, peer),
+            None => format!("timed out {}", operation),
+        };
+
+        io::Error::new(io::ErrorKind::TimedOut, msg)
+    }
+
+    /// Tracks how long writes wait for the transport to accept more data.
+    fn record_write<T>(&self, poll: &Poll<T>) {
+        let mut since = self
+            .write_pending_since
+            .lock()
+            .expect("lock not to be poisoned");
+
+        match (poll, *since) {
+            (Poll::Pending, None) => *since = Some(Instant::now()),
+            (Poll::Ready(_), Some(pending_since)) => {
+                let nanos = pending_since.elapsed().as_nanos() as u64;
+                self.write_pending.fetch_add(nanos, Ordering::Relaxed);
+                *since = None;
+            }
+            _ => {}
+        }
+    }
+}
+
+pub struct ProtocolInfo {
+    protocols: Vec<&'static [u8]>,
+    connection: Arc<ConnectionShared>,
+    /// The slot the negotiated substream takes, a fresh one if `None`.
+    slot: Option<SubstreamSlot>,
+}
+
+impl ProtocolInfo {
+    fn new(protocols: Vec<&'static [u8]>, connection: Arc<ConnectionShared>) -> Self {
+        Self {
+            protocols,
+            connection,
+            slot: None,
+        }
+    }
+
+    fn with_slot(self, slot: Option<SubstreamSlot>) -> Self {
+        Self { slot, ..self }
+    }
+
+    /// Wraps the socket through [`Behaviour::wrap_substream`], if set, counts it towards the
+    /// open substreams, limits its writes by [`Behaviour::set_write_rate_limit`] and its reads
+    /// and writes by the bandwidth limit of the peer.
+    fn wrap(&mut self, socket: NegotiatedSubstream) -> Box<dyn Io> {
+        let wrap_substream = self.connection.wrap_substream.clone();
+        let wrap_substream = wrap_substream.read().expect("lock not to be poisoned");
+        let limit = self.connection.egress_limit.clone();
+        let budget = self.connection.bandwidth.clone();
+
+        match &*wrap_substream {
+            Some(wrap) => Box::new(LimitedSocket::new(
+                PeerSocket::new(self.counted(wrap(socket)), budget),
+                limit,
+            )),
+            None => Box::new(LimitedSocket::new(
+                PeerSocket::new(self.counted(socket), budget),
+                limit,
+            )),
+        }
+    }
+
+    fn counted<T>(&mut self, socket: T) -> CountedSocket<T> {
+        let open = &self.connection.open_substreams;
+
+        CountedSocket {
+            socket,
+            _slot: self
+                .slot
+                .take()
+                .unwrap_or_else(|| SubstreamSlot::new(open.clone())),
+        }
+    }
+}
+
+impl UpgradeInfo for ProtocolInfo {
+    type Info = &'static [u8];
+    type InfoIter = std::vec::IntoIter<&'static [u8]>;
+
+    fn protocol_info(&self) -> Self::InfoIter {
+        self.protocols.clone().into_iter()
+    }
+}
+
+pub struct InboundSubstream(
+    Box<dyn Io>,
+    &'static [u8],
+    Arc<ConnectionShared>,
+    Arc<TransportError>,
+    PartialFrame,
+    Arc<dyn Framing>,
+);
+
+pub struct OutboundSubstream(
+    Box<dyn Io>,
+    &'static [u8],
+    Arc<ConnectionShared>,
+    Arc<TransportError>,
+    PartialFrame,
+    Arc<dyn Framing>,
+);
+
+/// The substream types handed to protocol fns.
+trait Substream: AsyncRead + AsyncWrite + Unpin + Send + 'static {
+    fn direction(&self) -> Direction;
+
+    fn transport_error(&self) -> Arc<TransportError>;
+
+    fn negotiated_protocol(&self) -> &'static [u8];
+}
+
+/// The underlying socket of a substream.
+trait Io: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T> Io for T where T: AsyncRead + AsyncWrite + Unpin + Send {}
+
+macro_rules! impl_read_write {
+    ($t:ty, $direction:expr) => {
+        impl Drop for $t {
+            fn drop(&mut self) {
+                self.2.drain(&mut *self.0);
+                self.2.stash(&mut self.0, &self.5);
+            }
+        }
+
+        impl Substream for $t {
+            fn direction(&self) -> Direction {
+                $direction
+            }
+
+            fn transport_error(&self) -> Arc<TransportError> {
+                self.3.clone()
+            }
+
+            fn negotiated_protocol(&self) -> &'static [u8] {
+                self.1
+            }
+        }
+
+        impl AsyncRead for $t {
+            fn poll_read(
+                mut self: Pin<&mut Self>,
+                cx: &mut Context<'_>,
+                buf: &mut [u8],
+            ) -> Poll<io::Result<usize>> {
+                self.2.touch();
+                if self.2.finish_requested.load(Ordering::SeqCst) {
+                    return Pin::new(&mut self.0).poll_close(cx).map_ok(|()| 0);
+                }
+
+                let poll = Pin::new(&mut self.0).poll_read(cx, buf);
+                match &poll {
+                    Poll::Ready(Err(e)) => self.3.record(e),
+                    Poll::Ready(Ok(read)) => {
+                        self.2.bytes_read.fetch_add(*read as u64, Ordering::Relaxed);
+                        if *read > 0 {
+                            self.2.progress();
+                            self.3.recovered();
+                        }
+                    }
+                    Poll::Pending => {
+                        let mut wakers = self.2.read_wakers();
+                        if !wakers.iter().any(|waker| waker.will_wake(cx.waker())) {
+                            wakers.push(cx.waker().clone());
+                        }
+                    }
+                }
+                poll
+            }
+        }
+
+        impl AsyncWrite for $t {
+            fn poll_write(
+                mut self: Pin<&mut Self>,
+                cx: &mut Context<'_>,
+                buf: &[u8],
+            ) -> Poll<io::Result<usize>> {
+                self.2.touch();
+                let poll = Pin::new(&mut self.0).poll_write(cx, buf);
+                self.2.record_write(&poll);
+                match &poll {
+                    Poll::Ready(Err(e)) => self.3.record(e),
+                    Poll::Ready(Ok(written)) => {
+                        self.2
+                            .bytes_written
+                            .fetch_add(*written as u64, Ordering::Relaxed);
+                        if *written > 0 {
+                            self.2.progress();
+                            self.3.recovered();
+                        }
+                    }
+                    Poll::Pending => {}
+                }
+                poll
+            }
+
+            fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+                self.2.touch();
+                let poll = Pin::new(&mut self.0).poll_flush(cx);
+                self.2.record_write(&poll);
+                if let Poll::Ready(Err(e)) = &poll {
+                    self.3.record(e);
+                }
+                poll
+            }
+
+            fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+                self.2.touch();
+                let poll = Pin::new(&mut self.0).poll_close(cx);
+                if let Poll::Ready(Err(e)) = &poll {
+                    self.3.record(e);
+                }
+                poll
+            }
+        }
+
+        impl fmt::Debug for $t {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                let mut f = f.debug_tuple(stringify!($t));
+                f.field(&String::from_utf8_lossy(self.1));
+                if let Some(peer) = &self.2.peer {
+                    f.field(peer);
+                }
+                f.finish()
+            }
+        }
+
+        impl $t {
+            /// Wraps a socket that was negotiated for the given protocol outside of a `Swarm`.
+            ///
+            /// Useful together with [`driver::Driver`] to execute protocol fns on substreams of
+            /// a custom runtime.
+            pub fn new(
+                socket: impl AsyncRead + AsyncWrite + Unpin + Send + 'static,
+                protocol: &'static [u8],
+            ) -> Self {
+                Self(
+                    Box::new(socket),
+                    protocol,
+                    Arc::new(ConnectionShared::detached()),
+                    Arc::default(),
+                    PartialFrame::default(),
+                    Arc::new(LengthPrefixed),
+                )
+            }
+
+            /// The protocol that was negotiated for this substream.
+            pub fn protocol(&self) -> &'static [u8] {
+                self.1
+            }
+
+            /// The remote of this substream, `None` for substreams constructed with `new`.
+            ///
+            /// Read and write timeouts name the remote in their error message if it is known.
+            pub fn peer(&self) -> Option<PeerId> {
+                self.2.peer
+            }
+
+            /// Requests the connection to the remote to be closed once this protocol terminated.
+            ///
+            /// The behaviour reports the request as [`BehaviourOutEvent::DisconnectPeer`].
+            pub fn request_disconnect(&self) {
+                self.2.disconnect_requested.store(true, Ordering::SeqCst);
+            }
+
+            /// When any substream of this connection was last read from or written to.
+            pub fn last_activity(&self) -> Instant {
+                self.2.last_activity()
+            }
+
+            /// Opens another substream for `protocol` on this connection.
+            ///
+            /// The substream is not counted as a protocol of its own and neither the preface nor
+            /// the framing version are exchanged on it. Fails with [`Failure::ConnectionClosed`]
+            /// for substreams constructed outside of a [`Handler`].
+            pub fn open_outbound(
+                &self,
+                protocol: &'static [u8],
+            ) -> impl Future<Output = Result<OutboundSubstream, Failure>> + Send + 'static {
+                self.opener().open_outbound(protocol)
+            }
+
+            /// A handle for opening further substreams on this connection that outlives borrows
+            /// of the substream, see [`SubstreamOpener`].
+            pub fn opener(&self) -> SubstreamOpener {
+                SubstreamOpener(self.2.clone())
+            }
+
+            /// Waits for the remote to open another substream on this connection.
+            ///
+            /// The next inbound substream is handed to this protocol fn instead of starting a new
+            /// listener protocol, including ones that arrived before it was asked for. Fails with
+            /// [`Failure::ConnectionClosed`] for substreams constructed outside of a [`Handler`].
+            pub fn accept_inbound(
+                &self,
+            ) -> impl Future<Output = Result<InboundSubstream, Failure>> + Send + 'static {
+                let connection = self.2.clone();
+
+                async move { connection.accept_inbound().await }
+            }
+
+            /// Writes the message as a single frame.
+            ///
+            /// Fails with [`FramingError::Io`] of kind [`io::ErrorKind::TimedOut`] if writing
+            /// takes longer than [`Behaviour::set_write_timeout`] allows.
+            pub async fn write_message(&mut self, msg: &[u8]) -> Result<(), FramingError> {
+                let timeout = self.2.io_timeouts.write();
+
+                with_timeout(timeout, self.write_message_no_timeout(msg))
+                    .await
+                    .unwrap_or_else(|| Err(FramingError::Io(self.2.timed_out("writing message"))))
+            }
+
+            /// Like `write_message` but ignores [`Behaviour::set_write_timeout`].
+            pub async fn write_message_no_timeout(
+                &mut self,
+                msg: &[u8],
+            ) -> Result<(), FramingError> {
+                let framing = self.5.clone();
+                let res = async {
+                    framing.write_frame(self, msg).await?;
+                    self.flush().await
+                }
+                .await;
+                if let Err(e) = &res {
+                    self.3.record(e);
+                }
+                res.map_err(FramingError::from)
+            }
+
+            /// Reads a single frame of at most `max_size` bytes.
+            ///
+            /// Fails with [`FramingError::Io`] of kind [`io::ErrorKind::TimedOut`] if no message
+            /// arrives within [`Behaviour::set_read_timeout`].
+            pub async fn read_message(&mut self, max_size: usize) -> Result<Vec<u8>, FramingError> {
+                self.read_message_ranged(0, max_size).await
+            }
+
+            /// Like `read_message` but ignores [`Behaviour::set_read_timeout`].
+            pub async fn read_message_no_timeout(
+                &mut self,
+                max_size: usize,
+            ) -> Result<Vec<u8>, FramingError> {
+                self.read_message_ranged_no_timeout(0, max_size).await
+            }
+
+            /// Like `read_message` but also fails for messages shorter than `min_size`.
+            pub async fn read_message_ranged(
+                &mut self,
+                min_size: usize,
+                max_size: usize,
+            ) -> Result<Vec<u8>, FramingError> {
+                let timeout = self.2.io_timeouts.read();
+
+                with_timeout(
+                    timeout,
+                    self.read_message_ranged_no_timeout(min_size, max_size),
+                )
+                .await
+                .unwrap_or_else(|| Err(FramingError::Io(self.2.timed_out("reading message"))))
+            }
+
+            /// Like `read_message_ranged` but ignores [`Behaviour::set_read_timeout`].
+            pub async fn read_message_ranged_no_timeout(
+                &mut self,
+                min_size: usize,
+                max_size: usize,
+            ) -> Result<Vec<u8>, FramingError> {
+                let res = self.read_frame(min_size, max_size).await;
+                if let Some(e) = res.as_ref().err().and_then(FramingError::transport) {
+                    self.3.record(e);
+                }
+                res
+            }
+
+            /// Reads a single frame of at most `max_size` bytes in chunks of at most `chunk_size`
+            /// bytes, calling `sink` with each chunk in order.
+            ///
+            /// Unlike `read_message`, only one chunk is held in memory at a time, regardless of
+            /// how large the frame is. Returns the length of the frame. Fails with
+            /// [`FramingError::TooLarge`] before reading any of a larger frame and with
+            /// [`FramingError::Io`] of kind [`io::ErrorKind::TimedOut`] if the whole frame does not
+            /// arrive within [`Behaviour::set_read_timeout`].
+            pub async fn read_message_chunked(
+                &mut self,
+                max_size: usize,
+                chunk_size: usize,
+                mut sink: impl FnMut(&[u8]) + Send,
+            ) -> Result<usize, FramingError> {
+                let timeout = self.2.io_timeouts.read();
+                let framing = self.5.clone();
+                let res = with_timeout(timeout, async {
+                    let mut memory = FrameMemory::new(&self.2.memory);
+                    framing
+                        .read_frame_chunked(self, max_size, chunk_size, &mut memory, &mut sink)
+                        .await
+                })
+                .await
+                .unwrap_or_else(|| Err(FramingError::Io(self.2.timed_out("reading message"))));
+                if let Some(e) = res.as_ref().err().and_then(FramingError::transport) {
+                    self.3.record(e);
+                }
+                res
+            }
+
+            /// Reads a single frame of at most `max_size` bytes, giving up once `timeout` passed.
+            ///
+            /// Returns the bytes of the message that arrived so far, as far as the framing can
+            /// tell them apart, see [`Framing::partial_payload`], and whether the frame is
+            /// complete. An incomplete frame is not discarded: the substream remembers it and the
+            /// next call continues reading it where this one stopped, returning the frame from
+            /// its start again. Until the frame completed, other reads have to be avoided, they
+            /// would take its remaining bytes as a frame of their own. A call after the frame
+            /// completed starts reading the next one. Failing discards the incomplete frame.
+            /// Ignores [`Behaviour::set_read_timeout`].
+            pub async fn read_message_timeout_partial(
+                &mut self,
+                max_size: usize,
+                timeout: Duration,
+            ) -> Result<(Vec<u8>, bool), FramingError> {
+                let framing = self.5.clone();
+                let mut partial = mem::take(&mut self.4);
+                let res = with_timeout(Some(timeout), async {
+                    let mut memory = FrameMemory::new(&self.2.memory);
+                    framing
+                        .read_frame(&mut partial.replay(&mut *self), max_size, &mut memory)
+                        .await
+                })
+                .await;
+
+                match res {
+                    Some(Ok(frame)) => Ok((frame, true)),
+                    Some(Err(e)) => {
+                        if let FramingError::Io(e) | FramingError::ConnectionClosed(e) = &e {
+                            self.3.record(e);
+                        }
+                        Err(e)
+                    }
+                    None => {
+                        let payload = framing.partial_payload(&partial.received).to_vec();
+                        self.4 = partial;
+                        Ok((payload, false))
+                    }
+                }
+            }
+
+            /// Reads frames until the remote closes the substream, at most `max_total` bytes of
+            /// them in total.
+            ///
+            /// The remote has to close the substream in between frames, closing it in the middle
+            /// of one fails with [`FramingError::ConnectionClosed`]. Exceeding the limit fails with
+            /// [`FramingError::TooLarge`] for the total size. [`Behaviour::set_read_timeout`] applies
+            /// to each frame and to the remote closing the substream.
+            pub async fn read_to_end_framed(
+                &mut self,
+                max_total: usize,
+            ) -> Result<Vec<Vec<u8>>, FramingError> {
+                let mut frames = Vec::new();
+                let mut total = 0;
+
+                loop {
+                    let timeout = self.2.io_timeouts.read();
+                    let res = with_timeout(timeout, self.read_frame_or_eof(max_total - total))
+                        .await
+                        .unwrap_or_else(|| {
+                            Err(FramingError::Io(self.2.timed_out("reading message")))
+                        });
+                    if let Err(FramingError::Io(e)) | Err(FramingError::ConnectionClosed(e)) = &res
+                    {
+                        self.3.record(e);
+                    }
+
+                    match res {
+                        Ok(Some(frame)) => {
+                            total += frame.len();
+                            frames.push(frame);
+                        }
+                        Ok(None) => return Ok(frames),
+                        Err(FramingError::TooLarge { length, .. }) => {
+                            return Err(FramingError::TooLarge {
+                                length: total + length,
+                                max_size: max_total,
+                            })
+                        }
+                        Err(e) => return Err(e),
+                    }
+                }
+            }
+
+            /// Writes the message followed by its CRC-32 within a single frame.
+            #[cfg(feature = "crc")]
+            pub async fn write_message_crc32(&mut self, msg: &[u8]) -> Result<(), FramingError> {
+                self.write_message(&crc::append_checksum(msg)).await
+            }
+
+            /// Reads a frame written by `write_message_crc32` and verifies its checksum.
+            ///
+            /// `max_size` limits the message without its checksum.
+            #[cfg(feature = "crc")]
+            pub async fn read_message_crc32(
+                &mut self,
+                max_size: usize,
+            ) -> Result<Vec<u8>, ChecksumError> {
+                let frame = self
+                    .read_message_ranged(
+                        crc::CHECKSUM_LEN,
+                        max_size.saturating_add(crc::CHECKSUM_LEN),
+                    )
+                    .await?;
+
+                crc::verify_checksum(frame)
+            }
+
+            /// Writes the message prefixed with the next sequence number of `writer`.
+            ///
+            /// Returns the sequence number of the message.
+            pub async fn write_seq_message(
+                &mut self,
+                writer: &mut sequence::SequenceWriter,
+                msg: &[u8],
+            ) -> Result<u64, FramingError> {
+                let (sequence, frame) = writer.frame(msg);
+                self.write_message(&frame).await?;
+
+                Ok(sequence)
+            }
+
+            /// Reads a message written by `write_seq_message` and checks its sequence number
+            /// against `reader`.
+            ///
+            /// `max_size` limits the message without its sequence number.
+            pub async fn read_seq_message(
+                &mut self,
+                reader: &mut sequence::SequenceReader,
+                max_size: usize,
+            ) -> Result<(u64, Vec<u8>), sequence::SequenceError> {
+                let frame = self
+                    .read_message_ranged(
+                        sequence::SEQUENCE_LEN,
+                        max_size.saturating_add(sequence::SEQUENCE_LEN),
+                    )
+                    .await?;
+
+                reader.check(frame)
+            }
+
+            /// Writes a request carrying the given id within the same frame.
+            ///
+            /// Several requests can be in flight on one substream. The remote echoes the id with
+            /// `write_response`, so responses can be matched to their request regardless of the
+            /// order they arrive in. Allocating ids is up to the caller.
+            pub async fn write_request(
+                &mut self,
+                id: u32,
+                payload: &[u8],
+            ) -> Result<(), FramingError> {
+                self.write_message(&with_request_id(id, payload)).await
+            }
+
+            /// Reads a request written by `write_request` and returns its id.
+            ///
+            /// `max_size` limits the payload without its id.
+            pub async fn read_request(
+                &mut self,
+                max_size: usize,
+            ) -> Result<(u32, Vec<u8>), FramingError> {
+                let frame = self
+                    .read_message_ranged(REQUEST_ID_LEN, max_size.saturating_add(REQUEST_ID_LEN))
+                    .await?;
+
+                Ok(split_request_id(frame))
+            }
+
+            /// Writes the response to the request with the given id.
+            pub async fn write_response(
+                &mut self,
+                id: u32,
+                payload: &[u8],
+            ) -> Result<(), FramingError> {
+                self.write_message(&with_request_id(id, payload)).await
+            }
+
+            /// Reads a response written by `write_response` and returns the id of its request.
+            ///
+            /// `max_size` limits the payload without its id.
+            pub async fn read_response(
+                &mut self,
+                max_size: usize,
+            ) -> Result<(u32, Vec<u8>), FramingError> {
+                self.read_request(max_size).await
+            }
+
+            /// Sends everything read from `reader` in chunks of up to `chunk_size` bytes, to be
+            /// received with `recv_stream`.
+            ///
+            /// `on_progress` is called with the number of bytes sent so far after each chunk.
+            /// Returns the total number of bytes sent.
+            pub async fn send_stream(
+                &mut self,
+                mut reader: impl AsyncRead + Unpin,
+                chunk_size: usize,
+                mut on_progress: impl FnMut(u64),
+            ) -> Result<u64, FramingError> {
+                let mut frame = vec![0; 1 + chunk_size.clamp(1, transfer::MAX_CHUNK_SIZE)];
+                frame[0] = transfer::DATA;
+                let mut sent = 0;
+
+                loop {
+                    let read = reader.read(&mut frame[1..]).await?;
+                    if read == 0 {
+                        break;
+                    }
+
+                    self.write_message(&frame[..=read]).await?;
+                    sent += read as u64;
+                    on_progress(sent);
+                }
+                self.write_message(&transfer::end_frame(sent)).await?;
+
+                Ok(sent)
+            }
+
+            /// Receives a transfer sent with `send_stream` and writes it to `writer`.
+            ///
+            /// Fails once the transfer exceeds `max_size` bytes. `on_progress` is called with
+            /// the number of bytes received so far after each chunk. Returns the total number of
+            /// bytes received.
+            pub async fn recv_stream(
+                &mut self,
+                mut writer: impl AsyncWrite + Unpin,
+                max_size: u64,
+                mut on_progress: impl FnMut(u64),
+            ) -> Result<u64, transfer::TransferError> {
+                let mut received = 0;
+
+                loop {
+                    let frame = self.read_message(1 + transfer::MAX_CHUNK_SIZE).await?;
+                    match transfer::parse(&frame)? {
+                        transfer::Frame::Data(chunk) => {
+                            received += chunk.len() as u64;
+                            if received > max_size {
+                                return Err(transfer::TransferError::TooLarge { max_size });
+                            }
+
+                            writer
+                                .write_all(chunk)
+                                .await
+                                .map_err(transfer::TransferError::Write)?;
+                            on_progress(received);
+                        }
+                        transfer::Frame::End(announced) if announced != received => {
+                            return Err(transfer::TransferError::SizeMismatch {
+                                announced,
+                                received,
+                            });
+                        }
+                        transfer::Frame::End(_) => break,
+                    }
+                }
+                writer
+                    .flush()
+                    .await
+                    .map_err(transfer::TransferError::Write)?;
+
+                Ok(received)
+            }
+
+            /// Reads a token from the remote and checks it with `verifier` before handing the
+            /// substream back.
+            ///
+            /// `verifier` is called with `expected_peer` and the token. Protocol fns typically
+            /// capture the peer they are executed with, e.g. from
+            /// [`Behaviour::do_protocol_listener_for`].
+            pub async fn verify_token(
+                mut self,
+                expected_peer: &PeerId,
+                verifier: impl FnOnce(&PeerId, &[u8]) -> bool,
+            ) -> Result<Self, auth::AuthError> {
+                let token = self.read_message(auth::MAX_TOKEN_SIZE).await?;
+                if !verifier(expected_peer, &token) {
+                    return Err(auth::AuthError::Invalid(*expected_peer));
+                }
+
+                Ok(self)
+            }
+
+            /// Sends our agent string and returns the one of the remote, which has to call this
+            /// as well.
+            ///
+            /// Agent strings of the remote longer than [`agent::MAX_AGENT_SIZE`] are rejected.
+            pub async fn exchange_agent(
+                &mut self,
+                local_agent: &str,
+            ) -> Result<String, agent::AgentError> {
+                self.write_message(local_agent.as_bytes())
+                    .await
+                    .map_err(agent::AgentError::Write)?;
+                let remote_agent = self.read_message(agent::MAX_AGENT_SIZE).await?;
+
+                String::from_utf8(remote_agent).map_err(agent::AgentError::InvalidUtf8)
+            }
+
+            /// Advertises `max_size` to the remote, which has to call this as well, and returns
+            /// the substream with the smaller of both limits.
+            ///
+            /// Protocols call this before anything else in both directions and read all further
+            /// messages with the returned limit, see [`limit`].
+            pub async fn begin(
+                mut self,
+                max_size: usize,
+            ) -> Result<(Self, usize), limit::LimitError> {
+                self.write_message(&limit::encode(max_size))
+                    .await
+                    .map_err(limit::LimitError::Write)?;
+                let remote = self
+                    .read_message_ranged(limit::LIMIT_FRAME_SIZE, limit::LIMIT_FRAME_SIZE)
+                    .await?;
+
+                Ok((self, limit::effective(max_size, &remote)))
+            }
+
+            /// Wraps the substream to ping the remote every `interval` while reading, failing
+            /// reads once the remote stays silent for longer than `timeout` after a ping.
+            ///
+            /// The remote has to wrap its substream as well, see [`keepalive`].
+            #[cfg(feature = "keepalive")]
+            pub fn with_keepalive(
+                self,
+                interval: Duration,
+                timeout: Duration,
+            ) -> keepalive::Keepalive<Self> {
+                keepalive::Keepalive::new(self, interval, timeout)
+            }
+
+            async fn read_frame(
+                &mut self,
+                min_size: usize,
+                max_size: usize,
+            ) -> Result<Vec<u8>, FramingError> {
+                let mut memory = FrameMemory::new(&self.2.memory);
+                let framing = self.5.clone();
+                let message = framing.read_frame(self, max_size, &mut memory).await?;
+                if message.len() < min_size {
+                    return Err(FramingError::TooShort {
+                        length: message.len(),
+                        min_size,
+                    });
+                }
+
+                Ok(message)
+            }
+
+            /// Like `read_frame` but resolves to `None` if the remote closed the substream
+            /// instead of starting another frame.
+            async fn read_frame_or_eof(
+                &mut self,
+                max_size: usize,
+            ) -> Result<Option<Vec<u8>>, FramingError> {
+                let mut memory = FrameMemory::new(&self.2.memory);
+                let framing = self.5.clone();
+                framing.read_frame_or_eof(self, max_size, &mut memory).await
+            }
+        }
+    };
+}
+
+/// Panics if the protocol info does not follow the libp2p convention, see
+/// [`Behaviour::with_protocols`].
+fn validate_protocol_info(info: &[u8]) {
+    assert!(
+        info.starts_with(b"/"),
+        "protocol {:?} has to start with '/'",
+        String::from_utf8_lossy(info)
+    );
+    assert!(
+        !info.contains(&b'\n'),
+        "protocol {:?} may not contain a newline",
+        String::from_utf8_lossy(info)
+    );
+}
+
+/// Panics if any of the protocols is not valid or there are none, see
+/// [`Behaviour::with_protocols`].
+fn validate_protocols(protocols: impl IntoIterator<Item = &'static [u8]>) -> Vec<&'static [u8]> {
+    let protocols = protocols
+        .into_iter()
+        .inspect(|info| validate_protocol_info(info))
+        .collect::<Vec<_>>();
+    assert!(!protocols.is_empty(), "at least one protocol is required");
+
+    protocols
+}
+
+/// Resolves to `None` if the future does not complete within the timeout, if any.
+async fn with_timeout<T>(timeout: Option<Duration>, future: impl Future<Output = T>) -> Option<T> {
+    let timeout = match timeout {
+        Some(timeout) => timeout,
+        None => return Some(future.await),
+    };
+    libp2p::futures::pin_mut!(future);
+
+    match future::select(future, Delay::new(timeout)).await {
+        Either::Left((output, _)) => Some(output),
+        Either::Right(_) => None,
+    }
+}
+
+/// The number of bytes the id of a request or response takes up in a frame.
+const REQUEST_ID_LEN: usize = 4;
+
+fn with_request_id(id: u32, payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(REQUEST_ID_LEN + payload.len());
+    frame.extend_from_slice(&id.to_be_bytes());
+    frame.extend_from_slice(payload);
+
+    frame
+}
+
+fn split_request_id(mut frame: Vec<u8>) -> (u32, Vec<u8>) {
+    let mut id = [0; REQUEST_ID_LEN];
+    id.copy_from_slice(&frame[..REQUEST_ID_LEN]);
+    frame.drain(..REQUEST_ID_LEN);
+
+    (u32::from_be_bytes(id), frame)
+}
+
+/// Prepends the unsigned varint length of the message.
+///
+/// Writing both at once avoids sending the length prefix in a frame of its own on multiplexed
+/// substreams.
+fn length_prefixed(msg: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(msg.len() + 10);
+    let mut length = msg.len();
+    while length >= 0x80 {
+        frame.push(length as u8 | 0x80);
+        length >>= 7;
+    }
+    frame.push(length as u8);
+    frame.extend_from_slice(msg);
+
+    frame
+}
+
+/// The error of the message helpers of substreams, reading and writing alike.
+#[derive(Debug)]
+pub enum FramingError {
+    Io(io::Error),
+    /// The connection was reset or closed in the middle of the message, i.e. it ended early.
+    ConnectionClosed(io::Error),
+    /// The message is shorter than the allowed minimum.
+    TooShort {
+        length: usize,
+        min_size: usize,
+    },
+    /// The message is longer than the allowed maximum.
+    TooLarge {
+        length: usize,
+        max_size: usize,
+    },
+    /// Buffering the message would exceed
+    /// [`Behaviour::set_max_read_memory_per_connection`].
+    MemoryLimit {
+        wanted: usize,
+        available: usize,
+    },
+    /// The message could not be converted from or to a typed value, see [`typed::Message`].
+    Serialization(Box<dyn std::error::Error + Send + Sync>),
+}
+
+impl From<io::Error> for FramingError {
+    fn from(e: io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::BrokenPipe
+            | io::ErrorKind::UnexpectedEof => FramingError::ConnectionClosed(e),
+            _ => FramingError::Io(e),
+        }
+    }
+}
+
+impl fmt::Display for FramingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FramingError::Io(e) => write!(f, "failed to transfer message: {}", e),
+            FramingError::ConnectionClosed(_) => {
+                write!(f, "connection closed while transferring message")
+            }
+            FramingError::TooShort { length, min_size } => write!(
+                f,
+                "message of {} bytes is shorter than the minimum of {} bytes",
+                length, min_size
+            ),
+            FramingError::TooLarge { length, max_size } => write!(
+                f,
+                "message of {} bytes is longer than the maximum of {} bytes",
+                length, max_size
+            ),
+            FramingError::MemoryLimit { wanted, available } => write!(
+                f,
+                "buffering {} bytes exceeds the {} bytes of memory available to reads",
+                wanted, available
+            ),
+            FramingError::Serialization(e) => write!(f, "invalid message: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for FramingError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            FramingError::Io(e) | FramingError::ConnectionClosed(e) => Some(e),
+            FramingError::Serialization(e) => Some(&**e),
+            _ => None,
+        }
+    }
+}
+
+impl From<FramingError> for io::Error {
+    fn from(e: FramingError) -> Self {
+        match e {
+            FramingError::Io(e) | FramingError::ConnectionClosed(e) => e,
+            e => io::Error::new(io::ErrorKind::InvalidData, e),
+        }
+    }
+}
+
+impl FramingError {
+    /// The IO error the transport failed with, if any.
+    fn transport(&self) -> Option<&io::Error> {
+        match self {
+            FramingError::Io(e) | FramingError::ConnectionClosed(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl_read_write!(InboundSubstream, Direction::Inbound);
+impl_read_write!(OutboundSubstream, Direction::Outbound);
+
+impl InboundSubstream {
+    /// Reads a request, writes the reply computed from it and flushes the substream.
+    ///
+    /// Failing to write the reply is reported as [`FramingError::Io`] or
+    /// [`FramingError::ConnectionClosed`].
+    pub async fn respond(
+        &mut self,
+        max_request: usize,
+        reply: impl FnOnce(Vec<u8>) -> Vec<u8>,
+    ) -> Result<(), FramingError> {
+        self.respond_with(max_request, |request| future::ready(reply(request)))
+            .await
+    }
+
+    /// Like [`InboundSubstream::respond`] but computes the reply asynchronously.
+    pub async fn respond_with<F>(
+        &mut self,
+        max_request: usize,
+        reply: impl FnOnce(Vec<u8>) -> F,
+    ) -> Result<(), FramingError>
+    where
+        F: Future<Output = Vec<u8>>,
+    {
+        let request = self.read_message(max_request).await?;
+        let reply = reply(request).await;
+        self.write_message(&reply).await?;
+
+        Ok(())
+    }
+}
+
+impl InboundUpgrade<NegotiatedSubstream> for ProtocolInfo {
+    type Output = InboundSubstream;
+    type Error = Infallible;
+    type Future = Ready<Result<Self::Output, Self::Error>>;
+
+    fn upgrade_inbound(mut self, socket: NegotiatedSubstream, info: Self::Info) -> Self::Future {
+        let socket = self.wrap(socket);
+        let framing = self.connection.framing.get();
+
+        std::future::ready(Ok(InboundSubstream(
+            socket,
+            info,
+            self.connection,
+            Arc::default(),
+            PartialFrame::default(),
+            framing,
+        )))
+    }
+}
+
+impl OutboundUpgrade<NegotiatedSubstream> for ProtocolInfo {
+    type Output = OutboundSubstream;
+    type Error = Infallible;
+    type Future = Ready<Result<Self::Output, Self::Error>>;
+
+    fn upgrade_outbound(mut self, socket: NegotiatedSubstream, info: Self::Info) -> Self::Future {
+        let socket = self.wrap(socket);
+        let framing = self.connection.framing.get();
+
+        std::future::ready(Ok(OutboundSubstream(
+            socket,
+            info,
+            self.connection,
+            Arc::default(),
+            PartialFrame::default(),
+            framing,
+        )))
+    }
+}
+
+pub enum ProtocolInEvent<I, O, E> {
+    ExecuteInbound(InboundProtocolFn<I, E>),
+    /// Executes the protocol fn on an outbound substream, negotiated for the given protocol only
+    /// if any.
+    ExecuteOutbound(
+        OutboundProtocolFn<O, E>,
+        Option<&'static [u8]>,
+        SubstreamSlot,
+    ),
+    /// Executes the protocol fn on the given substream instead of an inbound one.
+    ExecuteInboundOn(InboundProtocolFn<I, E>, InboundSubstream),
+    /// Executes the protocol fn on the given substream instead of opening one.
+    ExecuteOutboundOn(OutboundProtocolFn<O, E>, OutboundSubstream),
+    KeepAliveUntil(Instant),
+    /// Sends the message as a single frame on a new outbound substream.
+    Notify(Vec<u8>, SubstreamSlot),
+    /// Drops the executing outbound protocol, if any.
+    CancelOutbound,
+    /// Drops the executing protocol, if any, regardless of its direction.
+    Ban,
+    /// Makes the substreams of the executing protocol, if any, close their write side and read
+    /// EOF.
+    Finish,
+    /// Drops the executing protocol, if any, and closes the connection.
+    Disconnect,
+    /// Replaces the protocols substreams are negotiated for from now on.
+    SetProtocols(Vec<&'static [u8]>),
+    /// Probes which of the advertised protocols the remote supports.
+    #[cfg(feature = "probe")]
+    Probe,
+}
+
+pub enum ProtocolOutEvent<I, O, E> {
+    Inbound(Result<I, ProtocolError<E>>),
+    Outbound(Result<O, ProtocolError<E>>),
+    InboundFailed(Failure),
+    OutboundFailed(Failure),
+    Rejected(&'static [u8]),
+    DisconnectRequested,
+    NotifyFailed(Failure),
+    /// The protocol is still executing.
+    Progress,
+    /// The handler terminated its protocol and is ready to execute the next one.
+    Idle,
+    /// The protocol started executing on a substream negotiated for the given protocol.
+    Executing(&'static [u8]),
+    /// The remote supports the given ones of the advertised protocols.
+    #[cfg(feature = "probe")]
+    Probed(Vec<&'static [u8]>),
+}
+
+impl<I, O, E> ProtocolOutEvent<I, O, E> {
+    /// Whether this event terminates the protocol the handler was executing.
+    fn is_terminal(&self) -> bool {
+        matches!(
+            self,
+            ProtocolOutEvent::Inbound(_)
+                | ProtocolOutEvent::Outbound(_)
+                | ProtocolOutEvent::InboundFailed(_)
+                | ProtocolOutEvent::OutboundFailed(_)
+        )
+    }
+}
+
+/// The reason a protocol terminated without being executed to completion.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Failure {
+    /// Negotiating the substream with the remote failed.
+    NegotiationFailed,
+    /// Negotiating the substream with the remote timed out.
+    NegotiationTimeout,
+    /// The connection closed before the protocol completed.
+    ConnectionClosed,
+    /// The connection was busy executing another protocol.
+    ConnectionBusy,
+    /// The remote did not send the expected preface.
+    PrefaceMismatch,
+    /// The remote does not support any framing version we support.
+    IncompatibleFraming,
+    /// Sending a notification over its substream failed.
+    SendFailed,
+    /// The protocol was cancelled through [`Behaviour::cancel_where`] or [`Behaviour::clear`].
+    Cancelled(CancelReason),
+    /// The protocol stayed queued for longer than [`Behaviour::set_max_queue_time`] allows.
+    QueueTimeout,
+    /// The peer is banned by the checker set through [`Behaviour::set_ban_checker`].
+    Banned,
+    /// The protocol did not complete within the timeout set through
+    /// [`Behaviour::set_protocol_timeout`].
+    Timeout,
+    /// The remote started a protocol at the same time and won the tie-break, see
+    /// [`Behaviour::set_resolve_simultaneous_open`].
+    SimultaneousOpen,
+    /// The protocol did not complete by the deadline given to
+    /// [`Behaviour::do_protocol_dialer_until`].
+    DeadlineExceeded,
+    /// The protocol went without reading or writing for longer than
+    /// [`Behaviour::set_stall_timeout`] allows.
+    Stalled,
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Failure::NegotiationFailed => write!(f, "failed to negotiate substream"),
+            Failure::NegotiationTimeout => write!(f, "timed out negotiating substream"),
+            Failure::ConnectionClosed => write!(f, "connection closed"),
+            Failure::ConnectionBusy => write!(f, "connection is busy with another protocol"),
+            Failure::PrefaceMismatch => write!(f, "remote did not send the expected preface"),
+            Failure::IncompatibleFraming => {
+                write!(f, "remote does not support a compatible framing")
+            }
+            Failure::SendFailed => write!(f, "failed to send notification"),
+            Failure::Cancelled(CancelReason::Unspecified) => write!(f, "protocol was cancelled"),
+            Failure::Cancelled(reason) => write!(f, "protocol was cancelled: {}", reason),
+            Failure::QueueTimeout => write!(f, "protocol was queued for too long"),
+            Failure::Banned => write!(f, "peer is banned"),
+            Failure::Timeout => write!(f, "protocol timed out"),
+            Failure::SimultaneousOpen => {
+                write!(f, "remote started a protocol at the same time")
+            }
+            Failure::DeadlineExceeded => write!(f, "protocol did not complete by its deadline"),
+            Failure::Stalled => write!(f, "protocol stopped making progress"),
+        }
+    }
+}
+
+/// Why a protocol was cancelled, see [`Behaviour::cancel_where_with_reason`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CancelReason {
+    /// No reason was given, as for all cancellations but those through
+    /// [`Behaviour::cancel_where_with_reason`].
+    Unspecified,
+    /// The application no longer needs the result.
+    UserInitiated,
+    /// Another protocol took over its work.
+    Superseded,
+    /// The application is shutting down.
+    Shutdown,
+    /// The application banned the peer.
+    Ban,
+    Other(&'static str),
+}
+
+impl fmt::Display for CancelReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CancelReason::Unspecified => write!(f, "unspecified"),
+            CancelReason::UserInitiated => write!(f, "user initiated"),
+            CancelReason::Superseded => write!(f, "superseded"),
+            CancelReason::Shutdown => write!(f, "shutdown"),
+            CancelReason::Ban => write!(f, "peer was banned"),
+            CancelReason::Other(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl std::error::Error for Failure {}
+
+/// The error a protocol fn terminated with.
+#[derive(Debug)]
+pub enum ProtocolError<E> {
+    /// The protocol fn returned an error of its own.
+    Application(E),
+    /// The protocol fn returned an error after reading from or writing to its substream failed.
+    ///
+    /// Protocol fns that transferred data on the substream again after the failure recovered
+    /// from it and report [`ProtocolError::Application`] instead.
+    Transport {
+        /// The IO error that failed the substream.
+        error: io::Error,
+        /// The error the protocol fn returned.
+        application: E,
+    },
+}
+
+impl<E> ProtocolError<E> {
+    /// Converts transport errors into the application's error type.
+    pub fn into_application(self) -> E
+    where
+        E: From<io::Error>,
+    {
+        match self {
+            ProtocolError::Application(e) => e,
+            ProtocolError::Transport { error, .. } => error.into(),
+        }
+    }
+}
+
+impl<E> Clone for ProtocolError<E>
+where
+    E: Clone,
+{
+    fn clone(&self) -> Self {
+        match self {
+            ProtocolError::Application(e) => ProtocolError::Application(e.clone()),
+            ProtocolError::Transport { error, application } => ProtocolError::Transport {
+                error: io::Error::new(error.kind(), error.to_string()),
+                application: application.clone(),
+            },
+        }
+    }
+}
+
+impl<E> fmt::Display for ProtocolError<E>
+where
+    E: fmt::Display,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProtocolError::Application(e) => write!(f, "{}", e),
+            ProtocolError::Transport { error, .. } => write!(f, "transport error: {}", error),
+        }
+    }
+}
+
+impl<E> std::error::Error for ProtocolError<E>
+where
+    E: std::error::Error + 'static,
+{
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ProtocolError::Application(e) => Some(e),
+            ProtocolError::Transport { error, .. } => Some(error),
+        }
+    }
+}
+
+impl<TInboundOut, TOutboundOut, TErr> Handler<TInboundOut, TOutboundOut, TErr>
+where
+    TInboundOut: Send + 'static,
+    TOutboundOut: Send + 'static,
+    TErr: Send + 'static,
+{
+    /// Hands an accepted inbound substream to the executing protocol, starts a protocol on it or
+    /// queues it until the handler is idle.
+    fn serve_inbound(&mut self, substream: InboundSubstream) {
+        if self.shared.is_banned(&self.peer)
+            || !self.shared.accepts_inbound(&self.peer, &self.point)
+        {
+            log::debug!(
+                target: LOG_TARGET,
+                "Dropping inbound substream, peer {} is not accepted.",
+                self.peer
+            );
+            self.pending_events
+                .push_back(ProtocolOutEvent::Rejected(substream.protocol()));
+            return;
+        }
+        if !self.shared.is_inbound_allowed(substream.protocol()) {
+            log::debug!(
+                target: LOG_TARGET,
+                "Dropping inbound substream, protocol {} is not allowed.",
+                String::from_utf8_lossy(substream.protocol())
+            );
+            self.pending_events
+                .push_back(ProtocolOutEvent::Rejected(substream.protocol()));
+            if let Some(frame) = self.shared.rejection_frame() {
+                let mut substream = substream;
+                self.rejections.push(
+                    async move {
+                        let res = async {
+                            substream.write_message(&frame).await?;
+                            substream.close().await
+                        };
+                        if let Err(e) = res.await {
+                            log::debug!(target: LOG_TARGET, "Failed to send rejection: {}", e);
+                        }
+                    }
+                    .boxed(),
+                );
+            }
+            return;
+        }
+
+        let mut substream = substream;
+        while let Some(waiting) = self.connection.substream_requests().inbound.pop_front() {
+            match waiting.send(substream) {
+                Ok(()) => {
+                    log::debug!(
+                        target: LOG_TARGET,
+                        "Inbound substream negotiated, handing it to the executing protocol."
+                    );
+                    return;
+                }
+                Err(returned) => substream = returned,
+            }
+        }
+
+        if matches!(self.state, ProtocolState::None) && self.shared.ready.load(Ordering::SeqCst) {
+            let handler = {
+                let handlers = self
+                    .inbound_handlers
+                    .read()
+                    .expect("lock not to be poisoned");
+                if handlers.is_empty() {
+                    None
+                } else {
+                    Some(handlers.get(substream.protocol()).cloned())
+                }
+            };
+            let protocol_fn: Option<InboundProtocolFn<TInboundOut, TErr>> = match handler {
+                Some(Some(handler)) => {
+                    log::debug!(
+                        target: LOG_TARGET,
+                        "Inbound substream negotiated, starting handler for protocol {}.",
+                        String::from_utf8_lossy(substream.protocol())
+                    );
+                    Some(Box::new(move |substream| {
+                        handler(substream)
+                            .map(|res| res.map(|out| (out, None)))
+                            .boxed()
+                    }))
+                }
+                handler => {
+                    let pooled = self
+                        .inbound_pool
+                        .read()
+                        .expect("lock not to be poisoned")
+                        .as_ref()
+                        .and_then(InboundPool::take);
+                    match pooled {
+                        Some(protocol_fn) => {
+                            log::debug!(
+                                target: LOG_TARGET,
+                                "Inbound substream negotiated, starting pooled handler."
+                            );
+                            Some(protocol_fn)
+                        }
+                        None if handler.is_some() => {
+                            log::debug!(
+                                target: LOG_TARGET,
+                                "Dropping inbound substream, no handler for protocol {}.",
+                                String::from_utf8_lossy(substream.protocol())
+                            );
+                            self.pending_events
+                                .push_back(ProtocolOutEvent::Rejected(substream.protocol()));
+                            return;
+                        }
+                        None => None,
+                    }
+                }
+            };
+            if let Some(protocol_fn) = protocol_fn {
+                self.reusable_inbound = None;
+                self.state = ProtocolState::Inbound(InboundProtocolState::Executing(
+                    self.start_execution(protocol_fn, substream, self.shared.handshake()),
+                ));
+                return;
+            }
+        }
+
+        match mem::replace(&mut self.state, ProtocolState::Poisoned) {
+            ProtocolState::None if !self.shared.ready.load(Ordering::SeqCst) => {
+                log::debug!(
+                    target: LOG_TARGET,
+                    "Dropping inbound substream, behaviour is not ready yet."
+                );
+                self.state = ProtocolState::None;
+                drop(substream);
+            }
+            ProtocolState::None => {
+                log::debug!(
+                    target: LOG_TARGET,
+                    "Inbound substream negotiated, waiting for protocol fn."
+                );
+
+                // The remote opened a fresh substream, so it is not going to reuse the old one.
+                self.reusable_inbound = None;
+                self.state = ProtocolState::Inbound(
+                    InboundProtocolState::GotSubstreamNeedFunction(substream),
+                );
+            }
+            ProtocolState::Inbound(InboundProtocolState::GotFunctionNeedSubstream(protocol_fn)) => {
+                log::debug!(
+                    target: LOG_TARGET,
+                    "Inbound substream negotiated, starting protocol direction=inbound."
+                );
+                self.state = ProtocolState::Inbound(InboundProtocolState::Executing(
+                    self.start_execution(protocol_fn, substream, self.shared.handshake()),
+                ));
+            }
+            ProtocolState::Outbound(_) if self.shared.yields_to(&self.peer) => {
+                log::debug!(
+                    target: LOG_TARGET,
+                    "Simultaneous open, yielding to the remote's protocol."
+                );
+                self.pending_outbound_request = None;
+                self.pending_events
+                    .push_back(ProtocolOutEvent::OutboundFailed(Failure::SimultaneousOpen));
+                self.on_protocol_terminated();
+
+                self.reusable_inbound = None;
+                self.state = ProtocolState::Inbound(
+                    InboundProtocolState::GotSubstreamNeedFunction(substream),
+                );
+            }
+            // The remote yields to our protocol and abandons its substream.
+            state @ ProtocolState::Outbound(_) if self.shared.resolves_simultaneous_open() => {
+                log::debug!(
+                    target: LOG_TARGET,
+                    "Simultaneous open, dropping inbound substream of the yielding remote."
+                );
+                self.state = state;
+                drop(substream);
+            }
+            state @ ProtocolState::Inbound(_) | state @ ProtocolState::Outbound(_) => {
+                log::debug!(target: LOG_TARGET, "Queueing inbound substream, handler is busy.");
+                self.state = state;
+                self.queued_inbound.push_back(substream);
+            }
+            ProtocolState::Poisoned => {
+                panic!("Illegal state, currently in transient state poisoned.");
+            }
+        }
+    }
+}
+
+impl<TInboundOut, TOutboundOut, TErr> ProtocolsHandler for Handler<TInboundOut, TOutboundOut, TErr>
+where
+    TInboundOut: Send + 'static,
+    TOutboundOut: Send + 'static,
+    TErr: Send + 'static,
+{
+    type InEvent = ProtocolInEvent<TInboundOut, TOutboundOut, TErr>;
+    type OutEvent = ProtocolOutEvent<TInboundOut, TOutboundOut, TErr>;
+    type Error = Infallible;
+    type InboundProtocol = ProtocolInfo;
+    type OutboundProtocol = ProtocolInfo;
+    type InboundOpenInfo = ();
+    type OutboundOpenInfo = OutboundOpenInfo;
+
+    fn listen_protocol(&self) -> SubstreamProtocol<Self::InboundProtocol, Self::InboundOpenInfo> {
+        SubstreamProtocol::new(self.protocol_info(None), ())
+    }
+
+    fn inject_fully_negotiated_inbound(
+        &mut self,
+        substream: InboundSubstream,
+        _: Self::InboundOpenInfo,
+    ) {
+        if !self.deferred_inbound.is_empty() || self.shared.open_substreams.is_exceeded() {
+            log::debug!(
+                target: LOG_TARGET,
+                "Deferring inbound substream from peer {}, too many substreams are open.",
+                self.peer
+            );
+            let slot = DeferredSlot::new(self.shared.open_substreams.clone());
+            self.deferred_inbound.push_back((substream, slot));
+            return;
+        }
+
+        self.serve_inbound(substream);
+    }
+
+    fn inject_fully_negotiated_outbound(
+        &mut self,
+        substream: OutboundSubstream,
+        info: Self::OutboundOpenInfo,
+    ) {
+        let info = match info {
+            OutboundOpenInfo::Additional(AdditionalSubstream(sender)) => {
+                log::debug!(
+                    target: LOG_TARGET,
+                    "Outbound substream negotiated, handing it to the executing protocol."
+                );
+                let _ = sender.send(Ok(substream));
+                return;
+            }
+            info => info,
+        };
+
+        #[cfg(feature = "probe")]
+        if let OutboundOpenInfo::Probe(id, protocol) = info {
+            log::debug!(
+                target: LOG_TARGET,
+                "Remote supports probed protocol {}.",
+                String::from_utf8_lossy(protocol)
+            );
+            drop(substream);
+            if let Some(supported) = self.probes.record(id, protocol, true) {
+                self.pending_events
+                    .push_back(ProtocolOutEvent::Probed(supported));
+            }
+            return;
+        }
+
+        if let OutboundOpenInfo::Notification(message) = info {
+            log::debug!(target: LOG_TARGET, "Outbound substream negotiated, sending notification.");
+            self.requested_notifications -= 1;
+            self.notifications.push(execute(
+                Box::new(move |mut substream: OutboundSubstream| {
+                    async move {
+                        substream.write_message(&message).await?;
+                        substream.close().await?;
+
+                        Ok(((), None))
+                    }
+                    .boxed()
+                }),
+                substream,
+                self.shared.handshake(),
+            ));
+            return;
+        }
+
+        if let OutboundOpenInfo::Protocol(request) = info {
+            if self.pending_outbound_request != Some(request) {
+                log::debug!(
+                    target: LOG_TARGET,
+                    "Dropping outbound substream, its protocol was cancelled."
+                );
+                return;
+            }
+            self.pending_outbound_request = None;
+        }
+
+        match mem::replace(&mut self.state, ProtocolState::Poisoned) {
+            ProtocolState::Outbound(OutboundProtocolState::GotFunctionRequestedSubstream(
+                protocol_fn,
+            )) => {
+                log::debug!(
+                    target: LOG_TARGET,
+                    "Outbound substream negotiated, starting protocol direction=outbound."
+                );
+                self.state = ProtocolState::Outbound(OutboundProtocolState::Executing(
+                    self.start_execution(protocol_fn, substream, self.shared.handshake()),
+                ));
+            }
+            ProtocolState::None
+            | ProtocolState::Outbound(OutboundProtocolState::GotFunctionNeedSubstream(_)) => {
+                panic!("Illegal state, receiving substream means it was requested.");
+            }
+            ProtocolState::Outbound(_) => {
+                panic!("Illegal state, substream is already present.");
+            }
+            ProtocolState::Inbound(_) => {
+                panic!("Failed to process outbound substream in inbound protocol.");
+            }
+            ProtocolState::Poisoned => {
+                panic!("Illegal state, currently in transient state poisoned.");
+            }
+        }
+    }
+
+    fn inject_event(&mut self, event: Self::InEvent) {
+        match event {
+            ProtocolInEvent::KeepAliveUntil(deadline) => {
+                self.keep_alive_until = self.keep_alive_until.max(Some(deadline));
+            }
+            ProtocolInEvent::Notify(message, slot) => {
+                self.pending_notifications.push_back((message, slot));
+            }
+            #[cfg(feature = "probe")]
+            ProtocolInEvent::Probe => {
+                if let Some(supported) = self.probes.start(self.protocols.clone()) {
+                    self.pending_events
+                        .push_back(ProtocolOutEvent::Probed(supported));
+                }
+            }
+            ProtocolInEvent::CancelOutbound => match &self.state {
+                ProtocolState::Outbound(_) => {
+                    log::debug!(target: LOG_TARGET, "Cancelling protocol direction=outbound.");
+                    self.pending_outbound_request = None;
+                    self.pending_events
+                        .push_back(ProtocolOutEvent::OutboundFailed(Failure::Cancelled(
+                            CancelReason::Unspecified,
+                        )));
+                    self.on_protocol_cancelled();
+                }
+                _ => {
+                    log::debug!(target: LOG_TARGET, "Ignoring cancellation, protocol terminated.");
+                }
+            },
+            ProtocolInEvent::Ban => {
+                let failed = match &self.state {
+                    ProtocolState::Inbound(_) => ProtocolOutEvent::InboundFailed(Failure::Banned),
+                    ProtocolState::Outbound(_) => {
+                        self.pending_outbound_request = None;
+                        ProtocolOutEvent::OutboundFailed(Failure::Banned)
+                    }
+                    _ => {
+                        log::debug!(target: LOG_TARGET, "Ignoring ban, protocol terminated.");
+                        return;
+                    }
+                };
+                log::debug!(target: LOG_TARGET, "Dropping protocol, peer is banned.");
+                self.pending_events.push_back(failed);
+                self.on_protocol_terminated();
+            }
+            ProtocolInEvent::SetProtocols(protocols) => {
+                log::debug!(target: LOG_TARGET, "Replacing advertised protocols.");
+                self.protocols = protocols;
+            }
+            ProtocolInEvent::Disconnect => {
+                log::debug!(target: LOG_TARGET, "Closing connection.");
+                self.disconnecting = true;
+                let failed = match &self.state {
+                    ProtocolState::Inbound(_) => ProtocolOutEvent::InboundFailed(
+                        Failure::Cancelled(CancelReason::Unspecified),
+                    ),
+                    ProtocolState::Outbound(_) => {
+                        self.pending_outbound_request = None;
+                        ProtocolOutEvent::OutboundFailed(Failure::Cancelled(
+                            CancelReason::Unspecified,
+                        ))
+                    }
+                    _ => return,
+                };
+                self.pending_events.push_back(failed);
+                self.on_protocol_cancelled();
+            }
+            ProtocolInEvent::Finish => {
+                if !matches!(self.state, ProtocolState::None) {
+                    log::debug!(target: LOG_TARGET, "Finishing protocol.");
+                    self.connection.request_finish();
+                } else {
+                    log::debug!(target: LOG_TARGET, "Ignoring finish, protocol terminated.");
+                }
+            }
+            ProtocolInEvent::ExecuteInbound(protocol_fn) => {
+                match mem::replace(&mut self.state, ProtocolState::Poisoned) {
+                    ProtocolState::None => {
+                        self.state = ProtocolState::Inbound(match self.reusable_inbound.take() {
+                            Some(substream) => {
+                                log::debug!(
+                                    target: LOG_TARGET,
+                                    "Reusing inbound substream, starting protocol direction=inbound."
+                                );
+                                InboundProtocolState::Executing(self.start_execution(
+                                    protocol_fn,
+                                    substream,
+                                    Handshake::default(),
+                                ))
+                            }
+                            None => {
+                                log::debug!(
+                                    target: LOG_TARGET,
+                                    "Got protocol fn, waiting for inbound substream."
+                                );
+                                InboundProtocolState::GotFunctionNeedSubstream(protocol_fn)
+                            }
+                        });
+                    }
+                    ProtocolState::Inbound(InboundProtocolState::GotSubstreamNeedFunction(
+                        substream,
+                    )) => {
+                        log::debug!(
+                            target: LOG_TARGET,
+                            "Got protocol fn, starting protocol direction=inbound."
+                        );
+                        self.state = ProtocolState::Inbound(InboundProtocolState::Executing(
+                            self.start_execution(protocol_fn, substream, self.shared.handshake()),
+                        ));
+                    }
+                    state @ ProtocolState::Inbound(_) | state @ ProtocolState::Outbound(_) => {
+                        log::debug!(
+                            target: LOG_TARGET,
+                            "Rejecting protocol fn, handler is busy direction=inbound."
+                        );
+                        self.state = state;
+                        self.pending_events
+                            .push_back(ProtocolOutEvent::InboundFailed(Failure::ConnectionBusy));
+                    }
+                    ProtocolState::Poisoned => {
+                        panic!("Illegal state, currently in transient state poisoned.");
+                    }
+                }
+            }
+            ProtocolInEvent::ExecuteOutbound(protocol_fn, protocol, slot) => {
+                match mem::replace(&mut self.state, ProtocolState::Poisoned) {
+                    ProtocolState::None => {
+                        // A reused substream must have been negotiated for the requested protocol.
+                        let reusable = self.reusable_outbound.take().filter(|substream| {
+                            protocol.is_none() || protocol == Some(substream.protocol())
+                        });
+                        self.outbound_protocol = protocol;
+
+                        self.state = ProtocolState::Outbound(match reusable {
+                            Some(substream) => {
+                                log::debug!(
+                                    target: LOG_TARGET,
+                                    "Reusing outbound substream, starting protocol direction=outbound."
+                                );
+                                OutboundProtocolState::Executing(self.start_execution(
+                                    protocol_fn,
+                                    substream,
+                                    Handshake::default(),
+                                ))
+                            }
+                            None => {
+                                self.outbound_slot = Some(slot);
+                                OutboundProtocolState::GotFunctionNeedSubstream(protocol_fn)
+                            }
+                        });
+                    }
+                    state @ ProtocolState::Inbound(_) | state @ ProtocolState::Outbound(_) => {
+                        log::debug!(
+                            target: LOG_TARGET,
+                            "Rejecting protocol fn, handler is busy direction=outbound."
+                        );
+                        self.state = state;
+                        self.pending_events
+                            .push_back(ProtocolOutEvent::OutboundFailed(Failure::ConnectionBusy));
+                    }
+                    ProtocolState::Poisoned => {
+                        panic!("Illegal state, currently in transient state poisoned.");
+                    }
+                }
+            }
+            ProtocolInEvent::ExecuteInboundOn(protocol_fn, substream) => match self.state {
+                ProtocolState::None => {
+                    log::debug!(
+                        target: LOG_TARGET,
+                        "Got protocol fn and substream, starting protocol direction=inbound."
+                    );
+                    self.state = ProtocolState::Inbound(InboundProtocolState::Executing(
+                        self.start_execution(protocol_fn, substream, self.shared.handshake()),
+                    ));
+                }
+                _ => {
+                    log::debug!(
+                        target: LOG_TARGET,
+                        "Rejecting protocol fn, handler is busy direction=inbound."
+                    );
+                    self.pending_events
+                        .push_back(ProtocolOutEvent::InboundFailed(Failure::ConnectionBusy));
+                }
+            },
+            ProtocolInEvent::ExecuteOutboundOn(protocol_fn, substream) => match self.state {
+                ProtocolState::None => {
+                    log::debug!(
+                        target: LOG_TARGET,
+                        "Got protocol fn and substream, starting protocol direction=outbound."
+                    );
+                    self.state = ProtocolState::Outbound(OutboundProtocolState::Executing(
+                        self.start_execution(protocol_fn, substream, self.shared.handshake()),
+                    ));
+                }
+                _ => {
+                    log::debug!(
+                        target: LOG_TARGET,
+                        "Rejecting protocol fn, handler is busy direction=outbound."
+                    );
+                    self.pending_events
+                        .push_back(ProtocolOutEvent::OutboundFailed(Failure::ConnectionBusy));
+                }
+            },
+        }
+    }
+
+    fn inject_dial_upgrade_error(
+        &mut self,
+        info: Self::OutboundOpenInfo,
+        err: ProtocolsHandlerUpgrErr<<Self::OutboundProtocol as OutboundUpgradeSend>::Error>,
+    ) {
+        let failure = match err {
+            ProtocolsHandlerUpgrErr::Timeout => Failure::NegotiationTimeout,
+            _ => Failure::NegotiationFailed,
+        };
+
+        match info {
+            OutboundOpenInfo::Notification(_) => {
+                log::error!(target: LOG_TARGET, "Failed to upgrade: {}", err);
+                self.requested_notifications -= 1;
+                self.pending_events
+                    .push_back(ProtocolOutEvent::NotifyFailed(failure));
+                return;
+            }
+            OutboundOpenInfo::Protocol(request)
+                if self.pending_outbound_request != Some(request) =>
+            {
+                log::debug!(
+                    target: LOG_TARGET,
+                    "Failed to upgrade substream of a cancelled protocol: {}",
+                    err
+                );
+                return;
+            }
+            OutboundOpenInfo::Protocol(_) => {
+                log::error!(target: LOG_TARGET, "Failed to upgrade: {}", err);
+                self.pending_outbound_request = None;
+            }
+            OutboundOpenInfo::Additional(AdditionalSubstream(sender)) => {
+                log::error!(target: LOG_TARGET, "Failed to upgrade: {}", err);
+                let _ = sender.send(Err(failure));
+                return;
+            }
+            #[cfg(feature = "probe")]
+            OutboundOpenInfo::Probe(id, protocol) => {
+                log::debug!(
+                    target: LOG_TARGET,
+                    "Peer does not support probed protocol {}: {}",
+                    String::from_utf8_lossy(protocol),
+                    err
+                );
+                if let Some(supported) = self.probes.record(id, protocol, false) {
+                    self.pending_events
+                        .push_back(ProtocolOutEvent::Probed(supported));
+                }
+                return;
+            }
+        }
+
+        match mem::replace(&mut self.state, ProtocolState::Poisoned) {
+            ProtocolState::Outbound(OutboundProtocolState::GotFunctionRequestedSubstream(_)) => {
+                self.pending_events
+                    .push_back(ProtocolOutEvent::OutboundFailed(failure));
+                self.on_protocol_terminated();
+            }
+            ProtocolState::Poisoned => {
+                panic!("Illegal state, currently in transient state poisoned.");
+            }
+            other => {
+                self.state = other;
+            }
+        }
+    }
+
+    fn connection_keep_alive(&self) -> KeepAlive {
+        if self.disconnecting {
+            return KeepAlive::No;
+        }
+
+        if !matches!(self.state, ProtocolState::None)
+            || !self.queued_inbound.is_empty()
+            || !self.deferred_inbound.is_empty()
+            || self.has_notifications()
+            || !self.rejections.is_empty()
+        {
+            return KeepAlive::Yes;
+        }
+        #[cfg(feature = "probe")]
+        if !self.probes.is_empty() {
+            return KeepAlive::Yes;
+        }
+
+        let last_activity = self.connection.last_activity();
+        let keep_alive =
+            if self.executions > 0 && self.shared.keep_alive_policy() == KeepAlivePolicy::Never {
+                KeepAlive::No
+            } else {
+                let idle_deadline = self
+                    .shared
+                    .idle_timeout()
+                    .map(|timeout| last_activity + timeout);
+
+                match self.keep_alive_until.max(idle_deadline) {
+                    Some(deadline) => KeepAlive::Until(deadline),
+                    None => KeepAlive::Yes,
+                }
+            };
+
+        self.shared.keep_alive(
+            &self.peer,
+            &KeepAliveState {
+                executions: self.executions,
+                last_activity,
+                keep_alive,
+            },
+        )
+    }
+
+    #[allow(clippy::type_complexity)]
+    fn poll(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<
+        ProtocolsHandlerEvent<
+            Self::OutboundProtocol,
+            Self::OutboundOpenInfo,
+            Self::OutEvent,
+            Self::Error,
+        >,
+    > {
+        if !self.deferred_inbound.is_empty() {
+            let open_substreams = self.shared.open_substreams.clone();
+            open_substreams.register(cx.waker());
+            while !open_substreams.is_full() {
+                // Dropping the deferred slot lets the substream count towards the limit.
+                let (substream, _) = match self.deferred_inbound.pop_front() {
+                    Some(deferred) => deferred,
+                    None => break,
+                };
+                log::debug!(target: LOG_TARGET, "Accepting deferred inbound substream.");
+                self.serve_inbound(substream);
+            }
+        }
+
+        let is_waiting_for_substream = matches!(
+            self.state,
+            ProtocolState::None
+                | ProtocolState::Inbound(InboundProtocolState::GotFunctionNeedSubstream(_))
+        );
+        if is_waiting_for_substream && !self.disconnecting {
+            if let Some(substream) = self.queued_inbound.pop_front() {
+                log::debug!(target: LOG_TARGET, "Handler is idle, serving queued inbound substream.");
+                self.serve_inbound(substream);
+            }
+        }
+
+        if let Some(event) = self.pending_events.pop_front() {
+            return Poll::Ready(ProtocolsHandlerEvent::Custom(event));
+        }
+
+        if let Some((message, slot)) = self.pending_notifications.pop_front() {
+            log::debug!(target: LOG_TARGET, "Requesting outbound substream for notification.");
+            self.requested_notifications += 1;
+            return Poll::Ready(ProtocolsHandlerEvent::OutboundSubstreamRequest {
+                protocol: SubstreamProtocol::new(
+                    self.outbound_protocol_info(None).with_slot(Some(slot)),
+                    OutboundOpenInfo::Notification(message),
+                ),
+            });
+        }
+
+        #[cfg(feature = "probe")]
+        if let Some((id, protocol)) = self.probes.next_request() {
+            log::debug!(
+                target: LOG_TARGET,
+                "Requesting outbound substream to probe protocol {}.",
+                String::from_utf8_lossy(protocol)
+            );
+            return Poll::Ready(ProtocolsHandlerEvent::OutboundSubstreamRequest {
+                protocol: SubstreamProtocol::new(
+                    self.protocol_info(Some(protocol)),
+                    OutboundOpenInfo::Probe(id, protocol),
+                ),
+            });
+        }
+
+        {
+            let mut requests = self.connection.substream_requests();
+            requests.waker = Some(cx.waker().clone());
+
+            // Substreams that arrived before the protocol fn asked for them were queued.
+            while !self.queued_inbound.is_empty() {
+                let waiting = match requests.inbound.pop_front() {
+                    Some(waiting) => waiting,
+                    None => break,
+                };
+                let substream = self
+                    .queued_inbound
+                    .pop_front()
+                    .expect("queue not to be empty");
+                match waiting.send(substream) {
+                    Ok(()) => log::debug!(
+                        target: LOG_TARGET,
+                        "Handing queued inbound substream to the executing protocol."
+                    ),
+                    Err(substream) => self.queued_inbound.push_front(substream),
+                }
+            }
+
+            while let Some((protocol, request)) = requests.outbound.pop_front() {
+                if request.0.is_canceled() {
+                    continue;
+                }
+
+                log::debug!(
+                    target: LOG_TARGET,
+                    "Requesting additional outbound substream."
+                );
+                return Poll::Ready(ProtocolsHandlerEvent::OutboundSubstreamRequest {
+                    protocol: SubstreamProtocol::new(
+                        ProtocolInfo::new(vec![protocol], self.connection.clone()),
+                        OutboundOpenInfo::Additional(request),
+                    ),
+                });
+            }
+        }
+
+        if let (true, Some(interval)) = (self.is_executing(), self.shared.progress_interval()) {
+            let timer = self
+                .progress_timer
+                .get_or_insert_with(|| Delay::new(interval));
+
+            if timer.poll_unpin(cx).is_ready() {
+                timer.reset(interval);
+                return Poll::Ready(ProtocolsHandlerEvent::Custom(ProtocolOutEvent::Progress));
+            }
+        }
+
+        while let Poll::Ready(Some(())) = self.rejections.poll_next_unpin(cx) {}
+
+        loop {
+            let failure = match self.notifications.poll_next_unpin(cx) {
+                Poll::Ready(Some(Ok(Ok(_)))) => {
+                    log::debug!(target: LOG_TARGET, "Notification sent.");
+                    continue;
+                }
+                Poll::Ready(Some(Ok(Err(e)))) => {
+                    log::debug!(target: LOG_TARGET, "Failed to send notification: {}", e);
+                    Failure::SendFailed
+                }
+                Poll::Ready(Some(Err(failure))) => failure,
+                Poll::Ready(None) | Poll::Pending => break,
+            };
+
+            return Poll::Ready(ProtocolsHandlerEvent::Custom(
+                ProtocolOutEvent::NotifyFailed(failure),
+            ));
+        }
+
+        match mem::replace(&mut self.state, ProtocolState::Poisoned) {
+            ProtocolState::Inbound(InboundProtocolState::Executing(mut protocol)) => match protocol
+                .poll_unpin(cx)
+            {
+                Poll::Ready(Err(failure)) => {
+                    log::debug!(
+                        target: LOG_TARGET,
+                        "Protocol failed direction=inbound failure={}.",
+                        failure
+                    );
+                    self.on_protocol_terminated();
+                    Poll::Ready(ProtocolsHandlerEvent::Custom(
+                        ProtocolOutEvent::InboundFailed(failure),
+                    ))
+                }
+                Poll::Ready(Ok(res)) => {
+                    log::debug!(
+                        target: LOG_TARGET,
+                        "Protocol completed direction=inbound success={}.",
+                        res.is_ok()
+                    );
+                    self.on_protocol_terminated();
+                    let res = res.map(|(out, substream)| {
+                        self.reusable_inbound = substream;
+                        out
+                    });
+                    Poll::Ready(ProtocolsHandlerEvent::Custom(ProtocolOutEvent::Inbound(
+                        res,
+                    )))
+                }
+                Poll::Pending => {
+                    self.state = ProtocolState::Inbound(InboundProtocolState::Executing(protocol));
+                    Poll::Pending
+                }
+            },
+            ProtocolState::Outbound(OutboundProtocolState::Executing(mut protocol)) => {
+                match protocol.poll_unpin(cx) {
+                    Poll::Ready(Err(failure)) => {
+                        log::debug!(
+                            target: LOG_TARGET,
+                            "Protocol failed direction=outbound failure={}.",
+                            failure
+                        );
+                        self.on_protocol_terminated();
+                        Poll::Ready(ProtocolsHandlerEvent::Custom(
+                            ProtocolOutEvent::OutboundFailed(failure),
+                        ))
+                    }
+                    Poll::Ready(Ok(res)) => {
+                        log::debug!(
+                            target: LOG_TARGET,
+                            "Protocol completed direction=outbound success={}.",
+                            res.is_ok()
+                        );
+                        self.on_protocol_terminated();
+                        let res = res.map(|(out, substream)| {
+                            self.reusable_outbound = substream;
+                            out
+                        });
+                        Poll::Ready(ProtocolsHandlerEvent::Custom(ProtocolOutEvent::Outbound(
+                            res,
+                        )))
+                    }
+                    Poll::Pending => {
+                        self.state =
+                            ProtocolState::Outbound(OutboundProtocolState::Executing(protocol));
+                        Poll::Pending
+                    }
+                }
+            }
+            ProtocolState::Outbound(OutboundProtocolState::GotFunctionNeedSubstream(protocol)) => {
+                log::debug!(target: LOG_TARGET, "Requesting outbound substream.");
+                self.state = ProtocolState::Outbound(
+                    OutboundProtocolState::GotFunctionRequestedSubstream(protocol),
+                );
+                self.outbound_requests += 1;
+                self.pending_outbound_request = Some(self.outbound_requests);
+                Poll::Ready(ProtocolsHandlerEvent::OutboundSubstreamRequest {
+                    protocol: SubstreamProtocol::new(
+                        self.outbound_protocol_info(self.outbound_protocol)
+                            .with_slot(self.outbound_slot.take()),
+                        OutboundOpenInfo::Protocol(self.outbound_requests),
+                    ),
+                })
+            }
+            ProtocolState::Poisoned => {
+                unreachable!("Protocol is poisoned (transient state)")
+            }
+            other => {
+                self.state = other;
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// A protocol waiting for an idle connection.
+struct QueuedProtocol<I, O, E> {
+    peer: PeerId,
+    /// The number of the protocol in the order protocols were started.
+    issued: u64,
+    /// The protocol it counts against for concurrency limits.
+    kind: Option<&'static [u8]>,
+    tag: Option<Tag>,
+    /// The session whose connection it has to execute on, if any.
+    session: Option<SessionId>,
+    execution: QueuedExecution<I, O, E>,
+    queued_at: Instant,
+}
+
+/// The protocol fn of a queued protocol, by direction.
+enum QueuedExecution<I, O, E> {
+    Inbound(InboundProtocolFn<I, E>),
+    /// Executes on the given substream instead of an inbound one.
+    InboundOn(InboundProtocolFn<I, E>, InboundSubstream),
+    /// Opens a substream, negotiated for the given protocol only if any.
+    Outbound(OutboundProtocolFn<O, E>, Option<&'static [u8]>),
+    /// Executes on the given substream instead of opening one.
+    OutboundOn(OutboundProtocolFn<O, E>, OutboundSubstream),
+}
+
+impl<I, O, E> QueuedExecution<I, O, E> {
+    fn direction(&self) -> Direction {
+        match self {
+            QueuedExecution::Inbound(_) | QueuedExecution::InboundOn(..) => Direction::Inbound,
+            QueuedExecution::Outbound(..) | QueuedExecution::OutboundOn(..) => Direction::Outbound,
+        }
+    }
+
+    /// Whether dispatching it opens a new substream.
+    fn opens_substream(&self) -> bool {
+        matches!(self, QueuedExecution::Outbound(..))
+    }
+
+    /// The event handing the protocol fn to a handler, taking a slot for the substream it opens.
+    fn into_event(self, open_substreams: &Arc<OpenSubstreams>) -> ProtocolInEvent<I, O, E> {
+        match self {
+            QueuedExecution::Inbound(protocol_fn) => ProtocolInEvent::ExecuteInbound(protocol_fn),
+            QueuedExecution::InboundOn(protocol_fn, substream) => {
+                ProtocolInEvent::ExecuteInboundOn(protocol_fn, substream)
+            }
+            QueuedExecution::Outbound(protocol_fn, protocol) => {
+                let slot = SubstreamSlot::new(open_substreams.clone());
+
+                ProtocolInEvent::ExecuteOutbound(protocol_fn, protocol, slot)
+            }
+            QueuedExecution::OutboundOn(protocol_fn, substream) => {
+                ProtocolInEvent::ExecuteOutboundOn(protocol_fn, substream)
+            }
+        }
+    }
+}
+
+impl<I, O, E> QueuedProtocol<I, O, E> {
+    fn failed(self, failure: Failure) -> BehaviourOutEvent<I, O, E> {
+        match self.direction() {
+            Direction::Inbound => BehaviourOutEvent::InboundFailed(self.peer, failure),
+            Direction::Outbound => BehaviourOutEvent::OutboundFailed(self.peer, failure, self.tag),
+        }
+    }
+
+    fn direction(&self) -> Direction {
+        self.execution.direction()
+    }
+}
+
+/// The bytes a protocol transferred while executing, see [`Behaviour::protocol_throughput`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Throughput {
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+    /// The time from handing the substreams to the protocol fn until it terminated.
+    pub duration: Duration,
+}
+
+impl Throughput {
+    /// The bytes read per second, 0 for protocols that terminated within one tick of the clock.
+    pub fn read_per_sec(&self) -> f64 {
+        per_sec(self.bytes_read, self.duration)
+    }
+
+    /// The bytes written per second, 0 for protocols that terminated within one tick of the
+    /// clock.
+    pub fn write_per_sec(&self) -> f64 {
+        per_sec(self.bytes_written, self.duration)
+    }
+}
+
+/// The rate of `bytes` over `duration`, 0 if no time passed.
+fn per_sec(bytes: u64, duration: Duration) -> f64 {
+    if duration.is_zero() {
+        return 0.0;
+    }
+
+    bytes as f64 / duration.as_secs_f64()
+}
+
+/// How many protocols of a kind may execute at the same time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConcurrencyBudget {
+    /// Inbound and outbound protocols draw from one budget.
+    Shared(usize),
+    /// Inbound and outbound protocols each draw from their own budget.
+    Split { inbound: usize, outbound: usize },
+}
+
+/// Execution statistics of a negotiated protocol, see [`Behaviour::protocol_stats`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ProtocolStats {
+    /// Executions whose protocol fn returned `Ok`.
+    pub completed: u64,
+    /// Executions that failed, including those cut short by their connection closing.
+    pub failed: u64,
+    /// The time spent executing, from handing the substream to the protocol fn until it
+    /// terminated, summed over all executions.
+    pub total_duration: Duration,
+    /// The longest time a single execution took.
+    pub max_duration: Duration,
+    /// The time spent queued, from starting the protocol until it was dispatched to a
+    /// connection, summed over all executions.
+    pub total_queue_wait: Duration,
+    /// The longest time a single execution was queued.
+    pub max_queue_wait: Duration,
+}
+
+/// The protocols and connections of a peer, see [`Behaviour::peer_stats`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PeerStats {
+    /// Listener protocols queued for the peer.
+    pub pending_in: usize,
+    /// Dialer protocols queued for the peer.
+    pub pending_out: usize,
+    /// Protocols executing on connections to the peer.
+    pub in_flight: usize,
+    pub connections: usize,
+}
+
+/// The bytes exchanged with a peer, see [`Behaviour::peer_bandwidth`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PeerBandwidth {
+    /// The bytes read from substreams of the peer since it connected.
+    pub bytes_read: u64,
+    /// The bytes written to substreams of the peer since it connected.
+    pub bytes_written: u64,
+    /// The bytes per second it may exchange, if limited.
+    pub limit: Option<u64>,
+}
+
+/// A queued or executing protocol without its protocol fn, see [`Behaviour::export_pending`].
+#[derive(Clone, Debug)]
+pub struct PendingDescriptor {
+    pub peer: PeerId,
+    pub direction: Direction,
+    /// The tag attached to the protocol, if any.
+    pub tag: Option<Tag>,
+    /// The protocol its substream is negotiated for, if it is known or restricted to one.
+    pub protocol: Option<&'static [u8]>,
+    /// Whether the protocol was dispatched to a connection already.
+    pub dispatched: bool,
+}
+
+/// What happens to new events while [`Behaviour::set_max_events`] many are waiting to be taken.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum EventOverflow {
+    /// Queued protocols are not dispatched until events are taken. Protocols that are already
+    /// executing still have their events queued.
+    #[default]
+    Block,
+    /// The oldest waiting event is dropped to make room.
+    DropOldest,
+    /// The new event is dropped.
+    DropNewest,
+}
+
+/// Which of a peer's idle connections executes the next protocol with it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ConnectionStrategy {
+    /// Any idle connection, without guarantees about which one.
+    #[default]
+    Any,
+    /// The idle connection that had the fewest protocols dispatched to it, ties going to the one
+    /// established first.
+    LeastBusy,
+    /// The idle connections take turns in the order they were established.
+    RoundRobin,
+    /// The idle connection that was established first.
+    First,
+}
+
+/// Limits how often outbound protocols are dispatched, as a token bucket.
+///
+/// Dispatching a protocol takes a token and a token is added every `interval`, up to `burst`
+/// tokens. The bucket starts out full. A `burst` of 0 is treated as 1.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RateLimit {
+    pub burst: u32,
+    pub interval: Duration,
+}
+
+/// A snapshot of how a [`Behaviour`] is configured, see [`Behaviour::config`].
+///
+/// Each field reflects the setter of the same name. Callbacks are not included.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BehaviourConfig {
+    /// The protocols the behaviour advertises.
+    pub protocols: Vec<&'static [u8]>,
+    pub inbound_allowed: Option<Vec<&'static [u8]>>,
+    pub rejection_frame: Option<Vec<u8>>,
+    pub preface: Option<&'static [u8]>,
+    pub negotiate_framing: bool,
+    pub max_negotiation_protocols: Option<usize>,
+    pub resolve_simultaneous_open: bool,
+    pub max_concurrent: HashMap<&'static [u8], ConcurrencyBudget>,
+    pub max_outbound_per_peer: Option<usize>,
+    pub max_open_substreams: Option<usize>,
+    pub outbound_rate_limit: Option<RateLimit>,
+    pub outbound_rate_limit_per_peer: Option<RateLimit>,
+    pub write_rate_limit: Option<u64>,
+    pub bandwidth_limit_per_peer: Option<u64>,
+    /// The limits set by [`Behaviour::set_peer_bandwidth_limit`].
+    pub peer_bandwidth_limits: HashMap<PeerId, Option<u64>>,
+    pub max_read_memory_per_connection: Option<usize>,
+    pub max_queue_time: Option<Duration>,
+    pub inbound_timeout: Option<Duration>,
+    pub outbound_timeout: Option<Duration>,
+    pub stall_timeout: Option<Duration>,
+    pub read_timeout: Option<Duration>,
+    pub write_timeout: Option<Duration>,
+    pub peer_weights: HashMap<PeerId, u32>,
+    pub connection_strategy: ConnectionStrategy,
+    pub progress_interval: Option<Duration>,
+    pub idle_timeout: Option<Duration>,
+    pub keep_alive_policy: KeepAlivePolicy,
+    pub max_executions_per_connection: Option<usize>,
+    pub cancel_drain_limit: Option<usize>,
+    pub timeout_notification: Option<Vec<u8>>,
+    pub emit_connection_events: bool,
+    pub emit_idle_events: bool,
+    pub emit_drained_events: bool,
+    pub track_throughput: bool,
+    pub max_events: Option<usize>,
+    pub event_overflow: EventOverflow,
+    pub ordered_results: bool,
+}
+
+/// The tokens left of a [`RateLimit`].
+#[derive(Clone, Copy, Debug)]
+struct TokenBucket {
+    limit: RateLimit,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            tokens: f64::from(limit.burst.max(1)),
+            updated: Instant::now(),
+        }
+    }
+
+    fn tokens(&self, now: Instant) -> f64 {
+        let burst = f64::from(self.limit.burst.max(1));
+        if self.limit.interval.is_zero() {
+            return burst;
+        }
+        let added = now.saturating_duration_since(self.updated).as_secs_f64()
+            / self.limit.interval.as_secs_f64();
+
+        (self.tokens + added).min(burst)
+    }
+
+    /// Returns how long it takes until a token is available, `None` if one is available now.
+    fn wait(&self, now: Instant) -> Option<Duration> {
+        let missing = 1.0 - self.tokens(now);
+        if missing <= 0.0 {
+            return None;
+        }
+
+        Some(self.limit.interval.mul_f64(missing))
+    }
+
+    fn take(&mut self, now: Instant) {
+        self.tokens = self.tokens(now) - 1.0;
+        self.updated = now;
+    }
+}
+
+/// A protocol a connection is executing.
+struct InFlight {
+    issued: u64,
+    direction: Direction,
+    kind: Option<&'static [u8]>,
+    tag: Option<Tag>,
+    /// Whether the handler has been asked to cancel the protocol.
+    cancelled: bool,
+    /// The reason reported once the handler acted on the cancellation.
+    cancel_reason: CancelReason,
+    /// The protocol the substream was negotiated for and when the protocol fn started executing.
+    executing: Option<(&'static [u8], Instant)>,
+    /// How long the protocol was queued before it was dispatched.
+    queue_wait: Duration,
+}
+
+impl InFlight {
+    /// Counts the terminated protocol towards the statistics of its negotiated protocol, if it
+    /// got to execute.
+    fn record(&self, stats: &mut HashMap<&'static [u8], ProtocolStats>, succeeded: bool) {
+        let (protocol, since) = match self.executing {
+            Some(executing) => executing,
+            None => return,
+        };
+        let stats = stats.entry(protocol).or_default();
+        let duration = since.elapsed();
+
+        if succeeded {
+            stats.completed += 1;
+        } else {
+            stats.failed += 1;
+        }
+        stats.total_duration += duration;
+        stats.max_duration = stats.max_duration.max(duration);
+        stats.total_queue_wait += self.queue_wait;
+        stats.max_queue_wait = stats.max_queue_wait.max(self.queue_wait);
+    }
+
+    fn failed<I, O, E>(self, peer: PeerId, failure: Failure) -> BehaviourOutEvent<I, O, E> {
+        match self.direction {
+            Direction::Inbound => BehaviourOutEvent::InboundFailed(peer, failure),
+            Direction::Outbound => BehaviourOutEvent::OutboundFailed(peer, failure, self.tag),
+        }
+    }
+}
+
+/// A logical session with a peer opened through [`Behaviour::open_session`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SessionId {
+    peer: PeerId,
+    id: u64,
+}
+
+impl SessionId {
+    /// The peer the session is with.
+    pub fn peer(&self) -> PeerId {
+        self.peer
+    }
+}
+
+struct Session {
+    /// The connection the session is pinned to once its first protocol was dispatched.
+    connection: Option<ConnectionId>,
+    /// Whether the connection the session is pinned to was closed.
+    broken: bool,
+}
+
+/// Application metadata attached to a protocol through [`Behaviour::do_protocol_dialer_tagged`].
+#[derive(Clone)]
+pub struct Tag(Arc<dyn Any + Send + Sync>);
+
+impl Tag {
+    /// Returns the tag if it is of type `M`.
+    pub fn downcast_ref<M: Any>(&self) -> Option<&M> {
+        self.0.downcast_ref()
+    }
+}
+
+impl fmt::Debug for Tag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Tag").finish()
+    }
+}
+
+/// Progress accumulated by a protocol fn, see [`Behaviour::do_protocol_dialer_partial`].
+pub struct Partial<P>(Arc<Mutex<P>>);
+
+impl<P> Partial<P> {
+    /// Updates the accumulated progress.
+    pub fn update<R>(&self, f: impl FnOnce(&mut P) -> R) -> R {
+        f(&mut self.0.lock().expect("lock not to be poisoned"))
+    }
+
+    /// Takes the accumulated progress, leaving the default in its place.
+    pub fn take(&self) -> P
+    where
+        P: Default,
+    {
+        self.update(mem::take)
+    }
+}
+
+impl<P> Clone for Partial<P> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<P> fmt::Debug for Partial<P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Partial").finish()
+    }
+}
+
+/// A behaviour that can execute await/.async protocols.
+///
+/// Every call to one of the `do_protocol_*` functions eventually produces exactly one
+/// [`BehaviourOutEvent`] for it, unless the peer never connects and no
+/// [`Behaviour::set_max_queue_time`] is set.
+///
+/// Note: It is not possible to execute the same protocol with the same peer several simultaneous
+/// times on the same connection. Protocols are queued until a connection to the peer is idle.
+pub struct Behaviour<I, O, E> {
+    queued_protocols: Queue<QueuedProtocol<I, O, E>>,
+    events: VecDeque<BehaviourOutEvent<I, O, E>>,
+    keep_alive_updates: VecDeque<(PeerId, ConnectionId, Instant)>,
+    notifications: VecDeque<(PeerId, Vec<u8>)>,
+    /// Peers to probe once connected, see [`Behaviour::probe_protocols`].
+    #[cfg(feature = "probe")]
+    probes: VecDeque<PeerId>,
+    cancellations: VecDeque<(PeerId, ConnectionId)>,
+    bans: VecDeque<(PeerId, ConnectionId)>,
+    disconnects: VecDeque<(PeerId, ConnectionId)>,
+    /// Connections whose handlers advertise outdated protocols, see
+    /// [`Behaviour::upgrade_protocols`].
+    protocol_upgrades: VecDeque<(PeerId, ConnectionId)>,
+    finish_requests: VecDeque<(PeerId, ConnectionId)>,
+
+    connected_peers: HashMap<PeerId, Vec<(ConnectionId, ConnectedPoint)>>,
+    /// The protocol each busy connection is executing.
+    in_flight: HashMap<ConnectionId, InFlight>,
+    max_concurrent: HashMap<&'static [u8], ConcurrencyBudget>,
+    max_outbound_per_peer: Option<usize>,
+    outbound_bucket: Option<TokenBucket>,
+    peer_outbound_limit: Option<RateLimit>,
+    peer_outbound_buckets: HashMap<PeerId, TokenBucket>,
+    /// How long until a rate limited outbound protocol can be dispatched.
+    rate_limit_wait: Option<Duration>,
+    max_queue_time: Option<Duration>,
+    /// Wakes us up once a queued protocol can be dispatched or expires.
+    dispatch_timer: Option<Delay>,
+    /// How often the ban checker is consulted for peers with executing protocols and the timer
+    /// for the next sweep.
+    ban_sweep: Option<(Duration, Delay)>,
+    /// Peers whose queued protocols are not dispatched, see [`Behaviour::pause_peer`].
+    paused_peers: HashSet<PeerId>,
+    /// How many turns each peer gets when dispatching queued protocols, 1 if not set.
+    peer_weights: HashMap<PeerId, u32>,
+    /// The scheduling state of peers with queued protocols, see [`Behaviour::next_dispatch`].
+    current_weights: HashMap<PeerId, i64>,
+    connection_strategy: ConnectionStrategy,
+    /// How many protocols were dispatched to each connection.
+    dispatched: HashMap<ConnectionId, u64>,
+    /// The connection each peer's last protocol was dispatched to.
+    last_dispatched: HashMap<PeerId, ConnectionId>,
+    keep_alive_deadlines: HashMap<PeerId, Instant>,
+    sessions: HashMap<SessionId, Session>,
+    /// Application data attached to open connections, see [`Behaviour::set_connection_data`].
+    connection_data: HashMap<ConnectionId, Box<dyn Any + Send>>,
+    /// Connections a protocol requested to be closed.
+    disconnect_requested: HashSet<ConnectionId>,
+    next_session: u64,
+
+    protocols: Vec<&'static [u8]>,
+    shared: Arc<Shared>,
+    inbound_handlers: InboundHandlers<I, E>,
+    inbound_pool: SharedInboundPool<I, E>,
+    emit_connection_events: bool,
+    emit_idle_events: bool,
+    emit_drained_events: bool,
+    /// How many events were handed out since construction or the last [`Behaviour::clear`].
+    emitted_events: u64,
+    max_events: Option<usize>,
+    event_overflow: EventOverflow,
+    /// How many events were dropped because the queue was full.
+    dropped_events: u64,
+    ordered_results: bool,
+    next_issued: u64,
+    /// Protocols that were started but did not terminate yet, by the number they were issued.
+    outstanding: BTreeSet<u64>,
+    /// Results held back until the protocols started before them terminated, see
+    /// [`Behaviour::set_ordered_results`].
+    held_results: BTreeMap<u64, BehaviourOutEvent<I, O, E>>,
+    /// The fallbacks of protocols that did not terminate yet, by the number they were issued.
+    fallbacks: HashMap<u64, FallbackFn<O, E>>,
+    running_fallbacks: FuturesUnordered<Fallback<O, E>>,
+    /// The deadlines of protocols that did not terminate yet, by the number they were issued,
+    /// see [`Behaviour::do_protocol_dialer_until`].
+    deadlines: HashMap<u64, Instant>,
+    /// The deadlines that did not pass yet, earliest first.
+    pending_deadlines: BTreeSet<(Instant, u64)>,
+    protocol_stats: HashMap<&'static [u8], ProtocolStats>,
+    on_event_ready: Option<EventReadyFn>,
+    /// Wakes up [`Behaviour::next_event`] once an event is queued.
+    event_waker: Option<Waker>,
+    dial_address_selector: Option<DialAddressSelectorFn>,
+    /// Publishes every event on the channel set through [`Behaviour::set_event_sender`].
+    #[cfg(feature = "tokio")]
+    publish_event: Option<PublishEventFn<I, O, E>>,
+}
+
+impl<I, O, E> Behaviour<I, O, E> {
+    /// Constructs a new [`Behaviour`] with the given protocol info.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use libp2p_async_await::Behaviour;
+    ///
+    /// let _ = Behaviour::<(), (), ()>::new(b"/foo/bar/1.0.0");
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// If the protocol is not valid, see [`Behaviour::with_protocols`].
+    pub fn new(info: &'static [u8]) -> Self {
+        Self::with_protocols(iter::once(info))
+    }
+
+    /// Constructs a new [`Behaviour`] that advertises several protocols.
+    ///
+    /// Outbound substreams are negotiated using the protocols in the given order of preference.
+    ///
+    /// # Panics
+    ///
+    /// If any of the protocols is not valid or none are given. Following the libp2p convention,
+    /// protocols have to start with `/`, e.g. `/foo/1.0.0`, and may not contain a newline, which
+    /// delimits them during negotiation. Invalid protocols would otherwise only fail to negotiate
+    /// later on, and without any protocol nothing can be negotiated at all.
+    pub fn with_protocols(protocols: impl IntoIterator<Item = &'static [u8]>) -> Self {
+        let protocols = validate_protocols(protocols);
+
+        Self {
+            queued_protocols: Queue::default(),
+            events: VecDeque::default(),
+            emit_connection_events: false,
+            emit_idle_events: false,
+            emit_drained_events: false,
+            emitted_events: 0,
+            max_events: None,
+            event_overflow: EventOverflow::default(),
+            dropped_events: 0,
+            ordered_results: false,
+            next_issued: 0,
+            outstanding: BTreeSet::default(),
+            held_results: BTreeMap::default(),
+            fallbacks: HashMap::default(),
+            running_fallbacks: FuturesUnordered::new(),
+            deadlines: HashMap::default(),
+            pending_deadlines: BTreeSet::default(),
+            protocol_stats: HashMap::default(),
+            on_event_ready: None,
+            event_waker: None,
+            dial_address_selector: None,
+            #[cfg(feature = "tokio")]
+            publish_event: None,
+            keep_alive_updates: VecDeque::default(),
+            notifications: VecDeque::default(),
+            #[cfg(feature = "probe")]
+            probes: VecDeque::default(),
+            cancellations: VecDeque::default(),
+            bans: VecDeque::default(),
+            disconnects: VecDeque::default(),
+            protocol_upgrades: VecDeque::default(),
+            finish_requests: VecDeque::default(),
+            connected_peers: HashMap::default(),
+            in_flight: HashMap::default(),
+            max_concurrent: HashMap::default(),
+            max_outbound_per_peer: None,
+            outbound_bucket: None,
+            peer_outbound_limit: None,
+            peer_outbound_buckets: HashMap::default(),
+            rate_limit_wait: None,
+            max_queue_time: None,
+            dispatch_timer: None,
+            ban_sweep: None,
+            paused_peers: HashSet::default(),
+            peer_weights: HashMap::default(),
+            current_weights: HashMap::default(),
+            connection_strategy: ConnectionStrategy::default(),
+            dispatched: HashMap::default(),
+            last_dispatched: HashMap::default(),
+            keep_alive_deadlines: HashMap::default(),
+            sessions: HashMap::default(),
+            connection_data: HashMap::default(),
+            disconnect_requested: HashSet::default(),
+            next_session: 0,
+            protocols,
+            inbound_handlers: Arc::default(),
+            inbound_pool: Arc::default(),
+            shared: Arc::new(Shared {
+                ready: AtomicBool::new(true),
+                inbound_allowed: RwLock::new(None),
+                rejection_frame: RwLock::new(None),
+                preface: RwLock::new(None),
+                negotiate_framing: AtomicBool::new(false),
+                max_negotiation_protocols: RwLock::new(None),
+                idle_timeout: RwLock::new(None),
+                keep_alive_policy: RwLock::default(),
+                keep_alive_decider: RwLock::new(None),
+                max_executions: RwLock::default(),
+                cancel_drain_limit: RwLock::default(),
+                timeout_notification: RwLock::default(),
+                progress_interval: RwLock::new(None),
+                accept_inbound: RwLock::new(None),
+                is_banned: RwLock::new(None),
+                inbound_timeout: RwLock::new(None),
+                outbound_timeout: RwLock::new(None),
+                stall_timeout: RwLock::new(None),
+                io_timeouts: Arc::default(),
+                resolve_simultaneous_open: AtomicBool::new(false),
+                local_peer_id: RwLock::new(None),
+                write_pending: Mutex::default(),
+                open_substreams: Arc::default(),
+                wrap_substream: Arc::default(),
+                egress_limit: Arc::default(),
+                framing: Arc::default(),
+                read_memory: Arc::default(),
+                bandwidth: Bandwidth::default(),
+                track_throughput: AtomicBool::new(false),
+                throughput: Mutex::default(),
+            }),
+        }
+    }
+
+    /// Constructs a new [`Behaviour`] that starts out paused.
+    ///
+    /// While paused, inbound substreams are dropped and queued protocols are
+    /// not dispatched to any connection. Call [`Behaviour::set_ready`] once the
+    /// application is ready to serve protocols.
+    pub fn new_paused(info: &'static [u8]) -> Self {
+        let behaviour = Self::new(info);
+        behaviour.shared.ready.store(false, Ordering::SeqCst);
+
+        behaviour
+    }
+
+    /// Marks this [`Behaviour`] as ready (or paused).
+    pub fn set_ready(&mut self, ready: bool) {
+        self.shared.ready.store(ready, Ordering::SeqCst);
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.shared.ready.load(Ordering::SeqCst)
+    }
+
+    /// Stops dispatching protocols to the peer until [`Behaviour::resume_peer`] is called.
+    ///
+    /// Unlike [`Behaviour::set_ready`], this only affects protocols with the given peer, which
+    /// stay queued while it is paused. Executing protocols are not affected. The peer stays
+    /// paused across reconnects.
+    pub fn pause_peer(&mut self, peer: PeerId) {
+        self.paused_peers.insert(peer);
+    }
+
+    /// Resumes dispatching protocols to a peer paused through [`Behaviour::pause_peer`].
+    pub fn resume_peer(&mut self, peer: &PeerId) {
+        self.paused_peers.remove(peer);
+    }
+
+    pub fn is_peer_paused(&self, peer: &PeerId) -> bool {
+        self.paused_peers.contains(peer)
+    }
+
+    /// Replaces the advertised protocols with the given one.
+    ///
+    /// Only handlers created for new connections advertise it. Existing connections keep
+    /// negotiating the protocols they were established with until they close.
+    ///
+    /// # Panics
+    ///
+    /// If the protocol is not valid, see [`Behaviour::with_protocols`].
+    pub fn set_protocol_info(&mut self, info: &'static [u8]) {
+        validate_protocol_info(info);
+        self.protocols = vec![info];
+    }
+
+    /// Replaces the advertised protocols, on existing connections as well.
+    ///
+    /// Unlike [`Behaviour::set_protocol_info`], connections do not have to be re-established:
+    /// every substream negotiated from now on picks the first of the given protocols the remote
+    /// supports, so peers move to a new version once both upgraded. Protocol fns learn which
+    /// version they execute through `substream.protocol()`. Substreams that are already
+    /// negotiated keep their protocol.
+    ///
+    /// # Panics
+    ///
+    /// If any of the protocols is not valid or none are given, see [`Behaviour::with_protocols`].
+    pub fn upgrade_protocols(&mut self, protocols: impl IntoIterator<Item = &'static [u8]>) {
+        self.protocols = validate_protocols(protocols);
+
+        for (peer, connections) in self.connected_peers.iter() {
+            for (connection, _) in connections {
+                self.protocol_upgrades.push_back((*peer, *connection));
+            }
+        }
+    }
+
+    /// Offers at most `max` of the advertised protocols, the most preferred ones, when
+    /// negotiating outbound substreams.
+    ///
+    /// Negotiation tries the offered protocols one after the other, each taking a round trip, so
+    /// advertising many versions delays protocols with peers that only support the oldest ones.
+    /// Capping the offered protocols bounds that delay, at the cost of failing to negotiate with
+    /// peers that support none of them. Inbound substreams are still accepted for all advertised
+    /// protocols. A `max` of 0 is treated as 1. `None`, the default, offers all of them.
+    pub fn set_max_negotiation_protocols(&mut self, max: Option<usize>) {
+        *self
+            .shared
+            .max_negotiation_protocols
+            .write()
+            .expect("lock not to be poisoned") = max;
+    }
+
+    /// Executes the handler on every inbound substream the remote negotiates for the given
+    /// protocol, reporting its results as [`BehaviourOutEvent::Inbound`].
+    ///
+    /// Handlers serve substreams arriving on idle connections without queueing listener
+    /// protocols, which still take precedence if they are waiting for a substream. Once any
+    /// handler is set, inbound substreams for protocols without one are rejected unless a
+    /// listener protocol is waiting for them. Queued protocols dispatched to a connection while
+    /// a handler executes on it fail with [`Failure::ConnectionBusy`].
+    pub fn set_inbound_handler_for<F>(
+        &mut self,
+        info: &'static [u8],
+        handler: impl Fn(InboundSubstream) -> F + Send + Sync + 'static,
+    ) where
+        F: Future<Output = Result<I, E>> + Send + 'static,
+    {
+        self.inbound_handlers
+            .write()
+            .expect("lock not to be poisoned")
+            .insert(info, Arc::new(move |substream| handler(substream).boxed()));
+    }
+
+    /// Keeps the handler ready to serve up to `slots` inbound substreams at the same time,
+    /// reporting its results as [`BehaviourOutEvent::Inbound`].
+    ///
+    /// Like handlers set by [`Behaviour::set_inbound_handler_for`], the pool binds the handler to
+    /// substreams arriving on idle connections right away instead of waiting for a listener
+    /// protocol to be dispatched, but serves all protocols and only so many substreams at once.
+    /// Protocol specific handlers and listener protocols waiting for a substream take precedence.
+    /// Each substream the pool serves takes a slot until its protocol terminates. Substreams
+    /// arriving while all slots are taken are handled as if there was no pool.
+    ///
+    /// Substreams rejected by [`Behaviour::set_accept_inbound`], [`Behaviour::set_inbound_allowed`]
+    /// or a ban never reach the pool and do not take a slot, so the acceptance callback is
+    /// consulted before a pooled handler runs. Replacing the pool does not affect substreams
+    /// the previous one is serving.
+    pub fn set_inbound_pool<F>(
+        &mut self,
+        slots: usize,
+        handler: impl Fn(InboundSubstream) -> F + Send + Sync + 'static,
+    ) where
+        F: Future<Output = Result<I, E>> + Send + 'static,
+    {
+        *self.inbound_pool.write().expect("lock not to be poisoned") = Some(InboundPool {
+            handler: Arc::new(move |substream| handler(substream).boxed()),
+            free: Arc::new(AtomicUsize::new(slots)),
+        });
+    }
+
+    /// Removes the pool set by [`Behaviour::set_inbound_pool`].
+    pub fn clear_inbound_pool(&mut self) {
+        *self.inbound_pool.write().expect("lock not to be poisoned") = None;
+    }
+
+    /// Restricts the protocols we accept inbound substreams for.
+    ///
+    /// Inbound substreams negotiated for any other protocol are dropped and reported as
+    /// [`BehaviourOutEvent::Rejected`]. By default, all advertised protocols are allowed.
+    pub fn set_inbound_allowed(&mut self, protocols: &[&'static [u8]]) {
+        *self
+            .shared
+            .inbound_allowed
+            .write()
+            .expect("lock not to be poisoned") = Some(protocols.to_vec());
+    }
+
+    /// Writes the given frame on inbound substreams rejected by [`Behaviour::set_inbound_allowed`]
+    /// before closing them, so the remote learns that the protocol is not supported.
+    ///
+    /// The frame is written without waiting for a protocol fn and the preface and framing
+    /// handshakes do not take place. `None`, the default, drops rejected substreams right away.
+    pub fn set_rejection_frame(&mut self, frame: Option<Vec<u8>>) {
+        *self
+            .shared
+            .rejection_frame
+            .write()
+            .expect("lock not to be poisoned") = frame;
+    }
+
+    /// Sets the magic bytes both sides exchange before a protocol fn is handed a fresh substream.
+    ///
+    /// Protocols on substreams where the remote sends a different preface fail with
+    /// [`Failure::PrefaceMismatch`]. Reused substreams do not exchange the preface again.
+    pub fn set_preface(&mut self, preface: Option<&'static [u8]>) {
+        *self
+            .shared
+            .preface
+            .write()
+            .expect("lock not to be poisoned") = preface;
+    }
+
+    /// Fails protocols that execute for longer than the given duration with
+    /// [`Failure::Timeout`].
+    ///
+    /// The timeout starts once the protocol has its substream, before the preface and framing
+    /// handshakes, and applies to inbound and outbound protocols alike. Use
+    /// [`Behaviour::set_inbound_timeout`] and [`Behaviour::set_outbound_timeout`] to override it
+    /// for one direction. `None`, the default, lets protocols execute indefinitely.
+    pub fn set_protocol_timeout(&mut self, timeout: Option<Duration>) {
+        self.set_inbound_timeout(timeout);
+        self.set_outbound_timeout(timeout);
+    }
+
+    /// Writes `notification` as a message to the substreams of protocols that time out, so the
+    /// remote learns why they were closed.
+    ///
+    /// The message is written through the framing of the substream, the remote receives it from
+    /// its next `read_message` followed by EOF. Protocols that time out in the middle of writing
+    /// a message garble it. Writing and closing the substreams may take up to half a second, after
+    /// which they are dropped regardless. `None`, the default, drops the substreams right away.
+    pub fn set_timeout_notification(&mut self, notification: Option<Vec<u8>>) {
+        *self
+            .shared
+            .timeout_notification
+            .write()
+            .expect("lock not to be poisoned") = notification;
+    }
+
+    /// Like [`Behaviour::set_protocol_timeout`] but only for inbound protocols.
+    pub fn set_inbound_timeout(&mut self, timeout: Option<Duration>) {
+        *self
+            .shared
+            .inbound_timeout
+            .write()
+            .expect("lock not to be poisoned") = timeout;
+    }
+
+    /// Like [`Behaviour::set_protocol_timeout`] but only for outbound protocols.
+    pub fn set_outbound_timeout(&mut self, timeout: Option<Duration>) {
+        *self
+            .shared
+            .outbound_timeout
+            .write()
+            .expect("lock not to be poisoned") = timeout;
+    }
+
+    /// Fails protocols that go without reading or writing a single byte for longer than the given
+    /// duration with [`Failure::Stalled`].
+    ///
+    /// Unlike [`Behaviour::set_protocol_timeout`], long-running protocols are not cut short as
+    /// long as they make progress, which catches remotes that go silent in the middle of a
+    /// protocol. All substreams of the protocol's connection count, including additional ones.
+    /// Protocols that legitimately wait for longer, e.g. for the application, should exchange
+    /// keepalive pings. `None`, the default, disables the check.
+    pub fn set_stall_timeout(&mut self, timeout: Option<Duration>) {
+        *self
+            .shared
+            .stall_timeout
+            .write()
+            .expect("lock not to be poisoned") = timeout;
+    }
+
+    /// Fails reading a message on a substream with [`FramingError::Io`] of kind
+    /// [`io::ErrorKind::TimedOut`] if it does not arrive within the given duration.
+    ///
+    /// Applies to every read through the substream helpers, e.g.
+    /// [`InboundSubstream::read_message`], except for their `_no_timeout` variants. Changes take
+    /// effect for reads started afterwards. `None`, the default, lets reads wait indefinitely.
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) {
+        *self
+            .shared
+            .io_timeouts
+            .read
+            .write()
+            .expect("lock not to be poisoned") = timeout;
+    }
+
+    /// Like [`Behaviour::set_read_timeout`] but for writing a message, e.g. through
+    /// [`InboundSubstream::write_message`].
+    pub fn set_write_timeout(&mut self, timeout: Option<Duration>) {
+        *self
+            .shared
+            .io_timeouts
+            .write
+            .write()
+            .expect("lock not to be poisoned") = timeout;
+    }
+
+    /// Limits how many protocols started through `do_protocol_*_for` with the given protocol
+    /// execute at the same time, across all peers.
+    ///
+    /// Protocols beyond the limit stay queued until another one of their kind terminated.
+    /// Inbound and outbound protocols share the limit, see [`Behaviour::set_concurrency_budget`]
+    /// for limiting them separately.
+    pub fn set_max_concurrent(&mut self, protocol: &'static [u8], max: usize) {
+        self.set_concurrency_budget(protocol, ConcurrencyBudget::Shared(max));
+    }
+
+    /// Like [`Behaviour::set_max_concurrent`] but lets inbound and outbound protocols draw from
+    /// separate budgets.
+    pub fn set_concurrency_budget(&mut self, protocol: &'static [u8], budget: ConcurrencyBudget) {
+        self.max_concurrent.insert(protocol, budget);
+    }
+
+    /// Limits how many substreams may be open across all connections, see
+    /// [`Behaviour::open_substreams`].
+    ///
+    /// While the limit is reached, outbound protocols and notifications that need a new
+    /// substream stay queued until another substream closes. Inbound substreams are negotiated
+    /// by the remote before we learn about them, those beyond the limit are deferred: their
+    /// connection holds on to them without serving them until another substream closes, and they
+    /// do not count until then. `None`, the default, does not limit the number of substreams.
+    pub fn set_max_open_substreams(&mut self, max: Option<usize>) {
+        *self
+            .shared
+            .open_substreams
+            .max
+            .write()
+            .expect("lock not to be poisoned") = max;
+        self.shared.open_substreams.wake();
+    }
+
+    /// Limits how many events may wait to be taken and decides what happens beyond that.
+    ///
+    /// Consumers that take events more slowly than protocols produce them otherwise let the
+    /// queue grow without bounds. Dropped events are counted by [`Behaviour::dropped_events`] and
+    /// do not receive a sequence number. `None`, the default, does not limit the queue.
+    pub fn set_max_events(&mut self, max: Option<usize>, overflow: EventOverflow) {
+        self.max_events = max;
+        self.event_overflow = overflow;
+    }
+
+    /// Returns how many events were dropped because too many were waiting to be taken, see
+    /// [`Behaviour::set_max_events`].
+    pub fn dropped_events(&self) -> u64 {
+        self.dropped_events
+    }
+
+    /// Reports the results of protocols in the order they were started, regardless of the order
+    /// they terminate in.
+    ///
+    /// Applies to the [`BehaviourOutEvent::Inbound`], [`BehaviourOutEvent::Outbound`],
+    /// [`BehaviourOutEvent::InboundFailed`] and [`BehaviourOutEvent::OutboundFailed`] events of
+    /// protocols started through this behaviour, e.g. [`Behaviour::do_protocol_dialer`]. Other
+    /// events, including the results of handlers set through
+    /// [`Behaviour::set_inbound_handler_for`], are reported right away.
+    ///
+    /// This comes at the cost of head-of-line blocking: A protocol that takes long, e.g. because
+    /// it waits for its peer to connect, holds back the results of all protocols started after
+    /// it, even those with other peers. Combine it with [`Behaviour::set_max_queue_time`] and
+    /// protocol timeouts to bound the delay. Disabling it reports all held back results at once.
+    pub fn set_ordered_results(&mut self, ordered: bool) {
+        self.ordered_results = ordered;
+        self.release_results();
+    }
+
+    /// Assigns the next protocol its number in the order protocols were started.
+    fn issue(&mut self) -> u64 {
+        let issued = self.next_issued;
+        self.next_issued += 1;
+        self.outstanding.insert(issued);
+
+        issued
+    }
+
+    /// Reports the result of the protocol with the given number, holding it back while
+    /// [`Behaviour::set_ordered_results`] requires it to wait for earlier protocols.
+    fn push_result(&mut self, issued: u64, event: BehaviourOutEvent<I, O, E>) {
+        self.fallbacks.remove(&issued);
+        if let Some(deadline) = self.deadlines.remove(&issued) {
+            self.pending_deadlines.remove(&(deadline, issued));
+        }
+        self.outstanding.remove(&issued);
+        self.held_results.insert(issued, event);
+        self.release_results();
+    }
+
+    fn release_results(&mut self) {
+        while let Some(entry) = self.held_results.first_entry() {
+            let blocked = self.ordered_results
+                && self
+                    .outstanding
+                    .iter()
+                    .next()
+                    .is_some_and(|outstanding| outstanding < entry.key());
+            if blocked {
+                break;
+            }
+
+            let event = entry.remove();
+            self.push_event(event);
+        }
+    }
+
+    fn is_event_queue_full(&self) -> bool {
+        self.max_events.is_some_and(|max| self.events.len() >= max)
+    }
+
+    /// Limits how many outbound protocols execute concurrently with a single peer.
+    ///
+    /// Each connection executes one protocol at a time, so executing several with a peer requires
+    /// several connections to it. Protocols beyond the limit stay queued and are dispatched in the
+    /// order they were started, but may complete in any order. Use
+    /// [`Behaviour::do_protocol_dialer_tagged`] to correlate their results. `None`, the default,
+    /// removes the limit.
+    pub fn set_max_outbound_per_peer(&mut self, max: Option<usize>) {
+        self.max_outbound_per_peer = max;
+    }
+
+    /// Limits how often outbound protocols are dispatched, across all peers.
+    ///
+    /// Each dispatched outbound protocol opens a substream. Protocols beyond the limit stay queued
+    /// until the limit allows them. Notifications are not limited. `None`, the default, removes
+    /// the limit.
+    pub fn set_outbound_rate_limit(&mut self, limit: Option<RateLimit>) {
+        self.outbound_bucket = limit.map(TokenBucket::new);
+    }
+
+    /// Limits the bytes written across all substreams of all connections to `bytes_per_sec`.
+    ///
+    /// Writes wait until the budget allows them, large ones are split up. Up to one second
+    /// worth of bytes may be written at once after being idle. The bytes of the multistream-select
+    /// negotiation and the muxer do not count. `None`, the default, removes the limit.
+    pub fn set_write_rate_limit(&mut self, bytes_per_sec: Option<u64>) {
+        self.shared.egress_limit.set(bytes_per_sec);
+    }
+
+    /// Limits the bytes each peer may exchange, read and written alike, to `bytes_per_sec`.
+    ///
+    /// The budget is shared by all protocols and connections of a peer, so a single peer cannot
+    /// monopolize the bandwidth. Reads and writes exceeding it wait until the budget allows them
+    /// instead of failing, which slows down the protocol fn and, through the muxer's flow
+    /// control, the peer. Limits set for individual peers by
+    /// [`Behaviour::set_peer_bandwidth_limit`] take precedence. `None`, the default, removes the
+    /// limit.
+    pub fn set_bandwidth_limit_per_peer(&mut self, bytes_per_sec: Option<u64>) {
+        self.shared.bandwidth.set_default(bytes_per_sec);
+    }
+
+    /// Limits the bytes the peer may exchange to `bytes_per_sec` instead of the limit set by
+    /// [`Behaviour::set_bandwidth_limit_per_peer`], `None` lifts the limit for the peer.
+    pub fn set_peer_bandwidth_limit(&mut self, peer: PeerId, bytes_per_sec: Option<u64>) {
+        self.shared.bandwidth.set_override(peer, bytes_per_sec);
+    }
+
+    /// Has the peer fall back to the limit set by [`Behaviour::set_bandwidth_limit_per_peer`].
+    pub fn reset_peer_bandwidth_limit(&mut self, peer: &PeerId) {
+        self.shared.bandwidth.remove_override(peer);
+    }
+
+    /// Limits the memory the reads of each connection may buffer at once to `max` bytes.
+    ///
+    /// A read reserves the size of its frame, or of the chunks it hands the frame out in, while
+    /// it is in progress, as soon as the framing knows it. Reads exceeding the limit fail with
+    /// [`FramingError::MemoryLimit`] before buffering the frame. Messages that were read do not
+    /// count anymore. `None`, the default, removes the limit.
+    pub fn set_max_read_memory_per_connection(&mut self, max: Option<usize>) {
+        self.shared.read_memory.set_max_per_connection(max);
+    }
+
+    /// Delimits the messages of the substream helpers with the given framing instead of
+    /// prefixing them with their length, see [`framing`].
+    ///
+    /// Applies to substreams opened afterwards, substreams keep the framing they were opened with
+    /// so protocols that already run do not see the wire format change. Both peers have to use
+    /// the same framing. Notifications are framed like any other message.
+    pub fn set_framing(&mut self, framing: impl Framing + 'static) {
+        self.shared.framing.set(Arc::new(framing));
+    }
+
+    /// Like [`Behaviour::set_outbound_rate_limit`] but limits each peer separately.
+    pub fn set_outbound_rate_limit_per_peer(&mut self, limit: Option<RateLimit>) {
+        self.peer_outbound_limit = limit;
+        self.peer_outbound_buckets.clear();
+    }
+
+    /// Fails protocols that stay queued for longer than the given duration with
+    /// [`Failure::QueueTimeout`].
+    ///
+    /// Protocols stay queued while the peer is not connected, all its connections are busy or
+    /// a limit holds them back. Executing protocols are not affected. `None`, the default, lets
+    /// protocols wait indefinitely.
+    pub fn set_max_queue_time(&mut self, max: Option<Duration>) {
+        self.max_queue_time = max;
+    }
+
+    /// Sets how many turns the peer gets relative to others when dispatching queued protocols.
+    ///
+    /// Peers with queued protocols take turns in proportion to their weight, which defaults to 1.
+    /// Protocols of the same peer are dispatched in the order they were started. A weight of 0 is
+    /// treated as 1.
+    pub fn set_peer_weight(&mut self, peer: PeerId, weight: u32) {
+        self.peer_weights.insert(peer, weight.max(1));
+    }
+
+    /// Sets how a connection is chosen when protocols are started with a peer we have several
+    /// connections to.
+    pub fn set_connection_strategy(&mut self, strategy: ConnectionStrategy) {
+        self.connection_strategy = strategy;
+    }
+
+    /// Resolves protocols that both peers start on a connection at the same time.
+    ///
+    /// Each connection executes one protocol at a time, so if both peers start an outbound
+    /// protocol at once, each queues the substream of the other behind its own protocol and both
+    /// wait for an answer until a protocol timeout fails them. With this enabled, the peer with
+    /// the greater peer id fails its outbound protocol with [`Failure::SimultaneousOpen`] and
+    /// serves the remote's substream with its next listener protocol instead. This suits
+    /// symmetric protocols, where either side's protocol achieves the same. Both peers should
+    /// enable it, otherwise only the one yielding takes part.
+    pub fn set_resolve_simultaneous_open(&mut self, resolve: bool) {
+        self.shared
+            .resolve_simultaneous_open
+            .store(resolve, Ordering::SeqCst);
+    }
+
+    /// Makes both sides agree on a [`FRAMING_VERSION`] before a protocol fn is handed a fresh
+    /// substream.
+    ///
+    /// Each side sends the newest version it supports and the older of the two is used.
+    /// Protocols with peers that do not support any version we support fail with
+    /// [`Failure::IncompatibleFraming`]. Both sides must enable this, otherwise the version byte
+    /// is mistaken for a message.
+    pub fn set_negotiate_framing(&mut self, negotiate: bool) {
+        self.shared
+            .negotiate_framing
+            .store(negotiate, Ordering::SeqCst);
+    }
+
+    /// Makes executing protocols emit [`BehaviourOutEvent::Progress`] every `interval`.
+    pub fn set_progress_interval(&mut self, interval: Option<Duration>) {
+        *self
+            .shared
+            .progress_interval
+            .write()
+            .expect("lock not to be poisoned") = interval;
+    }
+
+    /// Wraps every substream negotiated from now on with `wrap` before protocols get to use it.
+    ///
+    /// This layers cross-cutting concerns, e.g. compression or logging, beneath all protocols.
+    /// The wrapper sits below everything this crate writes to the substream, including the
+    /// preface and framing handshakes. Both sides of a substream have to agree on the wrapping.
+    pub fn wrap_substream<W>(
+        &mut self,
+        wrap: impl Fn(NegotiatedSubstream) -> W + Send + Sync + 'static,
+    ) where
+        W: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        *self
+            .shared
+            .wrap_substream
+            .write()
+            .expect("lock not to be poisoned") =
+            Some(Box::new(move |substream| Box::new(wrap(substream))));
+    }
+
+    /// Consults the given callback before accepting an inbound substream from a peer.
+    ///
+    /// The callback is passed the peer and how we are connected to it, which for connections
+    /// accepted by one of our listeners includes the local address it listens on. Substreams the
+    /// callback returns `false` for are dropped before any protocol runs on them and reported as
+    /// [`BehaviourOutEvent::Rejected`].
+    pub fn set_accept_inbound(
+        &mut self,
+        accept: impl Fn(&PeerId, &ConnectedPoint) -> bool + Send + Sync + 'static,
+    ) {
+        *self
+            .shared
+            .accept_inbound
+            .write()
+            .expect("lock not to be poisoned") = Some(Box::new(accept));
+    }
+
+    /// Consults the given callback to enforce bans on peers.
+    ///
+    /// Queued protocols with a banned peer fail with [`Failure::Banned`] instead of being
+    /// dispatched and inbound substreams from it are dropped and reported as
+    /// [`BehaviourOutEvent::Rejected`]. Executing protocols, inbound and outbound, are checked
+    /// every `sweep_interval` and fail with [`Failure::Banned`] once their connection dropped
+    /// them. Call [`Behaviour::enforce_bans`] to check them right away, e.g. after the ban list
+    /// changed.
+    pub fn set_ban_checker(
+        &mut self,
+        sweep_interval: Duration,
+        is_banned: impl Fn(&PeerId) -> bool + Send + Sync + 'static,
+    ) {
+        *self
+            .shared
+            .is_banned
+            .write()
+            .expect("lock not to be poisoned") = Some(Box::new(is_banned));
+        self.ban_sweep = Some((sweep_interval, Delay::new(sweep_interval)));
+    }
+
+    /// Fails all queued and executing protocols with peers banned by the checker set through
+    /// [`Behaviour::set_ban_checker`].
+    pub fn enforce_bans(&mut self) {
+        let shared = self.shared.clone();
+        let banned = self
+            .queued_protocols
+            .remove_where(|queued| shared.is_banned(&queued.peer));
+
+        for queued in banned {
+            self.push_result(queued.issued, queued.failed(Failure::Banned));
+        }
+
+        for (peer, connections) in self.connected_peers.iter() {
+            if !shared.is_banned(peer) {
+                continue;
+            }
+            for (connection, _) in connections {
+                if let Some(in_flight) = self.in_flight.get_mut(connection) {
+                    if !in_flight.cancelled {
+                        in_flight.cancelled = true;
+                        self.bans.push_back((*peer, *connection));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Allows inbound substreams for all advertised protocols again.
+    pub fn allow_all_inbound(&mut self) {
+        *self
+            .shared
+            .inbound_allowed
+            .write()
+            .expect("lock not to be poisoned") = None;
+    }
+
+    /// Returns whether we currently consider the given peer connected.
+    pub fn is_connected(&self, peer: &PeerId) -> bool {
+        self.connected_peers.contains_key(peer)
+    }
+
+    /// Returns the local address the given connection was accepted on.
+    ///
+    /// Returns `None` for connections we dialed and connections we do not know about.
+    pub fn local_address(&self, peer: &PeerId, connection: &ConnectionId) -> Option<&Multiaddr> {
+        let (_, point) = self
+            .connected_peers
+            .get(peer)?
+            .iter()
+            .find(|(id, _)| id == connection)?;
+
+        match point {
+            ConnectedPoint::Listener { local_addr, .. } => Some(local_addr),
+            ConnectedPoint::Dialer { .. } => None,
+        }
+    }
+
+    /// Resolves to the next event of this behaviour.
+    ///
+    /// This is meant for driving the behaviour standalone, e.g. in tests or custom runtimes that
+    /// feed it handler events through [`NetworkBehaviour::inject_event`] themselves. It parks
+    /// until [`NetworkBehaviour::poll`] would generate an event, driving what produces events
+    /// without a swarm: queued protocols and deadlines expiring and fallbacks completing. Unlike
+    /// `poll`, it neither dispatches queued protocols nor notifies handlers, as there is no swarm
+    /// to hand them to. Within a `Swarm`, use the swarm's events instead.
+    pub async fn next_event(&mut self) -> BehaviourOutEvent<I, O, E> {
+        future::poll_fn(|cx| self.poll_next_event(cx)).await
+    }
+
+    fn poll_next_event(&mut self, cx: &mut Context<'_>) -> Poll<BehaviourOutEvent<I, O, E>> {
+        self.expire_queued(Instant::now());
+        self.expire_deadlines(Instant::now());
+        self.poll_fallbacks(cx);
+
+        if let Some(event) = self.pop_event() {
+            return Poll::Ready(event);
+        }
+        self.event_waker = Some(cx.waker().clone());
+
+        if let Some(wait) = self.next_expiry(Instant::now()) {
+            let timer = self.dispatch_timer.insert(Delay::new(wait));
+            if timer.poll_unpin(cx).is_ready() {
+                cx.waker().wake_by_ref();
+            }
+        }
+
+        Poll::Pending
+    }
+
+    /// Reports the results of fallbacks that completed.
+    fn poll_fallbacks(&mut self, cx: &mut Context<'_>) {
+        while let Poll::Ready(Some((issued, peer, tag, res))) =
+            self.running_fallbacks.poll_next_unpin(cx)
+        {
+            self.push_result(
+                issued,
+                BehaviourOutEvent::Outbound(peer, res.map_err(ProtocolError::Application), tag),
+            );
+        }
+    }
+
+    /// Takes all events that are currently queued, in the order they would have been handed out.
+    ///
+    /// Like [`Behaviour::next_event`], this is meant for driving the behaviour standalone and
+    /// does not dispatch queued protocols. It empties the queue the swarm takes events from, so
+    /// drained events are not yielded by the swarm anymore. Drained events count towards
+    /// [`Behaviour::last_event_sequence`].
+    pub fn drain_events(&mut self) -> Vec<BehaviourOutEvent<I, O, E>> {
+        let events = Vec::from(mem::take(&mut self.events));
+        self.emitted_events += events.len() as u64;
+
+        events
+    }
+
+    /// Returns the sequence number of the last event handed out, `None` if there was none.
+    ///
+    /// Events are numbered from 0 in the order they are handed out, i.e. yielded by the swarm or
+    /// returned by [`Behaviour::next_event`] or [`Behaviour::drain_events`]. Reading the
+    /// number right after receiving an event gives the number of that event, which lets consumers
+    /// that buffer events or fan them out restore their order and drop duplicates. Numbering
+    /// restarts after [`Behaviour::clear`].
+    pub fn last_event_sequence(&self) -> Option<u64> {
+        self.emitted_events.checked_sub(1)
+    }
+
+    /// How long writes to the peer waited for the transport to accept more data, summed over its
+    /// connections since it connected.
+    ///
+    /// Grows while the peer consumes data slower than protocol fns send it.
+    pub fn write_pending_time(&self, peer: &PeerId) -> Duration {
+        let nanos = self
+            .shared
+            .write_pending
+            .lock()
+            .expect("lock not to be poisoned")
+            .get(peer)
+            .map_or(0, |nanos| nanos.load(Ordering::Relaxed));
+
+        Duration::from_nanos(nanos)
+    }
+
+    /// The bytes exchanged with the peer since it connected and its limit, `None` if it is not
+    /// connected.
+    ///
+    /// Sampling it periodically yields the bandwidth the peer currently uses.
+    pub fn peer_bandwidth(&self, peer: &PeerId) -> Option<PeerBandwidth> {
+        let budget = self.shared.bandwidth.get(peer)?;
+
+        Some(PeerBandwidth {
+            bytes_read: budget.bytes_read(),
+            bytes_written: budget.bytes_written(),
+            limit: self.shared.bandwidth.limit(peer),
+        })
+    }
+
+    /// The number of substreams open across all connections.
+    ///
+    /// Inbound substreams count from being negotiated, or being accepted if they were deferred by
+    /// [`Behaviour::set_max_open_substreams`], outbound ones already from dispatching the
+    /// protocol or notification they are requested for. Both count until they are dropped, which
+    /// usually happens when the protocol fn they were handed to completes.
+    pub fn open_substreams(&self) -> usize {
+        self.shared.open_substreams.count()
+    }
+
+    /// The bytes reserved by reads in progress across all connections, see
+    /// [`Behaviour::set_max_read_memory_per_connection`].
+    pub fn estimated_memory(&self) -> usize {
+        self.shared.read_memory.reserved()
+    }
+
+    /// Returns the execution statistics of each protocol, keyed by the protocol the substreams
+    /// were negotiated for.
+    ///
+    /// Only protocols that got to execute on a substream are counted, protocols that failed
+    /// before, e.g. because negotiating their substream failed, are not. The statistics are kept
+    /// across [`Behaviour::clear`].
+    pub fn protocol_stats(&self) -> &HashMap<&'static [u8], ProtocolStats> {
+        &self.protocol_stats
+    }
+
+    /// The bytes transferred by the executions of the negotiated protocol and the time they
+    /// took, summed over all executions since [`Behaviour::set_track_throughput`] enabled
+    /// counting them. `None` if none terminated since.
+    ///
+    /// An execution counts the bytes of all substreams of its connection while it executed,
+    /// including additional ones and notifications. It is counted by the time the event
+    /// terminating it is emitted, and is kept across [`Behaviour::clear`] like the statistics.
+    pub fn protocol_throughput(&self, protocol: &[u8]) -> Option<Throughput> {
+        self.shared
+            .throughput
+            .lock()
+            .expect("lock not to be poisoned")
+            .get(protocol)
+            .copied()
+    }
+
+    /// The protocols and connections of the peer, `None` if it neither is connected nor has
+    /// protocols queued.
+    pub fn peer_stats(&self, peer: &PeerId) -> Option<PeerStats> {
+        let connections = self
+            .connected_peers
+            .get(peer)
+            .map_or(&[][..], Vec::as_slice);
+        let mut stats = PeerStats {
+            connections: connections.len(),
+            in_flight: connections
+                .iter()
+                .filter(|(connection, _)| self.in_flight.contains_key(connection))
+                .count(),
+            ..PeerStats::default()
+        };
+        for (_, queued) in self.queued_protocols.peer_items(peer) {
+            match queued.direction() {
+                Direction::Inbound => stats.pending_in += 1,
+                Direction::Outbound => stats.pending_out += 1,
+            }
+        }
+
+        if stats == PeerStats::default() {
+            return None;
+        }
+
+        Some(stats)
+    }
+
+    /// [`Behaviour::peer_stats`] of all peers that are connected or have protocols queued, in
+    /// no particular order.
+    pub fn all_peer_stats(&self) -> impl Iterator<Item = (PeerId, PeerStats)> + '_ {
+        let queued_only = self
+            .queued_protocols
+            .peers()
+            .map(|(peer, _)| peer)
+            .filter(move |peer| !self.connected_peers.contains_key(peer));
+
+        self.connected_peers
+            .keys()
+            .chain(queued_only)
+            .filter_map(move |peer| Some((*peer, self.peer_stats(peer)?)))
+    }
+
+    /// Describes the queued and executing protocols, in the order they were started.
+    ///
+    /// Protocol fns cannot be recorded, but the descriptors can, for example to issue the
+    /// protocols again after a restart. Cancelled protocols are left out.
+    pub fn export_pending(&self) -> Vec<PendingDescriptor> {
+        let queued = self
+            .queued_protocols
+            .peers()
+            .flat_map(|(peer, _)| self.queued_protocols.peer_items(peer))
+            .map(|(_, queued)| {
+                let descriptor = PendingDescriptor {
+                    peer: queued.peer,
+                    direction: queued.execution.direction(),
+                    tag: queued.tag.clone(),
+                    protocol: queued.kind,
+                    dispatched: false,
+                };
+
+                (queued.issued, descriptor)
+            });
+        let in_flight = self
+            .connected_peers
+            .iter()
+            .flat_map(|(peer, connections)| {
+                connections
+                    .iter()
+                    .map(move |(connection, _)| (peer, connection))
+            })
+            .filter_map(|(peer, connection)| {
+                let in_flight = self.in_flight.get(connection)?;
+                if in_flight.cancelled {
+                    return None;
+                }
+                let descriptor = PendingDescriptor {
+                    peer: *peer,
+                    direction: in_flight.direction,
+                    tag: in_flight.tag.clone(),
+                    protocol: in_flight
+                        .executing
+                        .map(|(protocol, _)| protocol)
+                        .or(in_flight.kind),
+                    dispatched: true,
+                };
+
+                Some((in_flight.issued, descriptor))
+            });
+
+        let mut pending = queued.chain(in_flight).collect::<Vec<_>>();
+        pending.sort_by_key(|(issued, _)| *issued);
+
+        pending
+            .into_iter()
+            .map(|(_, descriptor)| descriptor)
+            .collect()
+    }
+
+    /// Returns a snapshot of the current configuration.
+    ///
+    /// Useful for validating or logging the configuration before building on top of the
+    /// behaviour. Later calls to setters are not reflected in the snapshot.
+    pub fn config(&self) -> BehaviourConfig {
+        BehaviourConfig {
+            protocols: self.protocols.clone(),
+            inbound_allowed: self
+                .shared
+                .inbound_allowed
+                .read()
+                .expect("lock not to be poisoned")
+                .clone(),
+            rejection_frame: self
+                .shared
+                .rejection_frame
+                .read()
+                .expect("lock not to be poisoned")
+                .clone(),
+            preface: self.shared.handshake().preface,
+            negotiate_framing: self.shared.handshake().negotiate_framing,
+            max_negotiation_protocols: self.shared.max_negotiation_protocols(),
+            resolve_simultaneous_open: self.shared.resolve_simultaneous_open.load(Ordering::SeqCst),
+            max_concurrent: self.max_concurrent.clone(),
+            max_outbound_per_peer: self.max_outbound_per_peer,
+            max_open_substreams: self.shared.open_substreams.max(),
+            outbound_rate_limit: self.outbound_bucket.map(|bucket| bucket.limit),
+            outbound_rate_limit_per_peer: self.peer_outbound_limit,
+            write_rate_limit: self.shared.egress_limit.bytes_per_sec(),
+            bandwidth_limit_per_peer: self.shared.bandwidth.default_limit(),
+            peer_bandwidth_limits: self.shared.bandwidth.overrides(),
+            max_read_memory_per_connection: self.shared.read_memory.max_per_connection(),
+            max_queue_time: self.max_queue_time,
+            inbound_timeout: self.shared.protocol_timeout(Direction::Inbound),
+            outbound_timeout: self.shared.protocol_timeout(Direction::Outbound),
+            stall_timeout: self.shared.stall_timeout(),
+            read_timeout: self.shared.io_timeouts.read(),
+            write_timeout: self.shared.io_timeouts.write(),
+            peer_weights: self.peer_weights.clone(),
+            connection_strategy: self.connection_strategy,
+            progress_interval: self.shared.progress_interval(),
+            idle_timeout: self.shared.idle_timeout(),
+            keep_alive_policy: self.shared.keep_alive_policy(),
+            max_executions_per_connection: self.shared.max_executions(),
+            cancel_drain_limit: self.shared.cancel_drain_limit(),
+            timeout_notification: self.shared.timeout_notification(),
+            emit_connection_events: self.emit_connection_events,
+            emit_idle_events: self.emit_idle_events,
+            emit_drained_events: self.emit_drained_events,
+            track_throughput: self.shared.track_throughput.load(Ordering::SeqCst),
+            max_events: self.max_events,
+            event_overflow: self.event_overflow,
+            ordered_results: self.ordered_results,
+        }
+    }
+
+    /// Orders the addresses the swarm dials a peer at, the first one is tried first.
+    ///
+    /// The selector is handed the peer and the addresses we know for it, in the order of the
+    /// connections they belong to, and returns the addresses to dial, e.g. sorted by latency or
+    /// by how recently dialing them succeeded. It is consulted whenever the swarm dials a peer by
+    /// its id and must not call back into the behaviour.
+    pub fn set_dial_address_selector(
+        &mut self,
+        selector: impl Fn(&PeerId, Vec<Multiaddr>) -> Vec<Multiaddr> + Send + Sync + 'static,
+    ) {
+        self.dial_address_selector = Some(Box::new(selector));
+    }
+
+    /// Calls the given callback whenever an event becomes available while none was.
+    ///
+    /// This is meant for embedding the behaviour into event loops that are not driven by wakers:
+    /// the callback signals that polling the behaviour, or the swarm it is part of, yields an
+    /// event. It is called from within the behaviour, e.g. while it is being polled or notified
+    /// about a connection, and must therefore not call back into it.
+    pub fn set_on_event_ready(&mut self, callback: impl Fn() + Send + Sync + 'static) {
+        self.on_event_ready = Some(Box::new(callback));
+    }
+
+    fn push_event(&mut self, event: BehaviourOutEvent<I, O, E>) {
+        #[cfg(feature = "tokio")]
+        if let Some(publish) = &self.publish_event {
+            publish(&event);
+        }
+        if self.is_event_queue_full() {
+            match self.event_overflow {
+                EventOverflow::Block => {}
+                EventOverflow::DropOldest => {
+                    if self.events.pop_front().is_some() {
+                        self.dropped_events += 1;
+                    }
+                }
+                EventOverflow::DropNewest => {
+                    self.dropped_events += 1;
+                    return;
+                }
+            }
+        }
+        if self.events.is_empty() {
+            if let Some(callback) = &self.on_event_ready {
+                callback();
+            }
+            if let Some(waker) = self.event_waker.take() {
+                waker.wake();
+            }
+        }
+
+        self.events.push_back(event);
+    }
+
+    fn pop_event(&mut self) -> Option<BehaviourOutEvent<I, O, E>> {
+        let event = self.events.pop_front()?;
+        self.emitted_events += 1;
+
+        Some(event)
+    }
+
+    /// Returns the number of connections we currently have to the given peer.
+    pub fn connection_count(&self, peer: &PeerId) -> usize {
+        self.connected_peers.get(peer).map_or(0, Vec::len)
+    }
+
+    /// Enables emitting [`BehaviourOutEvent::PeerConnected`] and
+    /// [`BehaviourOutEvent::PeerDisconnected`] whenever a connection is established or closed.
+    pub fn set_emit_connection_events(&mut self, enabled: bool) {
+        self.emit_connection_events = enabled;
+    }
+
+    /// Enables emitting [`BehaviourOutEvent::HandlerIdle`] whenever a connection terminated its
+    /// protocol and is ready to execute the next one.
+    pub fn set_emit_idle_events(&mut self, enabled: bool) {
+        self.emit_idle_events = enabled;
+    }
+
+    /// Enables emitting [`BehaviourOutEvent::Drained`] whenever dispatching a protocol left no
+    /// protocols queued.
+    pub fn set_emit_drained_events(&mut self, enabled: bool) {
+        self.emit_drained_events = enabled;
+    }
+
+    /// Enables counting the bytes protocols transfer, see [`Behaviour::protocol_throughput`].
+    ///
+    /// Disabled by default, to not take a lock for every protocol that terminates.
+    pub fn set_track_throughput(&mut self, enabled: bool) {
+        self.shared
+            .track_throughput
+            .store(enabled, Ordering::SeqCst);
+    }
+
+    /// Keeps idle connections to the given peer alive for at least another `duration`.
+    ///
+    /// Once a deadline has been set, idle connections are closed after it passes unless it is
+    /// pushed out again. Connections that execute a protocol are always kept alive.
+    pub fn touch_keep_alive(&mut self, peer: PeerId, duration: Duration) {
+        let deadline = Instant::now() + duration;
+        let deadline = self
+            .keep_alive_deadlines
+            .get(&peer)
+            .copied()
+            .map_or(deadline, |current| current.max(deadline));
+
+        self.keep_alive_deadlines.insert(peer, deadline);
+
+        for (connection, _) in self.connected_peers.get(&peer).into_iter().flatten() {
+            self.keep_alive_updates
+                .push_back((peer, *connection, deadline));
+        }
+    }
+
+    /// Keeps idle connections alive for the given duration after any of their substreams was
+    /// last read from or written to.
+    ///
+    /// Without an idle timeout, idle connections are only closed once a deadline set through
+    /// [`Behaviour::touch_keep_alive`] passed.
+    pub fn set_idle_timeout(&mut self, timeout: Option<Duration>) {
+        *self
+            .shared
+            .idle_timeout
+            .write()
+            .expect("lock not to be poisoned") = timeout;
+    }
+
+    /// Sets whether handlers keep their connection alive while it is idle.
+    ///
+    /// See [`KeepAlivePolicy`] for how this interacts with executing protocols.
+    pub fn set_keep_alive_policy(&mut self, policy: KeepAlivePolicy) {
+        *self
+            .shared
+            .keep_alive_policy
+            .write()
+            .expect("lock not to be poisoned") = policy;
+    }
+
+    /// Consults the given decider whether idle connections are kept alive.
+    ///
+    /// The decider is passed the peer and the [`KeepAliveState`] of its connection, which
+    /// includes what the handler would keep it alive for on its own, and may override it with
+    /// application knowledge, e.g. keep connections to bootstrap nodes alive indefinitely. It is
+    /// consulted whenever the swarm polls an idle handler, so it should be cheap. Connections
+    /// with protocols waiting for or executing on a substream are kept alive regardless.
+    pub fn set_keep_alive_decider(&mut self, decider: impl KeepAliveDecider + 'static) {
+        *self
+            .shared
+            .keep_alive_decider
+            .write()
+            .expect("lock not to be poisoned") = Some(Box::new(decider));
+    }
+
+    /// Removes the decider set through [`Behaviour::set_keep_alive_decider`], handlers decide on
+    /// their own again.
+    pub fn clear_keep_alive_decider(&mut self) {
+        *self
+            .shared
+            .keep_alive_decider
+            .write()
+            .expect("lock not to be poisoned") = None;
+    }
+
+    /// Closes connections once they served the given number of protocols, inbound and outbound
+    /// alike.
+    ///
+    /// Bounds the state a long-lived connection accumulates and has the peer reconnect, and
+    /// authenticate again, after every `max` protocols. The connection closes right after the
+    /// last protocol terminated, protocols dispatched to it in the meantime fail with
+    /// [`Failure::ConnectionClosed`] and queued ones wait for the peer to reconnect. `None`, the
+    /// default, serves protocols indefinitely.
+    pub fn set_max_executions_per_connection(&mut self, max: Option<usize>) {
+        *self
+            .shared
+            .max_executions
+            .write()
+            .expect("lock not to be poisoned") = max;
+    }
+
+    /// Drains up to `limit` bytes the remote already sent from the substreams of a cancelled
+    /// protocol before dropping them.
+    ///
+    /// Cancelling a protocol, through [`Behaviour::cancel_where`] or [`Behaviour::disconnect`],
+    /// drops its substreams in the middle of whatever the remote was sending. Draining discards
+    /// the data that already arrived instead of leaving it unread, so the remote sees its
+    /// substream reset after what it sent was consumed rather than in the middle of a frame. Only
+    /// data that arrived already is drained, cancelling never waits for the remote. `None`, the
+    /// default, drops the substreams right away.
+    pub fn set_cancel_drain_limit(&mut self, limit: Option<usize>) {
+        *self
+            .shared
+            .cancel_drain_limit
+            .write()
+            .expect("lock not to be poisoned") = limit;
+    }
+
+    /// Attaches application data to the connection, replacing any data attached before.
+    ///
+    /// The data outlives the protocols executed on the connection, e.g. a session key derived by
+    /// a handshake protocol, and is dropped once the connection closes. Data for connections we
+    /// do not know about is dropped right away.
+    pub fn set_connection_data(&mut self, connection: ConnectionId, data: impl Any + Send) {
+        let is_open = self
+            .connected_peers
+            .values()
+            .flatten()
+            .any(|(id, _)| *id == connection);
+
+        if is_open {
+            self.connection_data.insert(connection, Box::new(data));
+        }
+    }
+
+    /// Returns the data attached to the connection, `None` if there is none or it is not a `T`.
+    pub fn get_connection_data<T: Any>(&self, connection: &ConnectionId) -> Option<&T> {
+        self.connection_data.get(connection)?.downcast_ref()
+    }
+
+    /// Returns the deadline until which idle connections to the given peer are kept alive.
+    pub fn keep_alive_deadline(&self, peer: &PeerId) -> Option<Instant> {
+        self.keep_alive_deadlines.get(peer).copied()
+    }
+
+    /// Prunes all peers we track as connected that are no longer connected.
+    ///
+    /// Our bookkeeping relies on the swarm notifying us about every closed
+    /// connection. This allows long-running nodes to reconcile against what
+    /// the swarm actually reports, e.g. by checking each peer against
+    /// `Swarm::is_connected`.
+    pub fn gc(&mut self, is_connected: impl Fn(&PeerId) -> bool) {
+        let in_flight = &mut self.in_flight;
+        let dispatched = &mut self.dispatched;
+        let stats = &mut self.protocol_stats;
+        let mut failed = Vec::new();
+        let mut closed = Vec::new();
+
+        self.connected_peers.retain(|peer, connections| {
+            if !connections.is_empty() && is_connected(peer) {
+                return true;
+            }
+
+            for (connection, _) in connections.iter() {
+                closed.push(*connection);
+                dispatched.remove(connection);
+                if let Some(in_flight) = in_flight.remove(connection) {
+                    in_flight.record(stats, false);
+                    failed.push((
+                        in_flight.issued,
+                        in_flight.failed(*peer, Failure::ConnectionClosed),
+                    ));
+                }
+            }
+
+            false
+        });
+
+        for (issued, event) in failed {
+            self.push_result(issued, event);
+        }
+        for connection in closed {
+            self.connection_data.remove(&connection);
+            self.disconnect_requested.remove(&connection);
+            self.break_sessions(connection);
+        }
+
+        let connected_peers = &self.connected_peers;
+        self.keep_alive_deadlines
+            .retain(|peer, _| connected_peers.contains_key(peer));
+        self.last_dispatched
+            .retain(|peer, _| connected_peers.contains_key(peer));
+        self.peer_outbound_buckets
+            .retain(|peer, _| connected_peers.contains_key(peer));
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<I, O, E> Behaviour<I, O, E>
+where
+    I: Clone + Send + 'static,
+    O: Clone + Send + 'static,
+    E: Clone + Send + 'static,
+{
+    /// Publishes every event on the given channel as well, in addition to handing it out as
+    /// usual.
+    ///
+    /// Each subscriber of the channel receives its own copy, which lets independent parts of an
+    /// application react to events. Publishing never blocks, subscribers that fall behind miss
+    /// the oldest events as described by [`tokio::sync::broadcast`]. Events are published once
+    /// they are produced, so subscribers also see events dropped by
+    /// [`Behaviour::set_max_events`].
+    pub fn set_event_sender(&mut self, sender: broadcast::Sender<BehaviourOutEvent<I, O, E>>) {
+        self.publish_event = Some(Box::new(move |event| {
+            // Sending only fails if nobody is subscribed.
+            let _ = sender.send(event.clone());
+        }));
+    }
+}
+
+impl<I, O, E> Behaviour<I, O, E> {
+    /// Picks the queued protocol to dispatch next and the connection to execute it on.
+    ///
+    /// Peers are served by smooth weighted round-robin: every peer with a dispatchable protocol
+    /// gains its weight, the one with the most gained is served and pays back the sum of all
+    /// weights. Ties go to the peer whose protocol was queued first. This visits every connected
+    /// peer that has queued protocols and an idle connection, the protocols of other peers are
+    /// not looked at.
+    fn next_dispatch(&mut self) -> Option<(PeerId, Position, ConnectionId)> {
+        let now = Instant::now();
+        self.rate_limit_wait = None;
+        // Protocols produce events, so they wait for the consumer to catch up.
+        if self.is_event_queue_full() && self.event_overflow == EventOverflow::Block {
+            return None;
+        }
+
+        let mut candidates: Vec<(PeerId, Position, ConnectionId)> = Vec::new();
+        let mut rate_limit_wait: Option<Duration> = None;
+        let mut consider = |peer: &PeerId| {
+            if self.paused_peers.contains(peer) {
+                return;
+            }
+            // Busy peers cost a single lookup, no matter how many protocols they have queued.
+            let idle = match self.idle_connection(peer) {
+                Some(idle) => idle,
+                None => return,
+            };
+
+            for (position, queued) in self.queued_protocols.peer_items(peer) {
+                let is_outbound = queued.direction() == Direction::Outbound;
+                if self.is_at_capacity(queued.kind, queued.direction())
+                    || (is_outbound && self.is_peer_at_outbound_capacity(peer))
+                {
+                    continue;
+                }
+                let pinned = queued
+                    .session
+                    .and_then(|session| self.sessions.get(&session)?.connection);
+                let connection = match pinned {
+                    Some(connection)
+                        if self.in_flight.contains_key(&connection)
+                            || self.disconnect_requested.contains(&connection) =>
+                    {
+                        continue
+                    }
+                    Some(connection) => connection,
+                    None => idle,
+                };
+                if queued.execution.opens_substream() {
+                    if self.shared.open_substreams.is_full() {
+                        continue;
+                    }
+                    if let Some(wait) = self.outbound_token_wait(peer, now) {
+                        rate_limit_wait = Some(rate_limit_wait.map_or(wait, |w| w.min(wait)));
+                        continue;
+                    }
+                }
+                candidates.push((*peer, position, connection));
+                break;
+            }
+        };
+
+        // Only connected peers with queued protocols matter, found through the smaller of both.
+        if self.connected_peers.len() < self.queued_protocols.peer_count() {
+            self.connected_peers
+                .keys()
+                .filter(|peer| self.queued_protocols.contains_peer(peer))
+                .for_each(&mut consider);
+        } else {
+            self.queued_protocols
+                .peers()
+                .map(|(peer, _)| peer)
+                .for_each(&mut consider);
+        }
+        self.rate_limit_wait = rate_limit_wait;
+
+        let weights = &self.peer_weights;
+        let weight = |peer: &PeerId| i64::from(*weights.get(peer).unwrap_or(&1));
+        let total: i64 = candidates.iter().map(|(peer, ..)| weight(peer)).sum();
+
+        let mut next: Option<(PeerId, Position, ConnectionId, i64)> = None;
+        for (peer, position, connection) in candidates {
+            let current = self.current_weights.entry(peer).or_default();
+            *current += weight(&peer);
+
+            let is_next = next.is_none_or(|(_, next_position, _, max)| {
+                *current > max || (*current == max && position < next_position)
+            });
+            if is_next {
+                next = Some((peer, position, connection, *current));
+            }
+        }
+
+        let (peer, position, connection, _) = next?;
+        *self
+            .current_weights
+            .get_mut(&peer)
+            .expect("candidates to have a current weight") -= total;
+
+        Some((peer, position, connection))
+    }
+
+    /// Returns a connection to the given peer that is not executing a protocol, chosen according
+    /// to the [`ConnectionStrategy`].
+    fn idle_connection(&self, peer: &PeerId) -> Option<ConnectionId> {
+        let connections = self.connected_peers.get(peer)?;
+        let is_idle = |connection: &ConnectionId| {
+            !self.in_flight.contains_key(connection)
+                && !self.disconnect_requested.contains(connection)
+        };
+        let mut idle = connections
+            .iter()
+            .map(|(connection, _)| *connection)
+            .filter(is_idle);
+
+        match self.connection_strategy {
+            ConnectionStrategy::Any | ConnectionStrategy::First => idle.next(),
+            ConnectionStrategy::LeastBusy => idle.min_by_key(|connection| {
+                self.dispatched.get(connection).copied().unwrap_or_default()
+            }),
+            ConnectionStrategy::RoundRobin => {
+                let start = self
+                    .last_dispatched
+                    .get(peer)
+                    .and_then(|last| connections.iter().position(|(id, _)| id == last))
+                    .map_or(0, |last| last + 1);
+
+                connections
+                    .iter()
+                    .cycle()
+                    .skip(start)
+                    .take(connections.len())
+                    .map(|(connection, _)| *connection)
+                    .find(is_idle)
+            }
+        }
+    }
+
+    /// Fails all queued protocols that exceeded [`Behaviour::set_max_queue_time`].
+    fn expire_queued(&mut self, now: Instant) {
+        let max = match self.max_queue_time {
+            Some(max) => max,
+            None => return,
+        };
+        // Protocols are queued in the order they arrive, so the oldest one expires first.
+        while let Some(queued) = self
+            .queued_protocols
+            .pop_oldest_if(|queued| now.saturating_duration_since(queued.queued_at) >= max)
+        {
+            log::debug!(
+                target: LOG_TARGET,
+                "Queued protocol expired peer={} direction={}.",
+                queued.peer,
+                queued.direction()
+            );
+            self.push_result(queued.issued, queued.failed(Failure::QueueTimeout));
+        }
+    }
+
+    /// Fails queued protocols whose deadline passed and cancels executing ones.
+    fn expire_deadlines(&mut self, now: Instant) {
+        while let Some(&(deadline, issued)) = self.pending_deadlines.iter().next() {
+            if deadline > now {
+                return;
+            }
+            self.pending_deadlines.remove(&(deadline, issued));
+
+            let expired = self
+                .queued_protocols
+                .remove_where(|queued| queued.issued == issued);
+            for queued in expired {
+                log::debug!(
+                    target: LOG_TARGET,
+                    "Queued protocol exceeded its deadline peer={}.",
+                    queued.peer
+                );
+                self.push_result(issued, queued.failed(Failure::DeadlineExceeded));
+            }
+
+            // The cancellation is reported as `Failure::DeadlineExceeded` once the handler acted
+            // on it, see `inject_event`.
+            for (peer, connections) in self.connected_peers.iter() {
+                for (connection, _) in connections {
+                    if let Some(in_flight) = self.in_flight.get_mut(connection) {
+                        if in_flight.issued == issued && !in_flight.cancelled {
+                            in_flight.cancelled = true;
+                            self.cancellations.push_back((*peer, *connection));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Returns how long it takes until the next queued protocol expires.
+    fn next_expiry(&self, now: Instant) -> Option<Duration> {
+        let queue_expiry = self.max_queue_time.and_then(|max| {
+            self.queued_protocols
+                .oldest()
+                .map(|queued| (queued.queued_at + max).saturating_duration_since(now))
+        });
+        let deadline = self
+            .pending_deadlines
+            .iter()
+            .next()
+            .map(|(deadline, _)| deadline.saturating_duration_since(now));
+
+        match (queue_expiry, deadline) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    fn queue(
+        &mut self,
+        peer: PeerId,
+        kind: Option<&'static [u8]>,
+        tag: Option<Tag>,
+        execution: QueuedExecution<I, O, E>,
+    ) -> u64 {
+        let issued = self.issue();
+        self.queued_protocols.push_back(
+            peer,
+            QueuedProtocol {
+                peer,
+                issued,
+                kind,
+                tag,
+                session: None,
+                execution,
+                queued_at: Instant::now(),
+            },
+        );
+
+        issued
+    }
+
+    /// Ends all sessions pinned to the given connection and fails their queued protocols.
+    fn break_sessions(&mut self, connection: ConnectionId) {
+        let mut broken = Vec::new();
+        for (id, session) in self.sessions.iter_mut() {
+            if session.connection == Some(connection) && !session.broken {
+                session.broken = true;
+                broken.push(*id);
+            }
+        }
+        if broken.is_empty() {
+            return;
+        }
+
+        let failed = self
+            .queued_protocols
+            .remove_where(|queued| queued.session.iter().any(|id| broken.contains(id)));
+
+        for queued in failed {
+            self.push_result(queued.issued, queued.failed(Failure::ConnectionClosed));
+        }
+    }
+
+    /// Whether another protocol of the given kind would exceed its concurrency limit.
+    fn is_at_capacity(&self, protocol: Option<&'static [u8]>, direction: Direction) -> bool {
+        let (max, shared) = match protocol.and_then(|protocol| self.max_concurrent.get(protocol)) {
+            Some(ConcurrencyBudget::Shared(max)) => (*max, true),
+            Some(ConcurrencyBudget::Split { inbound, .. }) if direction == Direction::Inbound => {
+                (*inbound, false)
+            }
+            Some(ConcurrencyBudget::Split { outbound, .. }) => (*outbound, false),
+            None => return false,
+        };
+        let executing = self
+            .in_flight
+            .values()
+            .filter(|in_flight| in_flight.kind == protocol)
+            .filter(|in_flight| shared || in_flight.direction == direction)
+            .count();
+
+        executing >= max
+    }
+
+    /// Returns how long it takes until the rate limits allow an outbound protocol with the given
+    /// peer, `None` if they allow one now.
+    fn outbound_token_wait(&self, peer: &PeerId, now: Instant) -> Option<Duration> {
+        let peer_wait = self
+            .peer_outbound_buckets
+            .get(peer)
+            .and_then(|bucket| bucket.wait(now));
+        let wait = self.outbound_bucket.and_then(|bucket| bucket.wait(now));
+
+        peer_wait.max(wait)
+    }
+
+    fn take_outbound_token(&mut self, peer: PeerId, now: Instant) {
+        if let Some(bucket) = &mut self.outbound_bucket {
+            bucket.take(now);
+        }
+        if let Some(limit) = self.peer_outbound_limit {
+            self.peer_outbound_buckets
+                .entry(peer)
+                .or_insert_with(|| TokenBucket::new(limit))
+                .take(now);
+        }
+    }
+
+    /// Whether another outbound protocol with the given peer would exceed the per-peer limit.
+    fn is_peer_at_outbound_capacity(&self, peer: &PeerId) -> bool {
+        let max = match self.max_outbound_per_peer {
+            Some(max) => max,
+            None => return false,
+        };
+        let executing = self
+            .connected_peers
+            .get(peer)
+            .into_iter()
+            .flatten()
+            .filter_map(|(connection, _)| self.in_flight.get(connection))
+            .filter(|in_flight| in_flight.direction == Direction::Outbound)
+            .count();
+
+        executing >= max
+    }
+
+    /// Returns the number of protocols queued for dispatch to a connection.
+    #[cfg(feature = "testing")]
+    pub fn pending_in_events(&self) -> usize {
+        self.queued_protocols.len()
+    }
+
+    /// Returns the number of protocols queued for dispatch, per peer.
+    #[cfg(feature = "testing")]
+    pub fn pending_in_events_per_peer(&self) -> HashMap<PeerId, usize> {
+        self.queued_protocols
+            .peers()
+            .map(|(peer, pending)| (*peer, pending))
+            .collect()
+    }
+
+    /// Cancels all protocols whose tag matches the given predicate.
+    ///
+    /// Only protocols started through [`Behaviour::do_protocol_dialer_tagged`] are considered.
+    /// Every cancelled protocol terminates with [`Failure::Cancelled`], unless it terminated
+    /// otherwise before its connection acted on the cancellation.
+    ///
+    /// The substreams of a cancelled protocol are dropped, which the muxer signals to the remote
+    /// as a reset. libp2p 0.37 offers no way to reset a substream explicitly, and yamux sends the
+    /// reset along with the next frame of the connection, so the remote may only notice once the
+    /// connection is used again.
+    pub fn cancel_where(&mut self, predicate: impl Fn(&Tag) -> bool) {
+        self.cancel_where_with_reason(predicate, CancelReason::Unspecified)
+    }
+
+    /// Like [`Behaviour::cancel_where`] but reports the given reason in the
+    /// [`Failure::Cancelled`] of the cancelled protocols.
+    pub fn cancel_where_with_reason(
+        &mut self,
+        predicate: impl Fn(&Tag) -> bool,
+        reason: CancelReason,
+    ) {
+        let matches = |tag: &Option<Tag>| tag.iter().any(&predicate);
+
+        for queued in self
+            .queued_protocols
+            .remove_where(|queued| matches(&queued.tag))
+        {
+            self.push_result(
+                queued.issued,
+                BehaviourOutEvent::OutboundFailed(
+                    queued.peer,
+                    Failure::Cancelled(reason),
+                    queued.tag,
+                ),
+            );
+        }
+
+        for (peer, connections) in self.connected_peers.iter() {
+            for (connection, _) in connections {
+                if let Some(in_flight) = self.in_flight.get_mut(connection) {
+                    if !in_flight.cancelled && matches(&in_flight.tag) {
+                        in_flight.cancelled = true;
+                        in_flight.cancel_reason = reason;
+                        self.cancellations.push_back((*peer, *connection));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Cancels all protocols of the peer and closes its connections.
+    ///
+    /// Queued protocols fail right away, executing ones once their handler dropped them, both
+    /// with [`Failure::Cancelled`] and before the connections close. The connections are
+    /// reported as closed with [`CloseReason::Graceful`]. The peer may connect again afterwards,
+    /// see [`Behaviour::set_ban_checker`] to keep it away.
+    pub fn disconnect(&mut self, peer: PeerId) {
+        for queued in self
+            .queued_protocols
+            .remove_where(|queued| queued.peer == peer)
+        {
+            self.push_result(
+                queued.issued,
+                queued.failed(Failure::Cancelled(CancelReason::Unspecified)),
+            );
+        }
+
+        for (connection, _) in self.connected_peers.get(&peer).into_iter().flatten() {
+            if let Some(in_flight) = self.in_flight.get_mut(connection) {
+                in_flight.cancelled = true;
+            }
+            self.disconnect_requested.insert(*connection);
+            self.disconnects.push_back((peer, *connection));
+        }
+    }
+
+    /// Asks all executing protocols whose tag matches the given predicate to finish.
+    ///
+    /// Unlike [`Behaviour::cancel_where`], which drops the protocol fn wherever it is, this lets
+    /// it complete on its own: its substreams close their write side, so the remote reads EOF,
+    /// and every read from them returns EOF from then on, i.e. [`InboundSubstream::read_message`]
+    /// returns an empty message. Protocol fns that read until EOF therefore terminate with their
+    /// regular result. A read that is in the middle of a frame fails instead and writes fail
+    /// once the write side is closed.
+    ///
+    /// Only protocols started through [`Behaviour::do_protocol_dialer_tagged`] that were
+    /// dispatched to a connection are considered, queued ones stay queued. Protocols that are
+    /// still waiting for their substream read EOF as soon as they get it.
+    pub fn finish_where(&mut self, predicate: impl Fn(&Tag) -> bool) {
+        for (peer, connections) in self.connected_peers.iter() {
+            for (connection, _) in connections {
+                if let Some(in_flight) = self.in_flight.get(connection) {
+                    if !in_flight.cancelled && in_flight.tag.iter().any(&predicate) {
+                        self.finish_requests.push_back((*peer, *connection));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Resets the protocol layer to a clean slate, leaving connections intact.
+    ///
+    /// Drops all events that were not polled yet and all notifications and keep-alive deadlines.
+    /// Every queued protocol terminates with [`Failure::Cancelled`], as does every executing
+    /// outbound protocol once its connection acted on the cancellation. Executing inbound
+    /// protocols run to completion.
+    ///
+    /// Connections, the peers they belong to and all configuration, e.g. concurrency limits and
+    /// peer weights, are kept. Event sequence numbers restart at 0, see
+    /// [`Behaviour::last_event_sequence`].
+    pub fn clear(&mut self) {
+        self.events.clear();
+        self.held_results.clear();
+        self.emitted_events = 0;
+        self.notifications.clear();
+        #[cfg(feature = "probe")]
+        self.probes.clear();
+        self.finish_requests.clear();
+        self.keep_alive_updates.clear();
+        self.keep_alive_deadlines.clear();
+        self.current_weights.clear();
+
+        for queued in self.queued_protocols.take() {
+            self.push_result(
+                queued.issued,
+                queued.failed(Failure::Cancelled(CancelReason::Unspecified)),
+            );
+        }
+
+        for (peer, connections) in self.connected_peers.iter() {
+            for (connection, _) in connections {
+                if let Some(in_flight) = self.in_flight.get_mut(connection) {
+                    if !in_flight.cancelled && in_flight.direction == Direction::Outbound {
+                        in_flight.cancelled = true;
+                        self.cancellations.push_back((*peer, *connection));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Sends the message to the peer as a single frame on its own substream.
+    ///
+    /// Unlike protocols, notifications do not wait for an idle connection and do not produce a
+    /// result. Only failing to send them is reported, as [`BehaviourOutEvent::NotifyFailed`].
+    /// Notifications that are in the process of being sent when their connection closes are
+    /// lost silently. The peer receives a notification by executing a listener protocol.
+    pub fn notify(&mut self, peer: PeerId, message: Vec<u8>) {
+        self.notifications.push_back((peer, message));
+    }
+
+    /// Probes which of our advertised protocols the peer supports.
+    ///
+    /// Opens a substream negotiated for each advertised protocol on a connection to the peer,
+    /// waiting for one if there is none, and closes it again right away. The result is reported
+    /// as [`BehaviourOutEvent::ProtocolsProbed`]. Like notifications, probes do not wait for an
+    /// idle connection.
+    ///
+    /// This is meant for diagnosing why protocols fail to negotiate. The remote sees every
+    /// substream it supports as an inbound substream that closes immediately, which fails a
+    /// listener protocol it happens to execute on it.
+    #[cfg(feature = "probe")]
+    pub fn probe_protocols(&mut self, peer: PeerId) {
+        self.probes.push_back(peer);
+    }
+
+    pub fn do_protocol_listener<F>(
+        &mut self,
+        peer: PeerId,
+        protocol: impl FnOnce(InboundSubstream) -> F + Send + 'static,
+    ) where
+        F: Future<Output = Result<I, E>> + Send + 'static,
+    {
+        self.do_protocol_listener_reusable(peer, move |substream| {
+            protocol(substream).map(|res| res.map(|out| (out, None)))
+        })
+    }
+
+    /// Like [`Behaviour::do_protocol_listener`] but the protocol may hand back its substream.
+    ///
+    /// A returned substream is kept by the connection's handler and passed to the next listener
+    /// protocol executed on that connection instead of waiting for a new inbound substream.
+    pub fn do_protocol_listener_reusable<F>(
+        &mut self,
+        peer: PeerId,
+        protocol: impl FnOnce(InboundSubstream) -> F + Send + 'static,
+    ) where
+        F: Future<Output = Result<(I, Option<InboundSubstream>), E>> + Send + 'static,
+    {
+        self.queue(
+            peer,
+            None,
+            None,
+            QueuedExecution::Inbound(Box::new(move |substream| protocol(substream).boxed())),
+        );
+    }
+
+    /// Like [`Behaviour::do_protocol_listener`] but counts against the concurrency limit of the
+    /// given protocol.
+    ///
+    /// Note that the remote decides which of our advertised protocols the inbound substream is
+    /// negotiated for.
+    pub fn do_protocol_listener_for<F>(
+        &mut self,
+        peer: PeerId,
+        info: &'static [u8],
+        protocol: impl FnOnce(InboundSubstream) -> F + Send + 'static,
+    ) where
+        F: Future<Output = Result<I, E>> + Send + 'static,
+    {
+        self.queue(
+            peer,
+            Some(info),
+            None,
+            QueuedExecution::Inbound(Box::new(move |substream| {
+                protocol(substream)
+                    .map(|res| res.map(|out| (out, None)))
+                    .boxed()
+            })),
+        );
+    }
+
+    /// Like [`Behaviour::do_protocol_listener`] but executes the protocol on the given substream
+    /// instead of waiting for the peer to open one.
+    ///
+    /// This hands substreams negotiated elsewhere, e.g. by another behaviour, to this behaviour,
+    /// constructed through [`InboundSubstream::new`]. The substream must have been negotiated
+    /// for the protocol already and nothing may have been read from or written to it that the
+    /// protocol does not expect. The preface and framing handshakes, if enabled, still take place
+    /// on it.
+    ///
+    /// The protocol is queued and dispatched to an idle connection to the peer like any other, it
+    /// occupies that connection while executing and fails with [`Failure::ConnectionClosed`] if
+    /// the peer disconnects before. The substream itself does not need to belong to that
+    /// connection, but `open_outbound` and `accept_inbound` fail on it.
+    pub fn do_protocol_listener_on<F>(
+        &mut self,
+        peer: PeerId,
+        substream: InboundSubstream,
+        protocol: impl FnOnce(InboundSubstream) -> F + Send + 'static,
+    ) where
         F: Future<Output = Result<I, E>> + Send + 'static,
     {
-        self.protocol_in_events.push_back((
+        self.queue(
             peer,
-            ProtocolInEvent::ExecuteInbound(Box::new(move |substream| protocol(substream).boxed())),
-        ));
+            None,
+            None,
+            QueuedExecution::InboundOn(
+                Box::new(move |substream| {
+                    protocol(substream)
+                        .map(|res| res.map(|out| (out, None)))
+                        .boxed()
+                }),
+                substream,
+            ),
+        );
     }
 
     pub fn do_protocol_dialer<F>(
@@ -375,19 +5950,434 @@ impl<I, O, E> Behaviour<I, O, E> {
     ) where
         F: Future<Output = Result<O, E>> + Send + 'static,
     {
-        self.protocol_in_events.push_back((
+        self.do_protocol_dialer_reusable(peer, move |substream| {
+            protocol(substream).map(|res| res.map(|out| (out, None)))
+        })
+    }
+
+    /// Like [`Behaviour::do_protocol_dialer`] but the protocol may hand back its substream.
+    ///
+    /// A returned substream is kept by the connection's handler and used by the next dialer
+    /// protocol executed on that connection instead of opening a new substream.
+    pub fn do_protocol_dialer_reusable<F>(
+        &mut self,
+        peer: PeerId,
+        protocol: impl FnOnce(OutboundSubstream) -> F + Send + 'static,
+    ) where
+        F: Future<Output = Result<(O, Option<OutboundSubstream>), E>> + Send + 'static,
+    {
+        self.queue(
+            peer,
+            None,
+            None,
+            QueuedExecution::Outbound(Box::new(move |substream| protocol(substream).boxed()), None),
+        );
+    }
+
+    /// Like [`Behaviour::do_protocol_dialer`] but executes the protocol on the given substream
+    /// instead of opening one.
+    ///
+    /// See [`Behaviour::do_protocol_listener_on`] for the requirements on the substream. The
+    /// protocol counts against [`Behaviour::set_max_outbound_per_peer`] but not against the
+    /// outbound rate limits, as it does not open a substream.
+    pub fn do_protocol_dialer_on<F>(
+        &mut self,
+        peer: PeerId,
+        substream: OutboundSubstream,
+        protocol: impl FnOnce(OutboundSubstream) -> F + Send + 'static,
+    ) where
+        F: Future<Output = Result<O, E>> + Send + 'static,
+    {
+        self.queue(
             peer,
-            ProtocolInEvent::ExecuteOutbound(Box::new(move |substream| {
-                protocol(substream).boxed()
-            })),
-        ));
+            None,
+            None,
+            QueuedExecution::OutboundOn(
+                Box::new(move |substream| {
+                    protocol(substream)
+                        .map(|res| res.map(|out| (out, None)))
+                        .boxed()
+                }),
+                substream,
+            ),
+        );
+    }
+
+    /// Like [`Behaviour::do_protocol_dialer`] but negotiates the substream for the given protocol
+    /// only and counts against its concurrency limit.
+    pub fn do_protocol_dialer_for<F>(
+        &mut self,
+        peer: PeerId,
+        info: &'static [u8],
+        protocol: impl FnOnce(OutboundSubstream) -> F + Send + 'static,
+    ) where
+        F: Future<Output = Result<O, E>> + Send + 'static,
+    {
+        self.queue(
+            peer,
+            Some(info),
+            None,
+            QueuedExecution::Outbound(
+                Box::new(move |substream| {
+                    protocol(substream)
+                        .map(|res| res.map(|out| (out, None)))
+                        .boxed()
+                }),
+                Some(info),
+            ),
+        );
+    }
+
+    /// Like [`Behaviour::do_protocol_dialer`] but transforms the protocol's output with `map`
+    /// before it is reported.
+    ///
+    /// `map` runs on the connection's task right after the protocol fn completed, which keeps
+    /// post-processing out of the code consuming the behaviour's events. It is not called if the
+    /// protocol fails.
+    pub fn do_protocol_dialer_map<T, F>(
+        &mut self,
+        peer: PeerId,
+        protocol: impl FnOnce(OutboundSubstream) -> F + Send + 'static,
+        map: impl FnOnce(T) -> O + Send + 'static,
+    ) where
+        F: Future<Output = Result<T, E>> + Send + 'static,
+    {
+        self.do_protocol_dialer(peer, move |substream| {
+            protocol(substream).map(|res| res.map(map))
+        })
+    }
+
+    /// Like [`Behaviour::do_protocol_dialer`] but runs `fallback` instead of reporting
+    /// [`Failure::NegotiationFailed`] if the peer does not support any of the protocols the
+    /// substream is negotiated for.
+    ///
+    /// The fallback does not get a substream and does not occupy the connection. Its result is
+    /// reported as the protocol's [`BehaviourOutEvent::Outbound`] event. Protocols failing for any
+    /// other reason, including [`Failure::NegotiationTimeout`], do not fall back.
+    pub fn do_protocol_dialer_with_fallback<F, G>(
+        &mut self,
+        peer: PeerId,
+        protocol: impl FnOnce(OutboundSubstream) -> F + Send + 'static,
+        fallback: impl FnOnce() -> G + Send + 'static,
+    ) where
+        F: Future<Output = Result<O, E>> + Send + 'static,
+        G: Future<Output = Result<O, E>> + Send + 'static,
+    {
+        let issued = self.queue(
+            peer,
+            None,
+            None,
+            QueuedExecution::Outbound(
+                Box::new(move |substream| {
+                    protocol(substream)
+                        .map(|res| res.map(|out| (out, None)))
+                        .boxed()
+                }),
+                None,
+            ),
+        );
+        self.fallbacks
+            .insert(issued, Box::new(move || fallback().boxed()));
+    }
+
+    /// Like [`Behaviour::do_protocol_dialer`] but fails with [`Failure::DeadlineExceeded`] if the
+    /// protocol did not complete by `deadline`.
+    ///
+    /// Unlike [`Behaviour::set_outbound_timeout`], the deadline is absolute and covers the time
+    /// the protocol is queued, which suits protocols that are part of a larger operation with a
+    /// deadline of its own. An executing protocol is cancelled like through
+    /// [`Behaviour::cancel_where`].
+    pub fn do_protocol_dialer_until<F>(
+        &mut self,
+        peer: PeerId,
+        deadline: Instant,
+        protocol: impl FnOnce(OutboundSubstream) -> F + Send + 'static,
+    ) where
+        F: Future<Output = Result<O, E>> + Send + 'static,
+    {
+        let issued = self.queue(
+            peer,
+            None,
+            None,
+            QueuedExecution::Outbound(
+                Box::new(move |substream| {
+                    protocol(substream)
+                        .map(|res| res.map(|out| (out, None)))
+                        .boxed()
+                }),
+                None,
+            ),
+        );
+        self.deadlines.insert(issued, deadline);
+        self.pending_deadlines.insert((deadline, issued));
+    }
+
+    /// Like [`Behaviour::do_protocol_dialer`] but pings the peer before running the protocol,
+    /// failing with [`keepalive::KeepaliveError::KeepaliveTimeout`] if it does not answer within
+    /// `timeout`.
+    ///
+    /// The protocol fn is handed the substream wrapped through `with_keepalive`, so the peer has
+    /// to wrap its substream as well and answers the ping with its first read.
+    #[cfg(feature = "keepalive")]
+    pub fn do_protocol_dialer_checked<F>(
+        &mut self,
+        peer: PeerId,
+        interval: Duration,
+        timeout: Duration,
+        protocol: impl FnOnce(keepalive::Keepalive<OutboundSubstream>) -> F + Send + 'static,
+    ) where
+        F: Future<Output = Result<O, E>> + Send + 'static,
+        E: From<keepalive::KeepaliveError>,
+    {
+        self.do_protocol_dialer(peer, move |substream| async move {
+            let mut substream = substream.with_keepalive(interval, timeout);
+            substream.ping().await?;
+
+            protocol(substream).await
+        })
+    }
+
+    /// Like [`Behaviour::do_protocol_dialer`] but attaches the given tag to the protocol.
+    ///
+    /// The tag is handed back in the [`BehaviourOutEvent::Outbound`] or
+    /// [`BehaviourOutEvent::OutboundFailed`] event of this protocol.
+    pub fn do_protocol_dialer_tagged<M, F>(
+        &mut self,
+        peer: PeerId,
+        tag: M,
+        protocol: impl FnOnce(OutboundSubstream) -> F + Send + 'static,
+    ) where
+        M: Send + Sync + 'static,
+        F: Future<Output = Result<O, E>> + Send + 'static,
+    {
+        self.queue(
+            peer,
+            None,
+            Some(Tag(Arc::new(tag))),
+            QueuedExecution::Outbound(
+                Box::new(move |substream| {
+                    protocol(substream)
+                        .map(|res| res.map(|out| (out, None)))
+                        .boxed()
+                }),
+                None,
+            ),
+        );
+    }
+
+    /// Like [`Behaviour::do_protocol_dialer`] but hands the protocol fn a [`Partial`] to
+    /// accumulate its progress in, starting out as `initial`.
+    ///
+    /// The partial is the protocol's tag, so it is handed back in the event terminating the
+    /// protocol, most importantly in [`BehaviourOutEvent::OutboundFailed`] if the protocol was
+    /// cancelled, timed out while queued or lost its connection. Retrieve it with
+    /// `tag.downcast_ref::<Partial<P>>()` to recover what the protocol received until then.
+    pub fn do_protocol_dialer_partial<P, F>(
+        &mut self,
+        peer: PeerId,
+        initial: P,
+        protocol: impl FnOnce(OutboundSubstream, Partial<P>) -> F + Send + 'static,
+    ) where
+        P: Send + 'static,
+        F: Future<Output = Result<O, E>> + Send + 'static,
+    {
+        let partial = Partial(Arc::new(Mutex::new(initial)));
+        let handle = partial.clone();
+
+        self.do_protocol_dialer_tagged(peer, partial, move |substream| protocol(substream, handle));
+    }
+
+    /// Opens a session with the peer whose protocols all execute on the same connection.
+    ///
+    /// The session is pinned to the connection its first protocol is dispatched to. Later
+    /// protocols wait for that connection even if others to the peer are idle. Once it is
+    /// closed, the session's queued protocols and all protocols started in it afterwards fail
+    /// with [`Failure::ConnectionClosed`].
+    pub fn open_session(&mut self, peer: PeerId) -> SessionId {
+        let id = SessionId {
+            peer,
+            id: self.next_session,
+        };
+        self.next_session += 1;
+        self.sessions.insert(
+            id,
+            Session {
+                connection: None,
+                broken: false,
+            },
+        );
+
+        id
+    }
+
+    /// Closes the session, failing its queued protocols with [`Failure::Cancelled`].
+    ///
+    /// Protocols started in a closed session fail with [`Failure::ConnectionClosed`].
+    pub fn close_session(&mut self, session: SessionId) {
+        self.sessions.remove(&session);
+
+        let cancelled = self
+            .queued_protocols
+            .remove_where(|queued| queued.session == Some(session));
+
+        for queued in cancelled {
+            self.push_result(
+                queued.issued,
+                queued.failed(Failure::Cancelled(CancelReason::Unspecified)),
+            );
+        }
+    }
+
+    /// Returns the connection the session is pinned to, `None` if none of its protocols was
+    /// dispatched yet or the session was closed.
+    pub fn session_connection(&self, session: SessionId) -> Option<ConnectionId> {
+        self.sessions.get(&session)?.connection
+    }
+
+    /// Like [`Behaviour::do_protocol_dialer`] but executes the protocol on the connection of the
+    /// given session, see [`Behaviour::open_session`].
+    pub fn do_protocol_dialer_in_session<F>(
+        &mut self,
+        session: SessionId,
+        protocol: impl FnOnce(OutboundSubstream) -> F + Send + 'static,
+    ) where
+        F: Future<Output = Result<O, E>> + Send + 'static,
+    {
+        if self
+            .sessions
+            .get(&session)
+            .is_none_or(|session| session.broken)
+        {
+            log::debug!(
+                target: LOG_TARGET,
+                "Failing protocol, session with peer {} is closed.",
+                session.peer
+            );
+            let issued = self.issue();
+            self.push_result(
+                issued,
+                BehaviourOutEvent::OutboundFailed(session.peer, Failure::ConnectionClosed, None),
+            );
+            return;
+        }
+
+        let issued = self.issue();
+        self.queued_protocols.push_back(
+            session.peer,
+            QueuedProtocol {
+                peer: session.peer,
+                issued,
+                kind: None,
+                tag: None,
+                session: Some(session),
+                execution: QueuedExecution::Outbound(
+                    Box::new(move |substream| {
+                        protocol(substream)
+                            .map(|res| res.map(|out| (out, None)))
+                            .boxed()
+                    }),
+                    None,
+                ),
+                queued_at: Instant::now(),
+            },
+        );
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub enum BehaviourOutEvent<I, O, E> {
-    Inbound(PeerId, Result<I, E>),
-    Outbound(PeerId, Result<O, E>),
+    Inbound(PeerId, Result<I, ProtocolError<E>>),
+    /// An outbound protocol terminated, carrying the tag it was started with, if any.
+    Outbound(PeerId, Result<O, ProtocolError<E>>, Option<Tag>),
+    /// An inbound protocol terminated without being executed to completion.
+    InboundFailed(PeerId, Failure),
+    /// An outbound protocol terminated without being executed to completion.
+    OutboundFailed(PeerId, Failure, Option<Tag>),
+    /// An inbound substream was dropped because its protocol is not allowed.
+    Rejected(PeerId, &'static [u8]),
+    /// A connection to the peer was established, carrying the new number of connections.
+    PeerConnected(PeerId, usize),
+    /// A connection to the peer was closed, carrying the remaining number of connections and
+    /// why it closed.
+    PeerDisconnected(PeerId, usize, CloseReason),
+    /// A protocol requested the connection to the peer to be closed.
+    ///
+    /// The connection is closed by the handler, use [`Swarm::ban_peer_id`](libp2p::Swarm::ban_peer_id)
+    /// to also keep the peer from reconnecting.
+    DisconnectPeer(PeerId),
+    /// A notification sent through [`Behaviour::notify`] could not be delivered to the peer.
+    NotifyFailed(PeerId, Failure),
+    /// A protocol executing on the given connection is still running.
+    ///
+    /// Emitted periodically once enabled through [`Behaviour::set_progress_interval`].
+    Progress(PeerId, ConnectionId),
+    /// The given connection terminated its protocol and is ready to execute the next one.
+    ///
+    /// Only emitted once enabled through [`Behaviour::set_emit_idle_events`].
+    HandlerIdle(PeerId, ConnectionId),
+    /// The last queued protocol was dispatched, all protocols started so far are executing or
+    /// terminated.
+    ///
+    /// Protocols leaving the queue without being dispatched, for example because they were
+    /// cancelled, do not count. Only emitted once enabled through
+    /// [`Behaviour::set_emit_drained_events`].
+    Drained,
+    /// The peer supports the given ones of our advertised protocols, in order of preference.
+    ///
+    /// Reports the result of [`Behaviour::probe_protocols`].
+    #[cfg(feature = "probe")]
+    ProtocolsProbed(PeerId, Vec<&'static [u8]>),
+}
+
+/// Whether a protocol was started by [`Behaviour::do_protocol_listener`] or
+/// [`Behaviour::do_protocol_dialer`] and their variants.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Inbound,
+    Outbound,
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Direction::Inbound => write!(f, "inbound"),
+            Direction::Outbound => write!(f, "outbound"),
+        }
+    }
+}
+
+impl<I, O, E> BehaviourOutEvent<I, O, E> {
+    fn from_protocol(
+        peer: PeerId,
+        connection: ConnectionId,
+        event: ProtocolOutEvent<I, O, E>,
+        tag: Option<Tag>,
+    ) -> Self {
+        match event {
+            ProtocolOutEvent::Inbound(res) => BehaviourOutEvent::Inbound(peer, res),
+            ProtocolOutEvent::Outbound(res) => BehaviourOutEvent::Outbound(peer, res, tag),
+            ProtocolOutEvent::InboundFailed(failure) => {
+                BehaviourOutEvent::InboundFailed(peer, failure)
+            }
+            ProtocolOutEvent::OutboundFailed(failure) => {
+                BehaviourOutEvent::OutboundFailed(peer, failure, tag)
+            }
+            ProtocolOutEvent::Rejected(protocol) => BehaviourOutEvent::Rejected(peer, protocol),
+            ProtocolOutEvent::DisconnectRequested => BehaviourOutEvent::DisconnectPeer(peer),
+            ProtocolOutEvent::Progress => BehaviourOutEvent::Progress(peer, connection),
+            ProtocolOutEvent::Idle => BehaviourOutEvent::HandlerIdle(peer, connection),
+            ProtocolOutEvent::NotifyFailed(failure) => {
+                BehaviourOutEvent::NotifyFailed(peer, failure)
+            }
+            ProtocolOutEvent::Executing(_) => {
+                unreachable!("the behaviour records executions without reporting them")
+            }
+            #[cfg(feature = "probe")]
+            ProtocolOutEvent::Probed(supported) => {
+                BehaviourOutEvent::ProtocolsProbed(peer, supported)
+            }
+        }
+    }
 }
 
 impl<I, O, E> NetworkBehaviour for Behaviour<I, O, E>
@@ -396,75 +6386,444 @@ where
     O: Send + 'static,
     E: Send + 'static,
 {
-    type ProtocolsHandler = Handler<I, O, E>;
+    type ProtocolsHandler = IntoHandler<I, O, E>;
     type OutEvent = BehaviourOutEvent<I, O, E>;
 
     fn new_handler(&mut self) -> Self::ProtocolsHandler {
-        Handler::new(self.info)
+        IntoHandler {
+            protocols: self.protocols.clone(),
+            shared: self.shared.clone(),
+            inbound_handlers: self.inbound_handlers.clone(),
+            inbound_pool: self.inbound_pool.clone(),
+            marker: PhantomData,
+        }
     }
 
     fn addresses_of_peer(&mut self, peer: &PeerId) -> Vec<Multiaddr> {
-        self.connected_peers.get(peer).cloned().unwrap_or_default()
+        let addresses = self
+            .connected_peers
+            .get(peer)
+            .into_iter()
+            .flatten()
+            .map(|(_, point)| point.get_remote_address().clone())
+            .collect();
+
+        match &self.dial_address_selector {
+            Some(selector) => selector(peer, addresses),
+            None => addresses,
+        }
     }
 
     fn inject_connected(&mut self, _: &PeerId) {}
 
-    fn inject_disconnected(&mut self, _: &PeerId) {}
+    fn inject_disconnected(&mut self, peer: &PeerId) {
+        for (connection, _) in self.connected_peers.remove(peer).into_iter().flatten() {
+            self.dispatched.remove(&connection);
+            self.disconnect_requested.remove(&connection);
+            if let Some(in_flight) = self.in_flight.remove(&connection) {
+                in_flight.record(&mut self.protocol_stats, false);
+                self.push_result(
+                    in_flight.issued,
+                    in_flight.failed(*peer, Failure::ConnectionClosed),
+                );
+            }
+        }
+        self.keep_alive_deadlines.remove(peer);
+        self.current_weights.remove(peer);
+        self.last_dispatched.remove(peer);
+        self.peer_outbound_buckets.remove(peer);
+        self.shared
+            .write_pending
+            .lock()
+            .expect("lock not to be poisoned")
+            .remove(peer);
+        self.shared.bandwidth.remove(peer);
+    }
 
     fn inject_connection_established(
         &mut self,
         peer: &PeerId,
-        _: &ConnectionId,
+        connection: &ConnectionId,
         point: &ConnectedPoint,
     ) {
-        let multiaddr = point.get_remote_address().clone();
-
         self.connected_peers
             .entry(*peer)
             .or_default()
-            .push(multiaddr);
+            .push((*connection, point.clone()));
+
+        if let Some(deadline) = self.keep_alive_deadlines.get(peer) {
+            self.keep_alive_updates
+                .push_back((*peer, *connection, *deadline));
+        }
+
+        if self.emit_connection_events {
+            self.push_event(BehaviourOutEvent::PeerConnected(
+                *peer,
+                self.connection_count(peer),
+            ));
+        }
     }
 
     fn inject_connection_closed(
         &mut self,
         peer: &PeerId,
-        _: &ConnectionId,
-        point: &ConnectedPoint,
+        connection: &ConnectionId,
+        _: &ConnectedPoint,
     ) {
-        let multiaddr = point.get_remote_address();
+        if let Some(connections) = self.connected_peers.get_mut(peer) {
+            connections.retain(|(id, _)| id != connection);
 
-        self.connected_peers
-            .entry(*peer)
-            .or_default()
-            .retain(|addr| addr != multiaddr);
+            if connections.is_empty() {
+                self.connected_peers.remove(peer);
+            }
+        }
+        self.dispatched.remove(connection);
+        self.connection_data.remove(connection);
+        self.break_sessions(*connection);
+
+        let reason = if self.disconnect_requested.remove(connection) {
+            CloseReason::Graceful
+        } else if self.in_flight.contains_key(connection) {
+            CloseReason::Error
+        } else {
+            CloseReason::KeepAliveTimeout
+        };
+        if let Some(in_flight) = self.in_flight.remove(connection) {
+            log::debug!(
+                target: LOG_TARGET,
+                "Connection closed during protocol peer={} connection={:?} direction={}.",
+                peer,
+                connection,
+                in_flight.direction
+            );
+            in_flight.record(&mut self.protocol_stats, false);
+            self.push_result(
+                in_flight.issued,
+                in_flight.failed(*peer, Failure::ConnectionClosed),
+            );
+        }
+
+        if self.emit_connection_events {
+            self.push_event(BehaviourOutEvent::PeerDisconnected(
+                *peer,
+                self.connection_count(peer),
+                reason,
+            ));
+        }
     }
 
-    fn inject_event(&mut self, peer: PeerId, _: ConnectionId, event: ProtocolOutEvent<I, O, E>) {
-        self.protocol_out_events.push_back((peer, event));
+    fn inject_event(
+        &mut self,
+        peer: PeerId,
+        connection: ConnectionId,
+        event: ProtocolOutEvent<I, O, E>,
+    ) {
+        if matches!(event, ProtocolOutEvent::Idle) && !self.emit_idle_events {
+            return;
+        }
+        if let ProtocolOutEvent::DisconnectRequested = event {
+            self.disconnect_requested.insert(connection);
+        }
+        if let ProtocolOutEvent::Executing(protocol) = event {
+            if let Some(in_flight) = self.in_flight.get_mut(&connection) {
+                in_flight.executing = Some((protocol, Instant::now()));
+            }
+            return;
+        }
+
+        let in_flight = if event.is_terminal() {
+            self.in_flight.remove(&connection)
+        } else {
+            None
+        };
+        match in_flight {
+            Some(in_flight)
+                if matches!(
+                    event,
+                    ProtocolOutEvent::OutboundFailed(Failure::NegotiationFailed)
+                ) && self.fallbacks.contains_key(&in_flight.issued) =>
+            {
+                log::debug!(
+                    target: LOG_TARGET,
+                    "Negotiation failed, running fallback peer={}.",
+                    peer
+                );
+                in_flight.record(&mut self.protocol_stats, false);
+                let issued = in_flight.issued;
+                let fallback = self.fallbacks.remove(&issued).expect("fallback to exist");
+                let tag = in_flight.tag;
+                self.running_fallbacks
+                    .push(fallback().map(move |res| (issued, peer, tag, res)).boxed());
+            }
+            Some(in_flight) => {
+                let event = match event {
+                    ProtocolOutEvent::OutboundFailed(Failure::Cancelled(_))
+                        if self
+                            .deadlines
+                            .get(&in_flight.issued)
+                            .is_some_and(|deadline| *deadline <= Instant::now()) =>
+                    {
+                        ProtocolOutEvent::OutboundFailed(Failure::DeadlineExceeded)
+                    }
+                    ProtocolOutEvent::OutboundFailed(Failure::Cancelled(_)) => {
+                        ProtocolOutEvent::OutboundFailed(Failure::Cancelled(
+                            in_flight.cancel_reason,
+                        ))
+                    }
+                    event => event,
+                };
+                let succeeded = matches!(
+                    event,
+                    ProtocolOutEvent::Inbound(Ok(_)) | ProtocolOutEvent::Outbound(Ok(_))
+                );
+                in_flight.record(&mut self.protocol_stats, succeeded);
+                self.push_result(
+                    in_flight.issued,
+                    BehaviourOutEvent::from_protocol(peer, connection, event, in_flight.tag),
+                );
+            }
+            None => self.push_event(BehaviourOutEvent::from_protocol(
+                peer, connection, event, None,
+            )),
+        }
     }
 
     fn poll(
         &mut self,
-        _: &mut Context<'_>,
-        _: &mut impl PollParameters,
+        cx: &mut Context<'_>,
+        params: &mut impl PollParameters,
     ) -> Poll<NetworkBehaviourAction<ProtocolInEvent<I, O, E>, Self::OutEvent>> {
-        if let Some((peer, event)) = self.protocol_in_events.pop_front() {
-            if !self.connected_peers.contains_key(&peer) {
-                self.protocol_in_events.push_back((peer, event));
+        if self
+            .shared
+            .local_peer_id
+            .read()
+            .expect("lock not to be poisoned")
+            .is_none()
+        {
+            *self
+                .shared
+                .local_peer_id
+                .write()
+                .expect("lock not to be poisoned") = Some(*params.local_peer_id());
+        }
+        self.expire_queued(Instant::now());
+        self.expire_deadlines(Instant::now());
+
+        let mut sweep_bans = false;
+        if let Some((interval, timer)) = &mut self.ban_sweep {
+            while timer.poll_unpin(cx).is_ready() {
+                timer.reset(*interval);
+                sweep_bans = true;
+            }
+        }
+        if sweep_bans {
+            self.enforce_bans();
+        }
+
+        while let Some((peer, connection, deadline)) = self.keep_alive_updates.pop_front() {
+            let is_open = self
+                .connected_peers
+                .get(&peer)
+                .into_iter()
+                .flatten()
+                .any(|(id, _)| *id == connection);
+
+            if is_open {
+                return Poll::Ready(NetworkBehaviourAction::NotifyHandler {
+                    peer_id: peer,
+                    handler: NotifyHandler::One(connection),
+                    event: ProtocolInEvent::KeepAliveUntil(deadline),
+                });
+            }
+        }
+
+        while let Some((peer, connection)) = self.cancellations.pop_front() {
+            if self.in_flight.contains_key(&connection) {
+                return Poll::Ready(NetworkBehaviourAction::NotifyHandler {
+                    peer_id: peer,
+                    handler: NotifyHandler::One(connection),
+                    event: ProtocolInEvent::CancelOutbound,
+                });
+            }
+        }
+
+        while let Some((peer, connection)) = self.bans.pop_front() {
+            if self.in_flight.contains_key(&connection) {
+                return Poll::Ready(NetworkBehaviourAction::NotifyHandler {
+                    peer_id: peer,
+                    handler: NotifyHandler::One(connection),
+                    event: ProtocolInEvent::Ban,
+                });
+            }
+        }
+
+        while let Some((peer, connection)) = self.disconnects.pop_front() {
+            if self.disconnect_requested.contains(&connection) {
+                return Poll::Ready(NetworkBehaviourAction::NotifyHandler {
+                    peer_id: peer,
+                    handler: NotifyHandler::One(connection),
+                    event: ProtocolInEvent::Disconnect,
+                });
+            }
+        }
+
+        while let Some((peer, connection)) = self.protocol_upgrades.pop_front() {
+            let connected = self
+                .connected_peers
+                .get(&peer)
+                .is_some_and(|connections| connections.iter().any(|(c, _)| *c == connection));
+            if connected {
+                return Poll::Ready(NetworkBehaviourAction::NotifyHandler {
+                    peer_id: peer,
+                    handler: NotifyHandler::One(connection),
+                    event: ProtocolInEvent::SetProtocols(self.protocols.clone()),
+                });
+            }
+        }
+
+        while let Some((peer, connection)) = self.finish_requests.pop_front() {
+            if self.in_flight.contains_key(&connection) {
+                return Poll::Ready(NetworkBehaviourAction::NotifyHandler {
+                    peer_id: peer,
+                    handler: NotifyHandler::One(connection),
+                    event: ProtocolInEvent::Finish,
+                });
+            }
+        }
+
+        // While paused, everything stays queued until the application flips us to ready.
+        if self.is_ready() {
+            let open_substreams = self.shared.open_substreams.clone();
+            if open_substreams.max().is_some() {
+                open_substreams.register(cx.waker());
+            }
+
+            let connected_peers = &self.connected_peers;
+            let next = if open_substreams.is_full() {
+                None
             } else {
+                self.notifications
+                    .iter()
+                    .enumerate()
+                    .find_map(|(index, (peer, _))| {
+                        Some((index, connected_peers.get(peer)?.first()?.0))
+                    })
+            };
+
+            if let Some((index, connection)) = next {
+                let (peer, message) = self
+                    .notifications
+                    .remove(index)
+                    .expect("index to be in bounds");
+
+                return Poll::Ready(NetworkBehaviourAction::NotifyHandler {
+                    peer_id: peer,
+                    handler: NotifyHandler::One(connection),
+                    event: ProtocolInEvent::Notify(message, SubstreamSlot::new(open_substreams)),
+                });
+            }
+
+            #[cfg(feature = "probe")]
+            if let Some((index, connection)) =
+                self.probes.iter().enumerate().find_map(|(index, peer)| {
+                    Some((index, self.connected_peers.get(peer)?.first()?.0))
+                })
+            {
+                let peer = self.probes.remove(index).expect("index to be in bounds");
+
+                return Poll::Ready(NetworkBehaviourAction::NotifyHandler {
+                    peer_id: peer,
+                    handler: NotifyHandler::One(connection),
+                    event: ProtocolInEvent::Probe,
+                });
+            }
+
+            while let Some((peer, position, connection)) = self.next_dispatch() {
+                let queued = self
+                    .queued_protocols
+                    .remove(&peer, position)
+                    .expect("dispatched protocol to be queued");
+                if self.shared.is_banned(&queued.peer) {
+                    log::debug!(
+                        target: LOG_TARGET,
+                        "Not dispatching protocol, peer {} is banned.",
+                        queued.peer
+                    );
+                    self.push_result(queued.issued, queued.failed(Failure::Banned));
+                    continue;
+                }
+                let direction = queued.direction();
+                let QueuedProtocol {
+                    peer,
+                    issued,
+                    kind,
+                    tag,
+                    session,
+                    execution,
+                    queued_at,
+                } = queued;
+                if let Some(session) = session.and_then(|session| self.sessions.get_mut(&session)) {
+                    session.connection.get_or_insert(connection);
+                }
+
+                log::debug!(
+                    target: LOG_TARGET,
+                    "Dispatching protocol peer={} connection={:?} direction={}.",
+                    peer,
+                    connection,
+                    direction
+                );
+                if execution.opens_substream() {
+                    self.take_outbound_token(peer, Instant::now());
+                }
+                let event = execution.into_event(&self.shared.open_substreams);
+                *self.dispatched.entry(connection).or_default() += 1;
+                self.last_dispatched.insert(peer, connection);
+                self.in_flight.insert(
+                    connection,
+                    InFlight {
+                        issued,
+                        direction,
+                        kind,
+                        tag,
+                        cancelled: false,
+                        cancel_reason: CancelReason::Unspecified,
+                        executing: None,
+                        queue_wait: queued_at.elapsed(),
+                    },
+                );
+                if self.emit_drained_events && self.queued_protocols.is_empty() {
+                    self.push_event(BehaviourOutEvent::Drained);
+                }
+
                 return Poll::Ready(NetworkBehaviourAction::NotifyHandler {
                     peer_id: peer,
-                    handler: NotifyHandler::Any,
+                    handler: NotifyHandler::One(connection),
                     event,
                 });
             }
         }
 
-        if let Some((peer, event)) = self.protocol_out_events.pop_front() {
-            return Poll::Ready(NetworkBehaviourAction::GenerateEvent(match event {
-                ProtocolOutEvent::Inbound(res) => BehaviourOutEvent::Inbound(peer, res),
-                ProtocolOutEvent::Outbound(res) => BehaviourOutEvent::Outbound(peer, res),
-            }));
+        self.poll_fallbacks(cx);
+
+        if let Some(event) = self.pop_event() {
+            return Poll::Ready(NetworkBehaviourAction::GenerateEvent(event));
+        }
+
+        // Wake up once the rate limits allow dispatching the protocols they held back or once
+        // a queued protocol expires.
+        let wait = match (
+            self.rate_limit_wait.take(),
+            self.next_expiry(Instant::now()),
+        ) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        if let Some(wait) = wait {
+            let timer = self.dispatch_timer.insert(Delay::new(wait));
+            if timer.poll_unpin(cx).is_ready() {
+                cx.waker().wake_by_ref();
+            }
         }
 
         Poll::Pending
@@ -0,0 +1,57 @@
+//! Agreeing on the maximum message size at the start of a protocol.
+//!
+//! Peers running different versions may read with different limits, so a message one of them
+//! considers fine can be too large for the other. Both sides advertise their limit in a fixed
+//! size frame and read all further messages with the smaller of the two.
+
+use crate::FramingError;
+use std::convert::TryFrom;
+use std::fmt;
+
+/// The size of the frame advertising a limit, which is encoded as a big-endian `u64`.
+pub const LIMIT_FRAME_SIZE: usize = 8;
+
+/// The error returned when agreeing on a limit fails.
+#[derive(Debug)]
+pub enum LimitError {
+    /// Sending our limit failed.
+    Write(FramingError),
+    Read(FramingError),
+}
+
+impl From<FramingError> for LimitError {
+    fn from(e: FramingError) -> Self {
+        LimitError::Read(e)
+    }
+}
+
+impl fmt::Display for LimitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LimitError::Write(e) => write!(f, "failed to send message size limit: {}", e),
+            LimitError::Read(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for LimitError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LimitError::Write(e) => Some(e),
+            LimitError::Read(e) => Some(e),
+        }
+    }
+}
+
+pub(crate) fn encode(max_size: usize) -> [u8; LIMIT_FRAME_SIZE] {
+    (max_size as u64).to_be_bytes()
+}
+
+/// Returns the limit both sides agree on, the smaller of both.
+pub(crate) fn effective(max_size: usize, remote: &[u8]) -> usize {
+    let mut frame = [0; LIMIT_FRAME_SIZE];
+    frame.copy_from_slice(remote);
+    let remote = usize::try_from(u64::from_be_bytes(frame)).unwrap_or(usize::MAX);
+
+    max_size.min(remote)
+}
@@ -0,0 +1,112 @@
+//! Accounting for the memory buffered by reads.
+//!
+//! A read reserves what it buffers for as long as it is in progress: the framing reserves the size
+//! of the frame, or of the chunks it hands the frame out in, once it knows it and before buffering
+//! any of it, see [`crate::framing::FrameMemory`]. The reservations of all substreams of a
+//! connection share one limit. Reads that do not fit fail with [`FramingError::MemoryLimit`]
+//! instead of buffering the frame.
+
+use crate::FramingError;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+
+/// The memory reserved across all connections, see
+/// [`crate::Behaviour::set_max_read_memory_per_connection`].
+#[derive(Default)]
+pub(crate) struct ReadMemory {
+    reserved: AtomicUsize,
+    max_per_connection: RwLock<Option<usize>>,
+}
+
+impl ReadMemory {
+    pub(crate) fn reserved(&self) -> usize {
+        self.reserved.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn max_per_connection(&self) -> Option<usize> {
+        *self
+            .max_per_connection
+            .read()
+            .expect("lock not to be poisoned")
+    }
+
+    pub(crate) fn set_max_per_connection(&self, max: Option<usize>) {
+        *self
+            .max_per_connection
+            .write()
+            .expect("lock not to be poisoned") = max;
+    }
+}
+
+/// The memory reserved by the reads of a single connection.
+pub(crate) struct ConnectionMemory {
+    reserved: Mutex<usize>,
+    total: Arc<ReadMemory>,
+}
+
+impl ConnectionMemory {
+    pub(crate) fn new(total: Arc<ReadMemory>) -> Self {
+        Self {
+            reserved: Mutex::new(0),
+            total,
+        }
+    }
+
+    /// Reserves `bytes` until the returned reservation is dropped.
+    pub(crate) fn reserve(self: &Arc<Self>, bytes: usize) -> Result<Reservation, FramingError> {
+        self.add(bytes)?;
+
+        Ok(Reservation {
+            memory: self.clone(),
+            bytes,
+        })
+    }
+
+    fn add(&self, bytes: usize) -> Result<(), FramingError> {
+        let mut reserved = self.reserved.lock().expect("lock not to be poisoned");
+        if let Some(max) = self.total.max_per_connection() {
+            let available = max.saturating_sub(*reserved);
+            if bytes > available {
+                return Err(FramingError::MemoryLimit {
+                    wanted: bytes,
+                    available,
+                });
+            }
+        }
+
+        *reserved += bytes;
+        self.total.reserved.fetch_add(bytes, Ordering::SeqCst);
+
+        Ok(())
+    }
+}
+
+/// Memory reserved by a read in progress.
+pub(crate) struct Reservation {
+    memory: Arc<ConnectionMemory>,
+    bytes: usize,
+}
+
+impl Reservation {
+    /// Reserves another `bytes` until the reservation is dropped.
+    pub(crate) fn extend(&mut self, bytes: usize) -> Result<(), FramingError> {
+        self.memory.add(bytes)?;
+        self.bytes += bytes;
+
+        Ok(())
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        *self
+            .memory
+            .reserved
+            .lock()
+            .expect("lock not to be poisoned") -= self.bytes;
+        self.memory
+            .total
+            .reserved
+            .fetch_sub(self.bytes, Ordering::SeqCst);
+    }
+}
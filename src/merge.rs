@@ -0,0 +1,236 @@
+//! Running two behaviours as one.
+//!
+//! A [`Merged`] behaviour drives both of its behaviours, has their handlers share each
+//! connection and reports the events of both as a single stream of [`MergedEvent`]s. It saves
+//! composing a behaviour by hand for the common case of two [`Behaviour`]s with outputs of
+//! different types. Use a [`crate::multi::MultiBehaviour`] to host more protocols on a single
+//! behaviour.
+//!
+//! ```
+//! # use libp2p::PeerId;
+//! # use libp2p_async_await::Behaviour;
+//! let ping = Behaviour::<(), u32, ()>::new(b"/ping/1.0.0");
+//! let name = Behaviour::<String, (), ()>::new(b"/name/1.0.0");
+//! let mut behaviour = ping.merge(name);
+//!
+//! behaviour
+//!     .first_mut()
+//!     .do_protocol_dialer(PeerId::random(), |_| async { Ok(42) });
+//! ```
+
+use crate::Behaviour;
+use libp2p::core::connection::{ConnectionId, ListenerId};
+use libp2p::core::either::EitherOutput;
+use libp2p::core::{ConnectedPoint, Multiaddr};
+use libp2p::futures::task::{Context, Poll};
+use libp2p::swarm::{
+    IntoProtocolsHandler, IntoProtocolsHandlerSelect, NetworkBehaviour, NetworkBehaviourAction,
+    PollParameters, ProtocolsHandler,
+};
+use libp2p::PeerId;
+use std::{error, io};
+
+/// Two behaviours run as one, see the [module docs](self).
+pub struct Merged<A, B> {
+    first: A,
+    second: B,
+}
+
+/// An event of either behaviour of a [`Merged`] behaviour.
+#[derive(Debug)]
+pub enum MergedEvent<A, B> {
+    First(A),
+    Second(B),
+}
+
+impl<A, B> Merged<A, B> {
+    pub fn new(first: A, second: B) -> Self {
+        Self { first, second }
+    }
+
+    pub fn first(&self) -> &A {
+        &self.first
+    }
+
+    pub fn first_mut(&mut self) -> &mut A {
+        &mut self.first
+    }
+
+    pub fn second(&self) -> &B {
+        &self.second
+    }
+
+    pub fn second_mut(&mut self) -> &mut B {
+        &mut self.second
+    }
+
+    pub fn into_inner(self) -> (A, B) {
+        (self.first, self.second)
+    }
+}
+
+impl<I, O, E> Behaviour<I, O, E> {
+    /// Runs this behaviour and `other` as one, see [`Merged`].
+    pub fn merge<B>(self, other: B) -> Merged<Self, B> {
+        Merged::new(self, other)
+    }
+}
+
+type HandlerInEvent<B> =
+    <<<B as NetworkBehaviour>::ProtocolsHandler as IntoProtocolsHandler>::Handler as ProtocolsHandler>::InEvent;
+type HandlerOutEvent<B> =
+    <<<B as NetworkBehaviour>::ProtocolsHandler as IntoProtocolsHandler>::Handler as ProtocolsHandler>::OutEvent;
+
+impl<A, B> NetworkBehaviour for Merged<A, B>
+where
+    A: NetworkBehaviour,
+    B: NetworkBehaviour,
+{
+    type ProtocolsHandler = IntoProtocolsHandlerSelect<A::ProtocolsHandler, B::ProtocolsHandler>;
+    type OutEvent = MergedEvent<A::OutEvent, B::OutEvent>;
+
+    fn new_handler(&mut self) -> Self::ProtocolsHandler {
+        self.first.new_handler().select(self.second.new_handler())
+    }
+
+    fn addresses_of_peer(&mut self, peer_id: &PeerId) -> Vec<Multiaddr> {
+        let mut addresses = self.first.addresses_of_peer(peer_id);
+        addresses.extend(self.second.addresses_of_peer(peer_id));
+
+        addresses
+    }
+
+    fn inject_connected(&mut self, peer_id: &PeerId) {
+        self.first.inject_connected(peer_id);
+        self.second.inject_connected(peer_id);
+    }
+
+    fn inject_disconnected(&mut self, peer_id: &PeerId) {
+        self.first.inject_disconnected(peer_id);
+        self.second.inject_disconnected(peer_id);
+    }
+
+    fn inject_connection_established(
+        &mut self,
+        peer_id: &PeerId,
+        connection: &ConnectionId,
+        endpoint: &ConnectedPoint,
+    ) {
+        self.first
+            .inject_connection_established(peer_id, connection, endpoint);
+        self.second
+            .inject_connection_established(peer_id, connection, endpoint);
+    }
+
+    fn inject_connection_closed(
+        &mut self,
+        peer_id: &PeerId,
+        connection: &ConnectionId,
+        endpoint: &ConnectedPoint,
+    ) {
+        self.first
+            .inject_connection_closed(peer_id, connection, endpoint);
+        self.second
+            .inject_connection_closed(peer_id, connection, endpoint);
+    }
+
+    fn inject_address_change(
+        &mut self,
+        peer_id: &PeerId,
+        connection: &ConnectionId,
+        old: &ConnectedPoint,
+        new: &ConnectedPoint,
+    ) {
+        self.first
+            .inject_address_change(peer_id, connection, old, new);
+        self.second
+            .inject_address_change(peer_id, connection, old, new);
+    }
+
+    fn inject_event(
+        &mut self,
+        peer_id: PeerId,
+        connection: ConnectionId,
+        event: EitherOutput<HandlerOutEvent<A>, HandlerOutEvent<B>>,
+    ) {
+        match event {
+            EitherOutput::First(event) => self.first.inject_event(peer_id, connection, event),
+            EitherOutput::Second(event) => self.second.inject_event(peer_id, connection, event),
+        }
+    }
+
+    fn inject_addr_reach_failure(
+        &mut self,
+        peer_id: Option<&PeerId>,
+        addr: &Multiaddr,
+        error: &dyn error::Error,
+    ) {
+        self.first.inject_addr_reach_failure(peer_id, addr, error);
+        self.second.inject_addr_reach_failure(peer_id, addr, error);
+    }
+
+    fn inject_dial_failure(&mut self, peer_id: &PeerId) {
+        self.first.inject_dial_failure(peer_id);
+        self.second.inject_dial_failure(peer_id);
+    }
+
+    fn inject_new_listener(&mut self, id: ListenerId) {
+        self.first.inject_new_listener(id);
+        self.second.inject_new_listener(id);
+    }
+
+    fn inject_new_listen_addr(&mut self, id: ListenerId, addr: &Multiaddr) {
+        self.first.inject_new_listen_addr(id, addr);
+        self.second.inject_new_listen_addr(id, addr);
+    }
+
+    fn inject_expired_listen_addr(&mut self, id: ListenerId, addr: &Multiaddr) {
+        self.first.inject_expired_listen_addr(id, addr);
+        self.second.inject_expired_listen_addr(id, addr);
+    }
+
+    fn inject_listener_error(&mut self, id: ListenerId, err: &(dyn error::Error + 'static)) {
+        self.first.inject_listener_error(id, err);
+        self.second.inject_listener_error(id, err);
+    }
+
+    fn inject_listener_closed(&mut self, id: ListenerId, reason: Result<(), &io::Error>) {
+        self.first.inject_listener_closed(id, reason);
+        self.second.inject_listener_closed(id, reason);
+    }
+
+    fn inject_new_external_addr(&mut self, addr: &Multiaddr) {
+        self.first.inject_new_external_addr(addr);
+        self.second.inject_new_external_addr(addr);
+    }
+
+    fn inject_expired_external_addr(&mut self, addr: &Multiaddr) {
+        self.first.inject_expired_external_addr(addr);
+        self.second.inject_expired_external_addr(addr);
+    }
+
+    fn poll(
+        &mut self,
+        cx: &mut Context<'_>,
+        params: &mut impl PollParameters,
+    ) -> Poll<
+        NetworkBehaviourAction<EitherOutput<HandlerInEvent<A>, HandlerInEvent<B>>, Self::OutEvent>,
+    > {
+        if let Poll::Ready(action) = self.first.poll(cx, params) {
+            return Poll::Ready(
+                action
+                    .map_in(EitherOutput::First)
+                    .map_out(MergedEvent::First),
+            );
+        }
+        if let Poll::Ready(action) = self.second.poll(cx, params) {
+            return Poll::Ready(
+                action
+                    .map_in(EitherOutput::Second)
+                    .map_out(MergedEvent::Second),
+            );
+        }
+
+        Poll::Pending
+    }
+}
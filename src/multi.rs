@@ -0,0 +1,99 @@
+//! Hosting several protocols with outputs of different types on one [`Behaviour`].
+//!
+//! A [`MultiBehaviour`] advertises all of its protocols on every connection and has a single
+//! handler per connection serve them, instead of composing one behaviour and handler per
+//! protocol. Inbound substreams are dispatched to the handler of their negotiated protocol and
+//! outputs of all protocols are reported as [`MultiOutput`], to be downcast by the consumer.
+//! Like with any [`Behaviour`], each connection executes one protocol at a time.
+//!
+//! ```
+//! # use libp2p::PeerId;
+//! # use libp2p_async_await::multi::MultiBehaviour;
+//! let mut behaviour = MultiBehaviour::<()>::with_protocols(vec![&b"/ping/1.0.0"[..], b"/name/1.0.0"]);
+//!
+//! behaviour.handle(b"/name/1.0.0", |_| async { Ok(String::from("alice")) });
+//! behaviour.dial(PeerId::random(), b"/ping/1.0.0", |_| async { Ok(42u32) });
+//! ```
+
+use crate::{Behaviour, InboundSubstream, OutboundSubstream};
+use libp2p::futures::FutureExt;
+use libp2p::PeerId;
+use std::any::Any;
+use std::fmt;
+use std::future::Future;
+
+/// A [`Behaviour`] serving several protocols with outputs of different types, see the
+/// [module docs](self).
+pub type MultiBehaviour<E> = Behaviour<MultiOutput, MultiOutput, E>;
+
+/// The output of a protocol hosted by a [`MultiBehaviour`].
+pub struct MultiOutput {
+    protocol: &'static [u8],
+    value: Box<dyn Any + Send>,
+}
+
+impl MultiOutput {
+    fn new<T: Any + Send>(protocol: &'static [u8], value: T) -> Self {
+        Self {
+            protocol,
+            value: Box::new(value),
+        }
+    }
+
+    /// The protocol that produced the output.
+    pub fn protocol(&self) -> &'static [u8] {
+        self.protocol
+    }
+
+    /// Returns the output if it is of the given type, hands it back otherwise.
+    pub fn downcast<T: Any>(self) -> Result<T, Self> {
+        let protocol = self.protocol;
+
+        match self.value.downcast() {
+            Ok(value) => Ok(*value),
+            Err(value) => Err(Self { protocol, value }),
+        }
+    }
+}
+
+impl fmt::Debug for MultiOutput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MultiOutput")
+            .field("protocol", &String::from_utf8_lossy(self.protocol))
+            .finish()
+    }
+}
+
+impl<E> Behaviour<MultiOutput, MultiOutput, E> {
+    /// Serves every inbound substream negotiated for `info` with `handler`.
+    ///
+    /// Once a handler is set, inbound substreams of protocols without one are rejected, see
+    /// [`Behaviour::set_inbound_handler_for`].
+    pub fn handle<T, F>(
+        &mut self,
+        info: &'static [u8],
+        handler: impl Fn(InboundSubstream) -> F + Send + Sync + 'static,
+    ) where
+        T: Any + Send,
+        F: Future<Output = Result<T, E>> + Send + 'static,
+    {
+        self.set_inbound_handler_for(info, move |substream| {
+            handler(substream).map(move |res| res.map(|value| MultiOutput::new(info, value)))
+        });
+    }
+
+    /// Executes `protocol` on a substream to the peer negotiated for `info`.
+    pub fn dial<T, F>(
+        &mut self,
+        peer: PeerId,
+        info: &'static [u8],
+        protocol: impl FnOnce(OutboundSubstream) -> F + Send + 'static,
+    ) where
+        T: Any + Send,
+        F: Future<Output = Result<T, E>> + Send + 'static,
+    {
+        self.do_protocol_dialer_for(peer, info, move |substream| {
+            protocol(substream).map(move |res| res.map(|value| MultiOutput::new(info, value)))
+        });
+    }
+}
@@ -0,0 +1,75 @@
+use crate::{InboundSubstream, OutboundSubstream};
+use libp2p::core::upgrade;
+use libp2p::futures::future::{self, Either};
+use libp2p::futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use std::io;
+
+/// The maximum size of a single frame forwarded by [`pipe`].
+const MAX_FRAME_SIZE: usize = 1024 * 1024;
+
+/// Shuttles length-prefixed frames between the two substreams in both directions.
+///
+/// Completes once either substream is closed by the remote, after closing the other one. Frames
+/// larger than 1 MiB are not forwarded and fail the pipe with [`io::ErrorKind::InvalidData`].
+pub async fn pipe(inbound: InboundSubstream, outbound: OutboundSubstream) -> io::Result<()> {
+    let (inbound_read, inbound_write) = inbound.split();
+    let (outbound_read, outbound_write) = outbound.split();
+
+    let inbound_to_outbound = Box::pin(forward(inbound_read, outbound_write));
+    let outbound_to_inbound = Box::pin(forward(outbound_read, inbound_write));
+
+    match future::select(inbound_to_outbound, outbound_to_inbound).await {
+        Either::Left((res, _)) | Either::Right((res, _)) => res,
+    }
+}
+
+async fn forward(
+    mut from: impl AsyncRead + Unpin,
+    mut to: impl AsyncWrite + Unpin,
+) -> io::Result<()> {
+    while let Some(frame) = read_frame(&mut from, MAX_FRAME_SIZE).await? {
+        upgrade::write_with_len_prefix(&mut to, &frame).await?;
+    }
+
+    to.close().await
+}
+
+/// Reads a length-prefixed frame, returning `None` if the substream is closed before a new frame
+/// starts.
+async fn read_frame(
+    socket: &mut (impl AsyncRead + Unpin),
+    max_size: usize,
+) -> io::Result<Option<Vec<u8>>> {
+    let mut len = 0usize;
+
+    for index in 0..10 {
+        let mut byte = [0u8];
+        if socket.read(&mut byte).await? == 0 {
+            return match index {
+                0 => Ok(None),
+                _ => Err(io::ErrorKind::UnexpectedEof.into()),
+            };
+        }
+
+        len |= usize::from(byte[0] & 0x7f) << (7 * index);
+
+        if byte[0] & 0x80 == 0 {
+            if len > max_size {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("frame of {} bytes exceeds maximum of {}", len, max_size),
+                ));
+            }
+
+            let mut frame = vec![0; len];
+            socket.read_exact(&mut frame).await?;
+
+            return Ok(Some(frame));
+        }
+    }
+
+    Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "frame length prefix is too long",
+    ))
+}
@@ -0,0 +1,86 @@
+//! Probing which of our advertised protocols a remote supports.
+//!
+//! A probe opens one substream per advertised protocol, each negotiated for that protocol only,
+//! and closes it again right away. The protocols whose substream negotiated are supported.
+
+use std::collections::{HashMap, VecDeque};
+
+/// The probes of a connection's handler.
+#[derive(Default)]
+pub(crate) struct Probes {
+    /// Substreams yet to be requested, by probe and protocol.
+    pending: VecDeque<(u64, &'static [u8])>,
+    running: HashMap<u64, Probe>,
+    next_id: u64,
+}
+
+struct Probe {
+    protocols: Vec<&'static [u8]>,
+    /// Whether each protocol negotiated, `None` while its substream is outstanding.
+    outcomes: Vec<Option<bool>>,
+}
+
+impl Probes {
+    /// Starts probing the given protocols, returning the result right away if there are none.
+    pub(crate) fn start(&mut self, protocols: Vec<&'static [u8]>) -> Option<Vec<&'static [u8]>> {
+        if protocols.is_empty() {
+            return Some(Vec::new());
+        }
+
+        let id = self.next_id;
+        self.next_id += 1;
+
+        self.pending
+            .extend(protocols.iter().map(|protocol| (id, *protocol)));
+        self.running.insert(
+            id,
+            Probe {
+                outcomes: vec![None; protocols.len()],
+                protocols,
+            },
+        );
+
+        None
+    }
+
+    /// The next substream to request, identified by its probe and protocol.
+    pub(crate) fn next_request(&mut self) -> Option<(u64, &'static [u8])> {
+        self.pending.pop_front()
+    }
+
+    /// Records whether the protocol negotiated, returning the supported protocols once the
+    /// probe is complete.
+    pub(crate) fn record(
+        &mut self,
+        id: u64,
+        protocol: &'static [u8],
+        supported: bool,
+    ) -> Option<Vec<&'static [u8]>> {
+        let probe = self.running.get_mut(&id)?;
+        let index = probe
+            .protocols
+            .iter()
+            .zip(&probe.outcomes)
+            .position(|(p, outcome)| *p == protocol && outcome.is_none())?;
+        probe.outcomes[index] = Some(supported);
+
+        if probe.outcomes.iter().any(Option::is_none) {
+            return None;
+        }
+
+        let probe = self.running.remove(&id).expect("probe to be running");
+        let supported = probe
+            .protocols
+            .into_iter()
+            .zip(probe.outcomes)
+            .filter(|(_, outcome)| *outcome == Some(true))
+            .map(|(protocol, _)| protocol)
+            .collect();
+
+        Some(supported)
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.running.is_empty()
+    }
+}
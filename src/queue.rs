@@ -0,0 +1,132 @@
+//! The queue of protocols waiting for an idle connection, indexed by peer.
+//!
+//! Dispatching only looks at the protocols of peers it can dispatch to, so protocols queued for
+//! peers that are not connected or busy cost nothing while they wait.
+
+use libp2p::PeerId;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::mem;
+
+/// Identifies a queued item, in the order items were queued.
+pub(crate) type Position = u64;
+
+pub(crate) struct Queue<T> {
+    by_peer: HashMap<PeerId, VecDeque<(Position, T)>>,
+    /// The peer of every queued item, in the order they were queued.
+    order: BTreeMap<Position, PeerId>,
+    next_position: Position,
+}
+
+impl<T> Default for Queue<T> {
+    fn default() -> Self {
+        Self {
+            by_peer: HashMap::default(),
+            order: BTreeMap::default(),
+            next_position: 0,
+        }
+    }
+}
+
+impl<T> Queue<T> {
+    pub(crate) fn push_back(&mut self, peer: PeerId, item: T) {
+        let position = self.next_position;
+        self.next_position += 1;
+
+        self.by_peer
+            .entry(peer)
+            .or_default()
+            .push_back((position, item));
+        self.order.insert(position, peer);
+    }
+
+    #[cfg(feature = "testing")]
+    pub(crate) fn len(&self) -> usize {
+        self.order.len()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.order.is_empty()
+    }
+
+    /// The number of peers with queued items.
+    pub(crate) fn peer_count(&self) -> usize {
+        self.by_peer.len()
+    }
+
+    pub(crate) fn contains_peer(&self, peer: &PeerId) -> bool {
+        self.by_peer.contains_key(peer)
+    }
+
+    /// The peers with queued items and how many each has.
+    pub(crate) fn peers(&self) -> impl Iterator<Item = (&PeerId, usize)> {
+        self.by_peer.iter().map(|(peer, items)| (peer, items.len()))
+    }
+
+    /// The items queued for the peer, in the order they were queued.
+    pub(crate) fn peer_items(&self, peer: &PeerId) -> impl Iterator<Item = (Position, &T)> {
+        self.by_peer
+            .get(peer)
+            .into_iter()
+            .flatten()
+            .map(|(position, item)| (*position, item))
+    }
+
+    /// The item that was queued first.
+    pub(crate) fn oldest(&self) -> Option<&T> {
+        let (_, peer) = self.order.iter().next()?;
+        let items = self.by_peer.get(peer).expect("ordered items to be queued");
+
+        items.front().map(|(_, item)| item)
+    }
+
+    /// Removes the item that was queued first if it matches the predicate.
+    pub(crate) fn pop_oldest_if(&mut self, predicate: impl FnOnce(&T) -> bool) -> Option<T> {
+        let (&position, &peer) = self.order.iter().next()?;
+        if !predicate(self.oldest()?) {
+            return None;
+        }
+
+        self.remove(&peer, position)
+    }
+
+    pub(crate) fn remove(&mut self, peer: &PeerId, position: Position) -> Option<T> {
+        let items = self.by_peer.get_mut(peer)?;
+        let index = items.binary_search_by_key(&position, |(p, _)| *p).ok()?;
+        let (_, item) = items.remove(index).expect("index to be in bounds");
+
+        if items.is_empty() {
+            self.by_peer.remove(peer);
+        }
+        self.order.remove(&position);
+
+        Some(item)
+    }
+
+    /// Removes all items matching the predicate, in the order they were queued.
+    pub(crate) fn remove_where(&mut self, mut predicate: impl FnMut(&T) -> bool) -> Vec<T> {
+        let mut removed = Vec::new();
+        self.by_peer.retain(|_, items| {
+            let (matching, kept): (VecDeque<_>, _) = mem::take(items)
+                .into_iter()
+                .partition(|(_, item)| predicate(item));
+            *items = kept;
+            removed.extend(matching);
+
+            !items.is_empty()
+        });
+        removed.sort_by_key(|(position, _)| *position);
+
+        removed
+            .into_iter()
+            .map(|(position, item)| {
+                self.order.remove(&position);
+                item
+            })
+            .collect()
+    }
+
+    /// Removes all items, in the order they were queued.
+    pub(crate) fn take(&mut self) -> Vec<T> {
+        self.remove_where(|_| true)
+    }
+}
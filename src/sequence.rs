@@ -0,0 +1,145 @@
+//! Sequence numbers for detecting lost and repeated messages across substreams.
+//!
+//! Messages written through `write_seq_message` carry the sequence number of a [`SequenceWriter`]
+//! in front of the message, within the same frame. `read_seq_message` checks them against a
+//! [`SequenceReader`]. Both outlive individual substreams, so a protocol that reconnects can
+//! continue where it left off and ask for the messages it missed.
+
+use crate::FramingError;
+use std::fmt;
+
+/// The number of bytes the sequence number takes up in a frame.
+pub(crate) const SEQUENCE_LEN: usize = 8;
+
+/// Hands out the sequence numbers of written messages, starting at 0.
+#[derive(Clone, Debug, Default)]
+pub struct SequenceWriter {
+    next: u64,
+}
+
+impl SequenceWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The sequence number of the next message.
+    pub fn next(&self) -> u64 {
+        self.next
+    }
+
+    /// Continues with the given sequence number, e.g. to retransmit messages the remote missed.
+    pub fn set_next(&mut self, next: u64) {
+        self.next = next;
+    }
+
+    pub(crate) fn frame(&mut self, msg: &[u8]) -> (u64, Vec<u8>) {
+        let sequence = self.next;
+        self.next += 1;
+
+        let mut frame = Vec::with_capacity(SEQUENCE_LEN + msg.len());
+        frame.extend_from_slice(&sequence.to_be_bytes());
+        frame.extend_from_slice(msg);
+
+        (sequence, frame)
+    }
+}
+
+/// Checks the sequence numbers of read messages, expecting 0 first.
+#[derive(Clone, Debug, Default)]
+pub struct SequenceReader {
+    expected: u64,
+    allow_gaps: bool,
+}
+
+impl SequenceReader {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The sequence number of the next message.
+    pub fn expected(&self) -> u64 {
+        self.expected
+    }
+
+    /// Accepts messages that skip sequence numbers instead of failing with
+    /// [`SequenceError::Gap`].
+    ///
+    /// Messages with a sequence number that was already read are rejected either way.
+    pub fn allow_gaps(&mut self, allow: bool) {
+        self.allow_gaps = allow;
+    }
+
+    /// Splits the sequence number off the frame and checks it.
+    ///
+    /// Rejected messages leave the expected sequence number unchanged.
+    pub(crate) fn check(&mut self, mut frame: Vec<u8>) -> Result<(u64, Vec<u8>), SequenceError> {
+        let mut sequence = [0; SEQUENCE_LEN];
+        sequence.copy_from_slice(&frame[..SEQUENCE_LEN]);
+        let received = u64::from_be_bytes(sequence);
+
+        if received < self.expected {
+            return Err(SequenceError::Repeated {
+                expected: self.expected,
+                received,
+            });
+        }
+        if received > self.expected && !self.allow_gaps {
+            return Err(SequenceError::Gap {
+                expected: self.expected,
+                received,
+            });
+        }
+        self.expected = received + 1;
+
+        Ok((received, frame.split_off(SEQUENCE_LEN)))
+    }
+}
+
+/// The error returned when reading a sequenced message fails.
+#[derive(Debug)]
+pub enum SequenceError {
+    Read(FramingError),
+    /// The message skipped the sequence numbers from `expected` up to `received`.
+    Gap {
+        expected: u64,
+        received: u64,
+    },
+    /// The message is a duplicate, arrived out of order or was skipped by an allowed gap.
+    Repeated {
+        expected: u64,
+        received: u64,
+    },
+}
+
+impl From<FramingError> for SequenceError {
+    fn from(e: FramingError) -> Self {
+        SequenceError::Read(e)
+    }
+}
+
+impl fmt::Display for SequenceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SequenceError::Read(e) => write!(f, "{}", e),
+            SequenceError::Gap { expected, received } => write!(
+                f,
+                "expected message {} but received message {}",
+                expected, received
+            ),
+            SequenceError::Repeated { expected, received } => write!(
+                f,
+                "received stale message {}, expected message {}",
+                received, expected
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SequenceError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SequenceError::Read(e) => Some(e),
+            SequenceError::Gap { .. } | SequenceError::Repeated { .. } => None,
+        }
+    }
+}
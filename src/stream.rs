@@ -0,0 +1,244 @@
+//! Adapters for consuming [`BehaviourOutEvent`]s as [`Stream`]s.
+
+use crate::{BehaviourOutEvent, Failure, ProtocolError};
+use libp2p::futures::task::{waker_ref, ArcWake, Context, Poll, Waker};
+use libp2p::futures::{Stream, StreamExt};
+use libp2p::PeerId;
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+
+/// Splits a stream of [`BehaviourOutEvent`]s into a stream of inbound results
+/// and a stream of outbound results.
+///
+/// Events are routed to the half they belong to, regardless of which half
+/// polled the underlying stream. Both halves end once the underlying stream
+/// ends and all buffered events have been consumed. Protocols that failed
+/// without being executed are reported as the outer [`Failure`], protocols
+/// that executed as the inner result of their protocol fn. Events that are
+/// not the result of a protocol are skipped.
+///
+/// Whichever half polls the underlying stream, both are woken once it has
+/// new events. Results are buffered until their half polls them, so a half
+/// that is kept around without being polled accumulates the results of its
+/// direction. Dropping a half discards its results instead.
+///
+/// # Example
+///
+/// ```
+/// # use libp2p::futures::{executor, stream, StreamExt};
+/// # use libp2p::PeerId;
+/// # use libp2p_async_await::{BehaviourOutEvent, Failure};
+/// let peer = PeerId::random();
+/// let events = stream::iter(vec![
+///     BehaviourOutEvent::<u32, &str, Failure>::Outbound(peer, Ok("pong"), None),
+///     BehaviourOutEvent::Inbound(peer, Ok(42)),
+/// ]);
+///
+/// let (inbound, outbound) = libp2p_async_await::stream::split(events);
+///
+/// let inbound = executor::block_on(inbound.collect::<Vec<_>>());
+/// let outbound = executor::block_on(outbound.collect::<Vec<_>>());
+///
+/// assert!(matches!(inbound.as_slice(), [(p, Ok(Ok(42)))] if *p == peer));
+/// assert!(matches!(outbound.as_slice(), [(p, Ok(Ok("pong")))] if *p == peer));
+/// ```
+#[allow(clippy::type_complexity)]
+pub fn split<S, I, O, E>(events: S) -> (InboundResults<S, I, O, E>, OutboundResults<S, I, O, E>)
+where
+    S: Stream<Item = BehaviourOutEvent<I, O, E>> + Unpin,
+{
+    let shared = Arc::new(Mutex::new(Shared {
+        events,
+        done: false,
+        inbound: Some(VecDeque::default()),
+        outbound: Some(VecDeque::default()),
+        wakers: Arc::default(),
+    }));
+
+    (
+        InboundResults {
+            shared: shared.clone(),
+        },
+        OutboundResults { shared },
+    )
+}
+
+/// The result of a protocol, failing with [`Failure`] if it did not execute.
+pub type ProtocolResult<T, E> = Result<Result<T, ProtocolError<E>>, Failure>;
+
+struct Shared<S, I, O, E> {
+    events: S,
+    done: bool,
+    /// The results not yet polled by each half, `None` once it was dropped.
+    inbound: Option<VecDeque<(PeerId, ProtocolResult<I, E>)>>,
+    outbound: Option<VecDeque<(PeerId, ProtocolResult<O, E>)>>,
+    wakers: Arc<Wakers>,
+}
+
+/// The tasks of both halves, woken together by the underlying stream.
+#[derive(Default)]
+struct Wakers {
+    inbound: Mutex<Option<Waker>>,
+    outbound: Mutex<Option<Waker>>,
+}
+
+impl Wakers {
+    fn register(&self, inbound: bool, waker: &Waker) {
+        let slot = if inbound {
+            &self.inbound
+        } else {
+            &self.outbound
+        };
+        *slot.lock().expect("lock not to be poisoned") = Some(waker.clone());
+    }
+
+    fn wake_half(&self, inbound: bool) {
+        let slot = if inbound {
+            &self.inbound
+        } else {
+            &self.outbound
+        };
+        if let Some(waker) = slot.lock().expect("lock not to be poisoned").take() {
+            waker.wake();
+        }
+    }
+}
+
+impl ArcWake for Wakers {
+    fn wake_by_ref(arc_self: &Arc<Self>) {
+        arc_self.wake_half(true);
+        arc_self.wake_half(false);
+    }
+}
+
+impl<S, I, O, E> Shared<S, I, O, E>
+where
+    S: Stream<Item = BehaviourOutEvent<I, O, E>> + Unpin,
+{
+    /// Pulls events from the underlying stream until one for the requested
+    /// direction shows up, the stream ends or it is pending.
+    fn poll_until(&mut self, cx: &mut Context<'_>, inbound: bool) -> Poll<()> {
+        self.wakers.register(inbound, cx.waker());
+        let wakers = self.wakers.clone();
+        let waker = waker_ref(&wakers);
+        let mut inner_cx = Context::from_waker(&waker);
+
+        loop {
+            let has_results = if inbound {
+                self.inbound
+                    .as_ref()
+                    .is_some_and(|results| !results.is_empty())
+            } else {
+                self.outbound
+                    .as_ref()
+                    .is_some_and(|results| !results.is_empty())
+            };
+            if has_results || self.done {
+                return Poll::Ready(());
+            }
+
+            match self.events.poll_next_unpin(&mut inner_cx) {
+                Poll::Ready(Some(BehaviourOutEvent::Inbound(peer, res))) => {
+                    self.push_inbound(peer, Ok(res));
+                }
+                Poll::Ready(Some(BehaviourOutEvent::InboundFailed(peer, failure))) => {
+                    self.push_inbound(peer, Err(failure));
+                }
+                Poll::Ready(Some(BehaviourOutEvent::Outbound(peer, res, _))) => {
+                    self.push_outbound(peer, Ok(res));
+                }
+                Poll::Ready(Some(BehaviourOutEvent::OutboundFailed(peer, failure, _))) => {
+                    self.push_outbound(peer, Err(failure));
+                }
+                Poll::Ready(Some(BehaviourOutEvent::Rejected(..)))
+                | Poll::Ready(Some(BehaviourOutEvent::PeerConnected(..)))
+                | Poll::Ready(Some(BehaviourOutEvent::PeerDisconnected(..)))
+                | Poll::Ready(Some(BehaviourOutEvent::DisconnectPeer(..)))
+                | Poll::Ready(Some(BehaviourOutEvent::NotifyFailed(..)))
+                | Poll::Ready(Some(BehaviourOutEvent::Progress(..)))
+                | Poll::Ready(Some(BehaviourOutEvent::HandlerIdle(..)))
+                | Poll::Ready(Some(BehaviourOutEvent::Drained)) => {}
+                #[cfg(feature = "probe")]
+                Poll::Ready(Some(BehaviourOutEvent::ProtocolsProbed(..))) => {}
+                Poll::Ready(None) => {
+                    self.done = true;
+                    self.wakers.wake_half(!inbound);
+                }
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+
+    fn push_inbound(&mut self, peer: PeerId, res: ProtocolResult<I, E>) {
+        if let Some(results) = self.inbound.as_mut() {
+            results.push_back((peer, res));
+            self.wakers.wake_half(true);
+        }
+    }
+
+    fn push_outbound(&mut self, peer: PeerId, res: ProtocolResult<O, E>) {
+        if let Some(results) = self.outbound.as_mut() {
+            results.push_back((peer, res));
+            self.wakers.wake_half(false);
+        }
+    }
+}
+
+/// The inbound half returned by [`split`].
+pub struct InboundResults<S, I, O, E> {
+    shared: Arc<Mutex<Shared<S, I, O, E>>>,
+}
+
+/// The outbound half returned by [`split`].
+pub struct OutboundResults<S, I, O, E> {
+    shared: Arc<Mutex<Shared<S, I, O, E>>>,
+}
+
+impl<S, I, O, E> Stream for InboundResults<S, I, O, E>
+where
+    S: Stream<Item = BehaviourOutEvent<I, O, E>> + Unpin,
+{
+    type Item = (PeerId, ProtocolResult<I, E>);
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut shared = self.shared.lock().expect("lock not to be poisoned");
+
+        match shared.poll_until(cx, true) {
+            Poll::Ready(()) => Poll::Ready(shared.inbound.as_mut().and_then(VecDeque::pop_front)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl<S, I, O, E> Stream for OutboundResults<S, I, O, E>
+where
+    S: Stream<Item = BehaviourOutEvent<I, O, E>> + Unpin,
+{
+    type Item = (PeerId, ProtocolResult<O, E>);
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut shared = self.shared.lock().expect("lock not to be poisoned");
+
+        match shared.poll_until(cx, false) {
+            Poll::Ready(()) => Poll::Ready(shared.outbound.as_mut().and_then(VecDeque::pop_front)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl<S, I, O, E> Drop for InboundResults<S, I, O, E> {
+    fn drop(&mut self) {
+        if let Ok(mut shared) = self.shared.lock() {
+            shared.inbound = None;
+        }
+    }
+}
+
+impl<S, I, O, E> Drop for OutboundResults<S, I, O, E> {
+    fn drop(&mut self) {
+        if let Ok(mut shared) = self.shared.lock() {
+            shared.outbound = None;
+        }
+    }
+}
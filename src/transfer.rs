@@ -0,0 +1,105 @@
+//! Bulk transfers of data that does not fit into a single message.
+//!
+//! `send_stream` splits the data read from an `AsyncRead` into chunks, each sent as a frame of
+//! its own, and ends the transfer with a frame announcing the total size. `recv_stream` writes
+//! the chunks to an `AsyncWrite` as they arrive. Both sides only move on to the next chunk once
+//! the previous one was written, so neither buffers more than a chunk if the other side or the
+//! `AsyncWrite` falls behind.
+
+use crate::FramingError;
+use std::{fmt, io};
+
+/// The largest chunk a transfer is split into, larger chunk sizes are clamped to it.
+pub const MAX_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Marks a frame carrying a chunk of the data.
+pub(crate) const DATA: u8 = 0;
+/// Marks the frame ending the transfer, followed by the total size.
+const END: u8 = 1;
+
+pub(crate) enum Frame<'a> {
+    Data(&'a [u8]),
+    End(u64),
+}
+
+pub(crate) fn end_frame(total: u64) -> [u8; 9] {
+    let mut frame = [END; 9];
+    frame[1..].copy_from_slice(&total.to_be_bytes());
+
+    frame
+}
+
+pub(crate) fn parse(frame: &[u8]) -> Result<Frame<'_>, TransferError> {
+    match frame {
+        [] => Err(TransferError::Truncated),
+        [DATA, chunk @ ..] => Ok(Frame::Data(chunk)),
+        [END, total @ ..] if total.len() == 8 => {
+            let mut bytes = [0; 8];
+            bytes.copy_from_slice(total);
+
+            Ok(Frame::End(u64::from_be_bytes(bytes)))
+        }
+        _ => Err(TransferError::Malformed),
+    }
+}
+
+/// The error returned when receiving a transfer fails.
+#[derive(Debug)]
+pub enum TransferError {
+    Read(FramingError),
+    /// Writing the received data failed.
+    Write(io::Error),
+    /// The substream was closed before the transfer ended.
+    Truncated,
+    /// The remote sent a frame that is not part of a transfer.
+    Malformed,
+    /// The transfer is larger than the allowed maximum.
+    TooLarge {
+        max_size: u64,
+    },
+    /// The remote announced a different size than it sent.
+    SizeMismatch {
+        announced: u64,
+        received: u64,
+    },
+}
+
+impl From<FramingError> for TransferError {
+    fn from(e: FramingError) -> Self {
+        TransferError::Read(e)
+    }
+}
+
+impl fmt::Display for TransferError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransferError::Read(e) => write!(f, "{}", e),
+            TransferError::Write(e) => write!(f, "failed to write received data: {}", e),
+            TransferError::Truncated => write!(f, "substream closed before the transfer ended"),
+            TransferError::Malformed => {
+                write!(f, "received a frame that is not part of a transfer")
+            }
+            TransferError::TooLarge { max_size } => {
+                write!(f, "transfer exceeds the maximum of {} bytes", max_size)
+            }
+            TransferError::SizeMismatch {
+                announced,
+                received,
+            } => write!(
+                f,
+                "remote announced {} bytes but sent {} bytes",
+                announced, received
+            ),
+        }
+    }
+}
+
+impl std::error::Error for TransferError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TransferError::Read(e) => Some(e),
+            TransferError::Write(e) => Some(e),
+            _ => None,
+        }
+    }
+}
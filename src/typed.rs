@@ -0,0 +1,107 @@
+//! Declaring request-response protocols as types.
+//!
+//! Implementing [`Protocol`] describes a protocol once: the [`Message`]s it exchanges and how
+//! requests are answered. A [`TypedBehaviour`] answers every inbound request with
+//! [`Protocol::handle`] and sends requests with [`Behaviour::request`], each on a substream of
+//! its own. Responses are reported as [`crate::BehaviourOutEvent::Outbound`], answered requests
+//! as [`crate::BehaviourOutEvent::Inbound`].
+//!
+//! ```
+//! # use libp2p::PeerId;
+//! # use libp2p_async_await::typed::{Protocol, TypedBehaviour};
+//! # use libp2p_async_await::Behaviour;
+//! struct Echo;
+//!
+//! impl Protocol for Echo {
+//!     type Request = String;
+//!     type Response = String;
+//!
+//!     const INFO: &'static [u8] = b"/echo/1.0.0";
+//!
+//!     fn handle(request: String) -> String {
+//!         request
+//!     }
+//! }
+//!
+//! let mut behaviour: TypedBehaviour<Echo> = Behaviour::typed::<Echo>();
+//! behaviour.request::<Echo>(PeerId::random(), String::from("hello"));
+//! ```
+
+use crate::{Behaviour, FramingError};
+use libp2p::PeerId;
+use std::io;
+
+/// A value sent as a single message.
+pub trait Message: Sized + Send + 'static {
+    fn encode(&self) -> Vec<u8>;
+
+    /// Fails with [`io::ErrorKind::InvalidData`] if the bytes are not a valid message, which
+    /// substreams report as [`FramingError::Serialization`].
+    fn decode(bytes: Vec<u8>) -> Result<Self, io::Error>;
+}
+
+impl Message for Vec<u8> {
+    fn encode(&self) -> Vec<u8> {
+        self.clone()
+    }
+
+    fn decode(bytes: Vec<u8>) -> Result<Self, io::Error> {
+        Ok(bytes)
+    }
+}
+
+impl Message for String {
+    fn encode(&self) -> Vec<u8> {
+        self.as_bytes().to_vec()
+    }
+
+    fn decode(bytes: Vec<u8>) -> Result<Self, io::Error> {
+        String::from_utf8(bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+/// A protocol that answers a single request with a single response.
+pub trait Protocol: 'static {
+    type Request: Message;
+    type Response: Message;
+
+    /// The protocol info the substreams are negotiated for.
+    const INFO: &'static [u8];
+    /// The maximum size of encoded requests and responses.
+    const MAX_SIZE: usize = 1024 * 1024;
+
+    /// Answers a request of the remote.
+    fn handle(request: Self::Request) -> Self::Response;
+}
+
+/// A [`Behaviour`] serving and sending requests of `P`, see the [module docs](self).
+pub type TypedBehaviour<P> = Behaviour<(), <P as Protocol>::Response, FramingError>;
+
+impl<O: Message> Behaviour<(), O, FramingError> {
+    /// Constructs a behaviour that advertises `P` and answers its inbound requests.
+    pub fn typed<P: Protocol<Response = O>>() -> Self {
+        let mut behaviour = Self::new(P::INFO);
+        behaviour.set_inbound_handler_for(P::INFO, |mut substream| async move {
+            let request = decode(substream.read_message(P::MAX_SIZE).await?)?;
+            let response = P::handle(request);
+            substream.write_message(&response.encode()).await?;
+
+            Ok(())
+        });
+
+        behaviour
+    }
+
+    /// Sends the request to the peer, reporting its response.
+    pub fn request<P: Protocol<Response = O>>(&mut self, peer: PeerId, request: P::Request) {
+        self.do_protocol_dialer_for(peer, P::INFO, move |mut substream| async move {
+            substream.write_message(&request.encode()).await?;
+
+            decode(substream.read_message(P::MAX_SIZE).await?)
+        });
+    }
+}
+
+fn decode<M: Message>(bytes: Vec<u8>) -> Result<M, FramingError> {
+    M::decode(bytes).map_err(|e| FramingError::Serialization(Box::new(e)))
+}
@@ -0,0 +1,65 @@
+use harness::{collect_events, connect, new_swarm};
+use libp2p_async_await::{Behaviour, BehaviourOutEvent};
+use std::time::{Duration, Instant};
+use tokio::runtime::Handle;
+
+mod harness;
+
+/// The last activity of the substream before and after the operation under test.
+type Activity = (Instant, Instant);
+type TestBehaviour = Behaviour<Activity, Activity, anyhow::Error>;
+
+async fn exchange_message() -> (
+    Vec<BehaviourOutEvent<Activity, Activity, anyhow::Error>>,
+    Vec<BehaviourOutEvent<Activity, Activity, anyhow::Error>>,
+) {
+    let (mut alice, _, alice_peer_id) =
+        new_swarm(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current());
+    let (mut bob, _, bob_peer_id) =
+        new_swarm(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current());
+    connect(&mut alice, &mut bob).await;
+
+    alice
+        .behaviour_mut()
+        .do_protocol_dialer(bob_peer_id, |mut substream| async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+
+            let before = substream.last_activity();
+            substream.write_message(b"hello").await?;
+
+            Ok((before, substream.last_activity()))
+        });
+    bob.behaviour_mut()
+        .do_protocol_listener(alice_peer_id, |mut substream| async move {
+            let before = substream.last_activity();
+            substream.read_message(1024).await?;
+
+            Ok((before, substream.last_activity()))
+        });
+
+    collect_events(&mut alice, &mut bob, Duration::from_secs(1)).await
+}
+
+#[tokio::test]
+async fn writing_updates_last_activity() {
+    let _ = env_logger::try_init();
+
+    let (alice_events, _) = exchange_message().await;
+
+    match alice_events.as_slice() {
+        [BehaviourOutEvent::Outbound(_, Ok((before, after)), _)] => assert!(after > before),
+        events => panic!("unexpected events {:?}", events),
+    }
+}
+
+#[tokio::test]
+async fn reading_updates_last_activity() {
+    let _ = env_logger::try_init();
+
+    let (_, bob_events) = exchange_message().await;
+
+    match bob_events.as_slice() {
+        [BehaviourOutEvent::Inbound(_, Ok((before, after)))] => assert!(after > before),
+        events => panic!("unexpected events {:?}", events),
+    }
+}
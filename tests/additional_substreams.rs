@@ -0,0 +1,172 @@
+use harness::{collect_events, new_connected_swarm_pair};
+use libp2p::futures::future;
+use libp2p::futures::future::try_join_all;
+use libp2p::futures::AsyncWriteExt;
+use libp2p_async_await::{Behaviour, BehaviourOutEvent};
+use std::time::Duration;
+use tokio::runtime::Handle;
+
+mod harness;
+
+type TestBehaviour = Behaviour<Vec<u8>, (), anyhow::Error>;
+
+#[tokio::test]
+async fn protocols_can_open_additional_substreams() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, mut bob) = new_connected_swarm_pair(
+        |_, _| TestBehaviour::new(b"/relay/1.0.0"),
+        Handle::current(),
+    )
+    .await;
+
+    alice
+        .swarm
+        .behaviour_mut()
+        .do_protocol_dialer(bob.peer_id, |mut substream| async move {
+            substream.write_message(b"hello").await?;
+            substream.read_message(1024).await?;
+
+            let mut additional = substream.open_outbound(b"/relay/1.0.0").await?;
+            additional.write_message(b"world").await?;
+
+            Ok(())
+        });
+    bob.swarm
+        .behaviour_mut()
+        .do_protocol_listener(alice.peer_id, |mut substream| async move {
+            substream.read_message(1024).await?;
+
+            // Wait for the additional substream before telling alice to open it.
+            let accept = substream.accept_inbound();
+            let (additional, ack) = future::join(accept, substream.write_message(b"ack")).await;
+            ack?;
+
+            let message = additional?.read_message(1024).await?;
+
+            Ok(message)
+        });
+
+    let (alice_events, bob_events) =
+        collect_events(&mut alice.swarm, &mut bob.swarm, Duration::from_secs(1)).await;
+
+    assert!(matches!(
+        alice_events.as_slice(),
+        [BehaviourOutEvent::Outbound(_, Ok(()), _)]
+    ));
+    match bob_events.as_slice() {
+        [BehaviourOutEvent::Inbound(_, Ok(message))] => assert_eq!(message, b"world"),
+        events => panic!("unexpected events {:?}", events),
+    }
+}
+
+#[tokio::test]
+async fn protocols_can_gather_results_of_concurrent_substreams() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, mut bob) = new_connected_swarm_pair(
+        |_, _| TestBehaviour::new(b"/relay/1.0.0"),
+        Handle::current(),
+    )
+    .await;
+
+    alice
+        .swarm
+        .behaviour_mut()
+        .do_protocol_dialer(bob.peer_id, |mut substream| async move {
+            substream.write_message(&[3]).await?;
+            let opener = substream.opener();
+            let substreams = opener.open_outbound_many(b"/relay/1.0.0", 3).await?;
+
+            let responses = try_join_all(substreams.into_iter().enumerate().map(
+                |(i, mut substream)| async move {
+                    substream.write_message(&[i as u8]).await?;
+                    let response = substream.read_message(1).await?;
+
+                    anyhow::Ok(response[0])
+                },
+            ))
+            .await?;
+            substream.write_message(&responses).await?;
+
+            Ok(())
+        });
+    bob.swarm
+        .behaviour_mut()
+        .do_protocol_listener(alice.peer_id, |mut substream| async move {
+            let count = substream.read_message(1).await?[0];
+
+            try_join_all((0..count).map(|_| {
+                let accept = substream.accept_inbound();
+                async move {
+                    let mut substream = accept.await?;
+                    let request = substream.read_message(1).await?;
+                    substream.write_message(&[request[0] * 10]).await?;
+
+                    anyhow::Ok(())
+                }
+            }))
+            .await?;
+
+            Ok(substream.read_message(1024).await?)
+        });
+
+    let (alice_events, bob_events) =
+        collect_events(&mut alice.swarm, &mut bob.swarm, Duration::from_secs(1)).await;
+
+    assert!(matches!(
+        alice_events.as_slice(),
+        [BehaviourOutEvent::Outbound(_, Ok(()), _)]
+    ));
+    match bob_events.as_slice() {
+        [BehaviourOutEvent::Inbound(_, Ok(message))] => assert_eq!(message, &[0, 10, 20]),
+        events => panic!("unexpected events {:?}", events),
+    }
+}
+
+#[tokio::test]
+async fn protocols_accept_substreams_that_arrived_before_they_asked_for_them() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, mut bob) = new_connected_swarm_pair(
+        |_, _| TestBehaviour::new(b"/relay/1.0.0"),
+        Handle::current(),
+    )
+    .await;
+
+    alice
+        .swarm
+        .behaviour_mut()
+        .do_protocol_dialer(bob.peer_id, |mut substream| async move {
+            substream.write_message(b"hello").await?;
+
+            let mut additional = substream.open_outbound(b"/relay/1.0.0").await?;
+            additional.write_message(b"world").await?;
+            additional.close().await?;
+
+            Ok(())
+        });
+    bob.swarm
+        .behaviour_mut()
+        .do_protocol_listener(alice.peer_id, |mut substream| async move {
+            substream.read_message(1024).await?;
+            // The additional substream is negotiated and queued in the meantime.
+            tokio::time::sleep(Duration::from_millis(300)).await;
+
+            let mut additional = substream.accept_inbound().await?;
+
+            Ok(additional.read_message(1024).await?)
+        });
+
+    let (alice_events, bob_events) =
+        collect_events(&mut alice.swarm, &mut bob.swarm, Duration::from_secs(1)).await;
+
+    assert!(matches!(
+        alice_events.as_slice(),
+        [BehaviourOutEvent::Outbound(_, Ok(()), _)]
+    ));
+    match bob_events.as_slice() {
+        [BehaviourOutEvent::Inbound(_, Ok(message))] => assert_eq!(message, b"world"),
+        events => panic!("unexpected events {:?}", events),
+    }
+}
@@ -0,0 +1,90 @@
+use harness::{collect_events, new_connected_swarm_pair};
+use libp2p_async_await::agent::{AgentError, MAX_AGENT_SIZE};
+use libp2p_async_await::{Behaviour, BehaviourOutEvent, FramingError};
+use std::time::Duration;
+use tokio::runtime::Handle;
+
+mod harness;
+
+type TestBehaviour = Behaviour<String, String, anyhow::Error>;
+
+#[tokio::test]
+async fn both_sides_learn_the_agent_of_the_other() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, mut bob) = new_connected_swarm_pair(
+        |_, _| TestBehaviour::new(b"/agent/1.0.0"),
+        Handle::current(),
+    )
+    .await;
+
+    alice
+        .swarm
+        .behaviour_mut()
+        .do_protocol_dialer(bob.peer_id, |mut substream| async move {
+            Ok(substream.exchange_agent("alice/1.0.0").await?)
+        });
+    bob.swarm
+        .behaviour_mut()
+        .do_protocol_listener(alice.peer_id, |mut substream| async move {
+            Ok(substream.exchange_agent("bob/2.0.0").await?)
+        });
+
+    let (alice_events, bob_events) =
+        collect_events(&mut alice.swarm, &mut bob.swarm, Duration::from_secs(1)).await;
+
+    match (alice_events.as_slice(), bob_events.as_slice()) {
+        (
+            [BehaviourOutEvent::Outbound(_, Ok(bob_agent), _)],
+            [BehaviourOutEvent::Inbound(_, Ok(alice_agent))],
+        ) => {
+            assert_eq!(bob_agent, "bob/2.0.0");
+            assert_eq!(alice_agent, "alice/1.0.0");
+        }
+        events => panic!("unexpected events {:?}", events),
+    }
+}
+
+#[tokio::test]
+async fn oversized_or_invalid_agents_are_rejected() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, mut bob) = new_connected_swarm_pair(
+        |_, _| TestBehaviour::new(b"/agent/1.0.0"),
+        Handle::current(),
+    )
+    .await;
+
+    for remote_agent in [vec![b'a'; MAX_AGENT_SIZE + 1], vec![0xff, 0xfe]] {
+        alice
+            .swarm
+            .behaviour_mut()
+            .do_protocol_dialer(bob.peer_id, |mut substream| async move {
+                match substream.exchange_agent("alice/1.0.0").await {
+                    Err(AgentError::Read(FramingError::TooLarge { .. })) => {
+                        Ok("too large".to_owned())
+                    }
+                    Err(AgentError::InvalidUtf8(_)) => Ok("invalid".to_owned()),
+                    res => Err(anyhow::anyhow!("unexpected result {:?}", res)),
+                }
+            });
+        bob.swarm
+            .behaviour_mut()
+            .do_protocol_listener(alice.peer_id, |mut substream| async move {
+                substream.write_message(&remote_agent).await?;
+                Ok(String::from_utf8(substream.read_message(1024).await?)?)
+            });
+    }
+
+    let (alice_events, _) =
+        collect_events(&mut alice.swarm, &mut bob.swarm, Duration::from_secs(1)).await;
+
+    match alice_events.as_slice() {
+        [BehaviourOutEvent::Outbound(_, Ok(first), _), BehaviourOutEvent::Outbound(_, Ok(second), _)] =>
+        {
+            assert_eq!(first, "too large");
+            assert_eq!(second, "invalid");
+        }
+        events => panic!("unexpected events {:?}", events),
+    }
+}
@@ -0,0 +1,164 @@
+use harness::{collect_events, connect, new_swarm};
+use libp2p::core::ConnectedPoint;
+use libp2p_async_await::{Behaviour, BehaviourOutEvent};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::runtime::Handle;
+
+mod harness;
+
+type TestBehaviour = Behaviour<(), (), anyhow::Error>;
+
+#[tokio::test]
+async fn disallowed_inbound_protocols_are_rejected() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, _, _) = new_swarm(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current());
+    let (mut bob, _, bob_peer_id) = new_swarm(
+        |_, _| {
+            let mut behaviour =
+                TestBehaviour::with_protocols(vec![&b"/foo/2.0.0"[..], &b"/foo/1.0.0"[..]]);
+            behaviour.set_inbound_allowed(&[b"/foo/2.0.0"]);
+
+            behaviour
+        },
+        Handle::current(),
+    );
+    connect(&mut alice, &mut bob).await;
+
+    alice
+        .behaviour_mut()
+        .do_protocol_dialer(bob_peer_id, |mut substream| async move {
+            substream.write_message(b"hello").await?;
+            substream.read_message(1024).await?;
+            Ok(())
+        });
+
+    let (alice_events, bob_events) =
+        collect_events(&mut alice, &mut bob, Duration::from_secs(1)).await;
+
+    assert!(matches!(
+        alice_events.as_slice(),
+        [BehaviourOutEvent::Outbound(_, Err(_), _)]
+    ));
+    assert!(matches!(
+        bob_events.as_slice(),
+        [BehaviourOutEvent::Rejected(_, b"/foo/1.0.0")]
+    ));
+}
+
+#[tokio::test]
+async fn rejected_inbound_protocols_can_be_told_about_their_rejection() {
+    let _ = env_logger::try_init();
+
+    type RejectionBehaviour = Behaviour<(), Vec<u8>, anyhow::Error>;
+
+    let (mut alice, _, _) = new_swarm(
+        |_, _| RejectionBehaviour::new(b"/foo/1.0.0"),
+        Handle::current(),
+    );
+    let (mut bob, _, bob_peer_id) = new_swarm(
+        |_, _| {
+            let mut behaviour =
+                RejectionBehaviour::with_protocols(vec![&b"/foo/2.0.0"[..], &b"/foo/1.0.0"[..]]);
+            behaviour.set_inbound_allowed(&[b"/foo/2.0.0"]);
+            behaviour.set_rejection_frame(Some(b"unsupported".to_vec()));
+
+            behaviour
+        },
+        Handle::current(),
+    );
+    connect(&mut alice, &mut bob).await;
+
+    alice
+        .behaviour_mut()
+        .do_protocol_dialer(bob_peer_id, |mut substream| async move {
+            Ok(substream.read_message(1024).await?)
+        });
+
+    let (alice_events, bob_events) =
+        collect_events(&mut alice, &mut bob, Duration::from_secs(1)).await;
+
+    assert!(matches!(
+        alice_events.as_slice(),
+        [BehaviourOutEvent::Outbound(_, Ok(frame), _)] if frame == b"unsupported"
+    ));
+    assert!(matches!(
+        bob_events.as_slice(),
+        [BehaviourOutEvent::Rejected(_, b"/foo/1.0.0")]
+    ));
+}
+
+#[tokio::test]
+async fn inbound_substreams_from_unaccepted_peers_are_rejected() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, _, alice_peer_id) =
+        new_swarm(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current());
+    let (mut bob, _, bob_peer_id) = new_swarm(
+        |_, _| {
+            let mut behaviour = TestBehaviour::new(b"/foo/1.0.0");
+            behaviour.set_accept_inbound(move |peer, _| *peer != alice_peer_id);
+
+            behaviour
+        },
+        Handle::current(),
+    );
+    connect(&mut alice, &mut bob).await;
+
+    let protocol_ran = Arc::new(AtomicBool::new(false));
+    alice
+        .behaviour_mut()
+        .do_protocol_dialer(bob_peer_id, |mut substream| async move {
+            substream.write_message(b"hello").await?;
+            substream.read_message(1024).await?;
+            Ok(())
+        });
+    bob.behaviour_mut().do_protocol_listener(alice_peer_id, {
+        let protocol_ran = protocol_ran.clone();
+        |_| async move {
+            protocol_ran.store(true, Ordering::SeqCst);
+            Ok(())
+        }
+    });
+
+    let (_, bob_events) = collect_events(&mut alice, &mut bob, Duration::from_secs(1)).await;
+
+    assert!(matches!(
+        bob_events.as_slice(),
+        [BehaviourOutEvent::Rejected(_, b"/foo/1.0.0")]
+    ));
+    assert!(!protocol_ran.load(Ordering::SeqCst));
+}
+
+#[tokio::test]
+async fn inbound_substreams_on_excluded_listeners_are_rejected() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, _, alice_peer_id) =
+        new_swarm(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current());
+    let (mut bob, bob_addr, bob_peer_id) =
+        new_swarm(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current());
+    bob.behaviour_mut().set_accept_inbound(move |_, point| {
+        matches!(point, ConnectedPoint::Listener { local_addr, .. } if *local_addr != bob_addr)
+    });
+    connect(&mut alice, &mut bob).await;
+
+    alice
+        .behaviour_mut()
+        .do_protocol_dialer(bob_peer_id, |mut substream| async move {
+            substream.write_message(b"hello").await?;
+            substream.read_message(1024).await?;
+            Ok(())
+        });
+    bob.behaviour_mut()
+        .do_protocol_listener(alice_peer_id, |_| async { Ok(()) });
+
+    let (_, bob_events) = collect_events(&mut alice, &mut bob, Duration::from_secs(1)).await;
+
+    assert!(matches!(
+        bob_events.as_slice(),
+        [BehaviourOutEvent::Rejected(_, b"/foo/1.0.0")]
+    ));
+}
@@ -0,0 +1,119 @@
+use libp2p::futures::io::Cursor;
+use libp2p::futures::StreamExt;
+use libp2p::PeerId;
+use libp2p_async_await::auth::AuthError;
+use libp2p_async_await::driver::Driver;
+use libp2p_async_await::{InboundSubstream, ProtocolOutEvent};
+
+#[cfg(feature = "signed-token")]
+use libp2p::identity::Keypair;
+#[cfg(feature = "signed-token")]
+use libp2p_async_await::auth::{self, SignedToken};
+
+/// Reads a token followed by a message and returns the message if the token was accepted.
+async fn authorize(
+    frames: Vec<u8>,
+    peer: PeerId,
+    verifier: impl FnOnce(&PeerId, &[u8]) -> bool + Send + 'static,
+) -> Result<Vec<u8>, AuthError> {
+    let mut driver = Driver::<Result<Vec<u8>, AuthError>, (), anyhow::Error>::new();
+    driver.execute_inbound(
+        InboundSubstream::new(Cursor::new(frames), b"/auth/1.0.0"),
+        move |substream| async move {
+            let mut substream = match substream.verify_token(&peer, verifier).await {
+                Ok(substream) => substream,
+                Err(e) => return Ok(Err(e)),
+            };
+
+            Ok(Ok(substream.read_message(1024).await?))
+        },
+    );
+
+    match driver.next().await {
+        Some(ProtocolOutEvent::Inbound(Ok(res))) => res,
+        _ => panic!("protocol failed"),
+    }
+}
+
+fn frame(msgs: &[&[u8]]) -> Vec<u8> {
+    let mut frames = Vec::new();
+    for msg in msgs {
+        let mut length = msg.len();
+        while length >= 0x80 {
+            frames.push(length as u8 | 0x80);
+            length >>= 7;
+        }
+        frames.push(length as u8);
+        frames.extend_from_slice(msg);
+    }
+
+    frames
+}
+
+#[tokio::test]
+async fn accepted_tokens_hand_back_the_substream() {
+    let peer = PeerId::random();
+
+    let res = authorize(frame(&[b"letmein", b"hello"]), peer, move |p, token| {
+        *p == peer && token == b"letmein"
+    })
+    .await;
+
+    assert_eq!(res.unwrap(), b"hello");
+}
+
+#[tokio::test]
+async fn rejected_tokens_fail_authorization() {
+    let peer = PeerId::random();
+
+    let res = authorize(frame(&[b"guessing", b"hello"]), peer, |_, token| {
+        token == b"letmein"
+    })
+    .await;
+
+    assert!(matches!(res, Err(AuthError::Invalid(p)) if p == peer));
+}
+
+#[cfg(feature = "signed-token")]
+#[tokio::test]
+async fn signed_tokens_are_bound_to_the_peer_id_of_the_signer() {
+    let keypair = Keypair::generate_ed25519();
+    let signer = keypair.public().into_peer_id();
+    let token = SignedToken::sign(&keypair, b"role=admin").unwrap();
+
+    let decoded = SignedToken::decode(&token).unwrap();
+    assert_eq!(decoded.peer_id(), signer);
+    assert_eq!(decoded.claims(), b"role=admin");
+
+    let res = authorize(
+        frame(&[&token, b"hello"]),
+        signer,
+        auth::verify_signed_token,
+    )
+    .await;
+    assert_eq!(res.unwrap(), b"hello");
+
+    let other = PeerId::random();
+    let res = authorize(frame(&[&token, b"hello"]), other, auth::verify_signed_token).await;
+    assert!(matches!(res, Err(AuthError::Invalid(p)) if p == other));
+}
+
+#[cfg(feature = "signed-token")]
+#[tokio::test]
+async fn tampered_signed_tokens_are_rejected() {
+    let keypair = Keypair::generate_ed25519();
+    let signer = keypair.public().into_peer_id();
+    let mut token = SignedToken::sign(&keypair, b"role=user").unwrap();
+
+    // The claims directly precede the 64 byte ed25519 signature.
+    let claims_end = token.len() - 64;
+    token[claims_end - 4..claims_end].copy_from_slice(b"root");
+
+    let res = authorize(
+        frame(&[&token, b"hello"]),
+        signer,
+        auth::verify_signed_token,
+    )
+    .await;
+    assert!(matches!(res, Err(AuthError::Invalid(_))));
+}
@@ -0,0 +1,52 @@
+use harness::{collect_events, new_connected_swarm_pair};
+use libp2p::futures::{AsyncReadExt, AsyncWriteExt};
+use libp2p_async_await::{Behaviour, BehaviourOutEvent};
+use std::time::Duration;
+use tokio::runtime::Handle;
+
+mod harness;
+
+type TestBehaviour = Behaviour<(), (), anyhow::Error>;
+
+const SIZE: usize = 2 * 1024 * 1024;
+
+#[tokio::test]
+async fn writes_to_slow_peers_are_counted_as_pending() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, mut bob) =
+        new_connected_swarm_pair(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current()).await;
+
+    alice
+        .swarm
+        .behaviour_mut()
+        .do_protocol_dialer(bob.peer_id, |mut substream| async move {
+            substream.write_all(&vec![0; SIZE]).await?;
+            substream.flush().await?;
+            Ok(())
+        });
+    bob.swarm
+        .behaviour_mut()
+        .do_protocol_listener(alice.peer_id, |mut substream| async move {
+            tokio::time::sleep(Duration::from_millis(500)).await;
+            substream.read_exact(&mut vec![0; SIZE]).await?;
+            Ok(())
+        });
+
+    let (alice_events, bob_events) =
+        collect_events(&mut alice.swarm, &mut bob.swarm, Duration::from_secs(2)).await;
+    assert!(matches!(
+        alice_events.as_slice(),
+        [BehaviourOutEvent::Outbound(_, Ok(()), _)]
+    ));
+    assert!(matches!(
+        bob_events.as_slice(),
+        [BehaviourOutEvent::Inbound(_, Ok(()))]
+    ));
+
+    assert!(alice.swarm.behaviour().write_pending_time(&bob.peer_id) >= Duration::from_millis(250));
+    assert_eq!(
+        bob.swarm.behaviour().write_pending_time(&alice.peer_id),
+        Duration::ZERO
+    );
+}
@@ -0,0 +1,94 @@
+use harness::{collect_events, connect, new_swarm};
+use libp2p::futures::future;
+use libp2p::PeerId;
+use libp2p_async_await::{Behaviour, BehaviourOutEvent, Failure};
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::runtime::Handle;
+
+mod harness;
+
+type TestBehaviour = Behaviour<(), (), anyhow::Error>;
+
+#[tokio::test]
+async fn protocols_with_banned_peers_are_not_dispatched() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, _, _) = new_swarm(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current());
+    let (mut bob, _, bob_peer_id) =
+        new_swarm(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current());
+    connect(&mut alice, &mut bob).await;
+
+    alice
+        .behaviour_mut()
+        .set_ban_checker(Duration::from_secs(60), move |peer| *peer == bob_peer_id);
+    alice
+        .behaviour_mut()
+        .do_protocol_dialer(bob_peer_id, |_| future::pending());
+    let (alice_events, bob_events) =
+        collect_events(&mut alice, &mut bob, Duration::from_millis(200)).await;
+
+    assert!(matches!(
+        alice_events.as_slice(),
+        [BehaviourOutEvent::OutboundFailed(peer, Failure::Banned, None)] if *peer == bob_peer_id
+    ));
+    assert!(bob_events.is_empty());
+}
+
+#[tokio::test]
+async fn executing_protocols_fail_once_the_peer_is_banned() {
+    let _ = env_logger::try_init();
+
+    let banned = Arc::new(Mutex::new(HashSet::<PeerId>::new()));
+    let (mut alice, _, alice_peer_id) =
+        new_swarm(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current());
+    let (mut bob, _, bob_peer_id) =
+        new_swarm(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current());
+    connect(&mut alice, &mut bob).await;
+
+    alice
+        .behaviour_mut()
+        .set_ban_checker(Duration::from_millis(100), {
+            let banned = banned.clone();
+            move |peer| banned.lock().unwrap().contains(peer)
+        });
+    alice
+        .behaviour_mut()
+        .do_protocol_listener(bob_peer_id, |_| future::pending());
+    bob.behaviour_mut()
+        .do_protocol_dialer(alice_peer_id, |_| future::pending());
+    let (alice_events, _) = collect_events(&mut alice, &mut bob, Duration::from_millis(200)).await;
+    assert!(alice_events.is_empty());
+
+    banned.lock().unwrap().insert(bob_peer_id);
+    let (alice_events, _) = collect_events(&mut alice, &mut bob, Duration::from_millis(300)).await;
+
+    assert!(matches!(
+        alice_events.as_slice(),
+        [BehaviourOutEvent::InboundFailed(peer, Failure::Banned)] if *peer == bob_peer_id
+    ));
+}
+
+#[tokio::test]
+async fn inbound_substreams_from_banned_peers_are_rejected() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, _, alice_peer_id) =
+        new_swarm(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current());
+    let (mut bob, _, bob_peer_id) =
+        new_swarm(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current());
+    connect(&mut alice, &mut bob).await;
+
+    bob.behaviour_mut()
+        .set_ban_checker(Duration::from_secs(60), move |peer| *peer == alice_peer_id);
+    alice
+        .behaviour_mut()
+        .do_protocol_dialer(bob_peer_id, |_| future::pending());
+    let (_, bob_events) = collect_events(&mut alice, &mut bob, Duration::from_millis(200)).await;
+
+    assert!(matches!(
+        bob_events.as_slice(),
+        [BehaviourOutEvent::Rejected(peer, b"/foo/1.0.0")] if *peer == alice_peer_id
+    ));
+}
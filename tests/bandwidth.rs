@@ -0,0 +1,115 @@
+use harness::{collect_events, connect, new_swarm};
+use libp2p::{PeerId, Swarm};
+use libp2p_async_await::{Behaviour, BehaviourOutEvent, PeerBandwidth};
+use std::time::{Duration, Instant};
+use tokio::runtime::Handle;
+
+mod harness;
+
+type TestBehaviour = Behaviour<(), Duration, anyhow::Error>;
+
+/// Has alice send two messages to bob, returning how long it took and bob's swarm.
+async fn send_twice(
+    configure_bob: impl Fn(&mut TestBehaviour, PeerId),
+) -> (Duration, Swarm<TestBehaviour>, PeerId) {
+    let (mut alice, _, alice_peer_id) =
+        new_swarm(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current());
+    let (mut bob, _, bob_peer_id) =
+        new_swarm(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current());
+    configure_bob(bob.behaviour_mut(), alice_peer_id);
+    connect(&mut alice, &mut bob).await;
+
+    alice
+        .behaviour_mut()
+        .do_protocol_dialer(bob_peer_id, |mut substream| async move {
+            let started = Instant::now();
+            substream.write_message(&[0; 1000]).await?;
+            substream.write_message(&[0; 1000]).await?;
+            substream.read_message(1).await?;
+
+            Ok(started.elapsed())
+        });
+    bob.behaviour_mut()
+        .do_protocol_listener(alice_peer_id, |mut substream| async move {
+            substream.read_message(1000).await?;
+            substream.read_message(1000).await?;
+            substream.write_message(&[0]).await?;
+
+            Ok(())
+        });
+
+    let (alice_events, bob_events) =
+        collect_events(&mut alice, &mut bob, Duration::from_secs(3)).await;
+
+    assert!(matches!(
+        bob_events.as_slice(),
+        [BehaviourOutEvent::Inbound(_, Ok(()))]
+    ));
+    match alice_events.as_slice() {
+        [BehaviourOutEvent::Outbound(_, Ok(elapsed), None)] => (*elapsed, bob, alice_peer_id),
+        events => panic!("unexpected events {:?}", events),
+    }
+}
+
+#[tokio::test]
+async fn reads_wait_for_the_budget_of_the_peer() {
+    let _ = env_logger::try_init();
+
+    // The bucket starts out with one second worth of bytes, the rest takes another second.
+    let (elapsed, _, _) = send_twice(|bob, _| bob.set_bandwidth_limit_per_peer(Some(1004))).await;
+
+    assert!(elapsed >= Duration::from_millis(900), "{:?}", elapsed);
+}
+
+#[tokio::test]
+async fn limits_of_individual_peers_take_precedence() {
+    let _ = env_logger::try_init();
+
+    let (elapsed, bob, alice_peer_id) = send_twice(|bob, alice| {
+        bob.set_bandwidth_limit_per_peer(Some(1004));
+        bob.set_peer_bandwidth_limit(alice, None);
+    })
+    .await;
+
+    assert!(elapsed < Duration::from_millis(500), "{:?}", elapsed);
+    assert_eq!(
+        bob.behaviour().config().bandwidth_limit_per_peer,
+        Some(1004)
+    );
+    assert_eq!(
+        bob.behaviour()
+            .config()
+            .peer_bandwidth_limits
+            .get(&alice_peer_id),
+        Some(&None)
+    );
+}
+
+#[tokio::test]
+async fn bandwidth_of_connected_peers_is_reported() {
+    let _ = env_logger::try_init();
+
+    let (_, mut bob, alice_peer_id) = send_twice(|_, _| {}).await;
+
+    match bob.behaviour().peer_bandwidth(&alice_peer_id) {
+        Some(PeerBandwidth {
+            bytes_read,
+            bytes_written,
+            limit: None,
+        }) => {
+            assert!(bytes_read >= 2000, "{}", bytes_read);
+            assert!(bytes_written >= 1, "{}", bytes_written);
+        }
+        bandwidth => panic!("unexpected bandwidth {:?}", bandwidth),
+    }
+    assert_eq!(bob.behaviour().peer_bandwidth(&PeerId::random()), None);
+
+    bob.behaviour_mut()
+        .set_peer_bandwidth_limit(alice_peer_id, Some(1024));
+    assert_eq!(
+        bob.behaviour()
+            .peer_bandwidth(&alice_peer_id)
+            .and_then(|bandwidth| bandwidth.limit),
+        Some(1024)
+    );
+}
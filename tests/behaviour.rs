@@ -0,0 +1,1215 @@
+use libp2p::core::connection::ConnectionId;
+use libp2p::core::{ConnectedPoint, Multiaddr};
+use libp2p::futures::task::{noop_waker_ref, Context, Poll};
+use libp2p::futures::FutureExt;
+use libp2p::swarm::{
+    AddressRecord, NetworkBehaviour, NetworkBehaviourAction, NotifyHandler, PollParameters,
+};
+use libp2p::PeerId;
+use libp2p_async_await::{
+    Behaviour, BehaviourOutEvent, CancelReason, CloseReason, ConcurrencyBudget, ConnectionStrategy,
+    Direction, EventOverflow, Failure, PeerStats, ProtocolInEvent, ProtocolOutEvent, RateLimit,
+};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+struct DummyPollParameters(PeerId);
+
+impl PollParameters for DummyPollParameters {
+    type SupportedProtocolsIter = std::iter::Empty<Vec<u8>>;
+    type ListenedAddressesIter = std::iter::Empty<Multiaddr>;
+    type ExternalAddressesIter = std::iter::Empty<AddressRecord>;
+
+    fn supported_protocols(&self) -> Self::SupportedProtocolsIter {
+        std::iter::empty()
+    }
+
+    fn listened_addresses(&self) -> Self::ListenedAddressesIter {
+        std::iter::empty()
+    }
+
+    fn external_addresses(&self) -> Self::ExternalAddressesIter {
+        std::iter::empty()
+    }
+
+    fn local_peer_id(&self) -> &PeerId {
+        &self.0
+    }
+}
+
+type TestBehaviour = Behaviour<(), (), ()>;
+type TestAction =
+    NetworkBehaviourAction<ProtocolInEvent<(), (), ()>, BehaviourOutEvent<(), (), ()>>;
+
+fn poll(behaviour: &mut TestBehaviour) -> Poll<TestAction> {
+    let mut cx = Context::from_waker(noop_waker_ref());
+
+    behaviour.poll(&mut cx, &mut DummyPollParameters(PeerId::random()))
+}
+
+/// Dispatches a protocol to the given peer and returns the connection it was dispatched to.
+fn dispatch(behaviour: &mut TestBehaviour, peer: PeerId) -> ConnectionId {
+    behaviour.do_protocol_dialer(peer, |_| async { Ok(()) });
+
+    match poll(behaviour) {
+        Poll::Ready(NetworkBehaviourAction::NotifyHandler {
+            handler: NotifyHandler::One(connection),
+            ..
+        }) => connection,
+        _ => panic!("expected the protocol to be dispatched"),
+    }
+}
+
+/// Like [`dispatch`] but also completes the protocol.
+fn dispatch_and_complete(behaviour: &mut TestBehaviour, peer: PeerId) -> ConnectionId {
+    let connection = dispatch(behaviour, peer);
+    complete(behaviour, peer, connection);
+
+    connection
+}
+
+fn complete(behaviour: &mut TestBehaviour, peer: PeerId, connection: ConnectionId) {
+    behaviour.inject_event(peer, connection, ProtocolOutEvent::Outbound(Ok(())));
+    assert!(matches!(
+        poll(behaviour),
+        Poll::Ready(NetworkBehaviourAction::GenerateEvent(
+            BehaviourOutEvent::Outbound(..)
+        ))
+    ));
+}
+
+fn dialer() -> ConnectedPoint {
+    ConnectedPoint::Dialer {
+        address: "/memory/1234".parse().unwrap(),
+    }
+}
+
+#[test]
+fn gc_removes_peers_whose_close_was_missed() {
+    let mut behaviour = TestBehaviour::new(b"/foo/bar/1.0.0");
+    let peer = PeerId::random();
+
+    behaviour.inject_connection_established(&peer, &ConnectionId::new(0), &dialer());
+    assert!(behaviour.is_connected(&peer));
+
+    behaviour.gc(|_| false);
+
+    assert!(!behaviour.is_connected(&peer));
+}
+
+#[test]
+fn closing_last_connection_removes_peer() {
+    let mut behaviour = TestBehaviour::new(b"/foo/bar/1.0.0");
+    let peer = PeerId::random();
+
+    behaviour.inject_connection_established(&peer, &ConnectionId::new(0), &dialer());
+    behaviour.inject_connection_closed(&peer, &ConnectionId::new(0), &dialer());
+
+    assert!(!behaviour.is_connected(&peer));
+}
+
+#[test]
+fn touching_keep_alive_only_ever_extends_the_deadline() {
+    let mut behaviour = TestBehaviour::new(b"/foo/bar/1.0.0");
+    let peer = PeerId::random();
+    behaviour.inject_connection_established(&peer, &ConnectionId::new(0), &dialer());
+
+    behaviour.touch_keep_alive(peer, Duration::from_secs(60));
+    let deadline = behaviour.keep_alive_deadline(&peer).unwrap();
+    behaviour.touch_keep_alive(peer, Duration::from_secs(1));
+
+    assert!(deadline >= Instant::now() + Duration::from_secs(59));
+    assert_eq!(behaviour.keep_alive_deadline(&peer), Some(deadline));
+}
+
+#[test]
+fn closing_a_connection_with_a_dispatched_protocol_emits_a_failure() {
+    let mut behaviour = TestBehaviour::new(b"/foo/bar/1.0.0");
+    let peer = PeerId::random();
+    let connection = ConnectionId::new(0);
+
+    behaviour.inject_connection_established(&peer, &connection, &dialer());
+    behaviour.do_protocol_dialer(peer, |_| async { Ok(()) });
+
+    assert!(matches!(
+        poll(&mut behaviour),
+        Poll::Ready(NetworkBehaviourAction::NotifyHandler { .. })
+    ));
+
+    behaviour.inject_connection_closed(&peer, &connection, &dialer());
+
+    assert!(matches!(
+        poll(&mut behaviour),
+        Poll::Ready(NetworkBehaviourAction::GenerateEvent(
+            BehaviourOutEvent::OutboundFailed(_, Failure::ConnectionClosed, _)
+        ))
+    ));
+    assert!(poll(&mut behaviour).is_pending());
+}
+
+#[test]
+fn protocols_wait_for_an_idle_connection() {
+    let mut behaviour = TestBehaviour::new(b"/foo/bar/1.0.0");
+    let peer = PeerId::random();
+
+    behaviour.inject_connection_established(&peer, &ConnectionId::new(0), &dialer());
+    behaviour.do_protocol_dialer(peer, |_| async { Ok(()) });
+    behaviour.do_protocol_dialer(peer, |_| async { Ok(()) });
+
+    assert!(poll(&mut behaviour).is_ready());
+    assert!(poll(&mut behaviour).is_pending());
+}
+
+#[test]
+fn connection_events_carry_the_connection_count() {
+    let mut behaviour = TestBehaviour::new(b"/foo/bar/1.0.0");
+    behaviour.set_emit_connection_events(true);
+    let peer = PeerId::random();
+
+    behaviour.inject_connection_established(&peer, &ConnectionId::new(0), &dialer());
+    behaviour.inject_connection_established(&peer, &ConnectionId::new(1), &dialer());
+    assert_eq!(behaviour.connection_count(&peer), 2);
+
+    behaviour.inject_connection_closed(&peer, &ConnectionId::new(0), &dialer());
+    assert_eq!(behaviour.connection_count(&peer), 1);
+
+    for &expected in [(true, 1), (true, 2), (false, 1)].iter() {
+        match poll(&mut behaviour) {
+            Poll::Ready(NetworkBehaviourAction::GenerateEvent(
+                BehaviourOutEvent::PeerConnected(_, count),
+            )) => assert_eq!((true, count), expected),
+            Poll::Ready(NetworkBehaviourAction::GenerateEvent(
+                BehaviourOutEvent::PeerDisconnected(_, count, _),
+            )) => assert_eq!((false, count), expected),
+            _ => panic!("expected a connection event"),
+        }
+    }
+}
+
+fn close_reason(behaviour: &mut TestBehaviour, peer: PeerId) -> CloseReason {
+    behaviour.inject_connection_closed(&peer, &ConnectionId::new(0), &dialer());
+
+    loop {
+        match poll(behaviour) {
+            Poll::Ready(NetworkBehaviourAction::GenerateEvent(
+                BehaviourOutEvent::PeerDisconnected(_, 0, reason),
+            )) => return reason,
+            Poll::Ready(_) => {}
+            Poll::Pending => panic!("expected a disconnect event"),
+        }
+    }
+}
+
+#[test]
+fn disconnect_events_carry_the_inferred_close_reason() {
+    let peer = PeerId::random();
+    let connection = ConnectionId::new(0);
+
+    let mut idle = TestBehaviour::new(b"/foo/bar/1.0.0");
+    idle.inject_connection_established(&peer, &connection, &dialer());
+    idle.set_emit_connection_events(true);
+    assert_eq!(close_reason(&mut idle, peer), CloseReason::KeepAliveTimeout);
+
+    let mut busy = TestBehaviour::new(b"/foo/bar/1.0.0");
+    busy.inject_connection_established(&peer, &connection, &dialer());
+    busy.set_emit_connection_events(true);
+    dispatch(&mut busy, peer);
+    assert_eq!(close_reason(&mut busy, peer), CloseReason::Error);
+
+    let mut requested = TestBehaviour::new(b"/foo/bar/1.0.0");
+    requested.inject_connection_established(&peer, &connection, &dialer());
+    requested.set_emit_connection_events(true);
+    requested.inject_event(peer, connection, ProtocolOutEvent::DisconnectRequested);
+    assert_eq!(close_reason(&mut requested, peer), CloseReason::Graceful);
+}
+
+#[test]
+fn disconnecting_a_peer_cancels_its_protocols_and_closes_its_connections() {
+    let mut behaviour = TestBehaviour::new(b"/foo/bar/1.0.0");
+    let peer = PeerId::random();
+    let connection = ConnectionId::new(0);
+    behaviour.inject_connection_established(&peer, &connection, &dialer());
+
+    dispatch(&mut behaviour, peer);
+    behaviour.do_protocol_dialer(peer, |_| async { Ok(()) });
+    behaviour.disconnect(peer);
+
+    assert!(matches!(
+        poll(&mut behaviour),
+        Poll::Ready(NetworkBehaviourAction::NotifyHandler {
+            event: ProtocolInEvent::Disconnect,
+            ..
+        })
+    ));
+    assert!(matches!(
+        poll(&mut behaviour),
+        Poll::Ready(NetworkBehaviourAction::GenerateEvent(
+            BehaviourOutEvent::OutboundFailed(_, Failure::Cancelled(_), _)
+        ))
+    ));
+
+    behaviour.inject_event(
+        peer,
+        connection,
+        ProtocolOutEvent::OutboundFailed(Failure::Cancelled(CancelReason::Unspecified)),
+    );
+    behaviour.do_protocol_dialer(peer, |_| async { Ok(()) });
+    assert!(matches!(
+        poll(&mut behaviour),
+        Poll::Ready(NetworkBehaviourAction::GenerateEvent(
+            BehaviourOutEvent::OutboundFailed(_, Failure::Cancelled(_), _)
+        ))
+    ));
+    assert!(poll(&mut behaviour).is_pending());
+
+    behaviour.set_emit_connection_events(true);
+    assert_eq!(close_reason(&mut behaviour, peer), CloseReason::Graceful);
+}
+
+#[test]
+fn drained_events_follow_the_dispatch_of_the_last_queued_protocol() {
+    let mut behaviour = TestBehaviour::new(b"/foo/bar/1.0.0");
+    behaviour.set_emit_drained_events(true);
+    let peer = PeerId::random();
+    behaviour.inject_connection_established(&peer, &ConnectionId::new(0), &dialer());
+    behaviour.inject_connection_established(&peer, &ConnectionId::new(1), &dialer());
+
+    behaviour.do_protocol_dialer(peer, |_| async { Ok(()) });
+    behaviour.do_protocol_dialer(peer, |_| async { Ok(()) });
+
+    assert!(dispatched(&mut behaviour).is_some());
+    assert!(dispatched(&mut behaviour).is_some());
+    assert!(matches!(
+        poll(&mut behaviour),
+        Poll::Ready(NetworkBehaviourAction::GenerateEvent(
+            BehaviourOutEvent::Drained
+        ))
+    ));
+    assert!(poll(&mut behaviour).is_pending());
+}
+
+#[test]
+fn protocols_beyond_their_concurrency_limit_stay_queued() {
+    let mut behaviour =
+        TestBehaviour::with_protocols(vec![&b"/cheap/1.0.0"[..], b"/expensive/1.0.0"]);
+    behaviour.set_max_concurrent(b"/expensive/1.0.0", 1);
+    let peer = PeerId::random();
+    let connection = ConnectionId::new(0);
+
+    for id in 0..3 {
+        behaviour.inject_connection_established(&peer, &ConnectionId::new(id), &dialer());
+    }
+    behaviour.do_protocol_dialer_for(peer, b"/expensive/1.0.0", |_| async { Ok(()) });
+    behaviour.do_protocol_dialer_for(peer, b"/expensive/1.0.0", |_| async { Ok(()) });
+    behaviour.do_protocol_dialer_for(peer, b"/cheap/1.0.0", |_| async { Ok(()) });
+
+    assert!(matches!(
+        poll(&mut behaviour),
+        Poll::Ready(NetworkBehaviourAction::NotifyHandler {
+            handler: NotifyHandler::One(id),
+            ..
+        }) if id == connection
+    ));
+    assert!(matches!(
+        poll(&mut behaviour),
+        Poll::Ready(NetworkBehaviourAction::NotifyHandler {
+            event: ProtocolInEvent::ExecuteOutbound(_, Some(b"/cheap/1.0.0"), _),
+            ..
+        })
+    ));
+    assert!(poll(&mut behaviour).is_pending());
+
+    behaviour.inject_event(peer, connection, ProtocolOutEvent::Outbound(Ok(())));
+
+    assert!(matches!(
+        poll(&mut behaviour),
+        Poll::Ready(NetworkBehaviourAction::NotifyHandler {
+            event: ProtocolInEvent::ExecuteOutbound(_, Some(b"/expensive/1.0.0"), _),
+            ..
+        })
+    ));
+}
+
+#[test]
+fn outbound_protocols_beyond_the_per_peer_limit_stay_queued_in_order() {
+    let mut behaviour = TestBehaviour::new(b"/foo/bar/1.0.0");
+    behaviour.set_max_outbound_per_peer(Some(2));
+    let peer = PeerId::random();
+
+    for id in 0..4 {
+        behaviour.inject_connection_established(&peer, &ConnectionId::new(id), &dialer());
+    }
+    for id in 0..4u32 {
+        behaviour.do_protocol_dialer_tagged(peer, id, |_| async { Ok(()) });
+    }
+
+    for id in 0..2 {
+        assert!(matches!(
+            poll(&mut behaviour),
+            Poll::Ready(NetworkBehaviourAction::NotifyHandler {
+                handler: NotifyHandler::One(connection),
+                ..
+            }) if connection == ConnectionId::new(id)
+        ));
+    }
+    assert!(poll(&mut behaviour).is_pending());
+
+    // Completing a protocol dispatches the next queued one in order, before reporting the result.
+    for tag in 1..3u32 {
+        behaviour.inject_event(
+            peer,
+            ConnectionId::new(1),
+            ProtocolOutEvent::Outbound(Ok(())),
+        );
+
+        assert!(matches!(
+            poll(&mut behaviour),
+            Poll::Ready(NetworkBehaviourAction::NotifyHandler {
+                handler: NotifyHandler::One(connection),
+                ..
+            }) if connection == ConnectionId::new(1)
+        ));
+        match poll(&mut behaviour) {
+            Poll::Ready(NetworkBehaviourAction::GenerateEvent(BehaviourOutEvent::Outbound(
+                _,
+                Ok(()),
+                Some(completed),
+            ))) => assert_eq!(completed.downcast_ref::<u32>(), Some(&tag)),
+            _ => panic!("expected an outbound event"),
+        }
+        assert!(poll(&mut behaviour).is_pending());
+    }
+}
+
+#[test]
+fn peers_take_turns_dispatching_in_proportion_to_their_weight() {
+    let mut behaviour = TestBehaviour::new(b"/foo/bar/1.0.0");
+    let chatty = PeerId::random();
+    let premium = PeerId::random();
+    behaviour.set_peer_weight(premium, 2);
+
+    for id in 0..6 {
+        let peer = if id < 3 { chatty } else { premium };
+        behaviour.inject_connection_established(&peer, &ConnectionId::new(id), &dialer());
+    }
+    for peer in [chatty, premium] {
+        for _ in 0..3 {
+            behaviour.do_protocol_dialer(peer, |_| async { Ok(()) });
+        }
+    }
+
+    let dispatched = (0..6)
+        .map(|_| match poll(&mut behaviour) {
+            Poll::Ready(NetworkBehaviourAction::NotifyHandler { peer_id, .. }) => peer_id,
+            _ => panic!("expected a protocol to be dispatched"),
+        })
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        dispatched,
+        vec![premium, chatty, premium, premium, chatty, chatty]
+    );
+}
+
+#[test]
+fn next_event_resolves_to_queued_events() {
+    let mut behaviour = TestBehaviour::new(b"/foo/bar/1.0.0");
+    let peer = PeerId::random();
+    let connection = ConnectionId::new(0);
+
+    behaviour.inject_connection_established(&peer, &connection, &dialer());
+    behaviour.do_protocol_dialer(peer, |_| async { Ok(()) });
+    assert!(poll(&mut behaviour).is_ready());
+
+    assert!(behaviour.next_event().now_or_never().is_none());
+
+    behaviour.inject_event(peer, connection, ProtocolOutEvent::Outbound(Ok(())));
+
+    assert!(matches!(
+        behaviour.next_event().now_or_never(),
+        Some(BehaviourOutEvent::Outbound(p, Ok(()), None)) if p == peer
+    ));
+}
+
+#[tokio::test]
+async fn next_event_parks_until_a_queued_protocol_expires() {
+    let mut behaviour = TestBehaviour::new(b"/foo/bar/1.0.0");
+    behaviour.set_max_queue_time(Some(Duration::from_millis(100)));
+    behaviour.do_protocol_dialer(PeerId::random(), |_| async { Ok(()) });
+
+    let started = Instant::now();
+    let event = tokio::time::timeout(Duration::from_secs(1), behaviour.next_event())
+        .await
+        .expect("queued protocol to expire");
+
+    assert!(matches!(
+        event,
+        BehaviourOutEvent::OutboundFailed(_, Failure::QueueTimeout, None)
+    ));
+    assert!(started.elapsed() >= Duration::from_millis(100));
+}
+
+#[test]
+fn drain_events_takes_all_queued_events() {
+    let mut behaviour = TestBehaviour::new(b"/foo/bar/1.0.0");
+    let peer = PeerId::random();
+    let connection = ConnectionId::new(0);
+
+    behaviour.inject_connection_established(&peer, &connection, &dialer());
+    assert!(behaviour.drain_events().is_empty());
+
+    behaviour.inject_event(peer, connection, ProtocolOutEvent::Progress);
+    behaviour.inject_event(peer, connection, ProtocolOutEvent::Progress);
+    behaviour.do_protocol_dialer(peer, |_| async { Ok(()) });
+    assert!(poll(&mut behaviour).is_ready());
+    behaviour.inject_event(peer, connection, ProtocolOutEvent::Outbound(Ok(())));
+
+    let events = behaviour.drain_events();
+    assert!(matches!(
+        events.as_slice(),
+        [
+            BehaviourOutEvent::Progress(..),
+            BehaviourOutEvent::Progress(..),
+            BehaviourOutEvent::Outbound(_, Ok(()), None)
+        ]
+    ));
+    assert_eq!(behaviour.last_event_sequence(), Some(2));
+    assert!(behaviour.next_event().now_or_never().is_none());
+}
+
+#[test]
+fn ordered_results_wait_for_protocols_started_earlier() {
+    let mut behaviour = TestBehaviour::new(b"/foo/bar/1.0.0");
+    let first = PeerId::random();
+    let second = PeerId::random();
+
+    behaviour.inject_connection_established(&first, &ConnectionId::new(0), &dialer());
+    behaviour.inject_connection_established(&second, &ConnectionId::new(1), &dialer());
+    behaviour.set_ordered_results(true);
+    let first_connection = dispatch(&mut behaviour, first);
+    let second_connection = dispatch(&mut behaviour, second);
+
+    behaviour.inject_event(
+        second,
+        second_connection,
+        ProtocolOutEvent::OutboundFailed(Failure::Timeout),
+    );
+    assert!(behaviour.drain_events().is_empty());
+
+    behaviour.inject_event(first, first_connection, ProtocolOutEvent::Outbound(Ok(())));
+    let events = behaviour.drain_events();
+    assert!(matches!(
+        events.as_slice(),
+        [
+            BehaviourOutEvent::Outbound(peer, Ok(()), None),
+            BehaviourOutEvent::OutboundFailed(other, Failure::Timeout, None),
+        ] if *peer == first && *other == second
+    ));
+}
+
+#[test]
+fn protocol_stats_separate_queue_wait_from_execution() {
+    let mut behaviour = TestBehaviour::new(b"/foo/bar/1.0.0");
+    let peer = PeerId::random();
+
+    behaviour.do_protocol_dialer(peer, |_| async { Ok(()) });
+    std::thread::sleep(Duration::from_millis(50));
+    behaviour.inject_connection_established(&peer, &ConnectionId::new(0), &dialer());
+    let connection = match poll(&mut behaviour) {
+        Poll::Ready(NetworkBehaviourAction::NotifyHandler {
+            handler: NotifyHandler::One(connection),
+            ..
+        }) => connection,
+        _ => panic!("expected the protocol to be dispatched"),
+    };
+    behaviour.inject_event(
+        peer,
+        connection,
+        ProtocolOutEvent::Executing(b"/foo/bar/1.0.0"),
+    );
+    behaviour.inject_event(peer, connection, ProtocolOutEvent::Outbound(Ok(())));
+
+    let stats = behaviour.protocol_stats()[&b"/foo/bar/1.0.0"[..]];
+    assert!(stats.max_queue_wait >= Duration::from_millis(50));
+    assert_eq!(stats.total_queue_wait, stats.max_queue_wait);
+    assert!(stats.max_duration < Duration::from_millis(50));
+}
+
+#[test]
+fn full_event_queue_drops_events_according_to_overflow() {
+    let peer = PeerId::random();
+    let connection = ConnectionId::new(0);
+
+    for (overflow, expected) in [
+        (EventOverflow::DropOldest, b"/bar/1.0.0"),
+        (EventOverflow::DropNewest, b"/foo/1.0.0"),
+    ] {
+        let mut behaviour = TestBehaviour::new(b"/foo/bar/1.0.0");
+        behaviour.set_max_events(Some(1), overflow);
+        behaviour.inject_connection_established(&peer, &connection, &dialer());
+
+        behaviour.inject_event(peer, connection, ProtocolOutEvent::Rejected(b"/foo/1.0.0"));
+        behaviour.inject_event(peer, connection, ProtocolOutEvent::Rejected(b"/bar/1.0.0"));
+
+        assert!(matches!(
+            behaviour.drain_events().as_slice(),
+            [BehaviourOutEvent::Rejected(_, protocol)] if protocol == expected
+        ));
+        assert_eq!(behaviour.dropped_events(), 1);
+    }
+}
+
+#[test]
+fn full_event_queue_blocks_dispatching_until_events_are_taken() {
+    let mut behaviour = TestBehaviour::new(b"/foo/bar/1.0.0");
+    let peer = PeerId::random();
+    let connection = ConnectionId::new(0);
+
+    behaviour.set_max_events(Some(1), EventOverflow::Block);
+    behaviour.inject_connection_established(&peer, &connection, &dialer());
+    behaviour.inject_event(peer, connection, ProtocolOutEvent::Progress);
+    behaviour.do_protocol_dialer(peer, |_| async { Ok(()) });
+
+    assert!(matches!(
+        poll(&mut behaviour),
+        Poll::Ready(NetworkBehaviourAction::GenerateEvent(
+            BehaviourOutEvent::Progress(..)
+        ))
+    ));
+    assert!(matches!(
+        poll(&mut behaviour),
+        Poll::Ready(NetworkBehaviourAction::NotifyHandler { .. })
+    ));
+    assert_eq!(behaviour.dropped_events(), 0);
+}
+
+#[test]
+fn connection_data_is_dropped_once_the_connection_closes() {
+    let mut behaviour = TestBehaviour::new(b"/foo/bar/1.0.0");
+    let peer = PeerId::random();
+    let connection = ConnectionId::new(0);
+    let unknown = ConnectionId::new(1);
+
+    behaviour.inject_connection_established(&peer, &connection, &dialer());
+    behaviour.set_connection_data(connection, b"session key".to_vec());
+    behaviour.set_connection_data(unknown, b"session key".to_vec());
+
+    assert_eq!(
+        behaviour.get_connection_data::<Vec<u8>>(&connection),
+        Some(&b"session key".to_vec())
+    );
+    assert_eq!(behaviour.get_connection_data::<String>(&connection), None);
+    assert_eq!(behaviour.get_connection_data::<Vec<u8>>(&unknown), None);
+
+    behaviour.inject_connection_closed(&peer, &connection, &dialer());
+
+    assert_eq!(behaviour.get_connection_data::<Vec<u8>>(&connection), None);
+}
+
+#[test]
+fn dial_address_selector_orders_the_addresses_of_a_peer() {
+    let mut behaviour = TestBehaviour::new(b"/foo/bar/1.0.0");
+    let peer = PeerId::random();
+    let first: Multiaddr = "/memory/1".parse().unwrap();
+    let second: Multiaddr = "/memory/2".parse().unwrap();
+
+    for (id, address) in vec![first.clone(), second.clone()].into_iter().enumerate() {
+        behaviour.inject_connection_established(
+            &peer,
+            &ConnectionId::new(id),
+            &ConnectedPoint::Dialer { address },
+        );
+    }
+    assert_eq!(
+        behaviour.addresses_of_peer(&peer),
+        vec![first.clone(), second.clone()]
+    );
+
+    behaviour.set_dial_address_selector(move |p, mut addresses| {
+        assert_eq!(*p, peer);
+        addresses.reverse();
+        addresses
+    });
+
+    assert_eq!(behaviour.addresses_of_peer(&peer), vec![second, first]);
+}
+
+#[test]
+fn paused_peers_are_not_dispatched_to_until_resumed() {
+    let mut behaviour = TestBehaviour::new(b"/foo/bar/1.0.0");
+    let paused = PeerId::random();
+    let other = PeerId::random();
+
+    behaviour.inject_connection_established(&paused, &ConnectionId::new(0), &dialer());
+    behaviour.inject_connection_established(&other, &ConnectionId::new(1), &dialer());
+    behaviour.pause_peer(paused);
+    behaviour.do_protocol_dialer(paused, |_| async { Ok(()) });
+    behaviour.do_protocol_dialer(other, |_| async { Ok(()) });
+
+    assert!(matches!(
+        poll(&mut behaviour),
+        Poll::Ready(NetworkBehaviourAction::NotifyHandler { peer_id, .. }) if peer_id == other
+    ));
+    assert!(poll(&mut behaviour).is_pending());
+
+    behaviour.resume_peer(&paused);
+
+    assert!(matches!(
+        poll(&mut behaviour),
+        Poll::Ready(NetworkBehaviourAction::NotifyHandler { peer_id, .. }) if peer_id == paused
+    ));
+}
+
+#[test]
+fn clearing_cancels_protocols_but_keeps_connections() {
+    let mut behaviour = TestBehaviour::new(b"/foo/bar/1.0.0");
+    let peer = PeerId::random();
+    let connection = ConnectionId::new(0);
+
+    behaviour.inject_connection_established(&peer, &connection, &dialer());
+    behaviour.do_protocol_dialer(peer, |_| async { Ok(()) });
+    behaviour.do_protocol_listener(peer, |_| async { Ok(()) });
+    assert!(poll(&mut behaviour).is_ready());
+    behaviour.inject_event(peer, connection, ProtocolOutEvent::Progress);
+
+    behaviour.clear();
+
+    assert!(behaviour.is_connected(&peer));
+    assert!(matches!(
+        poll(&mut behaviour),
+        Poll::Ready(NetworkBehaviourAction::NotifyHandler {
+            event: ProtocolInEvent::CancelOutbound,
+            ..
+        })
+    ));
+    assert!(matches!(
+        poll(&mut behaviour),
+        Poll::Ready(NetworkBehaviourAction::GenerateEvent(
+            BehaviourOutEvent::InboundFailed(_, Failure::Cancelled(_))
+        ))
+    ));
+    assert!(poll(&mut behaviour).is_pending());
+}
+
+#[test]
+fn split_concurrency_budgets_limit_each_direction_separately() {
+    let mut behaviour = TestBehaviour::new(b"/expensive/1.0.0");
+    behaviour.set_concurrency_budget(
+        b"/expensive/1.0.0",
+        ConcurrencyBudget::Split {
+            inbound: 1,
+            outbound: 1,
+        },
+    );
+    let peer = PeerId::random();
+
+    for id in 0..3 {
+        behaviour.inject_connection_established(&peer, &ConnectionId::new(id), &dialer());
+    }
+    behaviour.do_protocol_dialer_for(peer, b"/expensive/1.0.0", |_| async { Ok(()) });
+    behaviour.do_protocol_dialer_for(peer, b"/expensive/1.0.0", |_| async { Ok(()) });
+    behaviour.do_protocol_listener_for(peer, b"/expensive/1.0.0", |_| async { Ok(()) });
+
+    assert!(matches!(
+        poll(&mut behaviour),
+        Poll::Ready(NetworkBehaviourAction::NotifyHandler {
+            event: ProtocolInEvent::ExecuteOutbound(..),
+            ..
+        })
+    ));
+    assert!(matches!(
+        poll(&mut behaviour),
+        Poll::Ready(NetworkBehaviourAction::NotifyHandler {
+            event: ProtocolInEvent::ExecuteInbound(_),
+            ..
+        })
+    ));
+    assert!(poll(&mut behaviour).is_pending());
+}
+
+#[test]
+fn tagged_protocols_hand_back_their_tag() {
+    let mut behaviour = TestBehaviour::new(b"/foo/bar/1.0.0");
+    let peer = PeerId::random();
+    let connection = ConnectionId::new(0);
+
+    behaviour.inject_connection_established(&peer, &connection, &dialer());
+    behaviour.do_protocol_dialer_tagged(peer, 42u32, |_| async { Ok(()) });
+
+    assert!(poll(&mut behaviour).is_ready());
+    behaviour.inject_event(peer, connection, ProtocolOutEvent::Outbound(Ok(())));
+
+    match poll(&mut behaviour) {
+        Poll::Ready(NetworkBehaviourAction::GenerateEvent(BehaviourOutEvent::Outbound(
+            _,
+            Ok(()),
+            Some(tag),
+        ))) => assert_eq!(tag.downcast_ref::<u32>(), Some(&42)),
+        _ => panic!("expected a tagged outbound event"),
+    }
+}
+
+#[cfg(feature = "testing")]
+#[test]
+fn queued_protocols_are_counted_until_dispatched() {
+    let mut behaviour = TestBehaviour::new(b"/foo/bar/1.0.0");
+    let alice = PeerId::random();
+    let bob = PeerId::random();
+
+    behaviour.inject_connection_established(&alice, &ConnectionId::new(0), &dialer());
+    behaviour.do_protocol_dialer(alice, |_| async { Ok(()) });
+    behaviour.do_protocol_dialer(bob, |_| async { Ok(()) });
+    behaviour.do_protocol_listener(bob, |_| async { Ok(()) });
+    assert_eq!(behaviour.pending_in_events(), 3);
+
+    assert!(poll(&mut behaviour).is_ready());
+
+    assert_eq!(behaviour.pending_in_events(), 2);
+    assert_eq!(behaviour.pending_in_events_per_peer().get(&alice), None);
+    assert_eq!(behaviour.pending_in_events_per_peer().get(&bob), Some(&2));
+}
+
+#[test]
+fn peer_stats_combine_queued_and_in_flight_protocols() {
+    let mut behaviour = TestBehaviour::new(b"/foo/bar/1.0.0");
+    let alice = PeerId::random();
+    let bob = PeerId::random();
+
+    behaviour.inject_connection_established(&alice, &ConnectionId::new(0), &dialer());
+    behaviour.inject_connection_established(&alice, &ConnectionId::new(1), &dialer());
+    dispatch(&mut behaviour, alice);
+    behaviour.do_protocol_dialer(bob, |_| async { Ok(()) });
+    behaviour.do_protocol_listener(bob, |_| async { Ok(()) });
+
+    assert_eq!(
+        behaviour.peer_stats(&alice),
+        Some(PeerStats {
+            pending_in: 0,
+            pending_out: 0,
+            in_flight: 1,
+            connections: 2,
+        })
+    );
+    assert_eq!(
+        behaviour.peer_stats(&bob),
+        Some(PeerStats {
+            pending_in: 1,
+            pending_out: 1,
+            in_flight: 0,
+            connections: 0,
+        })
+    );
+    assert_eq!(behaviour.peer_stats(&PeerId::random()), None);
+    assert_eq!(behaviour.all_peer_stats().count(), 2);
+}
+
+#[test]
+fn exported_descriptors_follow_the_order_protocols_were_started_in() {
+    let mut behaviour = TestBehaviour::new(b"/foo/bar/1.0.0");
+    let alice = PeerId::random();
+    let bob = PeerId::random();
+
+    behaviour.inject_connection_established(&alice, &ConnectionId::new(0), &dialer());
+    behaviour.do_protocol_dialer_tagged(alice, "first", |_| async { Ok(()) });
+    behaviour.do_protocol_listener_for(bob, b"/foo/bar/1.0.0", |_| async { Ok(()) });
+    assert!(poll(&mut behaviour).is_ready());
+
+    let pending = behaviour.export_pending();
+
+    match pending.as_slice() {
+        [first, second] => {
+            assert_eq!(first.peer, alice);
+            assert_eq!(first.direction, Direction::Outbound);
+            assert_eq!(
+                first
+                    .tag
+                    .as_ref()
+                    .and_then(|tag| tag.downcast_ref::<&str>()),
+                Some(&"first")
+            );
+            assert_eq!(first.protocol, None);
+            assert!(first.dispatched);
+
+            assert_eq!(second.peer, bob);
+            assert_eq!(second.direction, Direction::Inbound);
+            assert!(second.tag.is_none());
+            assert_eq!(second.protocol, Some(&b"/foo/bar/1.0.0"[..]));
+            assert!(!second.dispatched);
+        }
+        pending => panic!("unexpected descriptors {:?}", pending),
+    }
+}
+
+#[test]
+fn local_address_is_tracked_for_accepted_connections() {
+    let mut behaviour = TestBehaviour::new(b"/foo/bar/1.0.0");
+    let peer = PeerId::random();
+    let local_addr: Multiaddr = "/memory/1".parse().unwrap();
+    let listener = ConnectedPoint::Listener {
+        local_addr: local_addr.clone(),
+        send_back_addr: "/memory/2".parse().unwrap(),
+    };
+
+    behaviour.inject_connection_established(&peer, &ConnectionId::new(0), &listener);
+    behaviour.inject_connection_established(&peer, &ConnectionId::new(1), &dialer());
+
+    assert_eq!(
+        behaviour.local_address(&peer, &ConnectionId::new(0)),
+        Some(&local_addr)
+    );
+    assert_eq!(behaviour.local_address(&peer, &ConnectionId::new(1)), None);
+}
+
+#[test]
+fn cancelling_tagged_protocols_fails_queued_and_notifies_in_flight_ones() {
+    let mut behaviour = TestBehaviour::new(b"/foo/bar/1.0.0");
+    let peer = PeerId::random();
+
+    behaviour.inject_connection_established(&peer, &ConnectionId::new(0), &dialer());
+    behaviour.do_protocol_dialer_tagged(peer, "query", |_| async { Ok(()) });
+    behaviour.do_protocol_dialer_tagged(peer, "query", |_| async { Ok(()) });
+    behaviour.do_protocol_dialer_tagged(peer, "other", |_| async { Ok(()) });
+    assert!(poll(&mut behaviour).is_ready());
+
+    behaviour.cancel_where(|tag| tag.downcast_ref::<&str>() == Some(&"query"));
+
+    assert!(matches!(
+        poll(&mut behaviour),
+        Poll::Ready(NetworkBehaviourAction::NotifyHandler {
+            event: ProtocolInEvent::CancelOutbound,
+            ..
+        })
+    ));
+    assert!(matches!(
+        poll(&mut behaviour),
+        Poll::Ready(NetworkBehaviourAction::GenerateEvent(
+            BehaviourOutEvent::OutboundFailed(_, Failure::Cancelled(_), Some(_))
+        ))
+    ));
+    assert!(poll(&mut behaviour).is_pending());
+}
+
+#[test]
+fn connection_strategies_choose_among_idle_connections() {
+    let connection = ConnectionId::new;
+    let setup = |strategy| {
+        let mut behaviour = TestBehaviour::new(b"/foo/bar/1.0.0");
+        behaviour.set_connection_strategy(strategy);
+        let peer = PeerId::random();
+        for id in 0..3 {
+            behaviour.inject_connection_established(&peer, &connection(id), &dialer());
+        }
+
+        (behaviour, peer)
+    };
+
+    let (mut behaviour, peer) = setup(ConnectionStrategy::First);
+    assert_eq!(dispatch_and_complete(&mut behaviour, peer), connection(0));
+    assert_eq!(dispatch_and_complete(&mut behaviour, peer), connection(0));
+
+    let (mut behaviour, peer) = setup(ConnectionStrategy::RoundRobin);
+    for id in [0, 1, 2, 0] {
+        assert_eq!(dispatch_and_complete(&mut behaviour, peer), connection(id));
+    }
+    // Busy connections are skipped without losing their turn for good.
+    assert_eq!(dispatch(&mut behaviour, peer), connection(1));
+    assert_eq!(dispatch_and_complete(&mut behaviour, peer), connection(2));
+    assert_eq!(dispatch_and_complete(&mut behaviour, peer), connection(0));
+    complete(&mut behaviour, peer, connection(1));
+    assert_eq!(dispatch_and_complete(&mut behaviour, peer), connection(1));
+
+    let (mut behaviour, peer) = setup(ConnectionStrategy::LeastBusy);
+    assert_eq!(dispatch(&mut behaviour, peer), connection(0));
+    assert_eq!(dispatch_and_complete(&mut behaviour, peer), connection(1));
+    assert_eq!(dispatch_and_complete(&mut behaviour, peer), connection(2));
+    assert_eq!(dispatch_and_complete(&mut behaviour, peer), connection(1));
+    complete(&mut behaviour, peer, connection(0));
+    assert_eq!(dispatch_and_complete(&mut behaviour, peer), connection(0));
+    assert_eq!(dispatch_and_complete(&mut behaviour, peer), connection(2));
+}
+
+#[test]
+fn rate_limited_outbound_protocols_wait_for_tokens() {
+    let mut behaviour = TestBehaviour::new(b"/foo/bar/1.0.0");
+    let limit = RateLimit {
+        burst: 2,
+        interval: Duration::from_millis(50),
+    };
+    behaviour.set_outbound_rate_limit(Some(limit));
+    let peer = PeerId::random();
+    for id in 0..4 {
+        behaviour.inject_connection_established(&peer, &ConnectionId::new(id), &dialer());
+    }
+
+    dispatch(&mut behaviour, peer);
+    dispatch(&mut behaviour, peer);
+    behaviour.do_protocol_dialer(peer, |_| async { Ok(()) });
+    assert!(poll(&mut behaviour).is_pending());
+
+    // Inbound protocols are not limited.
+    behaviour.do_protocol_listener(peer, |_| async { Ok(()) });
+    assert!(poll(&mut behaviour).is_ready());
+
+    std::thread::sleep(limit.interval);
+    assert!(matches!(
+        poll(&mut behaviour),
+        Poll::Ready(NetworkBehaviourAction::NotifyHandler {
+            event: ProtocolInEvent::ExecuteOutbound(..),
+            ..
+        })
+    ));
+}
+
+#[test]
+fn per_peer_rate_limits_do_not_hold_back_other_peers() {
+    let mut behaviour = TestBehaviour::new(b"/foo/bar/1.0.0");
+    behaviour.set_outbound_rate_limit_per_peer(Some(RateLimit {
+        burst: 1,
+        interval: Duration::from_secs(60),
+    }));
+    let alice = PeerId::random();
+    let bob = PeerId::random();
+    for id in 0..4 {
+        let peer = if id < 2 { alice } else { bob };
+        behaviour.inject_connection_established(&peer, &ConnectionId::new(id), &dialer());
+    }
+
+    dispatch(&mut behaviour, alice);
+    behaviour.do_protocol_dialer(alice, |_| async { Ok(()) });
+    assert!(poll(&mut behaviour).is_pending());
+
+    assert_eq!(dispatch(&mut behaviour, bob), ConnectionId::new(2));
+}
+
+#[test]
+fn events_are_numbered_until_cleared() {
+    let mut behaviour = TestBehaviour::new(b"/foo/bar/1.0.0");
+    let peer = PeerId::random();
+    let connection = ConnectionId::new(0);
+    behaviour.inject_connection_established(&peer, &connection, &dialer());
+    assert_eq!(behaviour.last_event_sequence(), None);
+
+    for sequence in 0..2 {
+        dispatch_and_complete(&mut behaviour, peer);
+        assert_eq!(behaviour.last_event_sequence(), Some(sequence));
+    }
+    behaviour.inject_event(peer, connection, ProtocolOutEvent::Progress);
+    assert!(behaviour.next_event().now_or_never().is_some());
+    assert_eq!(behaviour.last_event_sequence(), Some(2));
+
+    behaviour.clear();
+    assert_eq!(behaviour.last_event_sequence(), None);
+}
+
+#[test]
+fn protocols_queued_for_too_long_time_out() {
+    let mut behaviour = TestBehaviour::new(b"/foo/bar/1.0.0");
+    let max_queue_time = Duration::from_millis(20);
+    behaviour.set_max_queue_time(Some(max_queue_time));
+    let peer = PeerId::random();
+
+    behaviour.do_protocol_dialer(peer, |_| async { Ok(()) });
+    behaviour.do_protocol_listener(peer, |_| async { Ok(()) });
+    assert!(poll(&mut behaviour).is_pending());
+
+    std::thread::sleep(max_queue_time);
+
+    assert!(matches!(
+        poll(&mut behaviour),
+        Poll::Ready(NetworkBehaviourAction::GenerateEvent(
+            BehaviourOutEvent::OutboundFailed(_, Failure::QueueTimeout, None)
+        ))
+    ));
+    assert!(matches!(
+        poll(&mut behaviour),
+        Poll::Ready(NetworkBehaviourAction::GenerateEvent(
+            BehaviourOutEvent::InboundFailed(_, Failure::QueueTimeout)
+        ))
+    ));
+    assert!(poll(&mut behaviour).is_pending());
+}
+
+#[test]
+fn event_ready_callback_fires_when_events_become_available() {
+    let mut behaviour = TestBehaviour::new(b"/foo/bar/1.0.0");
+    let ready = Arc::new(AtomicUsize::new(0));
+    behaviour.set_emit_connection_events(true);
+    behaviour.set_on_event_ready({
+        let ready = ready.clone();
+        move || {
+            ready.fetch_add(1, Ordering::SeqCst);
+        }
+    });
+    let peer = PeerId::random();
+
+    behaviour.inject_connection_established(&peer, &ConnectionId::new(0), &dialer());
+    behaviour.inject_connection_established(&peer, &ConnectionId::new(1), &dialer());
+    assert_eq!(ready.load(Ordering::SeqCst), 1);
+
+    assert!(poll(&mut behaviour).is_ready());
+    assert!(poll(&mut behaviour).is_ready());
+    assert!(poll(&mut behaviour).is_pending());
+
+    behaviour.inject_connection_closed(&peer, &ConnectionId::new(1), &dialer());
+    assert_eq!(ready.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn config_reflects_the_configured_limits() {
+    let mut behaviour = TestBehaviour::new(b"/foo/bar/1.0.0");
+
+    let defaults = behaviour.config();
+    assert_eq!(defaults.protocols, vec![&b"/foo/bar/1.0.0"[..]]);
+    assert_eq!(defaults.max_outbound_per_peer, None);
+    assert_eq!(defaults.outbound_rate_limit, None);
+    assert_eq!(defaults.max_queue_time, None);
+    assert!(defaults.max_concurrent.is_empty());
+
+    let limit = RateLimit {
+        burst: 5,
+        interval: Duration::from_millis(100),
+    };
+    behaviour.set_max_concurrent(b"/foo/bar/1.0.0", 3);
+    behaviour.set_max_outbound_per_peer(Some(2));
+    behaviour.set_outbound_rate_limit(Some(limit));
+    behaviour.set_write_rate_limit(Some(64 * 1024));
+    behaviour.set_max_read_memory_per_connection(Some(1024 * 1024));
+    behaviour.set_max_executions_per_connection(Some(100));
+    behaviour.set_emit_drained_events(true);
+    behaviour.set_max_queue_time(Some(Duration::from_secs(10)));
+    behaviour.set_idle_timeout(Some(Duration::from_secs(30)));
+    behaviour.set_connection_strategy(ConnectionStrategy::LeastBusy);
+
+    let config = behaviour.config();
+    assert_eq!(
+        config.max_concurrent.get(&b"/foo/bar/1.0.0"[..]),
+        Some(&ConcurrencyBudget::Shared(3))
+    );
+    assert_eq!(config.max_outbound_per_peer, Some(2));
+    assert_eq!(config.outbound_rate_limit, Some(limit));
+    assert_eq!(config.outbound_rate_limit_per_peer, None);
+    assert_eq!(config.write_rate_limit, Some(64 * 1024));
+    assert_eq!(config.max_read_memory_per_connection, Some(1024 * 1024));
+    assert_eq!(config.max_executions_per_connection, Some(100));
+    assert!(config.emit_drained_events);
+    assert_eq!(config.max_queue_time, Some(Duration::from_secs(10)));
+    assert_eq!(config.idle_timeout, Some(Duration::from_secs(30)));
+    assert_eq!(config.connection_strategy, ConnectionStrategy::LeastBusy);
+    assert_ne!(config, defaults);
+}
+
+/// Polls the behaviour and returns the connection a protocol was dispatched to, if any.
+fn dispatched(behaviour: &mut TestBehaviour) -> Option<ConnectionId> {
+    match poll(behaviour) {
+        Poll::Ready(NetworkBehaviourAction::NotifyHandler {
+            handler: NotifyHandler::One(connection),
+            ..
+        }) => Some(connection),
+        _ => None,
+    }
+}
+
+#[test]
+fn session_protocols_stick_to_one_connection() {
+    let mut behaviour = TestBehaviour::new(b"/foo/bar/1.0.0");
+    let peer = PeerId::random();
+    for id in 0..2 {
+        behaviour.inject_connection_established(&peer, &ConnectionId::new(id), &dialer());
+    }
+
+    let session = behaviour.open_session(peer);
+    assert_eq!(session.peer(), peer);
+    assert_eq!(behaviour.session_connection(session), None);
+
+    behaviour.do_protocol_dialer_in_session(session, |_| async { Ok(()) });
+    let pinned = dispatched(&mut behaviour).expect("protocol to be dispatched");
+    assert_eq!(behaviour.session_connection(session), Some(pinned));
+    complete(&mut behaviour, peer, pinned);
+
+    // Keep the pinned connection busy, the session has to wait for it.
+    assert_eq!(dispatch(&mut behaviour, peer), pinned);
+    behaviour.do_protocol_dialer_in_session(session, |_| async { Ok(()) });
+    assert!(poll(&mut behaviour).is_pending());
+
+    // Queued protocols are dispatched before events are handed out.
+    behaviour.inject_event(peer, pinned, ProtocolOutEvent::Outbound(Ok(())));
+    assert_eq!(dispatched(&mut behaviour), Some(pinned));
+}
+
+#[test]
+fn sessions_fail_once_their_connection_closed() {
+    let mut behaviour = TestBehaviour::new(b"/foo/bar/1.0.0");
+    let peer = PeerId::random();
+    for id in 0..2 {
+        behaviour.inject_connection_established(&peer, &ConnectionId::new(id), &dialer());
+    }
+
+    let session = behaviour.open_session(peer);
+    behaviour.do_protocol_dialer_in_session(session, |_| async { Ok(()) });
+    let pinned = dispatched(&mut behaviour).expect("protocol to be dispatched");
+    behaviour.do_protocol_dialer_in_session(session, |_| async { Ok(()) });
+    assert!(poll(&mut behaviour).is_pending());
+
+    behaviour.inject_connection_closed(&peer, &pinned, &dialer());
+    for _ in 0..2 {
+        assert!(matches!(
+            poll(&mut behaviour),
+            Poll::Ready(NetworkBehaviourAction::GenerateEvent(
+                BehaviourOutEvent::OutboundFailed(_, Failure::ConnectionClosed, None)
+            ))
+        ));
+    }
+
+    behaviour.do_protocol_dialer_in_session(session, |_| async { Ok(()) });
+    assert!(matches!(
+        poll(&mut behaviour),
+        Poll::Ready(NetworkBehaviourAction::GenerateEvent(
+            BehaviourOutEvent::OutboundFailed(_, Failure::ConnectionClosed, None)
+        ))
+    ));
+    assert!(poll(&mut behaviour).is_pending());
+}
+
+#[test]
+fn dispatching_preserves_the_direction_of_queued_protocols() {
+    let mut behaviour = TestBehaviour::new(b"/foo/bar/1.0.0");
+    let peer = PeerId::random();
+    behaviour.inject_connection_established(&peer, &ConnectionId::new(0), &dialer());
+    behaviour.inject_connection_established(&peer, &ConnectionId::new(1), &dialer());
+
+    behaviour.do_protocol_listener(peer, |_| async { Ok(()) });
+    behaviour.do_protocol_dialer(peer, |_| async { Ok(()) });
+
+    let inbound = match poll(&mut behaviour) {
+        Poll::Ready(NetworkBehaviourAction::NotifyHandler {
+            handler: NotifyHandler::One(connection),
+            event: ProtocolInEvent::ExecuteInbound(_),
+            ..
+        }) => connection,
+        _ => panic!("expected the listener to be dispatched first"),
+    };
+    let outbound = match poll(&mut behaviour) {
+        Poll::Ready(NetworkBehaviourAction::NotifyHandler {
+            handler: NotifyHandler::One(connection),
+            event: ProtocolInEvent::ExecuteOutbound(..),
+            ..
+        }) => connection,
+        _ => panic!("expected the dialer to be dispatched second"),
+    };
+
+    behaviour.inject_connection_closed(&peer, &inbound, &dialer());
+    assert!(matches!(
+        poll(&mut behaviour),
+        Poll::Ready(NetworkBehaviourAction::GenerateEvent(
+            BehaviourOutEvent::InboundFailed(_, Failure::ConnectionClosed)
+        ))
+    ));
+    behaviour.inject_connection_closed(&peer, &outbound, &dialer());
+    assert!(matches!(
+        poll(&mut behaviour),
+        Poll::Ready(NetworkBehaviourAction::GenerateEvent(
+            BehaviourOutEvent::OutboundFailed(_, Failure::ConnectionClosed, _)
+        ))
+    ));
+}
@@ -0,0 +1,45 @@
+#![cfg(feature = "tokio")]
+
+use harness::{collect_events, new_connected_swarm_pair};
+use libp2p_async_await::{Behaviour, BehaviourOutEvent};
+use std::time::Duration;
+use tokio::runtime::Handle;
+use tokio::sync::broadcast;
+
+mod harness;
+
+type TestBehaviour = Behaviour<(), (), ()>;
+
+#[tokio::test]
+async fn every_subscriber_receives_every_event() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, mut bob) =
+        new_connected_swarm_pair(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current()).await;
+    let (sender, mut first) = broadcast::channel(16);
+    let mut second = sender.subscribe();
+    alice.swarm.behaviour_mut().set_event_sender(sender);
+
+    alice
+        .swarm
+        .behaviour_mut()
+        .do_protocol_dialer(bob.peer_id, |_| async { Ok(()) });
+    bob.swarm
+        .behaviour_mut()
+        .do_protocol_listener(alice.peer_id, |_| async { Ok(()) });
+
+    let (alice_events, _) =
+        collect_events(&mut alice.swarm, &mut bob.swarm, Duration::from_secs(1)).await;
+
+    assert!(matches!(
+        alice_events.as_slice(),
+        [BehaviourOutEvent::Outbound(_, Ok(()), _)]
+    ));
+    for subscriber in [&mut first, &mut second] {
+        assert!(matches!(
+            subscriber.try_recv(),
+            Ok(BehaviourOutEvent::Outbound(_, Ok(()), _))
+        ));
+        assert!(subscriber.try_recv().is_err());
+    }
+}
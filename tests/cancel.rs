@@ -0,0 +1,182 @@
+use harness::{collect_events, connect, new_swarm};
+use libp2p::futures::future;
+use libp2p::futures::task::{Context, Poll};
+use libp2p::futures::{AsyncRead, AsyncWrite};
+use libp2p_async_await::{Behaviour, BehaviourOutEvent, CancelReason, Failure};
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::runtime::Handle;
+
+mod harness;
+
+type TestBehaviour = Behaviour<(), (), anyhow::Error>;
+
+#[tokio::test]
+async fn cancelled_protocols_terminate_with_their_tag() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, _, _) = new_swarm(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current());
+    let (mut bob, _, bob_peer_id) =
+        new_swarm(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current());
+    connect(&mut alice, &mut bob).await;
+
+    alice
+        .behaviour_mut()
+        .do_protocol_dialer_tagged(bob_peer_id, 1u32, |_| future::pending());
+    collect_events(&mut alice, &mut bob, Duration::from_millis(200)).await;
+
+    alice
+        .behaviour_mut()
+        .cancel_where(|tag| tag.downcast_ref::<u32>() == Some(&1));
+    let (alice_events, _) = collect_events(&mut alice, &mut bob, Duration::from_millis(200)).await;
+
+    match alice_events.as_slice() {
+        [BehaviourOutEvent::OutboundFailed(
+            _,
+            Failure::Cancelled(CancelReason::Unspecified),
+            Some(tag),
+        )] => {
+            assert_eq!(tag.downcast_ref::<u32>(), Some(&1))
+        }
+        events => panic!("unexpected events {:?}", events),
+    }
+}
+
+#[tokio::test]
+async fn cancelled_protocols_report_the_given_reason() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, _, _) = new_swarm(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current());
+    let (mut bob, _, bob_peer_id) =
+        new_swarm(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current());
+    connect(&mut alice, &mut bob).await;
+
+    alice
+        .behaviour_mut()
+        .do_protocol_dialer_tagged(bob_peer_id, 1u32, |_| future::pending());
+    collect_events(&mut alice, &mut bob, Duration::from_millis(200)).await;
+    // Stays queued behind the first one.
+    alice
+        .behaviour_mut()
+        .do_protocol_dialer_tagged(bob_peer_id, 2u32, |_| future::pending());
+
+    alice
+        .behaviour_mut()
+        .cancel_where_with_reason(|_| true, CancelReason::Superseded);
+    let (alice_events, _) = collect_events(&mut alice, &mut bob, Duration::from_millis(200)).await;
+
+    assert_eq!(alice_events.len(), 2);
+    for event in alice_events {
+        match event {
+            BehaviourOutEvent::OutboundFailed(
+                _,
+                Failure::Cancelled(CancelReason::Superseded),
+                Some(_),
+            ) => {}
+            event => panic!("unexpected event {:?}", event),
+        }
+    }
+}
+
+/// Counts the bytes read from the substream.
+struct Counted<S>(S, Arc<AtomicUsize>);
+
+impl<S: AsyncRead + Unpin> AsyncRead for Counted<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let read = match Pin::new(&mut self.0).poll_read(cx, buf)? {
+            Poll::Ready(read) => read,
+            Poll::Pending => return Poll::Pending,
+        };
+        self.1.fetch_add(read, Ordering::SeqCst);
+
+        Poll::Ready(Ok(read))
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Counted<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_close(cx)
+    }
+}
+
+/// Cancels a protocol the remote sent 10000 bytes to and returns how many of them were read.
+async fn bytes_read_by_cancelling(limit: Option<usize>) -> usize {
+    let read = Arc::new(AtomicUsize::new(0));
+    let counter = read.clone();
+    let (mut alice, _, alice_peer_id) = new_swarm(
+        move |_, _| {
+            let counter = counter.clone();
+            let mut behaviour = TestBehaviour::new(b"/foo/1.0.0");
+            behaviour.wrap_substream(move |substream| Counted(substream, counter.clone()));
+            behaviour.set_cancel_drain_limit(limit);
+            behaviour
+        },
+        Handle::current(),
+    );
+    let (mut bob, _, bob_peer_id) =
+        new_swarm(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current());
+    connect(&mut alice, &mut bob).await;
+
+    alice.behaviour_mut().do_protocol_dialer_tagged(
+        bob_peer_id,
+        1u32,
+        |mut substream| async move {
+            substream.write_message(b"start").await?;
+            future::pending().await
+        },
+    );
+    bob.behaviour_mut()
+        .do_protocol_listener(alice_peer_id, |mut substream| async move {
+            substream.read_message(1024).await?;
+            substream.write_message(&[0; 10000]).await?;
+            future::pending().await
+        });
+    collect_events(&mut alice, &mut bob, Duration::from_millis(300)).await;
+
+    let before = read.load(Ordering::SeqCst);
+    alice.behaviour_mut().cancel_where(|_| true);
+    let (alice_events, _) = collect_events(&mut alice, &mut bob, Duration::from_millis(300)).await;
+    assert!(matches!(
+        alice_events.as_slice(),
+        [BehaviourOutEvent::OutboundFailed(
+            _,
+            Failure::Cancelled(_),
+            Some(_)
+        )]
+    ));
+
+    read.load(Ordering::SeqCst) - before
+}
+
+#[tokio::test]
+async fn cancelled_protocols_drain_what_the_remote_sent_up_to_the_limit() {
+    let _ = env_logger::try_init();
+
+    assert_eq!(bytes_read_by_cancelling(Some(4096)).await, 4096);
+}
+
+#[tokio::test]
+async fn cancelled_protocols_do_not_drain_by_default() {
+    let _ = env_logger::try_init();
+
+    assert_eq!(bytes_read_by_cancelling(None).await, 0);
+}
@@ -0,0 +1,100 @@
+#![cfg(feature = "crc")]
+
+use harness::{collect_events, new_connected_swarm_pair};
+use libp2p::futures::io::Cursor;
+use libp2p::futures::StreamExt;
+use libp2p_async_await::driver::Driver;
+use libp2p_async_await::{
+    Behaviour, BehaviourOutEvent, ChecksumError, InboundSubstream, ProtocolOutEvent,
+};
+use std::time::Duration;
+use tokio::runtime::Handle;
+
+mod harness;
+
+type TestBehaviour = Behaviour<Vec<u8>, (), anyhow::Error>;
+
+#[tokio::test]
+async fn checksummed_messages_round_trip() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, mut bob) =
+        new_connected_swarm_pair(|_, _| TestBehaviour::new(b"/crc/1.0.0"), Handle::current()).await;
+
+    alice
+        .swarm
+        .behaviour_mut()
+        .do_protocol_dialer(bob.peer_id, |mut substream| async move {
+            substream.write_message_crc32(b"hello").await?;
+            Ok(())
+        });
+    bob.swarm
+        .behaviour_mut()
+        .do_protocol_listener(alice.peer_id, |mut substream| async move {
+            Ok(substream.read_message_crc32(5).await?)
+        });
+
+    let (_, bob_events) =
+        collect_events(&mut alice.swarm, &mut bob.swarm, Duration::from_secs(1)).await;
+
+    match bob_events.as_slice() {
+        [BehaviourOutEvent::Inbound(_, Ok(message))] => assert_eq!(message, b"hello"),
+        events => panic!("unexpected events {:?}", events),
+    }
+}
+
+#[tokio::test]
+async fn corrupted_messages_fail_verification() {
+    let _ = env_logger::try_init();
+
+    // The standard check value of CRC-32, followed by a frame of "hellO" with the checksum of
+    // "hello".
+    let mut frame = vec![13];
+    frame.extend_from_slice(b"123456789");
+    frame.extend_from_slice(&0xcbf4_3926u32.to_be_bytes());
+    frame.push(9);
+    frame.extend_from_slice(b"hellO");
+    frame.extend_from_slice(&0x3610_a686u32.to_be_bytes());
+
+    let mut driver = Driver::<bool, (), anyhow::Error>::new();
+    driver.execute_inbound(
+        InboundSubstream::new(Cursor::new(frame), b"/crc/1.0.0"),
+        |mut substream| async move {
+            let check = substream.read_message_crc32(1024).await?;
+            let res = substream.read_message_crc32(1024).await;
+            Ok(check == b"123456789"
+                && matches!(
+                    res,
+                    Err(ChecksumError::Mismatch {
+                        expected: 0x3610_a686,
+                        ..
+                    })
+                ))
+        },
+    );
+
+    assert!(matches!(
+        driver.next().await,
+        Some(ProtocolOutEvent::Inbound(Ok(true)))
+    ));
+}
+
+#[tokio::test]
+async fn unlimited_max_sizes_do_not_overflow() {
+    let _ = env_logger::try_init();
+
+    let mut frame = vec![13];
+    frame.extend_from_slice(b"123456789");
+    frame.extend_from_slice(&0xcbf4_3926u32.to_be_bytes());
+
+    let mut driver = Driver::<Vec<u8>, (), anyhow::Error>::new();
+    driver.execute_inbound(
+        InboundSubstream::new(Cursor::new(frame), b"/crc/1.0.0"),
+        |mut substream| async move { Ok(substream.read_message_crc32(usize::MAX).await?) },
+    );
+
+    assert!(matches!(
+        driver.next().await,
+        Some(ProtocolOutEvent::Inbound(Ok(message))) if message == b"123456789"
+    ));
+}
@@ -0,0 +1,195 @@
+use harness::{collect_events, connect, new_swarm};
+use libp2p::futures::future::BoxFuture;
+use libp2p::futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, FutureExt};
+use libp2p_async_await::framing::{FrameMemory, Framing, LengthPrefixed};
+use libp2p_async_await::{Behaviour, BehaviourOutEvent, FramingError, InboundSubstream};
+use std::io;
+use std::time::Duration;
+use tokio::runtime::Handle;
+
+mod harness;
+
+type TestBehaviour = Behaviour<Vec<Vec<u8>>, (), anyhow::Error>;
+
+/// Frames of exactly four bytes.
+struct FourBytes;
+
+impl Framing for FourBytes {
+    fn write_frame<'a>(
+        &'a self,
+        socket: &'a mut (dyn AsyncWrite + Unpin + Send),
+        msg: &'a [u8],
+    ) -> BoxFuture<'a, io::Result<()>> {
+        async move {
+            if msg.len() != 4 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "not four bytes",
+                ));
+            }
+            socket.write_all(msg).await
+        }
+        .boxed()
+    }
+
+    fn read_frame<'a>(
+        &'a self,
+        socket: &'a mut (dyn AsyncRead + Unpin + Send),
+        _: usize,
+        memory: &'a mut FrameMemory,
+    ) -> BoxFuture<'a, Result<Vec<u8>, FramingError>> {
+        async move {
+            memory.reserve(4)?;
+            let mut frame = vec![0; 4];
+            socket.read_exact(&mut frame).await?;
+
+            Ok(frame)
+        }
+        .boxed()
+    }
+}
+
+fn new_behaviour(framing: bool) -> TestBehaviour {
+    let mut behaviour = TestBehaviour::new(b"/foo/1.0.0");
+    if framing {
+        behaviour.set_framing(FourBytes);
+    }
+
+    behaviour
+}
+
+/// Has alice send two messages with the custom framing and bob read them with `read`.
+async fn send_two<F>(bob_framing: bool, read: fn(InboundSubstream) -> F) -> Vec<Vec<u8>>
+where
+    F: std::future::Future<Output = anyhow::Result<Vec<Vec<u8>>>> + Send + 'static,
+{
+    let (mut alice, _, alice_peer_id) = new_swarm(|_, _| new_behaviour(true), Handle::current());
+    let (mut bob, _, bob_peer_id) =
+        new_swarm(move |_, _| new_behaviour(bob_framing), Handle::current());
+    connect(&mut alice, &mut bob).await;
+
+    alice
+        .behaviour_mut()
+        .do_protocol_dialer(bob_peer_id, |mut substream| async move {
+            substream.write_message(b"ping").await?;
+            substream.write_message(b"pong").await?;
+            substream.close().await?;
+            Ok(())
+        });
+    bob.behaviour_mut()
+        .do_protocol_listener(alice_peer_id, read);
+
+    let (_, bob_events) = collect_events(&mut alice, &mut bob, Duration::from_secs(1)).await;
+
+    match bob_events.as_slice() {
+        [BehaviourOutEvent::Inbound(_, Ok(messages))] => messages.clone(),
+        events => panic!("unexpected events {:?}", events),
+    }
+}
+
+#[tokio::test]
+async fn messages_are_framed_by_the_configured_framing() {
+    let _ = env_logger::try_init();
+
+    let messages = send_two(true, |mut substream| async move {
+        Ok(vec![
+            substream.read_message(1024).await?,
+            substream.read_message(1024).await?,
+        ])
+    })
+    .await;
+
+    assert_eq!(messages, vec![b"ping".to_vec(), b"pong".to_vec()]);
+}
+
+#[tokio::test]
+async fn custom_framing_replaces_the_length_prefix_on_the_wire() {
+    let _ = env_logger::try_init();
+
+    let messages = send_two(false, |mut substream| async move {
+        let mut bytes = Vec::new();
+        substream.read_to_end(&mut bytes).await?;
+        Ok(vec![bytes])
+    })
+    .await;
+
+    assert_eq!(messages, vec![b"pingpong".to_vec()]);
+}
+
+#[tokio::test]
+async fn chunked_partial_and_framed_reads_use_the_configured_framing() {
+    let _ = env_logger::try_init();
+
+    let chunked = send_two(true, |mut substream| async move {
+        let mut chunks = Vec::new();
+        for _ in 0..2 {
+            substream
+                .read_message_chunked(1024, 3, |chunk| chunks.push(chunk.to_vec()))
+                .await?;
+        }
+        Ok(chunks)
+    })
+    .await;
+    let partial = send_two(true, |mut substream| async move {
+        let (first, _) = substream
+            .read_message_timeout_partial(1024, Duration::from_secs(1))
+            .await?;
+        let (second, _) = substream
+            .read_message_timeout_partial(1024, Duration::from_secs(1))
+            .await?;
+        Ok(vec![first, second])
+    })
+    .await;
+    let framed = send_two(true, |mut substream| async move {
+        Ok(substream.read_to_end_framed(1024).await?)
+    })
+    .await;
+
+    assert_eq!(
+        chunked,
+        vec![
+            b"pin".to_vec(),
+            b"g".to_vec(),
+            b"pon".to_vec(),
+            b"g".to_vec()
+        ]
+    );
+    assert_eq!(partial, vec![b"ping".to_vec(), b"pong".to_vec()]);
+    assert_eq!(framed, vec![b"ping".to_vec(), b"pong".to_vec()]);
+}
+
+#[tokio::test]
+async fn substreams_keep_the_framing_they_were_opened_with() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, _, alice_peer_id) = new_swarm(|_, _| new_behaviour(true), Handle::current());
+    let (mut bob, _, bob_peer_id) = new_swarm(|_, _| new_behaviour(true), Handle::current());
+    connect(&mut alice, &mut bob).await;
+
+    alice
+        .behaviour_mut()
+        .do_protocol_dialer(bob_peer_id, |mut substream| async move {
+            substream.write_message(b"ping").await?;
+            substream.write_message(b"pong").await?;
+            Ok(())
+        });
+    bob.behaviour_mut()
+        .do_protocol_listener(alice_peer_id, |mut substream| async move {
+            let ping = substream.read_message(1024).await?;
+            tokio::time::sleep(Duration::from_millis(500)).await;
+            let pong = substream.read_message(1024).await?;
+            Ok(vec![ping, pong])
+        });
+
+    let (_, bob_events) = collect_events(&mut alice, &mut bob, Duration::from_millis(250)).await;
+    assert!(bob_events.is_empty());
+    bob.behaviour_mut().set_framing(LengthPrefixed);
+    let (_, bob_events) = collect_events(&mut alice, &mut bob, Duration::from_secs(1)).await;
+
+    match bob_events.as_slice() {
+        [BehaviourOutEvent::Inbound(_, Ok(messages))] => {
+            assert_eq!(messages, &vec![b"ping".to_vec(), b"pong".to_vec()])
+        }
+        events => panic!("unexpected events {:?}", events),
+    }
+}
@@ -0,0 +1,87 @@
+use harness::{collect_events, connect, new_swarm};
+use libp2p::futures::future;
+use libp2p_async_await::{Behaviour, BehaviourOutEvent, CloseReason, Failure};
+use std::time::Duration;
+use tokio::runtime::Handle;
+
+mod harness;
+
+type TestBehaviour = Behaviour<(), (), anyhow::Error>;
+
+#[tokio::test]
+async fn protocol_can_request_to_disconnect_peer() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, _, alice_peer_id) =
+        new_swarm(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current());
+    let (mut bob, _, bob_peer_id) =
+        new_swarm(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current());
+    connect(&mut alice, &mut bob).await;
+
+    alice
+        .behaviour_mut()
+        .do_protocol_dialer(bob_peer_id, |mut substream| async move {
+            substream.write_message(b"malicious").await?;
+            Ok(())
+        });
+    bob.behaviour_mut()
+        .do_protocol_listener(alice_peer_id, |mut substream| async move {
+            substream.read_message(1024).await?;
+            substream.request_disconnect();
+
+            anyhow::bail!("peer is malicious")
+        });
+
+    let (alice_events, bob_events) =
+        collect_events(&mut alice, &mut bob, Duration::from_secs(1)).await;
+
+    assert!(matches!(
+        alice_events.as_slice(),
+        [BehaviourOutEvent::Outbound(_, Ok(()), _)]
+    ));
+    assert!(matches!(
+        bob_events.as_slice(),
+        [
+            BehaviourOutEvent::Inbound(_, Err(_)),
+            BehaviourOutEvent::DisconnectPeer(peer)
+        ] if *peer == alice_peer_id
+    ));
+    assert!(!bob.behaviour().is_connected(&alice_peer_id));
+    assert!(!alice.behaviour().is_connected(&bob_peer_id));
+}
+
+#[tokio::test]
+async fn behaviour_can_disconnect_peer() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, _, alice_peer_id) =
+        new_swarm(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current());
+    let (mut bob, _, bob_peer_id) =
+        new_swarm(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current());
+    connect(&mut alice, &mut bob).await;
+
+    alice
+        .behaviour_mut()
+        .do_protocol_dialer(bob_peer_id, |mut substream| async move {
+            substream.read_message(1024).await?;
+            Ok(())
+        });
+    bob.behaviour_mut()
+        .do_protocol_listener(alice_peer_id, |_| future::pending());
+    collect_events(&mut alice, &mut bob, Duration::from_millis(200)).await;
+
+    alice.behaviour_mut().disconnect(bob_peer_id);
+    alice.behaviour_mut().set_emit_connection_events(true);
+
+    let (alice_events, _) = collect_events(&mut alice, &mut bob, Duration::from_secs(1)).await;
+
+    assert!(matches!(
+        alice_events.as_slice(),
+        [
+            BehaviourOutEvent::OutboundFailed(_, Failure::Cancelled(_), _),
+            BehaviourOutEvent::PeerDisconnected(_, 0, CloseReason::Graceful)
+        ]
+    ));
+    assert!(!alice.behaviour().is_connected(&bob_peer_id));
+    assert!(!bob.behaviour().is_connected(&alice_peer_id));
+}
@@ -0,0 +1,57 @@
+use libp2p::core::transport::{ListenerEvent, MemoryTransport, Transport};
+use libp2p::futures::StreamExt;
+use libp2p_async_await::driver::Driver;
+use libp2p_async_await::{InboundSubstream, OutboundSubstream, ProtocolOutEvent};
+
+#[tokio::test]
+async fn driver_executes_protocols_on_provided_substreams() {
+    let _ = env_logger::try_init();
+
+    let addr = format!("/memory/{}", rand::random::<u64>())
+        .parse()
+        .unwrap();
+    let mut listener = MemoryTransport.listen_on(addr).unwrap();
+    let addr = match listener.next().await {
+        Some(Ok(ListenerEvent::NewAddress(addr))) => addr,
+        _ => panic!("expected listen address"),
+    };
+    let (dialer, upgrade) = tokio::join!(MemoryTransport.dial(addr).unwrap(), async {
+        match listener.next().await {
+            Some(Ok(ListenerEvent::Upgrade { upgrade, .. })) => upgrade.await,
+            _ => panic!("expected incoming connection"),
+        }
+    });
+
+    let mut driver = Driver::<String, String, anyhow::Error>::new();
+    driver.set_preface(Some(b"/embedded"));
+    driver.execute_inbound(
+        InboundSubstream::new(upgrade.unwrap(), b"/foo/1.0.0"),
+        |mut substream| async move {
+            let ping = substream.read_message(1024).await?;
+            substream.write_message(b"pong").await?;
+            Ok(String::from_utf8(ping)?)
+        },
+    );
+    driver.execute_outbound(
+        OutboundSubstream::new(dialer.unwrap(), b"/foo/1.0.0"),
+        |mut substream| async move {
+            substream.write_message(b"ping").await?;
+            let pong = substream.read_message(1024).await?;
+            Ok(String::from_utf8(pong)?)
+        },
+    );
+
+    let mut inbound = None;
+    let mut outbound = None;
+    for _ in 0..2 {
+        match driver.next().await {
+            Some(ProtocolOutEvent::Inbound(Ok(ping))) => inbound = Some(ping),
+            Some(ProtocolOutEvent::Outbound(Ok(pong))) => outbound = Some(pong),
+            _ => panic!("unexpected event"),
+        }
+    }
+
+    assert_eq!(inbound.as_deref(), Some("ping"));
+    assert_eq!(outbound.as_deref(), Some("pong"));
+    assert!(driver.is_empty());
+}
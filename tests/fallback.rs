@@ -0,0 +1,54 @@
+use harness::{collect_events, connect, new_swarm};
+use libp2p_async_await::{Behaviour, BehaviourOutEvent};
+use std::time::Duration;
+use tokio::runtime::Handle;
+
+mod harness;
+
+type TestBehaviour = Behaviour<(), Vec<u8>, anyhow::Error>;
+
+#[tokio::test]
+async fn fallback_produces_the_result_if_negotiation_fails() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, _, _) = new_swarm(|_, _| TestBehaviour::new(b"/foo/2.0.0"), Handle::current());
+    let (mut bob, _, bob_peer_id) =
+        new_swarm(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current());
+    connect(&mut alice, &mut bob).await;
+
+    alice.behaviour_mut().do_protocol_dialer_with_fallback(
+        bob_peer_id,
+        |_| async { Ok(b"current".to_vec()) },
+        || async { Ok(b"legacy".to_vec()) },
+    );
+
+    let (alice_events, _) = collect_events(&mut alice, &mut bob, Duration::from_secs(1)).await;
+
+    assert!(matches!(
+        alice_events.as_slice(),
+        [BehaviourOutEvent::Outbound(_, Ok(result), None)] if result == b"legacy"
+    ));
+}
+
+#[tokio::test]
+async fn fallback_does_not_run_if_negotiation_succeeds() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, _, _) = new_swarm(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current());
+    let (mut bob, _, bob_peer_id) =
+        new_swarm(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current());
+    connect(&mut alice, &mut bob).await;
+
+    alice.behaviour_mut().do_protocol_dialer_with_fallback(
+        bob_peer_id,
+        |_| async { Ok(b"current".to_vec()) },
+        || async { Ok(b"legacy".to_vec()) },
+    );
+
+    let (alice_events, _) = collect_events(&mut alice, &mut bob, Duration::from_secs(1)).await;
+
+    assert!(matches!(
+        alice_events.as_slice(),
+        [BehaviourOutEvent::Outbound(_, Ok(result), None)] if result == b"current"
+    ));
+}
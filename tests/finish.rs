@@ -0,0 +1,62 @@
+use harness::{collect_events, new_connected_swarm_pair};
+use libp2p_async_await::{Behaviour, BehaviourOutEvent};
+use std::time::Duration;
+use tokio::runtime::Handle;
+
+mod harness;
+
+type TestBehaviour = Behaviour<usize, usize, anyhow::Error>;
+
+#[tokio::test]
+async fn finished_protocols_complete_after_reading_eof() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, mut bob) =
+        new_connected_swarm_pair(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current()).await;
+
+    alice.swarm.behaviour_mut().do_protocol_dialer_tagged(
+        bob.peer_id,
+        "subscription",
+        |mut substream| async move {
+            let mut received = 0;
+            while !substream.read_message(1024).await?.is_empty() {
+                received += 1;
+            }
+            Ok(received)
+        },
+    );
+    bob.swarm
+        .behaviour_mut()
+        .do_protocol_listener(alice.peer_id, |mut substream| async move {
+            substream.write_message(b"update").await?;
+            substream.write_message(b"update").await?;
+
+            let mut received = 0;
+            while !substream.read_message(1024).await?.is_empty() {
+                received += 1;
+            }
+            Ok(received)
+        });
+
+    let (alice_events, bob_events) =
+        collect_events(&mut alice.swarm, &mut bob.swarm, Duration::from_millis(500)).await;
+    assert!(alice_events.is_empty());
+    assert!(bob_events.is_empty());
+
+    alice
+        .swarm
+        .behaviour_mut()
+        .finish_where(|tag| tag.downcast_ref::<&str>() == Some(&"subscription"));
+
+    let (alice_events, bob_events) =
+        collect_events(&mut alice.swarm, &mut bob.swarm, Duration::from_secs(1)).await;
+
+    assert!(matches!(
+        alice_events.as_slice(),
+        [BehaviourOutEvent::Outbound(_, Ok(2), Some(_))]
+    ));
+    assert!(matches!(
+        bob_events.as_slice(),
+        [BehaviourOutEvent::Inbound(_, Ok(0))]
+    ));
+}
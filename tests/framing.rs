@@ -0,0 +1,50 @@
+use harness::{collect_events, connect, new_swarm};
+use libp2p_async_await::{Behaviour, BehaviourOutEvent};
+use std::time::Duration;
+use tokio::runtime::Handle;
+
+mod harness;
+
+type TestBehaviour = Behaviour<Vec<u8>, Vec<u8>, anyhow::Error>;
+
+fn new_behaviour() -> TestBehaviour {
+    let mut behaviour = TestBehaviour::new(b"/foo/1.0.0");
+    behaviour.set_negotiate_framing(true);
+
+    behaviour
+}
+
+#[tokio::test]
+async fn negotiated_framing_hands_substream_to_protocol() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, _, alice_peer_id) = new_swarm(|_, _| new_behaviour(), Handle::current());
+    let (mut bob, _, bob_peer_id) = new_swarm(|_, _| new_behaviour(), Handle::current());
+    connect(&mut alice, &mut bob).await;
+
+    alice
+        .behaviour_mut()
+        .do_protocol_dialer(bob_peer_id, |mut substream| async move {
+            substream.write_message(b"ping").await?;
+            let pong = substream.read_message(1024).await?;
+            Ok(pong)
+        });
+    bob.behaviour_mut()
+        .do_protocol_listener(alice_peer_id, |mut substream| async move {
+            let ping = substream.read_message(1024).await?;
+            substream.write_message(b"pong").await?;
+            Ok(ping)
+        });
+
+    let (alice_events, bob_events) =
+        collect_events(&mut alice, &mut bob, Duration::from_secs(1)).await;
+
+    assert!(matches!(
+        alice_events.as_slice(),
+        [BehaviourOutEvent::Outbound(_, Ok(pong), _)] if pong == b"pong"
+    ));
+    assert!(matches!(
+        bob_events.as_slice(),
+        [BehaviourOutEvent::Inbound(_, Ok(ping))] if ping == b"ping"
+    ));
+}
@@ -0,0 +1,72 @@
+use harness::{collect_events, new_connected_swarm_pair};
+use libp2p_async_await::{Behaviour, BehaviourOutEvent, InboundSubstream, OutboundSubstream};
+use std::time::Duration;
+use tokio::runtime::Handle;
+
+mod harness;
+
+type TestBehaviour = Behaviour<Option<InboundSubstream>, Option<OutboundSubstream>, anyhow::Error>;
+
+#[tokio::test]
+async fn protocols_execute_on_handed_over_substreams() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, mut bob) =
+        new_connected_swarm_pair(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current()).await;
+
+    // Negotiate a substream pair through one protocol and hand it out.
+    alice
+        .swarm
+        .behaviour_mut()
+        .do_protocol_dialer(bob.peer_id, |substream| async move { Ok(Some(substream)) });
+    bob.swarm
+        .behaviour_mut()
+        .do_protocol_listener(
+            alice.peer_id,
+            |substream| async move { Ok(Some(substream)) },
+        );
+
+    let (alice_events, bob_events) =
+        collect_events(&mut alice.swarm, &mut bob.swarm, Duration::from_secs(1)).await;
+    let outbound = match alice_events.into_iter().next() {
+        Some(BehaviourOutEvent::Outbound(_, Ok(Some(substream)), _)) => substream,
+        _ => panic!("expected an outbound substream"),
+    };
+    let inbound = match bob_events.into_iter().next() {
+        Some(BehaviourOutEvent::Inbound(_, Ok(Some(substream)))) => substream,
+        _ => panic!("expected an inbound substream"),
+    };
+
+    alice.swarm.behaviour_mut().do_protocol_dialer_on(
+        bob.peer_id,
+        outbound,
+        |mut substream| async move {
+            substream.write_message(b"ping").await?;
+            let pong = substream.read_message(1024).await?;
+            anyhow::ensure!(pong == b"pong", "unexpected message");
+
+            Ok(None)
+        },
+    );
+    bob.swarm.behaviour_mut().do_protocol_listener_on(
+        alice.peer_id,
+        inbound,
+        |mut substream| async move {
+            substream.respond(1024, |_| b"pong".to_vec()).await?;
+
+            Ok(None)
+        },
+    );
+
+    let (alice_events, bob_events) =
+        collect_events(&mut alice.swarm, &mut bob.swarm, Duration::from_secs(1)).await;
+
+    assert!(matches!(
+        alice_events.as_slice(),
+        [BehaviourOutEvent::Outbound(_, Ok(None), _)]
+    ));
+    assert!(matches!(
+        bob_events.as_slice(),
+        [BehaviourOutEvent::Inbound(_, Ok(None))]
+    ));
+}
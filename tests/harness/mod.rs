@@ -1,3 +1,5 @@
+#![allow(dead_code)]
+
 use libp2p::futures::future;
 use libp2p::futures::future::FutureExt;
 use libp2p::{
@@ -5,7 +7,7 @@ use libp2p::{
     identity,
     noise::{self, NoiseConfig, X25519Spec},
     swarm::{NetworkBehaviour, SwarmBuilder, SwarmEvent},
-    yamux::YamuxConfig,
+    yamux::{WindowUpdateMode, YamuxConfig},
     Multiaddr, PeerId, Swarm, Transport,
 };
 use std::{fmt::Debug, future::Future, time::Duration};
@@ -56,10 +58,15 @@ pub fn new_swarm<B: NetworkBehaviour, F: Fn(PeerId, identity::Keypair) -> B>(
         .expect("failed to create dh_keys");
     let noise = NoiseConfig::xx(dh_keys).into_authenticated();
 
-    let transport = MemoryTransport::default()
+    // Only granting the remote more window once data was read makes slow readers exert
+    // backpressure on writers, like they would on a real network.
+    let mut yamux = YamuxConfig::default();
+    yamux.set_window_update_mode(WindowUpdateMode::on_read());
+
+    let transport = MemoryTransport
         .upgrade(Version::V1)
         .authenticate(noise)
-        .multiplex(YamuxConfig::default())
+        .multiplex(yamux)
         .map(|(peer, muxer), _| (peer, StreamMuxerBox::new(muxer)))
         .boxed();
 
@@ -79,6 +86,56 @@ pub fn new_swarm<B: NetworkBehaviour, F: Fn(PeerId, identity::Keypair) -> B>(
     (swarm, addr, peer_id)
 }
 
+/// Drives both swarms for the given duration and collects all behaviour events they emit.
+pub async fn collect_events<B: NetworkBehaviour>(
+    alice: &mut Swarm<B>,
+    bob: &mut Swarm<B>,
+    duration: Duration,
+) -> (Vec<B::OutEvent>, Vec<B::OutEvent>) {
+    let mut alice_events = Vec::new();
+    let mut bob_events = Vec::new();
+
+    let deadline = time::sleep(duration).fuse();
+    libp2p::futures::pin_mut!(deadline);
+
+    loop {
+        libp2p::futures::select! {
+            event = alice.next_event().fuse() => {
+                if let SwarmEvent::Behaviour(event) = event {
+                    alice_events.push(event);
+                }
+            }
+            event = bob.next_event().fuse() => {
+                if let SwarmEvent::Behaviour(event) = event {
+                    bob_events.push(event);
+                }
+            }
+            _ = deadline => break,
+        }
+    }
+
+    (alice_events, bob_events)
+}
+
+/// Drives a single swarm for the given duration and collects all behaviour events it emits.
+pub async fn collect_events_single<B: NetworkBehaviour>(
+    swarm: &mut Swarm<B>,
+    duration: Duration,
+) -> Vec<B::OutEvent> {
+    let mut events = Vec::new();
+
+    let _ = time::timeout(duration, async {
+        loop {
+            if let SwarmEvent::Behaviour(event) = swarm.next_event().await {
+                events.push(event);
+            }
+        }
+    })
+    .await;
+
+    events
+}
+
 pub async fn await_events_or_timeout<A, B>(
     alice_event: impl Future<Output = A>,
     bob_event: impl Future<Output = B>,
@@ -0,0 +1,55 @@
+use harness::{collect_events, new_connected_swarm_pair};
+use libp2p_async_await::{Behaviour, BehaviourOutEvent};
+use std::time::Duration;
+use tokio::runtime::Handle;
+
+mod harness;
+
+type TestBehaviour = Behaviour<(), (), anyhow::Error>;
+
+#[tokio::test]
+async fn handlers_report_becoming_idle_after_the_protocol_terminated() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, mut bob) = new_connected_swarm_pair(
+        |_, _| {
+            let mut behaviour = TestBehaviour::new(b"/idle/1.0.0");
+            behaviour.set_emit_idle_events(true);
+            behaviour
+        },
+        Handle::current(),
+    )
+    .await;
+
+    alice
+        .swarm
+        .behaviour_mut()
+        .do_protocol_dialer(bob.peer_id, |mut substream| async move {
+            substream.write_message(b"hello").await?;
+            Ok(())
+        });
+    bob.swarm
+        .behaviour_mut()
+        .do_protocol_listener(alice.peer_id, |mut substream| async move {
+            substream.read_message(1024).await?;
+            Ok(())
+        });
+
+    let (alice_events, bob_events) =
+        collect_events(&mut alice.swarm, &mut bob.swarm, Duration::from_secs(1)).await;
+
+    assert!(matches!(
+        alice_events.as_slice(),
+        [
+            BehaviourOutEvent::Outbound(_, Ok(()), _),
+            BehaviourOutEvent::HandlerIdle(..)
+        ]
+    ));
+    assert!(matches!(
+        bob_events.as_slice(),
+        [
+            BehaviourOutEvent::Inbound(_, Ok(())),
+            BehaviourOutEvent::HandlerIdle(..)
+        ]
+    ));
+}
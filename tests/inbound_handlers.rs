@@ -0,0 +1,106 @@
+use harness::{collect_events, connect, new_swarm};
+use libp2p_async_await::{Behaviour, BehaviourOutEvent};
+use std::time::Duration;
+use tokio::runtime::Handle;
+
+mod harness;
+
+type TestBehaviour = Behaviour<&'static [u8], Vec<u8>, anyhow::Error>;
+
+fn new_behaviour() -> TestBehaviour {
+    TestBehaviour::with_protocols(vec![&b"/foo/1.0.0"[..], &b"/bar/1.0.0"[..]])
+}
+
+#[tokio::test]
+async fn inbound_substreams_are_served_by_the_handler_of_their_protocol() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, _, _) = new_swarm(|_, _| new_behaviour(), Handle::current());
+    let (mut bob, _, bob_peer_id) = new_swarm(
+        |_, _| {
+            let mut behaviour = new_behaviour();
+            behaviour.set_inbound_handler_for(b"/foo/1.0.0", |mut substream| async move {
+                substream.read_message(1024).await?;
+                substream.write_message(b"foo").await?;
+                Ok(&b"foo"[..])
+            });
+            behaviour.set_inbound_handler_for(b"/bar/1.0.0", |mut substream| async move {
+                substream.read_message(1024).await?;
+                substream.write_message(b"bar").await?;
+                Ok(&b"bar"[..])
+            });
+
+            behaviour
+        },
+        Handle::current(),
+    );
+    connect(&mut alice, &mut bob).await;
+
+    for info in [&b"/foo/1.0.0"[..], &b"/bar/1.0.0"[..]] {
+        alice.behaviour_mut().do_protocol_dialer_for(
+            bob_peer_id,
+            info,
+            |mut substream| async move {
+                substream.write_message(b"hello").await?;
+                Ok(substream.read_message(1024).await?)
+            },
+        );
+    }
+
+    let (alice_events, bob_events) =
+        collect_events(&mut alice, &mut bob, Duration::from_secs(1)).await;
+
+    let responses = alice_events
+        .iter()
+        .map(|event| match event {
+            BehaviourOutEvent::Outbound(_, Ok(response), _) => response.as_slice(),
+            event => panic!("unexpected event {:?}", event),
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(responses, vec![&b"foo"[..], &b"bar"[..]]);
+    assert!(matches!(
+        bob_events.as_slice(),
+        [
+            BehaviourOutEvent::Inbound(_, Ok(b"foo")),
+            BehaviourOutEvent::Inbound(_, Ok(b"bar"))
+        ]
+    ));
+}
+
+#[tokio::test]
+async fn inbound_substreams_without_a_handler_are_rejected() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, _, _) = new_swarm(|_, _| new_behaviour(), Handle::current());
+    let (mut bob, _, bob_peer_id) = new_swarm(
+        |_, _| {
+            let mut behaviour = new_behaviour();
+            behaviour.set_inbound_handler_for(b"/foo/1.0.0", |_| async { Ok(&b"foo"[..]) });
+
+            behaviour
+        },
+        Handle::current(),
+    );
+    connect(&mut alice, &mut bob).await;
+
+    alice.behaviour_mut().do_protocol_dialer_for(
+        bob_peer_id,
+        b"/bar/1.0.0",
+        |mut substream| async move {
+            substream.write_message(b"hello").await?;
+            Ok(substream.read_message(1024).await?)
+        },
+    );
+
+    let (alice_events, bob_events) =
+        collect_events(&mut alice, &mut bob, Duration::from_secs(1)).await;
+
+    assert!(matches!(
+        alice_events.as_slice(),
+        [BehaviourOutEvent::Outbound(_, Err(_), _)]
+    ));
+    assert!(matches!(
+        bob_events.as_slice(),
+        [BehaviourOutEvent::Rejected(_, b"/bar/1.0.0")]
+    ));
+}
@@ -0,0 +1,131 @@
+use harness::{collect_events, collect_events_single, connect, new_swarm};
+use libp2p::futures;
+use libp2p::Swarm;
+use libp2p_async_await::{Behaviour, BehaviourOutEvent};
+use std::time::Duration;
+use tokio::runtime::Handle;
+
+mod harness;
+
+type TestBehaviour = Behaviour<&'static [u8], Vec<u8>, anyhow::Error>;
+type Event = BehaviourOutEvent<&'static [u8], Vec<u8>, anyhow::Error>;
+
+fn pooled_behaviour(slots: usize) -> TestBehaviour {
+    let mut behaviour = TestBehaviour::new(b"/foo/1.0.0");
+    behaviour.set_inbound_pool(slots, |mut substream| async move {
+        substream.read_message(1024).await?;
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        substream.write_message(b"pool").await?;
+        Ok(&b"pool"[..])
+    });
+
+    behaviour
+}
+
+fn dial(behaviour: &mut TestBehaviour, peer: libp2p::PeerId) {
+    behaviour.do_protocol_dialer(peer, |mut substream| async move {
+        substream.write_message(b"hello").await?;
+        Ok(substream.read_message(1024).await?)
+    });
+}
+
+#[tokio::test]
+async fn inbound_substreams_are_served_by_the_pool_without_listener_protocols() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, _, _) = new_swarm(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current());
+    let (mut bob, _, bob_peer_id) = new_swarm(|_, _| pooled_behaviour(1), Handle::current());
+    connect(&mut alice, &mut bob).await;
+
+    dial(alice.behaviour_mut(), bob_peer_id);
+    dial(alice.behaviour_mut(), bob_peer_id);
+
+    let (alice_events, bob_events) =
+        collect_events(&mut alice, &mut bob, Duration::from_secs(2)).await;
+
+    assert_eq!(alice_events.len(), 2);
+    assert!(alice_events.iter().all(|event| matches!(
+        event,
+        BehaviourOutEvent::Outbound(_, Ok(response), _) if response == b"pool"
+    )));
+    assert!(matches!(
+        bob_events.as_slice(),
+        [
+            BehaviourOutEvent::Inbound(_, Ok(b"pool")),
+            BehaviourOutEvent::Inbound(_, Ok(b"pool"))
+        ]
+    ));
+}
+
+/// Drives all three swarms for the given duration, returning the events of each.
+async fn collect_events_of_three(
+    alice: &mut Swarm<TestBehaviour>,
+    bob: &mut Swarm<TestBehaviour>,
+    carol: &mut Swarm<TestBehaviour>,
+    duration: Duration,
+) -> (Vec<Event>, Vec<Event>, Vec<Event>) {
+    let ((alice_events, bob_events), carol_events) = futures::join!(
+        collect_events(alice, bob, duration),
+        collect_events_single(carol, duration)
+    );
+
+    (alice_events, bob_events, carol_events)
+}
+
+/// Returns the responses of the outbound protocols in the given events.
+fn responses(events: &[Event]) -> Vec<&[u8]> {
+    events
+        .iter()
+        .map(|event| match event {
+            BehaviourOutEvent::Outbound(_, Ok(response), _) => response.as_slice(),
+            event => panic!("unexpected event {:?}", event),
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn inbound_substreams_arriving_while_all_slots_are_taken_wait_for_listener_protocols() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, _, alice_peer_id) =
+        new_swarm(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current());
+    let (mut carol, _, carol_peer_id) =
+        new_swarm(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current());
+    let (mut bob, _, bob_peer_id) = new_swarm(|_, _| pooled_behaviour(1), Handle::current());
+    connect(&mut bob, &mut alice).await;
+    connect(&mut bob, &mut carol).await;
+
+    dial(alice.behaviour_mut(), bob_peer_id);
+    dial(carol.behaviour_mut(), bob_peer_id);
+
+    let (alice_events, bob_events, carol_events) =
+        collect_events_of_three(&mut alice, &mut bob, &mut carol, Duration::from_secs(1)).await;
+
+    let mut served = responses(&alice_events);
+    served.extend(responses(&carol_events));
+    assert_eq!(served, vec![&b"pool"[..]]);
+    assert!(matches!(
+        bob_events.as_slice(),
+        [BehaviourOutEvent::Inbound(_, Ok(b"pool"))]
+    ));
+
+    for peer in [alice_peer_id, carol_peer_id] {
+        bob.behaviour_mut()
+            .do_protocol_listener(peer, |mut substream| async move {
+                substream.read_message(1024).await?;
+                substream.write_message(b"listener").await?;
+                Ok(&b"listener"[..])
+            });
+    }
+
+    let (alice_events, bob_events, carol_events) =
+        collect_events_of_three(&mut alice, &mut bob, &mut carol, Duration::from_secs(1)).await;
+
+    let mut served = responses(&alice_events);
+    served.extend(responses(&carol_events));
+    assert_eq!(served, vec![&b"listener"[..]]);
+    assert!(matches!(
+        bob_events.as_slice(),
+        [BehaviourOutEvent::Inbound(_, Ok(b"listener"))]
+    ));
+}
@@ -0,0 +1,87 @@
+use harness::{collect_events, new_connected_swarm_pair};
+use libp2p::futures::future;
+use libp2p_async_await::{Behaviour, BehaviourOutEvent, FramingError};
+use std::io;
+use std::time::Duration;
+use tokio::runtime::Handle;
+
+mod harness;
+
+type TestBehaviour = Behaviour<Result<Vec<u8>, FramingError>, (), anyhow::Error>;
+
+#[tokio::test]
+async fn reads_time_out_after_the_configured_read_timeout() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, mut bob) = new_connected_swarm_pair(
+        |_, _| TestBehaviour::new(b"/timeout/1.0.0"),
+        Handle::current(),
+    )
+    .await;
+
+    alice
+        .swarm
+        .behaviour_mut()
+        .set_read_timeout(Some(Duration::from_millis(100)));
+    bob.swarm
+        .behaviour_mut()
+        .do_protocol_dialer(alice.peer_id, |_| future::pending());
+    alice
+        .swarm
+        .behaviour_mut()
+        .do_protocol_listener(bob.peer_id, |mut substream| async move {
+            Ok(substream.read_message(1024).await)
+        });
+
+    let (alice_events, _) =
+        collect_events(&mut alice.swarm, &mut bob.swarm, Duration::from_millis(500)).await;
+
+    match alice_events.as_slice() {
+        [BehaviourOutEvent::Inbound(_, Ok(Err(FramingError::Io(e))))] => {
+            assert_eq!(e.kind(), io::ErrorKind::TimedOut);
+            assert!(e.to_string().contains(&bob.peer_id.to_string()));
+        }
+        events => panic!("unexpected events {:?}", events),
+    }
+}
+
+#[tokio::test]
+async fn reads_without_timeout_ignore_the_configured_read_timeout() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, mut bob) = new_connected_swarm_pair(
+        |_, _| TestBehaviour::new(b"/timeout/1.0.0"),
+        Handle::current(),
+    )
+    .await;
+
+    alice
+        .swarm
+        .behaviour_mut()
+        .set_read_timeout(Some(Duration::from_millis(100)));
+    bob.swarm
+        .behaviour_mut()
+        .do_protocol_dialer(alice.peer_id, |mut substream| async move {
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            substream.write_message(b"late").await?;
+            future::pending().await
+        });
+    alice
+        .swarm
+        .behaviour_mut()
+        .do_protocol_listener(bob.peer_id, |mut substream| async move {
+            Ok(substream.read_message_no_timeout(1024).await)
+        });
+
+    let (alice_events, _) =
+        collect_events(&mut alice.swarm, &mut bob.swarm, Duration::from_secs(1)).await;
+
+    match alice_events.as_slice() {
+        [BehaviourOutEvent::Inbound(_, Ok(Ok(message)))] => assert_eq!(message, b"late"),
+        events => panic!("unexpected events {:?}", events),
+    }
+    assert_eq!(
+        alice.swarm.behaviour().config().read_timeout,
+        Some(Duration::from_millis(100))
+    );
+}
@@ -0,0 +1,85 @@
+use harness::{collect_events, connect, new_swarm};
+use libp2p::swarm::KeepAlive;
+use libp2p::{PeerId, Swarm};
+use libp2p_async_await::{Behaviour, BehaviourOutEvent, KeepAlivePolicy, KeepAliveState};
+use std::time::Duration;
+use tokio::runtime::Handle;
+
+mod harness;
+
+type TestBehaviour = Behaviour<(), (), anyhow::Error>;
+
+async fn execute_protocol(alice: &mut Swarm<TestBehaviour>, bob: &mut Swarm<TestBehaviour>) {
+    let alice_peer_id = *alice.local_peer_id();
+    let bob_peer_id = *bob.local_peer_id();
+
+    alice
+        .behaviour_mut()
+        .do_protocol_dialer(bob_peer_id, |mut substream| async move {
+            substream.write_message(b"hello").await?;
+            Ok(())
+        });
+    bob.behaviour_mut()
+        .do_protocol_listener(alice_peer_id, |mut substream| async move {
+            substream.read_message(1024).await?;
+            Ok(())
+        });
+
+    let (alice_events, _) = collect_events(alice, bob, Duration::from_secs(1)).await;
+
+    assert!(matches!(
+        alice_events.as_slice(),
+        [BehaviourOutEvent::Outbound(_, Ok(()), _)]
+    ));
+}
+
+#[tokio::test]
+async fn decider_keeps_connections_alive_the_policy_would_close() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, _, _) = new_swarm(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current());
+    let (mut bob, _, bob_peer_id) =
+        new_swarm(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current());
+    alice
+        .behaviour_mut()
+        .set_keep_alive_policy(KeepAlivePolicy::Never);
+    alice
+        .behaviour_mut()
+        .set_keep_alive_decider(move |peer: &PeerId, state: &KeepAliveState| {
+            if *peer == bob_peer_id {
+                KeepAlive::Yes
+            } else {
+                state.keep_alive
+            }
+        });
+    connect(&mut alice, &mut bob).await;
+
+    execute_protocol(&mut alice, &mut bob).await;
+
+    assert!(alice.behaviour().is_connected(&bob_peer_id));
+}
+
+#[tokio::test]
+async fn decider_closes_connections_once_they_are_idle() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, _, alice_peer_id) =
+        new_swarm(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current());
+    let (mut bob, _, bob_peer_id) =
+        new_swarm(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current());
+    alice
+        .behaviour_mut()
+        .set_keep_alive_decider(|_: &PeerId, state: &KeepAliveState| {
+            if state.executions > 0 {
+                KeepAlive::No
+            } else {
+                state.keep_alive
+            }
+        });
+    connect(&mut alice, &mut bob).await;
+
+    execute_protocol(&mut alice, &mut bob).await;
+
+    assert!(!alice.behaviour().is_connected(&bob_peer_id));
+    assert!(!bob.behaviour().is_connected(&alice_peer_id));
+}
@@ -0,0 +1,46 @@
+use harness::{collect_events, connect, new_swarm};
+use libp2p_async_await::{Behaviour, BehaviourOutEvent, KeepAlivePolicy};
+use std::time::Duration;
+use tokio::runtime::Handle;
+
+mod harness;
+
+type TestBehaviour = Behaviour<(), (), anyhow::Error>;
+
+fn new_behaviour() -> TestBehaviour {
+    let mut behaviour = TestBehaviour::new(b"/foo/1.0.0");
+    behaviour.set_keep_alive_policy(KeepAlivePolicy::Never);
+    behaviour
+}
+
+#[tokio::test]
+async fn executing_protocols_keep_the_connection_alive_until_they_terminated() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, _, alice_peer_id) = new_swarm(|_, _| new_behaviour(), Handle::current());
+    let (mut bob, _, bob_peer_id) = new_swarm(|_, _| new_behaviour(), Handle::current());
+    connect(&mut alice, &mut bob).await;
+
+    alice
+        .behaviour_mut()
+        .do_protocol_dialer(bob_peer_id, |mut substream| async move {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            substream.write_message(b"hello").await?;
+            Ok(())
+        });
+    bob.behaviour_mut()
+        .do_protocol_listener(alice_peer_id, |mut substream| async move {
+            substream.read_message(1024).await?;
+            Ok(())
+        });
+
+    let (alice_events, _) = collect_events(&mut alice, &mut bob, Duration::from_secs(1)).await;
+
+    // Bob's protocol may fail because alice closes the connection as soon as hers terminated.
+    assert!(matches!(
+        alice_events.as_slice(),
+        [BehaviourOutEvent::Outbound(_, Ok(()), _)]
+    ));
+    assert!(!alice.behaviour().is_connected(&bob_peer_id));
+    assert!(!bob.behaviour().is_connected(&alice_peer_id));
+}
@@ -0,0 +1,273 @@
+#![cfg(feature = "keepalive")]
+
+use harness::{collect_events, new_connected_swarm_pair};
+use libp2p::futures::AsyncWriteExt;
+use libp2p_async_await::keepalive::KeepaliveError;
+use libp2p_async_await::{Behaviour, BehaviourOutEvent, ProtocolError};
+use std::time::Duration;
+use tokio::runtime::Handle;
+
+mod harness;
+
+type TestBehaviour = Behaviour<Vec<u8>, Vec<u8>, anyhow::Error>;
+
+const INTERVAL: Duration = Duration::from_millis(50);
+const TIMEOUT: Duration = Duration::from_millis(200);
+
+#[tokio::test]
+async fn pings_keep_idle_protocols_alive() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, mut bob) =
+        new_connected_swarm_pair(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current()).await;
+
+    alice
+        .swarm
+        .behaviour_mut()
+        .do_protocol_dialer(bob.peer_id, |substream| async move {
+            let mut substream = substream.with_keepalive(INTERVAL, TIMEOUT);
+            substream.write_message(b"hello").await?;
+            Ok(substream.read_message(1024).await?)
+        });
+    bob.swarm
+        .behaviour_mut()
+        .do_protocol_listener(alice.peer_id, |substream| async move {
+            // Answer pings for much longer than the timeout before responding.
+            let mut substream = substream.with_keepalive(Duration::from_secs(60), TIMEOUT);
+            let hello = substream.read_message(1024).await?;
+            let _ = tokio::time::timeout(Duration::from_millis(600), substream.read_message(1024))
+                .await;
+            substream.write_message(b"world").await?;
+            Ok(hello)
+        });
+
+    let (alice_events, bob_events) =
+        collect_events(&mut alice.swarm, &mut bob.swarm, Duration::from_secs(1)).await;
+
+    match alice_events.as_slice() {
+        [BehaviourOutEvent::Outbound(_, Ok(world), _)] => assert_eq!(world, b"world"),
+        events => panic!("unexpected events {:?}", events),
+    }
+    match bob_events.as_slice() {
+        [BehaviourOutEvent::Inbound(_, Ok(hello))] => assert_eq!(hello, b"hello"),
+        events => panic!("unexpected events {:?}", events),
+    }
+}
+
+#[tokio::test]
+async fn silent_remotes_fail_with_a_keepalive_timeout() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, mut bob) =
+        new_connected_swarm_pair(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current()).await;
+
+    alice
+        .swarm
+        .behaviour_mut()
+        .do_protocol_dialer(bob.peer_id, |substream| async move {
+            let mut substream = substream.with_keepalive(INTERVAL, TIMEOUT);
+            substream.write_message(b"hello").await?;
+            match substream.read_message(1024).await {
+                Err(KeepaliveError::KeepaliveTimeout) => Ok(Vec::new()),
+                res => Err(anyhow::anyhow!("unexpected result {:?}", res)),
+            }
+        });
+    bob.swarm
+        .behaviour_mut()
+        .do_protocol_listener(alice.peer_id, |substream| async move {
+            // Never reads, so pings go unanswered.
+            tokio::time::sleep(Duration::from_secs(2)).await;
+            drop(substream);
+            Ok(Vec::new())
+        });
+
+    let (alice_events, _) =
+        collect_events(&mut alice.swarm, &mut bob.swarm, Duration::from_secs(1)).await;
+
+    assert!(matches!(
+        alice_events.as_slice(),
+        [BehaviourOutEvent::Outbound(_, Ok(_), _)]
+    ));
+}
+
+#[tokio::test]
+async fn checked_dialers_run_once_the_peer_answered_the_ping() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, mut bob) =
+        new_connected_swarm_pair(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current()).await;
+
+    alice.swarm.behaviour_mut().do_protocol_dialer_checked(
+        bob.peer_id,
+        INTERVAL,
+        TIMEOUT,
+        |mut substream| async move {
+            substream.write_message(b"hello").await?;
+            Ok(substream.read_message(1024).await?)
+        },
+    );
+    bob.swarm
+        .behaviour_mut()
+        .do_protocol_listener(alice.peer_id, |substream| async move {
+            let mut substream = substream.with_keepalive(INTERVAL, TIMEOUT);
+            let hello = substream.read_message(1024).await?;
+            substream.write_message(b"world").await?;
+            Ok(hello)
+        });
+
+    let (alice_events, bob_events) =
+        collect_events(&mut alice.swarm, &mut bob.swarm, Duration::from_secs(1)).await;
+
+    match alice_events.as_slice() {
+        [BehaviourOutEvent::Outbound(_, Ok(world), _)] => assert_eq!(world, b"world"),
+        events => panic!("unexpected events {:?}", events),
+    }
+    match bob_events.as_slice() {
+        [BehaviourOutEvent::Inbound(_, Ok(hello))] => assert_eq!(hello, b"hello"),
+        events => panic!("unexpected events {:?}", events),
+    }
+}
+
+#[tokio::test]
+async fn checked_dialers_fail_without_running_if_the_peer_is_silent() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, mut bob) =
+        new_connected_swarm_pair(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current()).await;
+
+    alice.swarm.behaviour_mut().do_protocol_dialer_checked(
+        bob.peer_id,
+        INTERVAL,
+        TIMEOUT,
+        |_| async { panic!("protocol must not run") },
+    );
+    bob.swarm
+        .behaviour_mut()
+        .do_protocol_listener(alice.peer_id, |substream| async move {
+            // Never reads, so the ping goes unanswered.
+            tokio::time::sleep(Duration::from_secs(2)).await;
+            drop(substream);
+            Ok(Vec::new())
+        });
+
+    let (alice_events, _) =
+        collect_events(&mut alice.swarm, &mut bob.swarm, Duration::from_secs(1)).await;
+
+    match alice_events.as_slice() {
+        [BehaviourOutEvent::Outbound(_, Err(ProtocolError::Application(e)), _)] => {
+            assert!(matches!(
+                e.downcast_ref::<KeepaliveError>(),
+                Some(KeepaliveError::KeepaliveTimeout)
+            ))
+        }
+        events => panic!("unexpected events {:?}", events),
+    }
+}
+
+#[tokio::test]
+async fn unlimited_max_sizes_do_not_overflow() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, mut bob) =
+        new_connected_swarm_pair(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current()).await;
+
+    alice
+        .swarm
+        .behaviour_mut()
+        .do_protocol_dialer(bob.peer_id, |substream| async move {
+            let mut substream = substream.with_keepalive(INTERVAL, TIMEOUT);
+            substream.write_message(b"hello").await?;
+            Ok(Vec::new())
+        });
+    bob.swarm
+        .behaviour_mut()
+        .do_protocol_listener(alice.peer_id, |substream| async move {
+            let mut substream = substream.with_keepalive(INTERVAL, TIMEOUT);
+            Ok(substream.read_message(usize::MAX).await?)
+        });
+
+    let (_, bob_events) =
+        collect_events(&mut alice.swarm, &mut bob.swarm, Duration::from_millis(500)).await;
+
+    match bob_events.as_slice() {
+        [BehaviourOutEvent::Inbound(_, Ok(hello))] => assert_eq!(hello, b"hello"),
+        events => panic!("unexpected events {:?}", events),
+    }
+}
+
+#[tokio::test]
+async fn remotes_answer_pings_while_running_a_future() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, mut bob) =
+        new_connected_swarm_pair(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current()).await;
+
+    alice
+        .swarm
+        .behaviour_mut()
+        .do_protocol_dialer(bob.peer_id, |substream| async move {
+            let mut substream = substream.with_keepalive(INTERVAL, TIMEOUT);
+            substream.write_message(b"hello").await?;
+            Ok(substream.read_message(1024).await?)
+        });
+    bob.swarm
+        .behaviour_mut()
+        .do_protocol_listener(alice.peer_id, |substream| async move {
+            let mut substream = substream.with_keepalive(INTERVAL, TIMEOUT);
+            let hello = substream.read_message(1024).await?;
+            // Computing the response takes much longer than the timeout.
+            substream
+                .run(tokio::time::sleep(Duration::from_millis(600)))
+                .await?;
+            substream.write_message(b"world").await?;
+            Ok(hello)
+        });
+
+    let (alice_events, bob_events) =
+        collect_events(&mut alice.swarm, &mut bob.swarm, Duration::from_secs(1)).await;
+
+    match alice_events.as_slice() {
+        [BehaviourOutEvent::Outbound(_, Ok(world), _)] => assert_eq!(world, b"world"),
+        events => panic!("unexpected events {:?}", events),
+    }
+    match bob_events.as_slice() {
+        [BehaviourOutEvent::Inbound(_, Ok(hello))] => assert_eq!(hello, b"hello"),
+        events => panic!("unexpected events {:?}", events),
+    }
+}
+
+#[tokio::test]
+async fn messages_arriving_slower_than_the_timeout_do_not_fail() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, mut bob) =
+        new_connected_swarm_pair(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current()).await;
+
+    alice
+        .swarm
+        .behaviour_mut()
+        .do_protocol_dialer(bob.peer_id, |substream| async move {
+            let mut substream = substream.with_keepalive(INTERVAL, TIMEOUT);
+            Ok(substream.read_message(1024).await?)
+        });
+    bob.swarm
+        .behaviour_mut()
+        .do_protocol_listener(alice.peer_id, |mut substream| async move {
+            // A message frame of 200 bytes, prefixed with its length including the type byte.
+            substream.write_all(&[0xc9, 0x01, 0]).await?;
+            for _ in 0..10 {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                substream.write_all(&[1; 20]).await?;
+                substream.flush().await?;
+            }
+            Ok(Vec::new())
+        });
+
+    let (alice_events, _) =
+        collect_events(&mut alice.swarm, &mut bob.swarm, Duration::from_secs(1)).await;
+
+    match alice_events.as_slice() {
+        [BehaviourOutEvent::Outbound(_, Ok(message), _)] => assert_eq!(message, &[1; 200]),
+        events => panic!("unexpected events {:?}", events),
+    }
+}
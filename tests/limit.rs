@@ -0,0 +1,92 @@
+use harness::{collect_events, new_connected_swarm_pair};
+use libp2p_async_await::limit::LimitError;
+use libp2p_async_await::{Behaviour, BehaviourOutEvent, FramingError};
+use std::time::Duration;
+use tokio::runtime::Handle;
+
+mod harness;
+
+type TestBehaviour = Behaviour<usize, usize, anyhow::Error>;
+
+#[tokio::test]
+async fn both_sides_read_with_the_smaller_limit() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, mut bob) = new_connected_swarm_pair(
+        |_, _| TestBehaviour::new(b"/limit/1.0.0"),
+        Handle::current(),
+    )
+    .await;
+
+    alice
+        .swarm
+        .behaviour_mut()
+        .do_protocol_dialer(bob.peer_id, |substream| async move {
+            let (mut substream, max_size) = substream.begin(1024).await?;
+            substream.write_message(&[0; 16]).await?;
+            substream.read_message(max_size).await?;
+
+            Ok(max_size)
+        });
+    bob.swarm
+        .behaviour_mut()
+        .do_protocol_listener(alice.peer_id, |substream| async move {
+            let (mut substream, max_size) = substream.begin(16).await?;
+            let message = substream.read_message(max_size).await?;
+            substream.write_message(&message).await?;
+
+            Ok(max_size)
+        });
+
+    let (alice_events, bob_events) =
+        collect_events(&mut alice.swarm, &mut bob.swarm, Duration::from_secs(1)).await;
+
+    assert!(matches!(
+        alice_events.as_slice(),
+        [BehaviourOutEvent::Outbound(_, Ok(16), _)]
+    ));
+    assert!(matches!(
+        bob_events.as_slice(),
+        [BehaviourOutEvent::Inbound(_, Ok(16))]
+    ));
+}
+
+#[tokio::test]
+async fn malformed_limits_are_rejected() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, mut bob) = new_connected_swarm_pair(
+        |_, _| TestBehaviour::new(b"/limit/1.0.0"),
+        Handle::current(),
+    )
+    .await;
+
+    alice
+        .swarm
+        .behaviour_mut()
+        .do_protocol_dialer(bob.peer_id, |substream| async move {
+            match substream.begin(1024).await {
+                Err(LimitError::Read(FramingError::TooShort { length: 3, .. })) => Ok(0),
+                res => Err(anyhow::anyhow!(
+                    "unexpected result {:?}",
+                    res.map(|(_, max_size)| max_size)
+                )),
+            }
+        });
+    bob.swarm
+        .behaviour_mut()
+        .do_protocol_listener(alice.peer_id, |mut substream| async move {
+            substream.write_message(b"big").await?;
+            substream.read_message(1024).await?;
+
+            Ok(0)
+        });
+
+    let (alice_events, _) =
+        collect_events(&mut alice.swarm, &mut bob.swarm, Duration::from_secs(1)).await;
+
+    assert!(matches!(
+        alice_events.as_slice(),
+        [BehaviourOutEvent::Outbound(_, Ok(0), _)]
+    ));
+}
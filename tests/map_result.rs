@@ -0,0 +1,71 @@
+use harness::{collect_events, new_connected_swarm_pair};
+use libp2p_async_await::{Behaviour, BehaviourOutEvent};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::runtime::Handle;
+
+mod harness;
+
+type TestBehaviour = Behaviour<(), String, anyhow::Error>;
+
+#[tokio::test]
+async fn the_output_of_the_protocol_is_mapped_before_it_is_reported() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, mut bob) =
+        new_connected_swarm_pair(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current()).await;
+
+    alice.swarm.behaviour_mut().do_protocol_dialer_map(
+        bob.peer_id,
+        |mut substream| async move { Ok(substream.read_message(1024).await?) },
+        |response| String::from_utf8_lossy(&response).to_uppercase(),
+    );
+    bob.swarm
+        .behaviour_mut()
+        .do_protocol_listener(alice.peer_id, |mut substream| async move {
+            substream.write_message(b"hello").await?;
+            Ok(())
+        });
+
+    let (alice_events, _) =
+        collect_events(&mut alice.swarm, &mut bob.swarm, Duration::from_secs(1)).await;
+
+    assert!(matches!(
+        alice_events.as_slice(),
+        [BehaviourOutEvent::Outbound(_, Ok(response), None)] if response == "HELLO"
+    ));
+}
+
+#[tokio::test]
+async fn failed_protocols_are_not_mapped() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, mut bob) =
+        new_connected_swarm_pair(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current()).await;
+    let mapped = Arc::new(AtomicBool::new(false));
+
+    alice.swarm.behaviour_mut().do_protocol_dialer_map(
+        bob.peer_id,
+        |_| async { Err::<(), _>(anyhow::anyhow!("failed")) },
+        {
+            let mapped = mapped.clone();
+            move |()| {
+                mapped.store(true, Ordering::SeqCst);
+                String::new()
+            }
+        },
+    );
+    bob.swarm
+        .behaviour_mut()
+        .do_protocol_listener(alice.peer_id, |_| async { Ok(()) });
+
+    let (alice_events, _) =
+        collect_events(&mut alice.swarm, &mut bob.swarm, Duration::from_secs(1)).await;
+
+    assert!(matches!(
+        alice_events.as_slice(),
+        [BehaviourOutEvent::Outbound(_, Err(_), None)]
+    ));
+    assert!(!mapped.load(Ordering::SeqCst));
+}
@@ -0,0 +1,76 @@
+use harness::{collect_events, connect, new_swarm};
+use libp2p_async_await::{Behaviour, BehaviourOutEvent};
+use std::time::Duration;
+use tokio::runtime::Handle;
+
+mod harness;
+
+type TestBehaviour = Behaviour<(), (), anyhow::Error>;
+
+/// Executes two protocols from alice to bob, who serves at most `max` per connection, and
+/// returns whether they are still connected afterwards.
+async fn execute_two(max: usize) -> bool {
+    let (mut alice, _, alice_peer_id) =
+        new_swarm(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current());
+    let (mut bob, _, bob_peer_id) = new_swarm(
+        |_, _| {
+            let mut behaviour = TestBehaviour::new(b"/foo/1.0.0");
+            behaviour.set_max_executions_per_connection(Some(max));
+            behaviour
+        },
+        Handle::current(),
+    );
+    connect(&mut alice, &mut bob).await;
+
+    for _ in 0..2 {
+        alice
+            .behaviour_mut()
+            .do_protocol_dialer(bob_peer_id, |mut substream| async move {
+                substream.write_message(b"hello").await?;
+                Ok(())
+            });
+        bob.behaviour_mut()
+            .do_protocol_listener(alice_peer_id, |mut substream| async move {
+                substream.read_message(1024).await?;
+                Ok(())
+            });
+    }
+
+    let (alice_events, bob_events) =
+        collect_events(&mut alice, &mut bob, Duration::from_secs(1)).await;
+
+    assert!(matches!(
+        alice_events.as_slice(),
+        [
+            BehaviourOutEvent::Outbound(_, Ok(()), _),
+            BehaviourOutEvent::Outbound(_, Ok(()), _)
+        ]
+    ));
+    assert!(matches!(
+        bob_events.as_slice(),
+        [
+            BehaviourOutEvent::Inbound(_, Ok(())),
+            BehaviourOutEvent::Inbound(_, Ok(()))
+        ]
+    ));
+    assert_eq!(
+        alice.behaviour().is_connected(&bob_peer_id),
+        bob.behaviour().is_connected(&alice_peer_id)
+    );
+
+    bob.behaviour().is_connected(&alice_peer_id)
+}
+
+#[tokio::test]
+async fn connections_close_after_serving_the_maximum_number_of_protocols() {
+    let _ = env_logger::try_init();
+
+    assert!(!execute_two(2).await);
+}
+
+#[tokio::test]
+async fn connections_below_the_maximum_stay_open() {
+    let _ = env_logger::try_init();
+
+    assert!(execute_two(3).await);
+}
@@ -0,0 +1,77 @@
+use harness::{collect_events, connect, new_swarm};
+use libp2p_async_await::merge::{Merged, MergedEvent};
+use libp2p_async_await::{Behaviour, BehaviourOutEvent};
+use std::time::Duration;
+use tokio::runtime::Handle;
+
+mod harness;
+
+type Ping = Behaviour<(), u32, anyhow::Error>;
+type Name = Behaviour<String, (), anyhow::Error>;
+
+fn merged() -> Merged<Ping, Name> {
+    Ping::new(b"/ping/1.0.0").merge(Name::new(b"/name/1.0.0"))
+}
+
+#[tokio::test]
+async fn merged_behaviours_report_the_events_of_both() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, _, alice_peer_id) = new_swarm(|_, _| merged(), Handle::current());
+    let (mut bob, _, bob_peer_id) = new_swarm(|_, _| merged(), Handle::current());
+    connect(&mut alice, &mut bob).await;
+
+    alice
+        .behaviour_mut()
+        .first_mut()
+        .do_protocol_dialer(bob_peer_id, |mut substream| async move {
+            substream.write_message(b"ping").await?;
+            substream.read_message(4).await?;
+            Ok(42)
+        });
+    bob.behaviour_mut().first_mut().do_protocol_listener(
+        alice_peer_id,
+        |mut substream| async move {
+            let ping = substream.read_message(4).await?;
+            substream.write_message(&ping).await?;
+            Ok(())
+        },
+    );
+    alice.behaviour_mut().second_mut().do_protocol_dialer(
+        bob_peer_id,
+        |mut substream| async move {
+            substream.write_message(b"alice").await?;
+            Ok(())
+        },
+    );
+    bob.behaviour_mut().second_mut().do_protocol_listener(
+        alice_peer_id,
+        |mut substream| async move {
+            let name = substream.read_message(1024).await?;
+            Ok(String::from_utf8(name)?)
+        },
+    );
+
+    let (alice_events, bob_events) =
+        collect_events(&mut alice, &mut bob, Duration::from_secs(1)).await;
+
+    assert_eq!(alice_events.len(), 2);
+    assert!(alice_events.iter().any(|event| matches!(
+        event,
+        MergedEvent::First(BehaviourOutEvent::Outbound(_, Ok(42), _))
+    )));
+    assert!(alice_events.iter().any(|event| matches!(
+        event,
+        MergedEvent::Second(BehaviourOutEvent::Outbound(_, Ok(()), _))
+    )));
+
+    assert_eq!(bob_events.len(), 2);
+    assert!(bob_events.iter().any(|event| matches!(
+        event,
+        MergedEvent::First(BehaviourOutEvent::Inbound(_, Ok(())))
+    )));
+    assert!(bob_events.iter().any(|event| matches!(
+        event,
+        MergedEvent::Second(BehaviourOutEvent::Inbound(_, Ok(name))) if name == "alice"
+    )));
+}
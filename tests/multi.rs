@@ -0,0 +1,73 @@
+use harness::{collect_events, connect, new_swarm};
+use libp2p_async_await::multi::{MultiBehaviour, MultiOutput};
+use libp2p_async_await::BehaviourOutEvent;
+use std::time::Duration;
+use tokio::runtime::Handle;
+
+mod harness;
+
+const PING: &[u8] = b"/ping/1.0.0";
+const NAME: &[u8] = b"/name/1.0.0";
+
+fn new_behaviour() -> MultiBehaviour<anyhow::Error> {
+    let mut behaviour = MultiBehaviour::with_protocols(vec![PING, NAME]);
+    behaviour.handle(PING, |mut substream| async move {
+        let ping = substream.read_message(8).await?;
+        substream.write_message(&ping).await?;
+        Ok(ping.len())
+    });
+    behaviour.handle(NAME, |mut substream| async move {
+        substream.write_message(b"bob").await?;
+        Ok(())
+    });
+
+    behaviour
+}
+
+#[tokio::test]
+async fn protocols_with_different_outputs_share_one_behaviour() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, _, _) = new_swarm(|_, _| new_behaviour(), Handle::current());
+    let (mut bob, _, bob_peer_id) = new_swarm(|_, _| new_behaviour(), Handle::current());
+    connect(&mut alice, &mut bob).await;
+
+    alice
+        .behaviour_mut()
+        .dial(bob_peer_id, PING, |mut substream| async move {
+            substream.write_message(b"ping").await?;
+            Ok(substream.read_message(8).await? == b"ping")
+        });
+    alice
+        .behaviour_mut()
+        .dial(bob_peer_id, NAME, |mut substream| async move {
+            Ok(String::from_utf8(substream.read_message(8).await?)?)
+        });
+
+    let (alice_events, bob_events) =
+        collect_events(&mut alice, &mut bob, Duration::from_secs(1)).await;
+
+    let outputs = |events: Vec<BehaviourOutEvent<MultiOutput, MultiOutput, anyhow::Error>>| {
+        events
+            .into_iter()
+            .map(|event| match event {
+                BehaviourOutEvent::Inbound(_, Ok(output))
+                | BehaviourOutEvent::Outbound(_, Ok(output), _) => output,
+                event => panic!("unexpected event {:?}", event),
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let mut alice_outputs = outputs(alice_events).into_iter();
+    let ping = alice_outputs.next().unwrap();
+    assert_eq!(ping.protocol(), PING);
+    assert!(ping.downcast::<bool>().unwrap());
+    let name = alice_outputs.next().unwrap();
+    assert_eq!(name.protocol(), NAME);
+    assert_eq!(name.downcast::<String>().unwrap(), "bob");
+
+    let mut bob_outputs = outputs(bob_events).into_iter();
+    assert_eq!(bob_outputs.next().unwrap().downcast::<usize>().unwrap(), 4);
+    let name = bob_outputs.next().unwrap();
+    assert!(name.downcast::<usize>().is_err());
+}
@@ -0,0 +1,57 @@
+use harness::{collect_events, connect, new_swarm};
+use libp2p_async_await::{Behaviour, BehaviourOutEvent, Failure};
+use std::time::Duration;
+use tokio::runtime::Handle;
+
+mod harness;
+
+type TestBehaviour = Behaviour<Vec<u8>, (), anyhow::Error>;
+
+#[tokio::test]
+async fn notifications_are_received_by_listener_protocols() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, _, alice_peer_id) =
+        new_swarm(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current());
+    let (mut bob, _, bob_peer_id) =
+        new_swarm(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current());
+    connect(&mut alice, &mut bob).await;
+
+    alice.behaviour_mut().notify(bob_peer_id, b"hello".to_vec());
+    bob.behaviour_mut()
+        .do_protocol_listener(alice_peer_id, |mut substream| async move {
+            let message = substream.read_message(1024).await?;
+            Ok(message)
+        });
+
+    let (alice_events, bob_events) =
+        collect_events(&mut alice, &mut bob, Duration::from_secs(1)).await;
+
+    assert!(alice_events.is_empty());
+    assert!(matches!(
+        bob_events.as_slice(),
+        [BehaviourOutEvent::Inbound(_, Ok(message))] if message == b"hello"
+    ));
+}
+
+#[tokio::test]
+async fn failing_to_send_a_notification_is_reported() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, _, _) = new_swarm(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current());
+    let (mut bob, _, bob_peer_id) =
+        new_swarm(|_, _| TestBehaviour::new(b"/bar/1.0.0"), Handle::current());
+    connect(&mut alice, &mut bob).await;
+
+    alice.behaviour_mut().notify(bob_peer_id, b"hello".to_vec());
+
+    let (alice_events, _) = collect_events(&mut alice, &mut bob, Duration::from_secs(1)).await;
+
+    assert!(matches!(
+        alice_events.as_slice(),
+        [BehaviourOutEvent::NotifyFailed(
+            _,
+            Failure::NegotiationFailed
+        )]
+    ));
+}
@@ -0,0 +1,168 @@
+use harness::{collect_events, connect, new_connected_swarm_pair, new_swarm};
+use libp2p::futures::StreamExt;
+use libp2p::swarm::SwarmEvent;
+use libp2p_async_await::{Behaviour, BehaviourOutEvent};
+use std::time::{Duration, Instant};
+use tokio::runtime::Handle;
+use tokio::time;
+
+mod harness;
+
+type TestBehaviour = Behaviour<(), (), anyhow::Error>;
+
+#[tokio::test]
+async fn outbound_protocols_wait_for_substreams_to_close() {
+    let _ = env_logger::try_init();
+
+    let new_behaviour = |_, _| TestBehaviour::new(b"/foo/1.0.0");
+    let (mut alice, _, alice_peer_id) = new_swarm(new_behaviour, Handle::current());
+    let (mut bob, _, bob_peer_id) = new_swarm(new_behaviour, Handle::current());
+    let (mut carol, _, carol_peer_id) = new_swarm(new_behaviour, Handle::current());
+    connect(&mut alice, &mut bob).await;
+    connect(&mut alice, &mut carol).await;
+
+    for (remote, peer) in [(&mut bob, alice_peer_id), (&mut carol, alice_peer_id)] {
+        remote
+            .behaviour_mut()
+            .do_protocol_listener(peer, |_| async {
+                time::sleep(Duration::from_millis(300)).await;
+                Ok(())
+            });
+    }
+    for remote in [bob, carol] {
+        tokio::spawn(remote.for_each(|_| async {}));
+    }
+
+    alice.behaviour_mut().set_max_open_substreams(Some(1));
+    for peer in [bob_peer_id, carol_peer_id] {
+        alice
+            .behaviour_mut()
+            .do_protocol_dialer(peer, |substream| async move {
+                time::sleep(Duration::from_millis(300)).await;
+                drop(substream);
+                Ok(())
+            });
+    }
+
+    // With a single substream allowed, the second protocol only starts once the first is done.
+    let start = Instant::now();
+    let mut completed = Vec::new();
+    let _ = time::timeout(Duration::from_secs(2), async {
+        while completed.len() < 2 {
+            if let SwarmEvent::Behaviour(BehaviourOutEvent::Outbound(_, Ok(()), _)) =
+                alice.next_event().await
+            {
+                assert!(alice.behaviour().open_substreams() <= 1);
+                completed.push(start.elapsed());
+            }
+        }
+    })
+    .await;
+
+    assert!(matches!(completed.as_slice(), [_, second] if *second >= Duration::from_millis(600)));
+    assert_eq!(alice.behaviour().open_substreams(), 0);
+}
+
+#[tokio::test]
+async fn inbound_substreams_beyond_the_limit_wait_for_the_limit_to_change() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, mut bob) = new_connected_swarm_pair(
+        |_, _| {
+            let mut behaviour = TestBehaviour::new(b"/foo/1.0.0");
+            behaviour.set_max_open_substreams(Some(0));
+            behaviour
+        },
+        Handle::current(),
+    )
+    .await;
+    assert_eq!(
+        alice.swarm.behaviour().config().max_open_substreams,
+        Some(0)
+    );
+    alice.swarm.behaviour_mut().set_max_open_substreams(None);
+
+    alice
+        .swarm
+        .behaviour_mut()
+        .do_protocol_dialer(bob.peer_id, |mut substream| async move {
+            substream.write_message(b"hello").await?;
+            Ok(())
+        });
+    bob.swarm
+        .behaviour_mut()
+        .do_protocol_listener(alice.peer_id, |mut substream| async move {
+            substream.read_message(1024).await?;
+            Ok(())
+        });
+
+    let (_, bob_events) =
+        collect_events(&mut alice.swarm, &mut bob.swarm, Duration::from_millis(500)).await;
+    assert!(bob_events.is_empty());
+    assert_eq!(bob.swarm.behaviour().open_substreams(), 0);
+
+    bob.swarm.behaviour_mut().set_max_open_substreams(None);
+    let (_, bob_events) =
+        collect_events(&mut alice.swarm, &mut bob.swarm, Duration::from_millis(500)).await;
+
+    assert!(matches!(
+        bob_events.as_slice(),
+        [BehaviourOutEvent::Inbound(_, Ok(()))]
+    ));
+    assert_eq!(alice.swarm.behaviour().open_substreams(), 0);
+    assert_eq!(bob.swarm.behaviour().open_substreams(), 0);
+}
+
+#[tokio::test]
+async fn deferred_inbound_substreams_are_served_once_a_substream_closes() {
+    let _ = env_logger::try_init();
+
+    let new_behaviour = |_, _| TestBehaviour::new(b"/foo/1.0.0");
+    let (mut alice, _, alice_peer_id) = new_swarm(new_behaviour, Handle::current());
+    let (mut bob, _, bob_peer_id) = new_swarm(new_behaviour, Handle::current());
+    let (mut carol, _, carol_peer_id) = new_swarm(new_behaviour, Handle::current());
+    connect(&mut alice, &mut bob).await;
+    connect(&mut bob, &mut carol).await;
+
+    carol
+        .behaviour_mut()
+        .do_protocol_listener(bob_peer_id, |_| async { Ok(()) });
+    tokio::spawn(carol.for_each(|_| async {}));
+
+    // Bob's only substream is taken by a protocol with carol for a while.
+    bob.behaviour_mut().set_max_open_substreams(Some(1));
+    bob.behaviour_mut()
+        .do_protocol_dialer(carol_peer_id, |substream| async move {
+            time::sleep(Duration::from_millis(300)).await;
+            drop(substream);
+            Ok(())
+        });
+    bob.behaviour_mut()
+        .do_protocol_listener(alice_peer_id, |mut substream| async move {
+            substream.read_message(1024).await?;
+            Ok(())
+        });
+    let (_, bob_events) = collect_events(&mut alice, &mut bob, Duration::from_millis(100)).await;
+    assert!(bob_events.is_empty());
+
+    alice
+        .behaviour_mut()
+        .do_protocol_dialer(bob_peer_id, |mut substream| async move {
+            substream.write_message(b"hello").await?;
+            Ok(())
+        });
+    let (_, bob_events) = collect_events(&mut alice, &mut bob, Duration::from_millis(100)).await;
+    assert!(bob_events.is_empty());
+    assert_eq!(bob.behaviour().open_substreams(), 1);
+
+    let (_, bob_events) = collect_events(&mut alice, &mut bob, Duration::from_millis(500)).await;
+
+    assert!(matches!(
+        bob_events.as_slice(),
+        [
+            BehaviourOutEvent::Outbound(_, Ok(()), _),
+            BehaviourOutEvent::Inbound(_, Ok(()))
+        ]
+    ));
+    assert_eq!(bob.behaviour().open_substreams(), 0);
+}
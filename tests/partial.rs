@@ -0,0 +1,54 @@
+use harness::{collect_events, new_connected_swarm_pair};
+use libp2p_async_await::{Behaviour, BehaviourOutEvent, Failure, Partial};
+use std::time::Duration;
+use tokio::runtime::Handle;
+
+mod harness;
+
+type TestBehaviour = Behaviour<(), (), anyhow::Error>;
+type Received = Partial<Vec<Vec<u8>>>;
+
+#[tokio::test]
+async fn cancelled_protocols_hand_back_their_partial_progress() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, mut bob) =
+        new_connected_swarm_pair(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current()).await;
+
+    alice.swarm.behaviour_mut().do_protocol_dialer_partial(
+        bob.peer_id,
+        Vec::new(),
+        |mut substream, received| async move {
+            loop {
+                let message = substream.read_message(1024).await?;
+                received.update(|received| received.push(message));
+            }
+        },
+    );
+    bob.swarm
+        .behaviour_mut()
+        .do_protocol_listener(alice.peer_id, |mut substream| async move {
+            substream.write_message(b"first").await?;
+            substream.write_message(b"second").await?;
+            substream.read_message(1024).await?;
+            Ok(())
+        });
+
+    collect_events(&mut alice.swarm, &mut bob.swarm, Duration::from_millis(500)).await;
+    alice
+        .swarm
+        .behaviour_mut()
+        .cancel_where(|tag| tag.downcast_ref::<Received>().is_some());
+    let (alice_events, _) =
+        collect_events(&mut alice.swarm, &mut bob.swarm, Duration::from_millis(500)).await;
+
+    match alice_events.as_slice() {
+        [BehaviourOutEvent::OutboundFailed(_, Failure::Cancelled(_), Some(tag))] => {
+            let received = tag
+                .downcast_ref::<Received>()
+                .expect("tag to be the partial");
+            assert_eq!(received.take(), vec![b"first".to_vec(), b"second".to_vec()]);
+        }
+        events => panic!("unexpected events {:?}", events),
+    }
+}
@@ -0,0 +1,91 @@
+use harness::{connect, new_swarm};
+use libp2p::futures::FutureExt;
+use libp2p::swarm::SwarmEvent;
+use libp2p_async_await::{pipe, Behaviour, BehaviourOutEvent, InboundSubstream, OutboundSubstream};
+use std::time::Duration;
+use tokio::runtime::Handle;
+
+mod harness;
+
+type RelayBehaviour = Behaviour<InboundSubstream, OutboundSubstream, anyhow::Error>;
+
+#[tokio::test]
+async fn pipe_forwards_frames_in_both_directions() {
+    let _ = env_logger::try_init();
+
+    let new_behaviour = |_, _| RelayBehaviour::new(b"/relay/1.0.0");
+    let (mut alice, _, alice_peer_id) = new_swarm(new_behaviour, Handle::current());
+    let (mut bob, _, bob_peer_id) = new_swarm(new_behaviour, Handle::current());
+    let (mut carol, _, carol_peer_id) = new_swarm(new_behaviour, Handle::current());
+    connect(&mut alice, &mut bob).await;
+    connect(&mut bob, &mut carol).await;
+
+    bob.behaviour_mut()
+        .do_protocol_listener(alice_peer_id, |substream| async move { Ok(substream) });
+    bob.behaviour_mut()
+        .do_protocol_dialer(carol_peer_id, |substream| async move { Ok(substream) });
+
+    alice
+        .behaviour_mut()
+        .do_protocol_dialer(bob_peer_id, |mut substream| async move {
+            substream.write_message(b"ping").await?;
+            let pong = substream.read_message(1024).await?;
+            anyhow::ensure!(pong == b"pong", "unexpected message");
+
+            Ok(substream)
+        });
+    carol
+        .behaviour_mut()
+        .do_protocol_listener(bob_peer_id, |mut substream| async move {
+            let ping = substream.read_message(1024).await?;
+            anyhow::ensure!(ping == b"ping", "unexpected message");
+            substream.write_message(b"pong").await?;
+
+            Ok(substream)
+        });
+
+    let mut relay_inbound = None;
+    let mut relay_outbound = None;
+    let mut alice_done = false;
+    let mut carol_done = false;
+
+    tokio::time::timeout(Duration::from_secs(10), async {
+        while !(alice_done && carol_done) {
+            libp2p::futures::select! {
+                event = alice.next_event().fuse() => {
+                    if let SwarmEvent::Behaviour(event) = event {
+                        assert!(matches!(event, BehaviourOutEvent::Outbound(_, Ok(_), _)));
+                        alice_done = true;
+                    }
+                }
+                event = bob.next_event().fuse() => {
+                    match event {
+                        SwarmEvent::Behaviour(BehaviourOutEvent::Inbound(_, Ok(substream))) => {
+                            relay_inbound = Some(substream);
+                        }
+                        SwarmEvent::Behaviour(BehaviourOutEvent::Outbound(_, Ok(substream), _)) => {
+                            relay_outbound = Some(substream);
+                        }
+                        SwarmEvent::Behaviour(other) => panic!("unexpected event {:?}", other),
+                        _ => {}
+                    }
+
+                    if let (Some(_), Some(_)) = (&relay_inbound, &relay_outbound) {
+                        tokio::spawn(pipe(
+                            relay_inbound.take().unwrap(),
+                            relay_outbound.take().unwrap(),
+                        ));
+                    }
+                }
+                event = carol.next_event().fuse() => {
+                    if let SwarmEvent::Behaviour(event) = event {
+                        assert!(matches!(event, BehaviourOutEvent::Inbound(_, Ok(_))));
+                        carol_done = true;
+                    }
+                }
+            }
+        }
+    })
+    .await
+    .expect("protocols to complete within 10 seconds");
+}
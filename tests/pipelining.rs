@@ -0,0 +1,54 @@
+use harness::{await_events_or_timeout, new_connected_swarm_pair};
+use libp2p::swarm::SwarmEvent;
+use libp2p_async_await::{Behaviour, BehaviourOutEvent};
+use tokio::runtime::Handle;
+
+mod harness;
+
+type PingBehaviour = Behaviour<Vec<u8>, Vec<u8>, anyhow::Error>;
+
+fn ping(behaviour: &mut PingBehaviour, peer: libp2p::PeerId, msg: &'static [u8]) {
+    behaviour.do_protocol_dialer_reusable(peer, move |mut substream| async move {
+        substream.write_message(msg).await?;
+        let pong = substream.read_message(1024).await?;
+
+        Ok((pong, Some(substream)))
+    });
+}
+
+fn pong(behaviour: &mut PingBehaviour, peer: libp2p::PeerId) {
+    behaviour.do_protocol_listener_reusable(peer, move |mut substream| async move {
+        let ping = substream.read_message(1024).await?;
+        substream.write_message(&ping).await?;
+
+        Ok((ping, Some(substream)))
+    });
+}
+
+#[tokio::test]
+async fn can_execute_several_protocols_on_a_reused_substream() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, mut bob) =
+        new_connected_swarm_pair(|_, _| PingBehaviour::new(b"/ping/1.0.0"), Handle::current())
+            .await;
+
+    for msg in [&b"first"[..], &b"second"[..]].iter().copied() {
+        ping(alice.swarm.behaviour_mut(), bob.peer_id, msg);
+        pong(bob.swarm.behaviour_mut(), alice.peer_id);
+
+        let (alice_event, bob_event) =
+            await_events_or_timeout(alice.swarm.next_event(), bob.swarm.next_event()).await;
+
+        match (alice_event, bob_event) {
+            (
+                SwarmEvent::Behaviour(BehaviourOutEvent::Outbound(_, Ok(pong), _)),
+                SwarmEvent::Behaviour(BehaviourOutEvent::Inbound(_, Ok(ping))),
+            ) => {
+                assert_eq!(pong, msg);
+                assert_eq!(ping, msg);
+            }
+            other => panic!("unexpected events: {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,82 @@
+use harness::{collect_events, connect, new_swarm};
+use libp2p_async_await::{Behaviour, BehaviourOutEvent, Failure};
+use std::time::Duration;
+use tokio::runtime::Handle;
+
+mod harness;
+
+type TestBehaviour = Behaviour<Vec<u8>, Vec<u8>, anyhow::Error>;
+
+fn new_behaviour(preface: &'static [u8]) -> TestBehaviour {
+    let mut behaviour = TestBehaviour::new(b"/foo/1.0.0");
+    behaviour.set_preface(Some(preface));
+
+    behaviour
+}
+
+async fn ping_pong(
+    alice_preface: &'static [u8],
+    bob_preface: &'static [u8],
+) -> (
+    Vec<BehaviourOutEvent<Vec<u8>, Vec<u8>, anyhow::Error>>,
+    Vec<BehaviourOutEvent<Vec<u8>, Vec<u8>, anyhow::Error>>,
+) {
+    let (mut alice, _, _) = new_swarm(|_, _| new_behaviour(alice_preface), Handle::current());
+    let (mut bob, _, bob_peer_id) = new_swarm(|_, _| new_behaviour(bob_preface), Handle::current());
+    connect(&mut alice, &mut bob).await;
+
+    alice
+        .behaviour_mut()
+        .do_protocol_dialer(bob_peer_id, |mut substream| async move {
+            substream.write_message(b"ping").await?;
+            let pong = substream.read_message(1024).await?;
+            Ok(pong)
+        });
+    bob.behaviour_mut()
+        .do_protocol_listener(*alice.local_peer_id(), |mut substream| async move {
+            let ping = substream.read_message(1024).await?;
+            substream.write_message(b"pong").await?;
+            Ok(ping)
+        });
+
+    collect_events(&mut alice, &mut bob, Duration::from_secs(1)).await
+}
+
+#[tokio::test]
+async fn matching_prefaces_hand_substream_to_protocol() {
+    let _ = env_logger::try_init();
+
+    let (alice_events, bob_events) = ping_pong(b"MAGIC", b"MAGIC").await;
+
+    assert!(matches!(
+        alice_events.as_slice(),
+        [BehaviourOutEvent::Outbound(_, Ok(pong), _)] if pong == b"pong"
+    ));
+    assert!(matches!(
+        bob_events.as_slice(),
+        [BehaviourOutEvent::Inbound(_, Ok(ping))] if ping == b"ping"
+    ));
+}
+
+#[tokio::test]
+async fn mismatching_prefaces_fail_both_sides() {
+    let _ = env_logger::try_init();
+
+    let (alice_events, bob_events) = ping_pong(b"MAGIC", b"OTHER").await;
+
+    assert!(matches!(
+        alice_events.as_slice(),
+        [BehaviourOutEvent::OutboundFailed(
+            _,
+            Failure::PrefaceMismatch,
+            _
+        )]
+    ));
+    assert!(matches!(
+        bob_events.as_slice(),
+        [BehaviourOutEvent::InboundFailed(
+            _,
+            Failure::PrefaceMismatch
+        )]
+    ));
+}
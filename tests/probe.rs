@@ -0,0 +1,37 @@
+#![cfg(feature = "probe")]
+
+use harness::{collect_events, connect, new_swarm};
+use libp2p_async_await::{Behaviour, BehaviourOutEvent};
+use std::time::Duration;
+use tokio::runtime::Handle;
+
+mod harness;
+
+type TestBehaviour = Behaviour<(), (), anyhow::Error>;
+
+#[tokio::test]
+async fn probing_reports_the_protocols_the_remote_supports() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, _, _) = new_swarm(
+        |_, _| {
+            TestBehaviour::with_protocols(vec![&b"/foo/2.0.0"[..], b"/bar/1.0.0", b"/foo/1.0.0"])
+        },
+        Handle::current(),
+    );
+    let (mut bob, _, bob_peer_id) = new_swarm(
+        |_, _| TestBehaviour::with_protocols(vec![&b"/foo/1.0.0"[..], b"/foo/2.0.0"]),
+        Handle::current(),
+    );
+    connect(&mut alice, &mut bob).await;
+
+    alice.behaviour_mut().probe_protocols(bob_peer_id);
+
+    let (alice_events, _) = collect_events(&mut alice, &mut bob, Duration::from_secs(1)).await;
+
+    assert!(matches!(
+        alice_events.as_slice(),
+        [BehaviourOutEvent::ProtocolsProbed(peer, supported)]
+            if *peer == bob_peer_id && supported == &[&b"/foo/2.0.0"[..], b"/foo/1.0.0"]
+    ));
+}
@@ -0,0 +1,53 @@
+use harness::{collect_events, connect, new_swarm};
+use libp2p_async_await::{Behaviour, BehaviourOutEvent};
+use std::time::Duration;
+use tokio::runtime::Handle;
+
+mod harness;
+
+type TestBehaviour = Behaviour<(), (), anyhow::Error>;
+
+#[tokio::test]
+async fn long_running_protocols_report_progress() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, _, alice_peer_id) = new_swarm(
+        |_, _| {
+            let mut behaviour = TestBehaviour::new(b"/foo/1.0.0");
+            behaviour.set_progress_interval(Some(Duration::from_millis(100)));
+
+            behaviour
+        },
+        Handle::current(),
+    );
+    let (mut bob, _, bob_peer_id) =
+        new_swarm(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current());
+    connect(&mut alice, &mut bob).await;
+
+    alice
+        .behaviour_mut()
+        .do_protocol_dialer(bob_peer_id, |mut substream| async move {
+            tokio::time::sleep(Duration::from_millis(350)).await;
+            substream.write_message(b"done").await?;
+            Ok(())
+        });
+    bob.behaviour_mut()
+        .do_protocol_listener(alice_peer_id, |mut substream| async move {
+            substream.read_message(1024).await?;
+            Ok(())
+        });
+
+    let (alice_events, bob_events) =
+        collect_events(&mut alice, &mut bob, Duration::from_secs(1)).await;
+
+    let (last, progress) = alice_events.split_last().unwrap();
+    assert!(matches!(last, BehaviourOutEvent::Outbound(_, Ok(()), _)));
+    assert!(progress.len() >= 2);
+    assert!(progress
+        .iter()
+        .all(|event| matches!(event, BehaviourOutEvent::Progress(..))));
+    assert!(matches!(
+        bob_events.as_slice(),
+        [BehaviourOutEvent::Inbound(_, Ok(()))]
+    ));
+}
@@ -0,0 +1,169 @@
+use harness::{collect_events, new_connected_swarm_pair};
+use libp2p::futures::io::Cursor;
+use libp2p::futures::task::{Context, Poll};
+use libp2p::futures::{AsyncRead, AsyncWrite, StreamExt};
+use libp2p_async_await::driver::Driver;
+use libp2p_async_await::{
+    Behaviour, BehaviourOutEvent, InboundSubstream, ProtocolError, ProtocolOutEvent,
+};
+use std::io;
+use std::pin::Pin;
+use std::time::Duration;
+use tokio::runtime::Handle;
+
+mod harness;
+
+type TestBehaviour = Behaviour<(), (), anyhow::Error>;
+
+#[tokio::test]
+async fn errors_returned_by_the_protocol_are_application_errors() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, mut bob) = new_connected_swarm_pair(
+        |_, _| TestBehaviour::new(b"/errors/1.0.0"),
+        Handle::current(),
+    )
+    .await;
+
+    alice
+        .swarm
+        .behaviour_mut()
+        .do_protocol_dialer(bob.peer_id, |mut substream| async move {
+            substream.write_message(b"hello").await?;
+            Err(anyhow::anyhow!("boom"))
+        });
+    bob.swarm
+        .behaviour_mut()
+        .do_protocol_listener(alice.peer_id, |mut substream| async move {
+            substream.read_message(1024).await?;
+            Ok(())
+        });
+
+    let (alice_events, _) =
+        collect_events(&mut alice.swarm, &mut bob.swarm, Duration::from_secs(1)).await;
+
+    match alice_events.as_slice() {
+        [BehaviourOutEvent::Outbound(_, Err(ProtocolError::Application(e)), _)] => {
+            assert_eq!(e.to_string(), "boom")
+        }
+        events => panic!("unexpected events {:?}", events),
+    }
+}
+
+/// A socket on which every operation fails.
+struct ResetSocket;
+
+impl AsyncRead for ResetSocket {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+        _: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Poll::Ready(Err(io::ErrorKind::ConnectionReset.into()))
+    }
+}
+
+impl AsyncWrite for ResetSocket {
+    fn poll_write(self: Pin<&mut Self>, _: &mut Context<'_>, _: &[u8]) -> Poll<io::Result<usize>> {
+        Poll::Ready(Err(io::ErrorKind::ConnectionReset.into()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Err(io::ErrorKind::ConnectionReset.into()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Err(io::ErrorKind::ConnectionReset.into()))
+    }
+}
+
+#[tokio::test]
+async fn failing_reads_are_transport_errors() {
+    let _ = env_logger::try_init();
+
+    let mut driver = Driver::<(), (), anyhow::Error>::new();
+    driver.execute_inbound(
+        InboundSubstream::new(ResetSocket, b"/errors/1.0.0"),
+        |mut substream| async move {
+            substream
+                .read_message(1024)
+                .await
+                .map_err(|_| anyhow::anyhow!("failed to read"))?;
+            Ok(())
+        },
+    );
+
+    match driver.next().await {
+        Some(ProtocolOutEvent::Inbound(Err(ProtocolError::Transport { error, application }))) => {
+            assert_eq!(error.kind(), io::ErrorKind::ConnectionReset);
+            assert_eq!(application.to_string(), "failed to read");
+        }
+        _ => panic!("expected a transport error"),
+    }
+}
+
+/// A socket whose first read fails and whose later reads return a single message.
+struct FlakySocket {
+    failed: bool,
+    message: Cursor<Vec<u8>>,
+}
+
+impl AsyncRead for FlakySocket {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        if !self.failed {
+            self.failed = true;
+            return Poll::Ready(Err(io::ErrorKind::Interrupted.into()));
+        }
+
+        AsyncRead::poll_read(Pin::new(&mut self.message), cx, buf)
+    }
+}
+
+impl AsyncWrite for FlakySocket {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[tokio::test]
+async fn protocols_that_recovered_from_failing_reads_report_application_errors() {
+    let _ = env_logger::try_init();
+
+    let socket = FlakySocket {
+        failed: false,
+        message: Cursor::new(b"\x05hello".to_vec()),
+    };
+    let mut driver = Driver::<(), (), anyhow::Error>::new();
+    driver.execute_inbound(
+        InboundSubstream::new(socket, b"/errors/1.0.0"),
+        |mut substream| async move {
+            assert!(substream.read_message(1024).await.is_err());
+            substream.read_message(1024).await?;
+
+            Err(anyhow::anyhow!("boom"))
+        },
+    );
+
+    match driver.next().await {
+        Some(ProtocolOutEvent::Inbound(Err(ProtocolError::Application(e)))) => {
+            assert_eq!(e.to_string(), "boom")
+        }
+        _ => panic!("expected an application error"),
+    }
+}
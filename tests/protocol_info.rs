@@ -0,0 +1,223 @@
+use harness::{collect_events, connect, new_swarm};
+use libp2p::Swarm;
+use libp2p_async_await::{Behaviour, BehaviourOutEvent, Failure};
+use std::time::Duration;
+use tokio::runtime::Handle;
+
+mod harness;
+
+type TestBehaviour = Behaviour<&'static [u8], &'static [u8], anyhow::Error>;
+
+#[tokio::test]
+async fn changed_protocol_info_only_applies_to_new_connections() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, _, _) = new_swarm(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current());
+    let (mut bob, _, bob_peer_id) =
+        new_swarm(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current());
+    connect(&mut alice, &mut bob).await;
+
+    alice.behaviour_mut().set_protocol_info(b"/foo/2.0.0");
+    bob.behaviour_mut().set_protocol_info(b"/foo/2.0.0");
+    assert_eq!(
+        alice.behaviour().config().protocols,
+        vec![&b"/foo/2.0.0"[..]]
+    );
+
+    alice
+        .behaviour_mut()
+        .do_protocol_dialer(
+            bob_peer_id,
+            |substream| async move { Ok(substream.protocol()) },
+        );
+    bob.behaviour_mut()
+        .do_protocol_listener(alice.local_peer_id().to_owned(), |substream| async move {
+            Ok(substream.protocol())
+        });
+
+    let (alice_events, bob_events) =
+        collect_events(&mut alice, &mut bob, Duration::from_secs(1)).await;
+
+    assert!(matches!(
+        alice_events.as_slice(),
+        [BehaviourOutEvent::Outbound(_, Ok(b"/foo/1.0.0"), _)]
+    ));
+    assert!(matches!(
+        bob_events.as_slice(),
+        [BehaviourOutEvent::Inbound(_, Ok(b"/foo/1.0.0"))]
+    ));
+
+    let (mut carol, _, carol_peer_id) =
+        new_swarm(|_, _| TestBehaviour::new(b"/foo/2.0.0"), Handle::current());
+    connect(&mut alice, &mut carol).await;
+
+    alice
+        .behaviour_mut()
+        .do_protocol_dialer(carol_peer_id, |substream| async move {
+            Ok(substream.protocol())
+        });
+    carol
+        .behaviour_mut()
+        .do_protocol_listener(alice.local_peer_id().to_owned(), |substream| async move {
+            Ok(substream.protocol())
+        });
+
+    let (alice_events, carol_events) =
+        collect_events(&mut alice, &mut carol, Duration::from_secs(1)).await;
+
+    assert!(matches!(
+        alice_events.as_slice(),
+        [BehaviourOutEvent::Outbound(_, Ok(b"/foo/2.0.0"), _)]
+    ));
+    assert!(matches!(
+        carol_events.as_slice(),
+        [BehaviourOutEvent::Inbound(_, Ok(b"/foo/2.0.0"))]
+    ));
+}
+
+/// Executes a protocol from alice to bob, returning the protocol both sides negotiated.
+async fn negotiated_protocol(
+    alice: &mut Swarm<TestBehaviour>,
+    bob: &mut Swarm<TestBehaviour>,
+) -> (&'static [u8], &'static [u8]) {
+    let alice_peer_id = *alice.local_peer_id();
+    let bob_peer_id = *bob.local_peer_id();
+
+    alice
+        .behaviour_mut()
+        .do_protocol_dialer(
+            bob_peer_id,
+            |substream| async move { Ok(substream.protocol()) },
+        );
+    bob.behaviour_mut()
+        .do_protocol_listener(alice_peer_id, |substream| async move {
+            Ok(substream.protocol())
+        });
+
+    let (alice_events, bob_events) = collect_events(alice, bob, Duration::from_secs(1)).await;
+
+    match (alice_events.as_slice(), bob_events.as_slice()) {
+        (
+            [BehaviourOutEvent::Outbound(_, Ok(dialer), _)],
+            [BehaviourOutEvent::Inbound(_, Ok(listener))],
+        ) => (dialer, listener),
+        events => panic!("unexpected events {:?}", events),
+    }
+}
+
+#[tokio::test]
+async fn upgraded_protocols_apply_to_later_substreams_of_existing_connections() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, _, _) = new_swarm(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current());
+    let (mut bob, _, bob_peer_id) =
+        new_swarm(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current());
+    connect(&mut alice, &mut bob).await;
+
+    assert_eq!(
+        negotiated_protocol(&mut alice, &mut bob).await,
+        (&b"/foo/1.0.0"[..], &b"/foo/1.0.0"[..])
+    );
+
+    // Bob does not support the new version yet.
+    alice
+        .behaviour_mut()
+        .upgrade_protocols(vec![&b"/foo/2.0.0"[..], b"/foo/1.0.0"]);
+    assert_eq!(
+        negotiated_protocol(&mut alice, &mut bob).await,
+        (&b"/foo/1.0.0"[..], &b"/foo/1.0.0"[..])
+    );
+
+    bob.behaviour_mut()
+        .upgrade_protocols(vec![&b"/foo/2.0.0"[..], b"/foo/1.0.0"]);
+    assert_eq!(
+        negotiated_protocol(&mut alice, &mut bob).await,
+        (&b"/foo/2.0.0"[..], &b"/foo/2.0.0"[..])
+    );
+
+    assert_eq!(
+        alice
+            .behaviour()
+            .peer_stats(&bob_peer_id)
+            .map(|stats| stats.connections),
+        Some(1)
+    );
+}
+
+#[tokio::test]
+async fn outbound_substreams_offer_only_the_capped_protocols() {
+    let _ = env_logger::try_init();
+
+    let versions = || vec![&b"/foo/3.0.0"[..], b"/foo/2.0.0", b"/foo/1.0.0"];
+    let (mut alice, _, _) = new_swarm(
+        |_, _| {
+            let mut behaviour = TestBehaviour::with_protocols(versions());
+            behaviour.set_max_negotiation_protocols(Some(2));
+            behaviour
+        },
+        Handle::current(),
+    );
+    let (mut bob, _, bob_peer_id) =
+        new_swarm(|_, _| TestBehaviour::new(b"/foo/2.0.0"), Handle::current());
+    connect(&mut alice, &mut bob).await;
+
+    assert_eq!(
+        alice.behaviour().config().max_negotiation_protocols,
+        Some(2)
+    );
+    assert_eq!(
+        negotiated_protocol(&mut alice, &mut bob).await,
+        (&b"/foo/2.0.0"[..], &b"/foo/2.0.0"[..])
+    );
+
+    // Bob supports an advertised protocol that is not offered anymore.
+    bob.behaviour_mut()
+        .upgrade_protocols(vec![&b"/foo/1.0.0"[..]]);
+    alice
+        .behaviour_mut()
+        .do_protocol_dialer(
+            bob_peer_id,
+            |substream| async move { Ok(substream.protocol()) },
+        );
+
+    let (alice_events, _) = collect_events(&mut alice, &mut bob, Duration::from_secs(1)).await;
+
+    assert!(matches!(
+        alice_events.as_slice(),
+        [BehaviourOutEvent::OutboundFailed(
+            _,
+            Failure::NegotiationFailed,
+            _
+        )]
+    ));
+}
+
+#[test]
+#[should_panic(expected = "protocol \"foo/1.0.0\" has to start with '/'")]
+fn protocols_have_to_start_with_a_slash() {
+    TestBehaviour::with_protocols(vec![&b"/foo/2.0.0"[..], b"foo/1.0.0"]);
+}
+
+#[test]
+#[should_panic(expected = "protocol \"\" has to start with '/'")]
+fn protocols_may_not_be_empty() {
+    TestBehaviour::new(b"");
+}
+
+#[test]
+#[should_panic(expected = "at least one protocol is required")]
+fn at_least_one_protocol_is_required() {
+    TestBehaviour::with_protocols(Vec::new());
+}
+
+#[test]
+#[should_panic(expected = "at least one protocol is required")]
+fn upgrades_need_at_least_one_protocol() {
+    TestBehaviour::new(b"/foo/1.0.0").upgrade_protocols(Vec::new());
+}
+
+#[test]
+#[should_panic(expected = "may not contain a newline")]
+fn protocols_may_not_contain_a_newline() {
+    TestBehaviour::new(b"/foo/1.0.0\n");
+}
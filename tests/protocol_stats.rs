@@ -0,0 +1,52 @@
+use harness::{collect_events, new_connected_swarm_pair};
+use libp2p_async_await::{Behaviour, ProtocolStats};
+use std::time::Duration;
+use tokio::runtime::Handle;
+
+mod harness;
+
+type TestBehaviour = Behaviour<(), (), anyhow::Error>;
+
+const FOO: &[u8] = b"/foo/1.0.0";
+const BAR: &[u8] = b"/bar/1.0.0";
+
+fn counts(stats: Option<&ProtocolStats>) -> Option<(u64, u64)> {
+    stats.map(|stats| (stats.completed, stats.failed))
+}
+
+#[tokio::test]
+async fn executions_are_counted_per_negotiated_protocol() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, mut bob) = new_connected_swarm_pair(
+        |_, _| TestBehaviour::with_protocols(vec![FOO, BAR]),
+        Handle::current(),
+    )
+    .await;
+
+    alice
+        .swarm
+        .behaviour_mut()
+        .do_protocol_dialer_for(bob.peer_id, FOO, |_| async { Ok(()) });
+    alice
+        .swarm
+        .behaviour_mut()
+        .do_protocol_dialer_for(bob.peer_id, BAR, |_| async {
+            Err(anyhow::anyhow!("failed"))
+        });
+    for _ in 0..2 {
+        bob.swarm
+            .behaviour_mut()
+            .do_protocol_listener(alice.peer_id, |_| async { Ok(()) });
+    }
+
+    collect_events(&mut alice.swarm, &mut bob.swarm, Duration::from_secs(1)).await;
+
+    let alice_stats = alice.swarm.behaviour().protocol_stats();
+    assert_eq!(counts(alice_stats.get(FOO)), Some((1, 0)));
+    assert_eq!(counts(alice_stats.get(BAR)), Some((0, 1)));
+
+    let bob_stats = bob.swarm.behaviour().protocol_stats();
+    assert_eq!(counts(bob_stats.get(FOO)), Some((1, 0)));
+    assert_eq!(counts(bob_stats.get(BAR)), Some((1, 0)));
+}
@@ -0,0 +1,134 @@
+use harness::{collect_events, connect, new_swarm};
+use libp2p::futures::AsyncWriteExt;
+use libp2p_async_await::{Behaviour, BehaviourOutEvent};
+use std::time::Duration;
+use tokio::runtime::Handle;
+
+mod harness;
+
+type TestBehaviour = Behaviour<Vec<u8>, Vec<u8>, anyhow::Error>;
+
+#[tokio::test]
+async fn inbound_substreams_negotiated_while_busy_are_served_in_order() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, _, alice_peer_id) =
+        new_swarm(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current());
+    let (mut bob, _, bob_peer_id) =
+        new_swarm(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current());
+    connect(&mut alice, &mut bob).await;
+
+    for _ in 0..3 {
+        bob.behaviour_mut()
+            .do_protocol_listener(alice_peer_id, |mut substream| async move {
+                let message = substream.read_message(1024).await?;
+                tokio::time::sleep(Duration::from_millis(300)).await;
+                Ok(message)
+            });
+    }
+
+    // The second and third substream arrive while the first protocol is still executing.
+    alice.behaviour_mut().notify(bob_peer_id, b"one".to_vec());
+    let (_, bob_events) = collect_events(&mut alice, &mut bob, Duration::from_millis(100)).await;
+    assert!(bob_events.is_empty());
+    alice.behaviour_mut().notify(bob_peer_id, b"two".to_vec());
+    let (_, bob_events) = collect_events(&mut alice, &mut bob, Duration::from_millis(100)).await;
+    assert!(bob_events.is_empty());
+    alice.behaviour_mut().notify(bob_peer_id, b"three".to_vec());
+
+    let (alice_events, bob_events) =
+        collect_events(&mut alice, &mut bob, Duration::from_secs(2)).await;
+
+    assert!(alice_events.is_empty());
+    let messages = bob_events
+        .into_iter()
+        .map(|event| match event {
+            BehaviourOutEvent::Inbound(_, Ok(message)) => message,
+            event => panic!("unexpected event {:?}", event),
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        messages,
+        vec![b"one".to_vec(), b"two".to_vec(), b"three".to_vec()]
+    );
+}
+
+#[tokio::test]
+async fn inbound_substreams_negotiated_during_an_outbound_protocol_are_served_after_it() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, _, alice_peer_id) =
+        new_swarm(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current());
+    let (mut bob, _, bob_peer_id) =
+        new_swarm(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current());
+    connect(&mut alice, &mut bob).await;
+
+    alice
+        .behaviour_mut()
+        .do_protocol_dialer(bob_peer_id, |mut substream| async move {
+            substream.write_message(b"ping").await?;
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            Ok(substream.read_message(1024).await?)
+        });
+    alice
+        .behaviour_mut()
+        .do_protocol_listener(bob_peer_id, |mut substream| async move {
+            Ok(substream.read_message(1024).await?)
+        });
+    bob.behaviour_mut()
+        .do_protocol_listener(alice_peer_id, |mut substream| async move {
+            substream.read_message(1024).await?;
+            substream.write_message(b"pong").await?;
+            Ok(Vec::new())
+        });
+    collect_events(&mut alice, &mut bob, Duration::from_millis(100)).await;
+
+    // Alice's dialer is still executing when bob's substream arrives.
+    bob.behaviour_mut().notify(alice_peer_id, b"hello".to_vec());
+
+    let (alice_events, _) = collect_events(&mut alice, &mut bob, Duration::from_secs(1)).await;
+
+    match alice_events.as_slice() {
+        [BehaviourOutEvent::Outbound(_, Ok(pong), _), BehaviourOutEvent::Inbound(_, Ok(hello))] => {
+            assert_eq!(pong, b"pong");
+            assert_eq!(hello, b"hello");
+        }
+        events => panic!("unexpected events {:?}", events),
+    }
+}
+
+#[tokio::test]
+async fn outbound_protocols_accept_substreams_negotiated_before_they_asked_for_them() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, _, alice_peer_id) =
+        new_swarm(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current());
+    let (mut bob, _, bob_peer_id) =
+        new_swarm(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current());
+    connect(&mut alice, &mut bob).await;
+
+    alice
+        .behaviour_mut()
+        .do_protocol_dialer(bob_peer_id, |mut substream| async move {
+            substream.write_message(b"hello").await?;
+            tokio::time::sleep(Duration::from_millis(300)).await;
+
+            let mut additional = substream.accept_inbound().await?;
+            Ok(additional.read_message(1024).await?)
+        });
+    bob.behaviour_mut()
+        .do_protocol_listener(alice_peer_id, |mut substream| async move {
+            substream.read_message(1024).await?;
+            let mut additional = substream.open_outbound(b"/foo/1.0.0").await?;
+            additional.write_message(b"world").await?;
+            additional.close().await?;
+            Ok(Vec::new())
+        });
+
+    let (alice_events, _) = collect_events(&mut alice, &mut bob, Duration::from_secs(1)).await;
+
+    match alice_events.as_slice() {
+        [BehaviourOutEvent::Outbound(_, Ok(message), _)] => assert_eq!(message, b"world"),
+        events => panic!("unexpected events {:?}", events),
+    }
+}
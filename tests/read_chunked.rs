@@ -0,0 +1,89 @@
+use harness::{collect_events, connect, new_swarm};
+use libp2p_async_await::{Behaviour, BehaviourOutEvent, FramingError};
+use std::time::Duration;
+use tokio::runtime::Handle;
+
+mod harness;
+
+type TestBehaviour = Behaviour<(usize, Vec<usize>, Vec<u8>), (), anyhow::Error>;
+
+#[tokio::test]
+async fn frames_are_handed_to_the_sink_in_bounded_chunks() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, _, alice_peer_id) =
+        new_swarm(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current());
+    let (mut bob, _, bob_peer_id) =
+        new_swarm(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current());
+    connect(&mut alice, &mut bob).await;
+
+    let message = (0..2500).map(|i| i as u8).collect::<Vec<_>>();
+    let sent = message.clone();
+    alice
+        .behaviour_mut()
+        .do_protocol_dialer(bob_peer_id, move |mut substream| async move {
+            substream.write_message(&sent).await?;
+            Ok(())
+        });
+    bob.behaviour_mut()
+        .do_protocol_listener(alice_peer_id, |mut substream| async move {
+            let mut chunk_sizes = Vec::new();
+            let mut received = Vec::new();
+            let length = substream
+                .read_message_chunked(4096, 1024, |chunk| {
+                    chunk_sizes.push(chunk.len());
+                    received.extend_from_slice(chunk);
+                })
+                .await?;
+
+            Ok((length, chunk_sizes, received))
+        });
+
+    let (_, bob_events) = collect_events(&mut alice, &mut bob, Duration::from_secs(1)).await;
+
+    assert!(matches!(
+        bob_events.as_slice(),
+        [BehaviourOutEvent::Inbound(_, Ok((2500, chunk_sizes, received)))]
+            if chunk_sizes == &[1024, 1024, 452] && received == &message
+    ));
+}
+
+#[tokio::test]
+async fn frames_larger_than_max_size_fail_before_any_chunk_is_read() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, _, alice_peer_id) =
+        new_swarm(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current());
+    let (mut bob, _, bob_peer_id) =
+        new_swarm(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current());
+    connect(&mut alice, &mut bob).await;
+
+    alice
+        .behaviour_mut()
+        .do_protocol_dialer(bob_peer_id, |mut substream| async move {
+            substream.write_message(&[0; 2500]).await?;
+            Ok(())
+        });
+    bob.behaviour_mut()
+        .do_protocol_listener(alice_peer_id, |mut substream| async move {
+            let mut chunk_sizes = Vec::new();
+            let res = substream
+                .read_message_chunked(1000, 1024, |chunk| chunk_sizes.push(chunk.len()))
+                .await;
+
+            match res {
+                Err(FramingError::TooLarge {
+                    length: 2500,
+                    max_size: 1000,
+                }) => Ok((0, chunk_sizes, Vec::new())),
+                res => anyhow::bail!("unexpected result {:?}", res),
+            }
+        });
+
+    let (_, bob_events) = collect_events(&mut alice, &mut bob, Duration::from_secs(1)).await;
+
+    assert!(matches!(
+        bob_events.as_slice(),
+        [BehaviourOutEvent::Inbound(_, Ok((0, chunk_sizes, _)))] if chunk_sizes.is_empty()
+    ));
+}
@@ -0,0 +1,97 @@
+use harness::{collect_events, connect, new_swarm};
+use libp2p::futures::{future, AsyncWriteExt};
+use libp2p_async_await::{Behaviour, BehaviourOutEvent, FramingError, ProtocolError};
+use std::time::Duration;
+use tokio::runtime::Handle;
+
+mod harness;
+
+type TestBehaviour = Behaviour<Vec<u8>, (), FramingError>;
+
+fn limited(max: usize) -> TestBehaviour {
+    let mut behaviour = TestBehaviour::new(b"/foo/1.0.0");
+    behaviour.set_max_read_memory_per_connection(Some(max));
+    behaviour
+}
+
+#[tokio::test]
+async fn reads_exceeding_the_memory_limit_fail() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, _, alice_peer_id) = new_swarm(|_, _| limited(1024), Handle::current());
+    let (mut bob, _, bob_peer_id) = new_swarm(|_, _| limited(1024), Handle::current());
+    connect(&mut alice, &mut bob).await;
+
+    alice
+        .behaviour_mut()
+        .do_protocol_dialer(bob_peer_id, |mut substream| async move {
+            substream.write_message(b"hello").await?;
+            substream.write_message(&[0; 2048]).await?;
+            future::pending().await
+        });
+    bob.behaviour_mut()
+        .do_protocol_listener(alice_peer_id, |mut substream| async move {
+            let hello = substream.read_message(4096).await?;
+            assert_eq!(hello, b"hello");
+            substream.read_message(4096).await
+        });
+
+    let (_, bob_events) = collect_events(&mut alice, &mut bob, Duration::from_millis(500)).await;
+
+    match bob_events.as_slice() {
+        [BehaviourOutEvent::Inbound(
+            _,
+            Err(ProtocolError::Application(FramingError::MemoryLimit {
+                wanted: 2048,
+                available: 1024,
+            })),
+        )] => {}
+        events => panic!("unexpected events {:?}", events),
+    }
+}
+
+#[tokio::test]
+async fn reads_in_progress_count_towards_the_estimated_memory() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, _, alice_peer_id) = new_swarm(|_, _| limited(1024), Handle::current());
+    let (mut bob, _, bob_peer_id) = new_swarm(|_, _| limited(1024), Handle::current());
+    connect(&mut alice, &mut bob).await;
+
+    alice
+        .behaviour_mut()
+        .do_protocol_dialer(bob_peer_id, |mut substream| async move {
+            substream.read_message(1024).await?;
+            Ok(())
+        });
+    bob.behaviour_mut()
+        .do_protocol_listener(alice_peer_id, |mut substream| async move {
+            // The length prefix of a 512 byte frame without the frame itself.
+            substream.write_all(&[0x80, 0x04]).await?;
+            substream.flush().await?;
+            future::pending().await
+        });
+    collect_events(&mut alice, &mut bob, Duration::from_millis(500)).await;
+
+    assert_eq!(alice.behaviour().estimated_memory(), 512);
+    assert_eq!(bob.behaviour().estimated_memory(), 0);
+}
+
+#[tokio::test]
+async fn reads_waiting_for_the_length_of_a_frame_reserve_nothing() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, _, _) = new_swarm(|_, _| limited(1024), Handle::current());
+    let (mut bob, _, bob_peer_id) = new_swarm(|_, _| limited(1024), Handle::current());
+    connect(&mut alice, &mut bob).await;
+
+    alice
+        .behaviour_mut()
+        .do_protocol_dialer(bob_peer_id, |mut substream| async move {
+            substream.read_message(4096).await?;
+            Ok(())
+        });
+    collect_events(&mut alice, &mut bob, Duration::from_millis(500)).await;
+
+    assert_eq!(alice.behaviour().estimated_memory(), 0);
+}
@@ -0,0 +1,99 @@
+use harness::{collect_events, connect, new_swarm};
+use libp2p::futures::AsyncWriteExt;
+use libp2p_async_await::{Behaviour, BehaviourOutEvent, FramingError};
+use std::time::Duration;
+use tokio::runtime::Handle;
+
+mod harness;
+
+type TestBehaviour = Behaviour<Vec<(Vec<u8>, bool)>, (), anyhow::Error>;
+
+#[tokio::test]
+async fn incomplete_frames_are_continued_by_the_next_read() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, _, alice_peer_id) =
+        new_swarm(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current());
+    let (mut bob, _, bob_peer_id) =
+        new_swarm(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current());
+    connect(&mut alice, &mut bob).await;
+
+    alice
+        .behaviour_mut()
+        .do_protocol_dialer(bob_peer_id, |mut substream| async move {
+            // The length prefix of the frame, followed by half of it.
+            substream.write_all(&[6, b'h', b'e', b'l']).await?;
+            substream.flush().await?;
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            substream.write_all(b"lo!").await?;
+            substream.write_message(b"next").await?;
+            substream.flush().await?;
+
+            Ok(())
+        });
+    bob.behaviour_mut()
+        .do_protocol_listener(alice_peer_id, |mut substream| async move {
+            let mut reads = Vec::new();
+            for timeout in [100, 1000].iter() {
+                let read = substream
+                    .read_message_timeout_partial(1024, Duration::from_millis(*timeout))
+                    .await?;
+                reads.push(read);
+            }
+            reads.push((substream.read_message(1024).await?, true));
+
+            Ok(reads)
+        });
+
+    let (_, bob_events) = collect_events(&mut alice, &mut bob, Duration::from_secs(1)).await;
+
+    match bob_events.as_slice() {
+        [BehaviourOutEvent::Inbound(_, Ok(reads))] => assert_eq!(
+            reads,
+            &[
+                (b"hel".to_vec(), false),
+                (b"hello!".to_vec(), true),
+                (b"next".to_vec(), true)
+            ]
+        ),
+        events => panic!("unexpected events {:?}", events),
+    }
+}
+
+#[tokio::test]
+async fn partial_reads_fail_for_frames_beyond_the_limit() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, _, alice_peer_id) =
+        new_swarm(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current());
+    let (mut bob, _, bob_peer_id) =
+        new_swarm(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current());
+    connect(&mut alice, &mut bob).await;
+
+    alice
+        .behaviour_mut()
+        .do_protocol_dialer(bob_peer_id, |mut substream| async move {
+            substream.write_message(&[0; 100]).await?;
+            Ok(())
+        });
+    bob.behaviour_mut()
+        .do_protocol_listener(alice_peer_id, |mut substream| async move {
+            match substream
+                .read_message_timeout_partial(10, Duration::from_secs(1))
+                .await
+            {
+                Err(FramingError::TooLarge {
+                    length: 100,
+                    max_size: 10,
+                }) => Ok(Vec::new()),
+                res => anyhow::bail!("unexpected result {:?}", res),
+            }
+        });
+
+    let (_, bob_events) = collect_events(&mut alice, &mut bob, Duration::from_secs(1)).await;
+
+    assert!(matches!(
+        bob_events.as_slice(),
+        [BehaviourOutEvent::Inbound(_, Ok(_))]
+    ));
+}
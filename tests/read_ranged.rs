@@ -0,0 +1,89 @@
+use harness::{collect_events, connect, new_swarm};
+use libp2p::futures::io::Cursor;
+use libp2p::futures::StreamExt;
+use libp2p_async_await::driver::Driver;
+use libp2p_async_await::{
+    Behaviour, BehaviourOutEvent, FramingError, InboundSubstream, ProtocolOutEvent,
+};
+use std::time::Duration;
+use tokio::runtime::Handle;
+
+mod harness;
+
+type TestBehaviour = Behaviour<Result<Vec<u8>, String>, (), anyhow::Error>;
+
+async fn read_ranged(
+    message: &'static [u8],
+    min_size: usize,
+    max_size: usize,
+) -> Result<Vec<u8>, String> {
+    let (mut alice, _, alice_peer_id) =
+        new_swarm(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current());
+    let (mut bob, _, bob_peer_id) =
+        new_swarm(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current());
+    connect(&mut alice, &mut bob).await;
+
+    alice
+        .behaviour_mut()
+        .do_protocol_dialer(bob_peer_id, move |mut substream| async move {
+            substream.write_message(message).await?;
+            Ok(())
+        });
+    bob.behaviour_mut()
+        .do_protocol_listener(alice_peer_id, move |mut substream| async move {
+            let res = substream.read_message_ranged(min_size, max_size).await;
+            Ok(res.map_err(|e| match e {
+                FramingError::TooShort { length, .. } => format!("too short: {}", length),
+                FramingError::TooLarge { length, .. } => format!("too large: {}", length),
+                FramingError::Io(e) | FramingError::ConnectionClosed(e) => e.to_string(),
+                e => e.to_string(),
+            }))
+        });
+
+    let (_, bob_events) = collect_events(&mut alice, &mut bob, Duration::from_secs(1)).await;
+
+    match bob_events.as_slice() {
+        [BehaviourOutEvent::Inbound(_, Ok(res))] => res.clone(),
+        events => panic!("unexpected events {:?}", events),
+    }
+}
+
+#[tokio::test]
+async fn messages_within_range_are_read() {
+    let _ = env_logger::try_init();
+
+    assert_eq!(read_ranged(b"hello", 5, 5).await, Ok(b"hello".to_vec()));
+}
+
+#[tokio::test]
+async fn messages_outside_range_report_their_length() {
+    let _ = env_logger::try_init();
+
+    assert_eq!(
+        read_ranged(b"hi", 4, 1024).await,
+        Err("too short: 2".to_owned())
+    );
+    assert_eq!(
+        read_ranged(b"hello", 0, 4).await,
+        Err("too large: 5".to_owned())
+    );
+}
+
+#[tokio::test]
+async fn truncated_messages_report_a_closed_connection() {
+    let _ = env_logger::try_init();
+
+    let mut driver = Driver::<bool, (), anyhow::Error>::new();
+    driver.execute_inbound(
+        InboundSubstream::new(Cursor::new(vec![5, b'h']), b"/foo/1.0.0"),
+        |mut substream| async move {
+            let res = substream.read_message(1024).await;
+            Ok(matches!(res, Err(FramingError::ConnectionClosed(_))))
+        },
+    );
+
+    assert!(matches!(
+        driver.next().await,
+        Some(ProtocolOutEvent::Inbound(Ok(true)))
+    ));
+}
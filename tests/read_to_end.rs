@@ -0,0 +1,78 @@
+use harness::{collect_events, connect, new_swarm};
+use libp2p::futures::AsyncWriteExt;
+use libp2p_async_await::{Behaviour, BehaviourOutEvent, FramingError};
+use std::time::Duration;
+use tokio::runtime::Handle;
+
+mod harness;
+
+type TestBehaviour = Behaviour<Result<Vec<Vec<u8>>, String>, (), anyhow::Error>;
+
+async fn read_to_end(frames: usize, max_total: usize) -> Result<Vec<Vec<u8>>, String> {
+    let (mut alice, _, alice_peer_id) =
+        new_swarm(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current());
+    let (mut bob, _, bob_peer_id) =
+        new_swarm(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current());
+    connect(&mut alice, &mut bob).await;
+
+    alice
+        .behaviour_mut()
+        .do_protocol_dialer(bob_peer_id, move |mut substream| async move {
+            for i in 0..frames {
+                substream
+                    .write_message(format!("frame {}", i).as_bytes())
+                    .await?;
+            }
+            substream.close().await?;
+            Ok(())
+        });
+    bob.behaviour_mut()
+        .do_protocol_listener(alice_peer_id, move |mut substream| async move {
+            let res = substream.read_to_end_framed(max_total).await;
+            Ok(res.map_err(|e| match e {
+                FramingError::TooLarge { length, max_size } => {
+                    format!("too large: {} > {}", length, max_size)
+                }
+                e => e.to_string(),
+            }))
+        });
+
+    let (_, bob_events) = collect_events(&mut alice, &mut bob, Duration::from_secs(1)).await;
+
+    match bob_events.as_slice() {
+        [BehaviourOutEvent::Inbound(_, Ok(res))] => res.clone(),
+        events => panic!("unexpected events {:?}", events),
+    }
+}
+
+#[tokio::test]
+async fn all_frames_are_read_until_the_remote_closes() {
+    let _ = env_logger::try_init();
+
+    let frames = read_to_end(5, 1024).await.unwrap();
+
+    assert_eq!(
+        frames,
+        (0..5)
+            .map(|i| format!("frame {}", i).into_bytes())
+            .collect::<Vec<_>>()
+    );
+}
+
+#[tokio::test]
+async fn closing_without_frames_reads_nothing() {
+    let _ = env_logger::try_init();
+
+    assert_eq!(read_to_end(0, 1024).await, Ok(Vec::new()));
+}
+
+#[tokio::test]
+async fn frames_beyond_the_total_limit_fail() {
+    let _ = env_logger::try_init();
+
+    // Each frame is 7 bytes long, the third one exceeds the limit.
+    assert_eq!(
+        read_to_end(5, 20).await,
+        Err("too large: 21 > 20".to_owned())
+    );
+}
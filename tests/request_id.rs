@@ -0,0 +1,96 @@
+use harness::{collect_events, new_connected_swarm_pair};
+use libp2p::futures::io::Cursor;
+use libp2p::futures::StreamExt;
+use libp2p_async_await::driver::Driver;
+use libp2p_async_await::{Behaviour, BehaviourOutEvent, InboundSubstream, ProtocolOutEvent};
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::runtime::Handle;
+
+mod harness;
+
+type TestBehaviour = Behaviour<Vec<u32>, Vec<(u32, Vec<u8>)>, anyhow::Error>;
+
+#[tokio::test]
+async fn responses_arriving_out_of_order_are_matched_by_id() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, mut bob) = new_connected_swarm_pair(
+        |_, _| TestBehaviour::new(b"/request-id/1.0.0"),
+        Handle::current(),
+    )
+    .await;
+
+    alice
+        .swarm
+        .behaviour_mut()
+        .do_protocol_dialer(bob.peer_id, |mut substream| async move {
+            let requests = [(1, &b"one"[..]), (2, b"two"), (3, b"three")];
+            for (id, payload) in requests.iter() {
+                substream.write_request(*id, payload).await?;
+            }
+
+            let mut responses = Vec::new();
+            for _ in 0..requests.len() {
+                responses.push(substream.read_response(1024).await?);
+            }
+
+            Ok(responses)
+        });
+    bob.swarm
+        .behaviour_mut()
+        .do_protocol_listener(alice.peer_id, |mut substream| async move {
+            let mut requests = Vec::new();
+            for _ in 0..3 {
+                requests.push(substream.read_request(1024).await?);
+            }
+
+            // Answer the requests in reverse order.
+            for (id, payload) in requests.iter().rev() {
+                substream
+                    .write_response(*id, &payload.to_ascii_uppercase())
+                    .await?;
+            }
+
+            Ok(requests.into_iter().map(|(id, _)| id).collect())
+        });
+
+    let (alice_events, bob_events) =
+        collect_events(&mut alice.swarm, &mut bob.swarm, Duration::from_secs(1)).await;
+
+    match bob_events.as_slice() {
+        [BehaviourOutEvent::Inbound(_, Ok(ids))] => assert_eq!(ids, &[1, 2, 3]),
+        events => panic!("unexpected events {:?}", events),
+    }
+    let responses = match alice_events.as_slice() {
+        [BehaviourOutEvent::Outbound(_, Ok(responses), _)] => responses,
+        events => panic!("unexpected events {:?}", events),
+    };
+    let arrival = responses.iter().map(|(id, _)| *id).collect::<Vec<_>>();
+    assert_eq!(arrival, [3, 2, 1]);
+
+    let matched = responses.iter().cloned().collect::<HashMap<_, _>>();
+    assert_eq!(matched[&1], b"ONE");
+    assert_eq!(matched[&2], b"TWO");
+    assert_eq!(matched[&3], b"THREE");
+}
+
+#[tokio::test]
+async fn unlimited_max_sizes_do_not_overflow() {
+    let _ = env_logger::try_init();
+
+    let mut frame = vec![7];
+    frame.extend_from_slice(&42u32.to_be_bytes());
+    frame.extend_from_slice(b"one");
+
+    let mut driver = Driver::<_, (), anyhow::Error>::new();
+    driver.execute_inbound(
+        InboundSubstream::new(Cursor::new(frame), b"/request-id/1.0.0"),
+        |mut substream| async move { Ok(substream.read_request(usize::MAX).await?) },
+    );
+
+    assert!(matches!(
+        driver.next().await,
+        Some(ProtocolOutEvent::Inbound(Ok((42, payload)))) if payload == b"one"
+    ));
+}
@@ -0,0 +1,73 @@
+use harness::{collect_events, new_connected_swarm_pair, Actor};
+use libp2p::futures::future;
+use libp2p_async_await::{Behaviour, BehaviourOutEvent, InboundSubstream};
+use std::time::Duration;
+use tokio::runtime::Handle;
+
+mod harness;
+
+type TestBehaviour = Behaviour<(), (), anyhow::Error>;
+
+/// Reads the message before the reset and expects writing afterwards to fail.
+async fn expect_reset(mut substream: InboundSubstream) -> anyhow::Result<()> {
+    substream.read_message(1024).await?;
+    // Reads of a reset substream end like reads of a closed one, so this only waits for the reset.
+    let _ = substream.read_message(1024).await;
+    match substream.write_message(b"still there?").await {
+        Err(_) => Ok(()),
+        Ok(()) => Err(anyhow::anyhow!("writing to a reset substream succeeded")),
+    }
+}
+
+/// Runs another protocol on the connection, yamux only sends the reset along with other frames.
+fn use_connection(alice: &mut Actor<TestBehaviour>, bob: &mut Actor<TestBehaviour>) {
+    alice
+        .swarm
+        .behaviour_mut()
+        .do_protocol_dialer(bob.peer_id, |mut substream| async move {
+            substream.write_message(b"hello").await?;
+            Ok(())
+        });
+    bob.swarm
+        .behaviour_mut()
+        .do_protocol_listener(alice.peer_id, |mut substream| async move {
+            substream.read_message(1024).await?;
+            Ok(())
+        });
+}
+
+#[tokio::test]
+async fn cancelling_a_protocol_resets_its_substream() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, mut bob) =
+        new_connected_swarm_pair(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current()).await;
+
+    alice.swarm.behaviour_mut().do_protocol_dialer_tagged(
+        bob.peer_id,
+        1u32,
+        |mut substream| async move {
+            substream.write_message(b"partial").await?;
+            future::pending().await
+        },
+    );
+    bob.swarm
+        .behaviour_mut()
+        .do_protocol_listener(alice.peer_id, expect_reset);
+    let (_, bob_events) =
+        collect_events(&mut alice.swarm, &mut bob.swarm, Duration::from_millis(200)).await;
+    assert!(bob_events.is_empty());
+
+    alice.swarm.behaviour_mut().cancel_where(|_| true);
+    use_connection(&mut alice, &mut bob);
+    let (_, bob_events) =
+        collect_events(&mut alice.swarm, &mut bob.swarm, Duration::from_secs(1)).await;
+
+    assert!(matches!(
+        bob_events.as_slice(),
+        [
+            BehaviourOutEvent::Inbound(_, Ok(())),
+            BehaviourOutEvent::Inbound(_, Ok(()))
+        ]
+    ));
+}
@@ -0,0 +1,56 @@
+use harness::{collect_events, new_connected_swarm_pair};
+use libp2p_async_await::{Behaviour, BehaviourOutEvent};
+use std::time::Duration;
+use tokio::runtime::Handle;
+
+mod harness;
+
+type TestBehaviour = Behaviour<(), Vec<Vec<u8>>, anyhow::Error>;
+
+#[tokio::test]
+async fn listener_responds_to_each_request() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, mut bob) =
+        new_connected_swarm_pair(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current()).await;
+
+    alice
+        .swarm
+        .behaviour_mut()
+        .do_protocol_dialer(bob.peer_id, |mut substream| async move {
+            let mut replies = Vec::new();
+            for request in [b"ping", b"pong"] {
+                substream.write_message(request).await?;
+                replies.push(substream.read_message(1024).await?);
+            }
+            Ok(replies)
+        });
+    bob.swarm
+        .behaviour_mut()
+        .do_protocol_listener(alice.peer_id, |mut substream| async move {
+            substream
+                .respond(1024, |mut request| {
+                    request.reverse();
+                    request
+                })
+                .await?;
+            substream
+                .respond_with(1024, |request| async move { request.repeat(2) })
+                .await?;
+            Ok(())
+        });
+
+    let (alice_events, bob_events) =
+        collect_events(&mut alice.swarm, &mut bob.swarm, Duration::from_secs(1)).await;
+
+    match alice_events.as_slice() {
+        [BehaviourOutEvent::Outbound(_, Ok(replies), _)] => {
+            assert_eq!(replies, &[b"gnip".to_vec(), b"pongpong".to_vec()])
+        }
+        events => panic!("unexpected events {:?}", events),
+    }
+    assert!(matches!(
+        bob_events.as_slice(),
+        [BehaviourOutEvent::Inbound(_, Ok(()))]
+    ));
+}
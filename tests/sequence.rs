@@ -0,0 +1,166 @@
+use harness::{collect_events, new_connected_swarm_pair};
+use libp2p::futures::io::Cursor;
+use libp2p::futures::StreamExt;
+use libp2p_async_await::driver::Driver;
+use libp2p_async_await::sequence::{SequenceError, SequenceReader, SequenceWriter};
+use libp2p_async_await::{Behaviour, BehaviourOutEvent, InboundSubstream, ProtocolOutEvent};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::runtime::Handle;
+
+mod harness;
+
+type TestBehaviour = Behaviour<Vec<(u64, Vec<u8>)>, Vec<u64>, anyhow::Error>;
+
+/// Encodes messages as written by `write_seq_message`.
+fn frames(messages: &[(u64, &[u8])]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for (sequence, msg) in messages {
+        bytes.push((8 + msg.len()) as u8);
+        bytes.extend_from_slice(&sequence.to_be_bytes());
+        bytes.extend_from_slice(msg);
+    }
+
+    bytes
+}
+
+async fn read_all(bytes: Vec<u8>, reader: SequenceReader) -> Vec<Result<(u64, Vec<u8>), String>> {
+    let mut driver = Driver::<_, (), anyhow::Error>::new();
+    driver.execute_inbound(
+        InboundSubstream::new(Cursor::new(bytes), b"/foo/1.0.0"),
+        |mut substream| async move {
+            let mut reader = reader;
+            let mut results = Vec::new();
+            loop {
+                match substream.read_seq_message(&mut reader, 1024).await {
+                    Err(SequenceError::Read(_)) => break,
+                    res => results.push(res.map_err(|e| e.to_string())),
+                }
+            }
+            Ok(results)
+        },
+    );
+
+    match driver.next().await {
+        Some(ProtocolOutEvent::Inbound(Ok(results))) => results,
+        _ => panic!("protocol failed"),
+    }
+}
+
+#[tokio::test]
+async fn sequence_continues_across_substreams() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, mut bob) =
+        new_connected_swarm_pair(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current()).await;
+    let writer = Arc::new(Mutex::new(SequenceWriter::new()));
+    let reader = Arc::new(Mutex::new(SequenceReader::new()));
+
+    for msg in [b"hello", b"world"] {
+        let writer = writer.clone();
+        alice.swarm.behaviour_mut().do_protocol_dialer(
+            bob.peer_id,
+            move |mut substream| async move {
+                let mut seq_writer = writer.lock().unwrap().clone();
+                let sequence = substream.write_seq_message(&mut seq_writer, msg).await?;
+                *writer.lock().unwrap() = seq_writer;
+                Ok(vec![sequence])
+            },
+        );
+        let reader = reader.clone();
+        bob.swarm.behaviour_mut().do_protocol_listener(
+            alice.peer_id,
+            move |mut substream| async move {
+                let mut seq_reader = reader.lock().unwrap().clone();
+                let message = substream.read_seq_message(&mut seq_reader, 5).await?;
+                *reader.lock().unwrap() = seq_reader;
+                Ok(vec![message])
+            },
+        );
+
+        let (alice_events, bob_events) =
+            collect_events(&mut alice.swarm, &mut bob.swarm, Duration::from_secs(1)).await;
+
+        assert!(matches!(
+            alice_events.as_slice(),
+            [BehaviourOutEvent::Outbound(_, Ok(_), _)]
+        ));
+        assert!(matches!(
+            bob_events.as_slice(),
+            [BehaviourOutEvent::Inbound(_, Ok(_))]
+        ));
+    }
+
+    assert_eq!(writer.lock().unwrap().next(), 2);
+    assert_eq!(reader.lock().unwrap().expected(), 2);
+}
+
+#[tokio::test]
+async fn gaps_are_detected() {
+    let _ = env_logger::try_init();
+
+    let bytes = frames(&[(0, b"a"), (2, b"c"), (1, b"b")]);
+
+    assert_eq!(
+        read_all(bytes, SequenceReader::new()).await,
+        vec![
+            Ok((0, b"a".to_vec())),
+            Err("expected message 1 but received message 2".to_owned()),
+            Ok((1, b"b".to_vec())),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn repeated_messages_are_rejected() {
+    let _ = env_logger::try_init();
+
+    let bytes = frames(&[(0, b"a"), (1, b"b"), (1, b"b"), (0, b"a")]);
+
+    assert_eq!(
+        read_all(bytes, SequenceReader::new()).await,
+        vec![
+            Ok((0, b"a".to_vec())),
+            Ok((1, b"b".to_vec())),
+            Err("received stale message 1, expected message 2".to_owned()),
+            Err("received stale message 0, expected message 2".to_owned()),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn gaps_can_be_allowed() {
+    let _ = env_logger::try_init();
+
+    let mut reader = SequenceReader::new();
+    reader.allow_gaps(true);
+    let bytes = frames(&[(0, b"a"), (3, b"d"), (2, b"c")]);
+
+    assert_eq!(
+        read_all(bytes, reader).await,
+        vec![
+            Ok((0, b"a".to_vec())),
+            Ok((3, b"d".to_vec())),
+            Err("received stale message 2, expected message 4".to_owned()),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn unlimited_max_sizes_do_not_overflow() {
+    let _ = env_logger::try_init();
+
+    let mut driver = Driver::<_, (), anyhow::Error>::new();
+    driver.execute_inbound(
+        InboundSubstream::new(Cursor::new(frames(&[(0, b"a")])), b"/foo/1.0.0"),
+        |mut substream| async move {
+            let mut reader = SequenceReader::new();
+            Ok(substream.read_seq_message(&mut reader, usize::MAX).await?)
+        },
+    );
+
+    assert!(matches!(
+        driver.next().await,
+        Some(ProtocolOutEvent::Inbound(Ok((0, message)))) if message == b"a"
+    ));
+}
@@ -1,8 +1,9 @@
-#![allow(clippy::blacklisted_name)]
+#![allow(clippy::disallowed_names)]
 
 use anyhow::{Context, Error};
 use harness::await_events_or_timeout;
 use harness::new_connected_swarm_pair;
+use libp2p::futures::FutureExt;
 use libp2p::swarm::SwarmEvent;
 use libp2p::PeerId;
 use libp2p_async_await::{Behaviour, BehaviourOutEvent};
@@ -34,6 +35,7 @@ struct BobResult {
 }
 
 #[derive(Debug)]
+#[allow(dead_code)]
 enum MyOutEvent {
     Alice(AliceResult),
     Bob(BobResult),
@@ -44,10 +46,30 @@ impl From<BehaviourOutEvent<BobResult, AliceResult, anyhow::Error>> for MyOutEve
     fn from(event: BehaviourOutEvent<BobResult, AliceResult, Error>) -> Self {
         match event {
             BehaviourOutEvent::Inbound(_, Ok(bob)) => MyOutEvent::Bob(bob),
-            BehaviourOutEvent::Outbound(_, Ok(alice)) => MyOutEvent::Alice(alice),
-            BehaviourOutEvent::Inbound(_, Err(e)) | BehaviourOutEvent::Outbound(_, Err(e)) => {
-                MyOutEvent::Failed(e)
+            BehaviourOutEvent::Outbound(_, Ok(alice), _) => MyOutEvent::Alice(alice),
+            BehaviourOutEvent::Inbound(_, Err(e)) | BehaviourOutEvent::Outbound(_, Err(e), _) => {
+                MyOutEvent::Failed(e.into_application())
             }
+            BehaviourOutEvent::InboundFailed(_, failure)
+            | BehaviourOutEvent::OutboundFailed(_, failure, _) => {
+                MyOutEvent::Failed(failure.into())
+            }
+            BehaviourOutEvent::Rejected(_, protocol) => MyOutEvent::Failed(anyhow::anyhow!(
+                "rejected {}",
+                String::from_utf8_lossy(protocol)
+            )),
+            BehaviourOutEvent::PeerConnected(..) | BehaviourOutEvent::PeerDisconnected(..) => {
+                unreachable!("connection events are not enabled")
+            }
+            BehaviourOutEvent::DisconnectPeer(..) => {
+                unreachable!("protocols never request to disconnect")
+            }
+            BehaviourOutEvent::NotifyFailed(..) => unreachable!("no notifications are sent"),
+            BehaviourOutEvent::Progress(..) => unreachable!("progress is not reported"),
+            BehaviourOutEvent::HandlerIdle(..) => unreachable!("idle handlers are not reported"),
+            BehaviourOutEvent::Drained => unreachable!("drained queues are not reported"),
+            #[cfg(feature = "probe")]
+            BehaviourOutEvent::ProtocolsProbed(..) => unreachable!("no peers are probed"),
         }
     }
 }
@@ -64,6 +86,12 @@ impl MyBehaviour {
             inner: Behaviour::new(b"/foo/bar/1.0.0"),
         }
     }
+
+    pub fn new_paused() -> Self {
+        Self {
+            inner: Behaviour::new_paused(b"/foo/bar/1.0.0"),
+        }
+    }
 }
 
 impl MyBehaviour {
@@ -123,8 +151,13 @@ async fn it_works() {
     let (mut alice, mut bob) =
         new_connected_swarm_pair(|_, _| MyBehaviour::new(), Handle::current()).await;
 
-    alice.swarm.alice_do_protocol(bob.peer_id, 10, 42);
-    bob.swarm.bob_do_protocol(alice.peer_id, 1337);
+    alice
+        .swarm
+        .behaviour_mut()
+        .alice_do_protocol(bob.peer_id, 10, 42);
+    bob.swarm
+        .behaviour_mut()
+        .bob_do_protocol(alice.peer_id, 1337);
 
     let (alice_event, bob_event) =
         await_events_or_timeout(alice.swarm.next_event(), bob.swarm.next_event()).await;
@@ -138,3 +171,41 @@ async fn it_works() {
         SwarmEvent::Behaviour(MyOutEvent::Bob(BobResult { foo: 10, baz: 42 }))
     ));
 }
+
+#[tokio::test]
+async fn paused_behaviour_declines_inbound_substreams() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, mut bob) =
+        new_connected_swarm_pair(|_, _| MyBehaviour::new_paused(), Handle::current()).await;
+    alice.swarm.behaviour_mut().inner.set_ready(true);
+
+    alice
+        .swarm
+        .behaviour_mut()
+        .alice_do_protocol(bob.peer_id, 10, 42);
+    bob.swarm
+        .behaviour_mut()
+        .bob_do_protocol(alice.peer_id, 1337);
+
+    let alice_event = tokio::time::timeout(std::time::Duration::from_secs(10), async {
+        loop {
+            libp2p::futures::select! {
+                event = alice.swarm.next_event().fuse() => {
+                    if let SwarmEvent::Behaviour(event) = event {
+                        break event;
+                    }
+                }
+                event = bob.swarm.next_event().fuse() => {
+                    if let SwarmEvent::Behaviour(event) = event {
+                        panic!("paused bob must not emit events: {:?}", event);
+                    }
+                }
+            }
+        }
+    })
+    .await
+    .expect("alice to emit an event within 10 seconds");
+
+    assert!(matches!(alice_event, MyOutEvent::Failed(_)));
+}
@@ -0,0 +1,93 @@
+use harness::{collect_events, new_connected_swarm_pair, Actor};
+use libp2p_async_await::{Behaviour, BehaviourOutEvent, Failure};
+use std::time::Duration;
+use tokio::runtime::Handle;
+
+mod harness;
+
+type TestBehaviour = Behaviour<Vec<u8>, Vec<u8>, anyhow::Error>;
+
+/// Starts a ping dialer and a listener answering pings with the given peer.
+fn ping_pong(actor: &mut Actor<TestBehaviour>, remote: libp2p::PeerId) {
+    let behaviour = actor.swarm.behaviour_mut();
+    behaviour.do_protocol_dialer(remote, |mut substream| async move {
+        substream.write_message(b"ping").await?;
+        Ok(substream.read_message(1024).await?)
+    });
+    behaviour.do_protocol_listener(remote, |mut substream| async move {
+        let ping = substream.read_message(1024).await?;
+        substream.write_message(b"pong").await?;
+        Ok(ping)
+    });
+}
+
+#[tokio::test]
+async fn simultaneously_opened_protocols_wait_for_each_other_without_tie_break() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, mut bob) = new_connected_swarm_pair(
+        |_, _| {
+            let mut behaviour = TestBehaviour::new(b"/sim/1.0.0");
+            behaviour.set_protocol_timeout(Some(Duration::from_millis(500)));
+            behaviour
+        },
+        Handle::current(),
+    )
+    .await;
+    let (alice_id, bob_id) = (alice.peer_id, bob.peer_id);
+    ping_pong(&mut alice, bob_id);
+    ping_pong(&mut bob, alice_id);
+
+    let (alice_events, bob_events) =
+        collect_events(&mut alice.swarm, &mut bob.swarm, Duration::from_secs(1)).await;
+
+    // Each side queues the substream of the other behind its own dialer.
+    for events in [alice_events, bob_events].iter() {
+        assert!(
+            matches!(
+                events.first(),
+                Some(BehaviourOutEvent::OutboundFailed(_, Failure::Timeout, None))
+            ),
+            "unexpected events {:?}",
+            events
+        );
+    }
+}
+
+#[tokio::test]
+async fn simultaneously_opened_protocols_are_resolved_by_tie_break() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, mut bob) = new_connected_swarm_pair(
+        |_, _| {
+            let mut behaviour = TestBehaviour::new(b"/sim/1.0.0");
+            behaviour.set_resolve_simultaneous_open(true);
+            behaviour
+        },
+        Handle::current(),
+    )
+    .await;
+    let (alice_id, bob_id) = (alice.peer_id, bob.peer_id);
+    ping_pong(&mut alice, bob_id);
+    ping_pong(&mut bob, alice_id);
+
+    let (alice_events, bob_events) =
+        collect_events(&mut alice.swarm, &mut bob.swarm, Duration::from_secs(1)).await;
+
+    let (winner_events, loser_events) = if alice_id.to_bytes() < bob_id.to_bytes() {
+        (alice_events, bob_events)
+    } else {
+        (bob_events, alice_events)
+    };
+    match winner_events.as_slice() {
+        [BehaviourOutEvent::Outbound(_, Ok(pong), None)] => assert_eq!(pong, b"pong"),
+        events => panic!("unexpected events {:?}", events),
+    }
+    match loser_events.as_slice() {
+        [BehaviourOutEvent::OutboundFailed(_, Failure::SimultaneousOpen, None), BehaviourOutEvent::Inbound(_, Ok(ping))] =>
+        {
+            assert_eq!(ping, b"ping")
+        }
+        events => panic!("unexpected events {:?}", events),
+    }
+}
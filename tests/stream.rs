@@ -0,0 +1,57 @@
+use libp2p::futures::channel::mpsc;
+use libp2p::futures::{poll, StreamExt};
+use libp2p::PeerId;
+use libp2p_async_await::stream::split;
+use libp2p_async_await::{BehaviourOutEvent, Failure};
+use std::time::Duration;
+
+type Event = BehaviourOutEvent<u32, &'static str, Failure>;
+
+#[tokio::test]
+async fn dropping_one_half_discards_its_results_and_keeps_the_other_draining() {
+    let peer = PeerId::random();
+    let (sender, receiver) = mpsc::unbounded::<Event>();
+    let (inbound, mut outbound) = split(receiver);
+    drop(inbound);
+
+    for i in 0..3 {
+        sender
+            .unbounded_send(BehaviourOutEvent::Inbound(peer, Ok(i)))
+            .unwrap();
+        sender
+            .unbounded_send(BehaviourOutEvent::Outbound(peer, Ok("pong"), None))
+            .unwrap();
+    }
+    drop(sender);
+
+    let outbound = outbound.by_ref().collect::<Vec<_>>().await;
+
+    assert_eq!(outbound.len(), 3);
+    assert!(outbound
+        .iter()
+        .all(|(p, res)| *p == peer && matches!(res, Ok(Ok("pong")))));
+}
+
+#[tokio::test]
+async fn halves_are_woken_after_the_other_half_polled_last_and_was_dropped() {
+    let peer = PeerId::random();
+    let (sender, receiver) = mpsc::unbounded::<Event>();
+    let (mut inbound, mut outbound) = split(receiver);
+
+    let outbound = tokio::spawn(async move { outbound.next().await });
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    // The inbound half polls the underlying stream last, then goes away.
+    assert!(poll!(inbound.next()).is_pending());
+    drop(inbound);
+
+    sender
+        .unbounded_send(BehaviourOutEvent::Outbound(peer, Ok("pong"), None))
+        .unwrap();
+
+    let result = tokio::time::timeout(Duration::from_secs(1), outbound)
+        .await
+        .expect("outbound half to be woken")
+        .unwrap();
+    assert!(matches!(result, Some((p, Ok(Ok("pong")))) if p == peer));
+}
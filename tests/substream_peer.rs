@@ -0,0 +1,50 @@
+use harness::{collect_events, new_connected_swarm_pair};
+use libp2p::futures::io::Cursor;
+use libp2p::PeerId;
+use libp2p_async_await::{Behaviour, BehaviourOutEvent, OutboundSubstream};
+use std::time::Duration;
+use tokio::runtime::Handle;
+
+mod harness;
+
+type TestBehaviour = Behaviour<Option<PeerId>, Option<PeerId>, anyhow::Error>;
+
+#[tokio::test]
+async fn substreams_know_their_remote() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, mut bob) =
+        new_connected_swarm_pair(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current()).await;
+
+    alice
+        .swarm
+        .behaviour_mut()
+        .do_protocol_dialer(bob.peer_id, |substream| async move { Ok(substream.peer()) });
+    bob.swarm
+        .behaviour_mut()
+        .do_protocol_listener(
+            alice.peer_id,
+            |substream| async move { Ok(substream.peer()) },
+        );
+
+    let (alice_events, bob_events) =
+        collect_events(&mut alice.swarm, &mut bob.swarm, Duration::from_secs(1)).await;
+
+    match (alice_events.as_slice(), bob_events.as_slice()) {
+        (
+            [BehaviourOutEvent::Outbound(_, Ok(alice_remote), None)],
+            [BehaviourOutEvent::Inbound(_, Ok(bob_remote))],
+        ) => {
+            assert_eq!(*alice_remote, Some(bob.peer_id));
+            assert_eq!(*bob_remote, Some(alice.peer_id));
+        }
+        events => panic!("unexpected events {:?}", events),
+    }
+}
+
+#[test]
+fn substreams_constructed_directly_have_no_remote() {
+    let substream = OutboundSubstream::new(Cursor::new(Vec::new()), b"/foo/1.0.0");
+
+    assert_eq!(substream.peer(), None);
+}
@@ -0,0 +1,180 @@
+use harness::{
+    collect_events, collect_events_single, connect, new_connected_swarm_pair, new_swarm,
+};
+use libp2p::futures::future;
+use libp2p_async_await::{Behaviour, BehaviourOutEvent, Failure};
+use std::time::Duration;
+use tokio::runtime::Handle;
+
+mod harness;
+
+type TestBehaviour = Behaviour<(), (), anyhow::Error>;
+
+async fn new_pair() -> (harness::Actor<TestBehaviour>, harness::Actor<TestBehaviour>) {
+    new_connected_swarm_pair(
+        |_, _| TestBehaviour::new(b"/terminal/1.0.0"),
+        Handle::current(),
+    )
+    .await
+}
+
+#[tokio::test]
+async fn successful_protocol_emits_exactly_one_event_per_side() {
+    let (mut alice, mut bob) = new_pair().await;
+
+    alice
+        .swarm
+        .behaviour_mut()
+        .do_protocol_dialer(bob.peer_id, |mut substream| async move {
+            substream.write_message(b"hello").await?;
+            Ok(())
+        });
+    bob.swarm
+        .behaviour_mut()
+        .do_protocol_listener(alice.peer_id, |mut substream| async move {
+            substream.read_message(1024).await?;
+            Ok(())
+        });
+
+    let (alice_events, bob_events) =
+        collect_events(&mut alice.swarm, &mut bob.swarm, Duration::from_secs(1)).await;
+
+    assert!(matches!(
+        alice_events.as_slice(),
+        [BehaviourOutEvent::Outbound(_, Ok(()), _)]
+    ));
+    assert!(matches!(
+        bob_events.as_slice(),
+        [BehaviourOutEvent::Inbound(_, Ok(()))]
+    ));
+}
+
+#[tokio::test]
+async fn failing_protocol_emits_exactly_one_event_per_side() {
+    let (mut alice, mut bob) = new_pair().await;
+
+    alice
+        .swarm
+        .behaviour_mut()
+        .do_protocol_dialer(bob.peer_id, |mut substream| async move {
+            substream.write_message(b"hello").await?;
+            Err(anyhow::anyhow!("boom"))
+        });
+    bob.swarm
+        .behaviour_mut()
+        .do_protocol_listener(alice.peer_id, |mut substream| async move {
+            substream.read_message(1024).await?;
+            Ok(())
+        });
+
+    let (alice_events, bob_events) =
+        collect_events(&mut alice.swarm, &mut bob.swarm, Duration::from_secs(1)).await;
+
+    assert!(matches!(
+        alice_events.as_slice(),
+        [BehaviourOutEvent::Outbound(_, Err(_), _)]
+    ));
+    assert!(matches!(
+        bob_events.as_slice(),
+        [BehaviourOutEvent::Inbound(_, _)]
+    ));
+}
+
+#[tokio::test]
+async fn negotiation_failure_emits_exactly_one_event() {
+    let (alice, _, _) = new_swarm(
+        |_, _| TestBehaviour::new(b"/terminal/1.0.0"),
+        Handle::current(),
+    );
+    let (bob, _, bob_peer_id) = new_swarm(
+        |_, _| TestBehaviour::new(b"/terminal/2.0.0"),
+        Handle::current(),
+    );
+    let (mut alice, mut bob) = (alice, bob);
+    connect(&mut alice, &mut bob).await;
+
+    alice
+        .behaviour_mut()
+        .do_protocol_dialer(bob_peer_id, |_| async move { Ok(()) });
+
+    let (alice_events, bob_events) =
+        collect_events(&mut alice, &mut bob, Duration::from_secs(1)).await;
+
+    assert!(matches!(
+        alice_events.as_slice(),
+        [BehaviourOutEvent::OutboundFailed(
+            _,
+            Failure::NegotiationFailed,
+            _
+        )]
+    ));
+    assert!(bob_events.is_empty());
+}
+
+#[tokio::test]
+async fn disconnect_mid_flight_emits_exactly_one_event() {
+    let (mut alice, mut bob) = new_pair().await;
+
+    alice
+        .swarm
+        .behaviour_mut()
+        .do_protocol_dialer(bob.peer_id, |_substream| future::pending());
+
+    let (alice_events, _) =
+        collect_events(&mut alice.swarm, &mut bob.swarm, Duration::from_millis(500)).await;
+    assert!(alice_events.is_empty());
+
+    drop(bob);
+
+    let alice_events = collect_events_single(&mut alice.swarm, Duration::from_secs(1)).await;
+
+    assert!(matches!(
+        alice_events.as_slice(),
+        [BehaviourOutEvent::OutboundFailed(
+            _,
+            Failure::ConnectionClosed,
+            _
+        )]
+    ));
+}
+
+#[tokio::test]
+async fn protocols_queued_on_a_busy_connection_each_emit_exactly_one_event() {
+    let (mut alice, mut bob) = new_pair().await;
+
+    for _ in 0..2 {
+        alice
+            .swarm
+            .behaviour_mut()
+            .do_protocol_dialer(bob.peer_id, |mut substream| async move {
+                substream.write_message(b"hello").await?;
+                substream.read_message(1024).await?;
+                Ok(())
+            });
+        bob.swarm
+            .behaviour_mut()
+            .do_protocol_listener(alice.peer_id, |mut substream| async move {
+                let msg = substream.read_message(1024).await?;
+                substream.write_message(&msg).await?;
+                Ok(())
+            });
+    }
+
+    let (alice_events, bob_events) =
+        collect_events(&mut alice.swarm, &mut bob.swarm, Duration::from_secs(1)).await;
+
+    assert!(matches!(
+        alice_events.as_slice(),
+        [
+            BehaviourOutEvent::Outbound(_, Ok(()), _),
+            BehaviourOutEvent::Outbound(_, Ok(()), _)
+        ]
+    ));
+    assert!(matches!(
+        bob_events.as_slice(),
+        [
+            BehaviourOutEvent::Inbound(_, Ok(())),
+            BehaviourOutEvent::Inbound(_, Ok(()))
+        ]
+    ));
+}
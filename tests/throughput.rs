@@ -0,0 +1,104 @@
+use harness::{collect_events, connect, new_swarm};
+use libp2p_async_await::{Behaviour, BehaviourOutEvent, Throughput};
+use std::time::Duration;
+use tokio::runtime::Handle;
+
+mod harness;
+
+type TestBehaviour = Behaviour<(), (), anyhow::Error>;
+
+fn new_behaviour() -> TestBehaviour {
+    let mut behaviour = TestBehaviour::new(b"/foo/1.0.0");
+    behaviour.set_track_throughput(true);
+
+    behaviour
+}
+
+#[tokio::test]
+async fn throughput_is_counted_when_protocols_terminate() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, _, alice_peer_id) = new_swarm(|_, _| new_behaviour(), Handle::current());
+    let (mut bob, _, bob_peer_id) = new_swarm(|_, _| new_behaviour(), Handle::current());
+    connect(&mut alice, &mut bob).await;
+
+    for _ in 0..2 {
+        alice
+            .behaviour_mut()
+            .do_protocol_dialer(bob_peer_id, |mut substream| async move {
+                substream.write_message(&[0; 1000]).await?;
+                Ok(())
+            });
+        bob.behaviour_mut()
+            .do_protocol_listener(alice_peer_id, |mut substream| async move {
+                substream.read_message(1000).await?;
+                Ok(())
+            });
+    }
+
+    let (alice_events, bob_events) =
+        collect_events(&mut alice, &mut bob, Duration::from_secs(1)).await;
+    assert!(matches!(
+        alice_events.as_slice(),
+        [
+            BehaviourOutEvent::Outbound(_, Ok(()), None),
+            BehaviourOutEvent::Outbound(_, Ok(()), None)
+        ]
+    ));
+    assert!(matches!(
+        bob_events.as_slice(),
+        [
+            BehaviourOutEvent::Inbound(_, Ok(())),
+            BehaviourOutEvent::Inbound(_, Ok(()))
+        ]
+    ));
+
+    // Each message is prefixed with its length, which takes up two bytes.
+    let throughput = alice
+        .behaviour()
+        .protocol_throughput(b"/foo/1.0.0")
+        .expect("protocols to be counted");
+    assert_eq!((throughput.bytes_read, throughput.bytes_written), (0, 2004));
+    assert!(throughput.write_per_sec() > 0.0);
+    let throughput = bob
+        .behaviour()
+        .protocol_throughput(b"/foo/1.0.0")
+        .expect("protocols to be counted");
+    assert_eq!((throughput.bytes_read, throughput.bytes_written), (2004, 0));
+}
+
+#[tokio::test]
+async fn throughput_is_not_counted_by_default() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, _, _) = new_swarm(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current());
+    let (mut bob, _, bob_peer_id) =
+        new_swarm(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current());
+    connect(&mut alice, &mut bob).await;
+
+    alice
+        .behaviour_mut()
+        .do_protocol_dialer(bob_peer_id, |mut substream| async move {
+            substream.write_message(b"hello").await?;
+            Ok(())
+        });
+    let (alice_events, _) = collect_events(&mut alice, &mut bob, Duration::from_secs(1)).await;
+
+    assert!(matches!(
+        alice_events.as_slice(),
+        [BehaviourOutEvent::Outbound(_, Ok(()), None)]
+    ));
+    assert_eq!(alice.behaviour().protocol_throughput(b"/foo/1.0.0"), None);
+}
+
+#[test]
+fn protocols_terminating_immediately_have_no_throughput() {
+    let throughput = Throughput {
+        bytes_read: 10,
+        bytes_written: 10,
+        duration: Duration::ZERO,
+    };
+
+    assert_eq!(throughput.read_per_sec(), 0.0);
+    assert_eq!(throughput.write_per_sec(), 0.0);
+}
@@ -0,0 +1,185 @@
+use harness::{collect_events, connect, new_swarm};
+use libp2p::futures::future;
+use libp2p_async_await::{Behaviour, BehaviourOutEvent, Failure};
+use std::time::{Duration, Instant};
+use tokio::runtime::Handle;
+
+mod harness;
+
+type TestBehaviour = Behaviour<(), (), anyhow::Error>;
+
+#[tokio::test]
+async fn outbound_protocols_fail_after_the_outbound_timeout() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, _, alice_peer_id) =
+        new_swarm(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current());
+    let (mut bob, _, bob_peer_id) =
+        new_swarm(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current());
+    connect(&mut alice, &mut bob).await;
+
+    alice
+        .behaviour_mut()
+        .set_outbound_timeout(Some(Duration::from_millis(100)));
+    bob.behaviour_mut()
+        .set_inbound_timeout(Some(Duration::from_secs(10)));
+    alice
+        .behaviour_mut()
+        .do_protocol_dialer(bob_peer_id, |_| future::pending());
+    bob.behaviour_mut()
+        .do_protocol_listener(alice_peer_id, |_| future::pending());
+    let (alice_events, bob_events) =
+        collect_events(&mut alice, &mut bob, Duration::from_millis(500)).await;
+
+    assert!(matches!(
+        alice_events.as_slice(),
+        [BehaviourOutEvent::OutboundFailed(_, Failure::Timeout, None)]
+    ));
+    assert!(bob_events.is_empty());
+}
+
+#[tokio::test]
+async fn directional_timeouts_override_the_protocol_timeout() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, _, alice_peer_id) =
+        new_swarm(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current());
+    let (mut bob, _, bob_peer_id) =
+        new_swarm(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current());
+    connect(&mut alice, &mut bob).await;
+
+    alice
+        .behaviour_mut()
+        .set_protocol_timeout(Some(Duration::from_secs(10)));
+    alice
+        .behaviour_mut()
+        .set_inbound_timeout(Some(Duration::from_millis(100)));
+    alice
+        .behaviour_mut()
+        .do_protocol_listener(bob_peer_id, |_| future::pending());
+    bob.behaviour_mut()
+        .do_protocol_dialer(alice_peer_id, |_| future::pending());
+    let (alice_events, _) = collect_events(&mut alice, &mut bob, Duration::from_millis(500)).await;
+
+    assert!(matches!(
+        alice_events.as_slice(),
+        [BehaviourOutEvent::InboundFailed(_, Failure::Timeout)]
+    ));
+
+    let config = alice.behaviour().config();
+    assert_eq!(config.inbound_timeout, Some(Duration::from_millis(100)));
+    assert_eq!(config.outbound_timeout, Some(Duration::from_secs(10)));
+}
+
+#[tokio::test]
+async fn executing_protocols_fail_once_their_deadline_passes() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, _, alice_peer_id) =
+        new_swarm(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current());
+    let (mut bob, _, bob_peer_id) =
+        new_swarm(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current());
+    connect(&mut alice, &mut bob).await;
+
+    alice.behaviour_mut().do_protocol_dialer_until(
+        bob_peer_id,
+        Instant::now() + Duration::from_millis(100),
+        |_| future::pending(),
+    );
+    bob.behaviour_mut()
+        .do_protocol_listener(alice_peer_id, |_| future::pending());
+    let (alice_events, _) = collect_events(&mut alice, &mut bob, Duration::from_millis(500)).await;
+
+    assert!(matches!(
+        alice_events.as_slice(),
+        [BehaviourOutEvent::OutboundFailed(
+            _,
+            Failure::DeadlineExceeded,
+            None
+        )]
+    ));
+}
+
+#[tokio::test]
+async fn queued_protocols_fail_once_their_deadline_passes() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, _, _) = new_swarm(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current());
+    let (mut bob, _, bob_peer_id) =
+        new_swarm(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current());
+
+    alice.behaviour_mut().do_protocol_dialer_until(
+        bob_peer_id,
+        Instant::now() + Duration::from_millis(100),
+        |_| future::pending(),
+    );
+    let (alice_events, _) = collect_events(&mut alice, &mut bob, Duration::from_millis(500)).await;
+
+    assert!(matches!(
+        alice_events.as_slice(),
+        [BehaviourOutEvent::OutboundFailed(
+            _,
+            Failure::DeadlineExceeded,
+            None
+        )]
+    ));
+}
+
+/// Times out a protocol of alice and returns what bob read from the other end of the substream,
+/// if anything arrived.
+async fn read_after_timeout(notification: Option<Vec<u8>>) -> Option<Vec<u8>> {
+    let (mut alice, _, alice_peer_id) = new_swarm(
+        |_, _| Behaviour::<Vec<u8>, (), anyhow::Error>::new(b"/foo/1.0.0"),
+        Handle::current(),
+    );
+    let (mut bob, _, bob_peer_id) = new_swarm(
+        |_, _| Behaviour::<Vec<u8>, (), anyhow::Error>::new(b"/foo/1.0.0"),
+        Handle::current(),
+    );
+    connect(&mut alice, &mut bob).await;
+
+    alice
+        .behaviour_mut()
+        .set_outbound_timeout(Some(Duration::from_millis(100)));
+    alice.behaviour_mut().set_timeout_notification(notification);
+    alice
+        .behaviour_mut()
+        .do_protocol_dialer(bob_peer_id, |mut substream| async move {
+            substream.write_message(b"start").await?;
+            future::pending().await
+        });
+    bob.behaviour_mut()
+        .do_protocol_listener(alice_peer_id, |mut substream| async move {
+            substream.read_message(1024).await?;
+            Ok(substream.read_message(1024).await?)
+        });
+    let (alice_events, bob_events) =
+        collect_events(&mut alice, &mut bob, Duration::from_millis(500)).await;
+
+    assert!(matches!(
+        alice_events.as_slice(),
+        [BehaviourOutEvent::OutboundFailed(_, Failure::Timeout, None)]
+    ));
+    match bob_events.as_slice() {
+        [] => None,
+        [BehaviourOutEvent::Inbound(_, Ok(msg))] => Some(msg.clone()),
+        events => panic!("unexpected events: {:?}", events),
+    }
+}
+
+#[tokio::test]
+async fn timed_out_protocols_notify_the_remote() {
+    let _ = env_logger::try_init();
+
+    assert_eq!(
+        read_after_timeout(Some(b"timed out".to_vec())).await,
+        Some(b"timed out".to_vec())
+    );
+}
+
+#[tokio::test]
+async fn timed_out_protocols_do_not_notify_the_remote_by_default() {
+    let _ = env_logger::try_init();
+
+    assert_eq!(read_after_timeout(None).await, None);
+}
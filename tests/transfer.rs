@@ -0,0 +1,129 @@
+use harness::{collect_events, new_connected_swarm_pair};
+use libp2p::futures::io::Cursor;
+use libp2p::futures::StreamExt;
+use libp2p_async_await::driver::Driver;
+use libp2p_async_await::transfer::TransferError;
+use libp2p_async_await::{Behaviour, BehaviourOutEvent, InboundSubstream, ProtocolOutEvent};
+use std::time::Duration;
+use tokio::runtime::Handle;
+
+mod harness;
+
+type TestBehaviour = Behaviour<(Vec<u8>, Vec<u64>), Vec<u64>, anyhow::Error>;
+
+#[tokio::test]
+async fn streams_are_reassembled_with_progress() {
+    let _ = env_logger::try_init();
+
+    let data = (0..10_000u32).map(|i| i as u8).collect::<Vec<_>>();
+    let (mut alice, mut bob) = new_connected_swarm_pair(
+        |_, _| TestBehaviour::new(b"/transfer/1.0.0"),
+        Handle::current(),
+    )
+    .await;
+
+    alice
+        .swarm
+        .behaviour_mut()
+        .do_protocol_dialer(bob.peer_id, {
+            let data = data.clone();
+            |mut substream| async move {
+                let mut progress = Vec::new();
+                substream
+                    .send_stream(Cursor::new(data), 4096, |sent| progress.push(sent))
+                    .await?;
+
+                Ok(progress)
+            }
+        });
+    bob.swarm
+        .behaviour_mut()
+        .do_protocol_listener(alice.peer_id, |mut substream| async move {
+            let mut received = Vec::new();
+            let mut progress = Vec::new();
+            substream
+                .recv_stream(&mut received, 1024 * 1024, |total| progress.push(total))
+                .await?;
+
+            Ok((received, progress))
+        });
+
+    let (alice_events, bob_events) =
+        collect_events(&mut alice.swarm, &mut bob.swarm, Duration::from_secs(1)).await;
+
+    match alice_events.as_slice() {
+        [BehaviourOutEvent::Outbound(_, Ok(progress), _)] => {
+            assert_eq!(progress, &[4096, 8192, 10_000])
+        }
+        events => panic!("unexpected events {:?}", events),
+    }
+    match bob_events.as_slice() {
+        [BehaviourOutEvent::Inbound(_, Ok((received, progress)))] => {
+            assert_eq!(received, &data);
+            assert_eq!(progress, &[4096, 8192, 10_000]);
+        }
+        events => panic!("unexpected events {:?}", events),
+    }
+}
+
+/// Receives a transfer from the given frames, as written by `send_stream`.
+async fn recv(frames: Vec<u8>, max_size: u64) -> Result<u64, TransferError> {
+    let mut driver = Driver::<Result<u64, TransferError>, (), anyhow::Error>::new();
+    driver.execute_inbound(
+        InboundSubstream::new(Cursor::new(frames), b"/transfer/1.0.0"),
+        move |mut substream| async move {
+            Ok(substream.recv_stream(Vec::new(), max_size, |_| {}).await)
+        },
+    );
+
+    match driver.next().await {
+        Some(ProtocolOutEvent::Inbound(Ok(res))) => res,
+        _ => panic!("protocol failed"),
+    }
+}
+
+fn data_frame(chunk: &[u8]) -> Vec<u8> {
+    let mut frame = vec![chunk.len() as u8 + 1, 0];
+    frame.extend_from_slice(chunk);
+
+    frame
+}
+
+fn end_frame(total: u64) -> Vec<u8> {
+    let mut frame = vec![9, 1];
+    frame.extend_from_slice(&total.to_be_bytes());
+
+    frame
+}
+
+#[tokio::test]
+async fn transfers_beyond_the_maximum_size_fail() {
+    let mut frames = data_frame(&[1; 100]);
+    frames.extend(data_frame(&[2; 100]));
+    frames.extend(end_frame(200));
+
+    assert_eq!(recv(frames.clone(), 200).await.unwrap(), 200);
+    assert!(matches!(
+        recv(frames, 150).await,
+        Err(TransferError::TooLarge { max_size: 150 })
+    ));
+}
+
+#[tokio::test]
+async fn incomplete_transfers_fail() {
+    let truncated = data_frame(b"hello");
+    assert!(matches!(
+        recv(truncated, 1024).await,
+        Err(TransferError::Truncated)
+    ));
+
+    let mut mismatched = data_frame(b"hello");
+    mismatched.extend(end_frame(6));
+    assert!(matches!(
+        recv(mismatched, 1024).await,
+        Err(TransferError::SizeMismatch {
+            announced: 6,
+            received: 5
+        })
+    ));
+}
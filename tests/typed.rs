@@ -0,0 +1,93 @@
+use harness::{collect_events, connect, new_swarm};
+use libp2p_async_await::typed::{Protocol, TypedBehaviour};
+use libp2p_async_await::{Behaviour, BehaviourOutEvent, FramingError, ProtocolError};
+use std::io;
+use std::time::Duration;
+use tokio::runtime::Handle;
+
+mod harness;
+
+struct Upper;
+
+impl Protocol for Upper {
+    type Request = String;
+    type Response = String;
+
+    const INFO: &'static [u8] = b"/upper/1.0.0";
+
+    fn handle(request: String) -> String {
+        request.to_uppercase()
+    }
+}
+
+/// Speaks [`Upper`] but sends raw bytes.
+struct Raw;
+
+impl Protocol for Raw {
+    type Request = Vec<u8>;
+    type Response = String;
+
+    const INFO: &'static [u8] = Upper::INFO;
+
+    fn handle(request: Vec<u8>) -> String {
+        String::from_utf8_lossy(&request).into_owned()
+    }
+}
+
+#[tokio::test]
+async fn requests_are_answered_by_the_protocol() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, _, _) = new_swarm(|_, _| Behaviour::typed::<Upper>(), Handle::current());
+    let (mut bob, _, bob_peer_id) =
+        new_swarm(|_, _| Behaviour::typed::<Upper>(), Handle::current());
+    connect(&mut alice, &mut bob).await;
+
+    alice
+        .behaviour_mut()
+        .request::<Upper>(bob_peer_id, String::from("hello"));
+
+    let (alice_events, bob_events) =
+        collect_events(&mut alice, &mut bob, Duration::from_secs(1)).await;
+
+    match alice_events.as_slice() {
+        [BehaviourOutEvent::Outbound(_, Ok(response), None)] => assert_eq!(response, "HELLO"),
+        events => panic!("unexpected events {:?}", events),
+    }
+    assert!(matches!(
+        bob_events.as_slice(),
+        [BehaviourOutEvent::Inbound(_, Ok(()))]
+    ));
+}
+
+#[tokio::test]
+async fn requests_that_fail_to_decode_fail_the_handler() {
+    let _ = env_logger::try_init();
+
+    let (mut alice, _, _) = new_swarm(
+        |_, _| -> TypedBehaviour<Raw> { Behaviour::typed::<Raw>() },
+        Handle::current(),
+    );
+    let (mut bob, _, bob_peer_id) =
+        new_swarm(|_, _| Behaviour::typed::<Upper>(), Handle::current());
+    connect(&mut alice, &mut bob).await;
+
+    alice
+        .behaviour_mut()
+        .request::<Raw>(bob_peer_id, vec![0xff]);
+
+    let (_, bob_events) = collect_events(&mut alice, &mut bob, Duration::from_secs(1)).await;
+
+    match bob_events.as_slice() {
+        [BehaviourOutEvent::Inbound(
+            _,
+            Err(ProtocolError::Application(FramingError::Serialization(e))),
+        )] => {
+            let e = e
+                .downcast_ref::<io::Error>()
+                .expect("decode errors to be io errors");
+            assert_eq!(e.kind(), io::ErrorKind::InvalidData)
+        }
+        events => panic!("unexpected events {:?}", events),
+    }
+}
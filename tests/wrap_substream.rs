@@ -0,0 +1,104 @@
+use harness::{collect_events, connect, new_swarm};
+use libp2p::futures::task::{Context, Poll};
+use libp2p::futures::{AsyncRead, AsyncWrite};
+use libp2p_async_await::{Behaviour, BehaviourOutEvent};
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::runtime::Handle;
+
+mod harness;
+
+type TestBehaviour = Behaviour<Vec<u8>, (), anyhow::Error>;
+
+/// Flips the bits of everything read and written.
+struct Inverted<S>(S);
+
+impl<S: AsyncRead + Unpin> AsyncRead for Inverted<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let read = match Pin::new(&mut self.0).poll_read(cx, buf)? {
+            Poll::Ready(read) => read,
+            Poll::Pending => return Poll::Pending,
+        };
+        for byte in &mut buf[..read] {
+            *byte = !*byte;
+        }
+
+        Poll::Ready(Ok(read))
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Inverted<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let inverted = buf.iter().map(|byte| !byte).collect::<Vec<_>>();
+
+        Pin::new(&mut self.0).poll_write(cx, &inverted)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_close(cx)
+    }
+}
+
+fn new_behaviour(wrapped: Arc<AtomicUsize>) -> TestBehaviour {
+    let mut behaviour = TestBehaviour::new(b"/foo/1.0.0");
+    behaviour.wrap_substream(move |substream| {
+        wrapped.fetch_add(1, Ordering::SeqCst);
+        Inverted(substream)
+    });
+
+    behaviour
+}
+
+#[tokio::test]
+async fn protocols_use_the_wrapped_substreams() {
+    let _ = env_logger::try_init();
+
+    let alice_wrapped = Arc::new(AtomicUsize::new(0));
+    let bob_wrapped = Arc::new(AtomicUsize::new(0));
+    let wrapped = alice_wrapped.clone();
+    let (mut alice, _, alice_peer_id) = new_swarm(
+        move |_, _| new_behaviour(wrapped.clone()),
+        Handle::current(),
+    );
+    let wrapped = bob_wrapped.clone();
+    let (mut bob, _, bob_peer_id) = new_swarm(
+        move |_, _| new_behaviour(wrapped.clone()),
+        Handle::current(),
+    );
+    connect(&mut alice, &mut bob).await;
+
+    alice
+        .behaviour_mut()
+        .do_protocol_dialer(bob_peer_id, |mut substream| async move {
+            substream.write_message(b"hello").await?;
+            Ok(())
+        });
+    bob.behaviour_mut()
+        .do_protocol_listener(alice_peer_id, |mut substream| async move {
+            Ok(substream.read_message(1024).await?)
+        });
+
+    let (_, bob_events) = collect_events(&mut alice, &mut bob, Duration::from_secs(1)).await;
+
+    assert!(matches!(
+        bob_events.as_slice(),
+        [BehaviourOutEvent::Inbound(_, Ok(message))] if message == b"hello"
+    ));
+    assert_eq!(alice_wrapped.load(Ordering::SeqCst), 1);
+    assert_eq!(bob_wrapped.load(Ordering::SeqCst), 1);
+}
@@ -0,0 +1,67 @@
+use harness::{collect_events, connect, new_swarm};
+use libp2p_async_await::{Behaviour, BehaviourOutEvent};
+use std::time::{Duration, Instant};
+use tokio::runtime::Handle;
+
+mod harness;
+
+type TestBehaviour = Behaviour<(), Duration, anyhow::Error>;
+
+async fn write_twice(write_rate_limit: Option<u64>) -> Duration {
+    let (mut alice, _, alice_peer_id) = new_swarm(
+        |_, _| {
+            let mut behaviour = TestBehaviour::new(b"/foo/1.0.0");
+            behaviour.set_write_rate_limit(write_rate_limit);
+            behaviour
+        },
+        Handle::current(),
+    );
+    let (mut bob, _, bob_peer_id) =
+        new_swarm(|_, _| TestBehaviour::new(b"/foo/1.0.0"), Handle::current());
+    connect(&mut alice, &mut bob).await;
+
+    alice
+        .behaviour_mut()
+        .do_protocol_dialer(bob_peer_id, |mut substream| async move {
+            let started = Instant::now();
+            substream.write_message(&[0; 1000]).await?;
+            substream.write_message(&[0; 1000]).await?;
+            substream.read_message(1).await?;
+
+            Ok(started.elapsed())
+        });
+    bob.behaviour_mut()
+        .do_protocol_listener(alice_peer_id, |mut substream| async move {
+            substream.read_message(1000).await?;
+            substream.read_message(1000).await?;
+            substream.write_message(&[0]).await?;
+
+            Ok(())
+        });
+
+    let (alice_events, _) = collect_events(&mut alice, &mut bob, Duration::from_secs(3)).await;
+
+    match alice_events.as_slice() {
+        [BehaviourOutEvent::Outbound(_, Ok(elapsed), None)] => *elapsed,
+        events => panic!("unexpected events {:?}", events),
+    }
+}
+
+#[tokio::test]
+async fn writes_wait_for_the_write_budget() {
+    let _ = env_logger::try_init();
+
+    // The bucket starts out with one second worth of bytes, the rest takes another second.
+    let elapsed = write_twice(Some(1002)).await;
+
+    assert!(elapsed >= Duration::from_millis(900), "{:?}", elapsed);
+}
+
+#[tokio::test]
+async fn writes_are_not_limited_by_default() {
+    let _ = env_logger::try_init();
+
+    let elapsed = write_twice(None).await;
+
+    assert!(elapsed < Duration::from_millis(500), "{:?}", elapsed);
+}